@@ -0,0 +1,45 @@
+// SPDX-FileCopyrightText: 2026 Joshua Goins <josh@redstrate.com>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::error::{physis_ErrorCode, set_last_error};
+use crate::panic::ffi_guard;
+
+/// The version of the libphysis ABI this header was generated for.
+///
+/// This is bumped whenever a function signature or the layout of a public struct changes, including when fields are
+/// appended. Most structs are returned by value, so a library with a larger struct would write past the caller's copy.
+///
+/// The `struct_size` fields are there to catch a mismatch anyway: if one doesn't match your own `sizeof`, the header
+/// you built against doesn't match the loaded library and nothing else should be called.
+pub const PHYSIS_ABI_VERSION: u32 = 1;
+
+/// The size of `T` in bytes, for filling in `struct_size` fields.
+pub(crate) const fn struct_size<T>() -> u32 {
+    size_of::<T>() as u32
+}
+
+/// Returns the ABI version of the library that's actually loaded. This can differ from `PHYSIS_ABI_VERSION` if the
+/// library was updated without rebuilding against the new header.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_get_abi_version() -> u32 {
+    ffi_guard(0, || PHYSIS_ABI_VERSION)
+}
+
+/// Checks that `version` (which should be `PHYSIS_ABI_VERSION`) matches the loaded library. If it doesn't, this sets
+/// the last error and returns false, and nothing else in libphysis should be called.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_check_abi_version(version: u32) -> bool {
+    ffi_guard(false, || {
+        if version == PHYSIS_ABI_VERSION {
+            return true;
+        }
+
+        set_last_error(
+            physis_ErrorCode::InvalidArgument,
+            format!(
+                "Built against libphysis ABI version {version}, but version {PHYSIS_ABI_VERSION} is loaded"
+            ),
+        );
+        false
+    })
+}
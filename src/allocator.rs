@@ -0,0 +1,204 @@
+// SPDX-FileCopyrightText: 2026 Joshua Goins <josh@redstrate.com>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::error::{physis_ErrorCode, set_last_error};
+use crate::panic::ffi_guard;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::UnsafeCell;
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Allocates `size` bytes. The returned memory must be aligned to at least 16 bytes, like `malloc`.
+pub type physis_MallocCallback = extern "C" fn(user_data: *mut c_void, size: usize) -> *mut c_void;
+
+/// Resizes `ptr` to `size` bytes, like `realloc`.
+pub type physis_ReallocCallback =
+    extern "C" fn(user_data: *mut c_void, ptr: *mut c_void, size: usize) -> *mut c_void;
+
+/// Frees `ptr`, like `free`.
+pub type physis_FreeCallback = extern "C" fn(user_data: *mut c_void, ptr: *mut c_void);
+
+/// The alignment the host allocator is expected to guarantee. Anything stricter is over-allocated and aligned by hand.
+const HOST_ALIGN: usize = 16;
+
+/// Nothing has been allocated yet, so the host allocator can still be installed.
+const STATE_UNUSED: u8 = 0;
+/// The host allocator is being installed, allocations have to wait for it.
+const STATE_INSTALLING: u8 = 1;
+/// Something was allocated with the system allocator, so it's too late to switch.
+const STATE_SYSTEM: u8 = 2;
+/// Every allocation goes through the host allocator.
+const STATE_HOST: u8 = 3;
+
+#[derive(Clone, Copy)]
+struct HostAllocator {
+    malloc: physis_MallocCallback,
+    realloc: physis_ReallocCallback,
+    free: physis_FreeCallback,
+    user_data: *mut c_void,
+}
+
+struct HostAllocatorCell(UnsafeCell<Option<HostAllocator>>);
+
+// Only written once while in STATE_INSTALLING, and only read after STATE_HOST was observed
+unsafe impl Sync for HostAllocatorCell {}
+
+static STATE: AtomicU8 = AtomicU8::new(STATE_UNUSED);
+static HOST_ALLOCATOR: HostAllocatorCell = HostAllocatorCell(UnsafeCell::new(None));
+
+/// Routes every allocation libphysis (and Physis) makes through the host allocator, if one was set.
+struct PhysisAllocator;
+
+#[global_allocator]
+static GLOBAL: PhysisAllocator = PhysisAllocator;
+
+impl PhysisAllocator {
+    /// Returns the host allocator if it's in use, or None if the system allocator should be used.
+    fn host(&self) -> Option<HostAllocator> {
+        loop {
+            // Only a plain load once the state is settled, this runs on every allocation
+            match STATE.load(Ordering::Acquire) {
+                STATE_SYSTEM => return None,
+                STATE_HOST => return unsafe { *HOST_ALLOCATOR.0.get() },
+                STATE_UNUSED => {
+                    if STATE
+                        .compare_exchange_weak(
+                            STATE_UNUSED,
+                            STATE_SYSTEM,
+                            Ordering::AcqRel,
+                            Ordering::Acquire,
+                        )
+                        .is_ok()
+                    {
+                        return None;
+                    }
+                }
+                _ => std::hint::spin_loop(),
+            }
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for PhysisAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some(host) = self.host() else {
+            return unsafe { System.alloc(layout) };
+        };
+
+        if layout.align() <= HOST_ALIGN {
+            return (host.malloc)(host.user_data, layout.size()) as *mut u8;
+        }
+
+        // Over-allocate so there's always room for the original pointer right before the aligned one
+        let Some(size) = layout.size().checked_add(layout.align()) else {
+            return std::ptr::null_mut();
+        };
+
+        let original = (host.malloc)(host.user_data, size) as *mut u8;
+        if original.is_null() {
+            return original;
+        }
+
+        unsafe {
+            let offset = layout.align() - (original as usize % layout.align());
+            let aligned = original.add(offset);
+            (aligned as *mut *mut u8).sub(1).write_unaligned(original);
+
+            aligned
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let Some(host) = self.host() else {
+            return unsafe { System.dealloc(ptr, layout) };
+        };
+
+        if layout.align() <= HOST_ALIGN {
+            (host.free)(host.user_data, ptr as *mut c_void);
+        } else {
+            let original = unsafe { (ptr as *mut *mut u8).sub(1).read_unaligned() };
+            (host.free)(host.user_data, original as *mut c_void);
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let Some(host) = self.host() else {
+            return unsafe { System.realloc(ptr, layout, new_size) };
+        };
+
+        if layout.align() <= HOST_ALIGN {
+            return (host.realloc)(host.user_data, ptr as *mut c_void, new_size) as *mut u8;
+        }
+
+        // The host's realloc doesn't know about our alignment, so move it by hand
+        unsafe {
+            let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+            let new_ptr = self.alloc(new_layout);
+            if !new_ptr.is_null() {
+                std::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+                self.dealloc(ptr, layout);
+            }
+
+            new_ptr
+        }
+    }
+}
+
+/// Routes all memory libphysis allocates through `malloc`, `realloc` and `free`, with `user_data` passed along. This
+/// includes every buffer, string and array returned to you, so the host can free them itself with `free` instead of
+/// the matching `physis_*_free` function (which still works, and also frees any memory owned by the object.)
+///
+/// This must be the very first libphysis call in the process, before any other thread could call into libphysis
+/// either. Any allocation made before this is called, by any libphysis function on any thread, permanently locks the
+/// whole process to the system allocator, since memory allocated by it can't be handed to the host's `free`. There's
+/// no way to undo that, and later calls to this function always fail.
+///
+/// Returns false and sets the last error if any of the callbacks are NULL, it's too late, or the allocator was already
+/// set. Setting the last error allocates, so after any failure the process stays on the system allocator.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_set_allocator(
+    malloc: Option<physis_MallocCallback>,
+    realloc: Option<physis_ReallocCallback>,
+    free: Option<physis_FreeCallback>,
+    user_data: *mut c_void,
+) -> bool {
+    ffi_guard(false, || {
+        // Reporting this allocates, so it's too late to try again with valid callbacks afterwards
+        let (Some(malloc), Some(realloc), Some(free)) = (malloc, realloc, free) else {
+            set_last_error(
+                physis_ErrorCode::InvalidArgument,
+                "The malloc, realloc and free callbacks can't be NULL",
+            );
+            return false;
+        };
+
+        if STATE
+            .compare_exchange(
+                STATE_UNUSED,
+                STATE_INSTALLING,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_err()
+        {
+            set_last_error(
+                physis_ErrorCode::InvalidArgument,
+                "The allocator must be set before anything else is allocated",
+            );
+            return false;
+        }
+
+        unsafe {
+            *HOST_ALLOCATOR.0.get() = Some(HostAllocator {
+                malloc,
+                realloc,
+                free,
+                user_data,
+            });
+        }
+
+        STATE.store(STATE_HOST, Ordering::Release);
+
+        true
+    })
+}
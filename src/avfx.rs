@@ -1,12 +1,16 @@
 // SPDX-FileCopyrightText: 2026 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::{ffi_to_c_string, physis_Buffer};
+use crate::error::{ffi_result, set_physis_error};
+use crate::json::{ToJson, array_to_json, ffi_parsed_to_json, raw_to_json, string_to_json};
+use crate::panic::ffi_guard;
+use crate::{ffi_free_string, ffi_to_c_string, ffi_to_vec, physis_Buffer};
 use physis::Platform;
 use physis::ReadableFile;
 use physis::avfx::{Avfx, DrawVertex};
+use serde_json::{Value, json};
 use std::ffi::c_char;
-use std::ptr::null_mut;
+use std::ptr::{null, null_mut};
 use std::slice;
 
 #[repr(C)]
@@ -40,51 +44,75 @@ pub struct physis_AvfxDrawModel {
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_avfx_parse(platform: Platform, buffer: physis_Buffer) -> physis_Avfx {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(physis_Avfx::default(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-    if let Ok(avfx) = Avfx::from_existing(platform, data) {
-        let mut c_textures = Vec::new();
-        for texture in &avfx.textures {
-            c_textures.push(ffi_to_c_string(&texture.path));
-        }
+        if let Some(avfx) = ffi_result(Avfx::from_existing(platform, data)) {
+            let mut c_textures = Vec::new();
+            for texture in &avfx.textures {
+                c_textures.push(ffi_to_c_string(&texture.path));
+            }
 
-        let mut c_models = vec![];
-        for model in &avfx.models {
-            let mut c_vertices = model.vertices.clone();
-            let mut c_indices = Vec::new();
-            for triangle in &model.triangles {
-                c_indices.push(triangle.indices[0]);
-                c_indices.push(triangle.indices[1]);
-                c_indices.push(triangle.indices[2]);
+            let mut c_models = vec![];
+            for model in &avfx.models {
+                let mut c_vertices = model.vertices.clone();
+                let mut c_indices = Vec::new();
+                for triangle in &model.triangles {
+                    c_indices.push(triangle.indices[0]);
+                    c_indices.push(triangle.indices[1]);
+                    c_indices.push(triangle.indices[2]);
+                }
+
+                let c_model = physis_AvfxDrawModel {
+                    vertex_count: c_vertices.len() as u32,
+                    vertices: c_vertices.as_mut_ptr(),
+                    index_count: c_indices.len() as u32,
+                    indices: c_indices.as_mut_ptr(),
+                };
+
+                std::mem::forget(c_vertices);
+                std::mem::forget(c_indices);
+
+                c_models.push(c_model);
             }
 
-            let c_model = physis_AvfxDrawModel {
-                vertex_count: c_vertices.len() as u32,
-                vertices: c_vertices.as_mut_ptr(),
-                index_count: c_indices.len() as u32,
-                indices: c_indices.as_mut_ptr(),
+            let c_avfx = physis_Avfx {
+                texture_count: c_textures.len() as u32,
+                textures: c_textures.as_mut_ptr(),
+                model_count: c_models.len() as u32,
+                models: c_models.as_mut_ptr(),
             };
 
-            std::mem::forget(c_vertices);
-            std::mem::forget(c_indices);
+            std::mem::forget(c_textures);
+            std::mem::forget(c_models);
 
-            c_models.push(c_model);
+            c_avfx
+        } else {
+            physis_Avfx::default()
         }
+    })
+}
 
-        let c_avfx = physis_Avfx {
-            texture_count: c_textures.len() as u32,
-            textures: c_textures.as_mut_ptr(),
-            model_count: c_models.len() as u32,
-            models: c_models.as_mut_ptr(),
-        };
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_avfx_free(avfx: &physis_Avfx) {
+    ffi_guard((), || {
+        if avfx.textures.is_null() {
+            return;
+        }
 
-        std::mem::forget(c_textures);
-        std::mem::forget(c_models);
+        let textures = ffi_to_vec(avfx.textures, avfx.texture_count);
+        for texture in &textures {
+            ffi_free_string(*texture);
+        }
+        drop(textures);
 
-        c_avfx
-    } else {
-        physis_Avfx::default()
-    }
+        let models = ffi_to_vec(avfx.models, avfx.model_count);
+        for model in &models {
+            drop(ffi_to_vec(model.vertices, model.vertex_count));
+            drop(ffi_to_vec(model.indices, model.index_count));
+        }
+        drop(models);
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -92,10 +120,46 @@ pub unsafe extern "C" fn physis_avfx_debug(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> *const c_char {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(null(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+
+        match Avfx::from_existing(platform, data) {
+            Ok(avfx) => ffi_to_c_string(&format!("{avfx:#?}")),
+            Err(err) => {
+                set_physis_error(&err);
+                ffi_to_c_string(&format!("{err:#?}"))
+            }
+        }
+    })
+}
 
-    match Avfx::from_existing(platform, data) {
-        Ok(avfx) => ffi_to_c_string(&format!("{avfx:#?}")),
-        Err(err) => ffi_to_c_string(&format!("{err:#?}")),
+impl ToJson for physis_Avfx {
+    fn to_json(&self) -> Value {
+        json!({
+            "textures": array_to_json(self.textures, self.texture_count as usize, |texture| {
+                string_to_json(*texture)
+            }),
+            "models": array_to_json(self.models, self.model_count as usize, |model| {
+                json!({
+                    "vertices": array_to_json(
+                        model.vertices,
+                        model.vertex_count as usize,
+                        raw_to_json
+                    ),
+                    "indices": array_to_json(
+                        model.indices,
+                        model.index_count as usize,
+                        |index| json!(index)
+                    ),
+                })
+            }),
+        })
     }
 }
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_avfx_to_json(platform: Platform, buffer: physis_Buffer) -> *const c_char {
+    ffi_guard(null(), || {
+        ffi_parsed_to_json(physis_avfx_parse(platform, buffer), physis_avfx_free)
+    })
+}
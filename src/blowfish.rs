@@ -1,8 +1,11 @@
 // SPDX-FileCopyrightText: 2024 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use crate::error::{physis_ErrorCode, set_last_error};
+use crate::panic::ffi_guard;
 use physis::blowfish::{SqexArgBlowfish, SteamTicketBlowfish};
 use std::os::raw::c_uint;
+use std::ptr::null_mut;
 use std::{mem, slice};
 
 #[unsafe(no_mangle)]
@@ -10,15 +13,17 @@ pub extern "C" fn physis_blowfish_initialize(
     key: *mut u8,
     key_size: c_uint,
 ) -> *mut SqexArgBlowfish {
-    let data = unsafe { slice::from_raw_parts(key, key_size as usize) };
-    Box::into_raw(Box::new(SqexArgBlowfish::new(data)))
+    ffi_guard(null_mut(), || {
+        let data = unsafe { slice::from_raw_parts(key, key_size as usize) };
+        Box::into_raw(Box::new(SqexArgBlowfish::new(data)))
+    })
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_blowfish_free(blowfish: *mut SqexArgBlowfish) {
-    unsafe {
+    ffi_guard((), || unsafe {
         drop(Box::from_raw(blowfish));
-    }
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -29,23 +34,28 @@ pub extern "C" fn physis_blowfish_encrypt(
     out_data: &mut *mut u8,
     out_data_size: *mut u32,
 ) -> bool {
-    let in_data = unsafe { slice::from_raw_parts(in_data, in_data_size as usize) };
+    ffi_guard(false, || {
+        let in_data = unsafe { slice::from_raw_parts(in_data, in_data_size as usize) };
 
-    let result = blowfish.encrypt(in_data);
+        let result = blowfish.encrypt(in_data);
 
-    match result {
-        Some(mut out_data_vec) => {
-            unsafe {
-                *out_data = out_data_vec.as_mut_ptr();
-                *out_data_size = out_data_vec.len() as u32;
-            }
+        match result {
+            Some(mut out_data_vec) => {
+                unsafe {
+                    *out_data = out_data_vec.as_mut_ptr();
+                    *out_data_size = out_data_vec.len() as u32;
+                }
 
-            mem::forget(out_data_vec);
+                mem::forget(out_data_vec);
 
-            true
+                true
+            }
+            None => {
+                set_last_error(physis_ErrorCode::InvalidArgument, "Failed to encrypt data");
+                false
+            }
         }
-        None => false,
-    }
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -56,23 +66,28 @@ pub extern "C" fn physism_blowfish_decrypt(
     out_data: &mut *mut u8,
     out_data_size: *mut u32,
 ) -> bool {
-    let in_data = unsafe { slice::from_raw_parts(in_data, in_data_size as usize) };
+    ffi_guard(false, || {
+        let in_data = unsafe { slice::from_raw_parts(in_data, in_data_size as usize) };
 
-    let result = blowfish.decrypt(in_data);
+        let result = blowfish.decrypt(in_data);
 
-    match result {
-        Some(mut out_data_vec) => {
-            unsafe {
-                *out_data = out_data_vec.as_mut_ptr();
-                *out_data_size = out_data_vec.len() as u32;
-            }
+        match result {
+            Some(mut out_data_vec) => {
+                unsafe {
+                    *out_data = out_data_vec.as_mut_ptr();
+                    *out_data_size = out_data_vec.len() as u32;
+                }
 
-            mem::forget(out_data_vec);
+                mem::forget(out_data_vec);
 
-            true
+                true
+            }
+            None => {
+                set_last_error(physis_ErrorCode::InvalidArgument, "Failed to decrypt data");
+                false
+            }
         }
-        None => false,
-    }
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -80,15 +95,17 @@ pub extern "C" fn physis_steamticket_blowfish_initialize(
     key: *mut u8,
     key_size: c_uint,
 ) -> *mut SteamTicketBlowfish {
-    let data = unsafe { slice::from_raw_parts(key, key_size as usize) };
-    Box::into_raw(Box::new(SteamTicketBlowfish::new(data)))
+    ffi_guard(null_mut(), || {
+        let data = unsafe { slice::from_raw_parts(key, key_size as usize) };
+        Box::into_raw(Box::new(SteamTicketBlowfish::new(data)))
+    })
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_steamticket_physis_blowfish_free(blowfish: *mut SteamTicketBlowfish) {
-    unsafe {
+    ffi_guard((), || unsafe {
         drop(Box::from_raw(blowfish));
-    }
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -97,7 +114,9 @@ pub extern "C" fn physis_steamticket_blowfish_encrypt(
     in_data: *mut u8,
     in_data_size: c_uint,
 ) {
-    let in_data = unsafe { slice::from_raw_parts_mut(in_data, in_data_size as usize) };
+    ffi_guard((), || {
+        let in_data = unsafe { slice::from_raw_parts_mut(in_data, in_data_size as usize) };
 
-    blowfish.encrypt(in_data);
+        blowfish.encrypt(in_data);
+    })
 }
@@ -1,37 +1,39 @@
 // SPDX-FileCopyrightText: 2024 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::{ffi_from_c_string, physis_Buffer};
+use crate::error::{ffi_result, physis_ErrorCode, set_last_error};
+use crate::handle::{HandleRegistry, physis_Handle};
+use crate::panic::ffi_guard;
+use crate::{ffi_from_c_string, ffi_to_buffer, ffi_to_c_string, physis_Buffer};
 use physis::cfg::ConfigFile;
 use physis::{Platform, ReadableFile, WritableFile};
+use serde_json::{Map, Value};
 use std::os::raw::c_char;
-use std::ptr::null_mut;
-use std::{mem, slice};
+use std::ptr::null;
+use std::slice;
+
+static CONFIG_FILES: HandleRegistry<ConfigFile> = HandleRegistry::new("ConfigFile");
 
 #[repr(C)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 pub struct physis_ConfigFile {
-    p_ptr: *mut ConfigFile,
-}
-
-impl Default for physis_ConfigFile {
-    fn default() -> Self {
-        Self { p_ptr: null_mut() }
-    }
+    handle: physis_Handle,
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_cfg_parse(buffer: physis_Buffer) -> physis_ConfigFile {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(physis_ConfigFile::default(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-    // TODO: don't hardcode Platform
-    if let Ok(cfg) = ConfigFile::from_existing(Platform::Win32, data) {
-        physis_ConfigFile {
-            p_ptr: Box::leak(Box::new(cfg)),
+        // TODO: don't hardcode Platform
+        if let Some(cfg) = ffi_result(ConfigFile::from_existing(Platform::Win32, data)) {
+            physis_ConfigFile {
+                handle: CONFIG_FILES.insert(cfg),
+            }
+        } else {
+            physis_ConfigFile::default()
         }
-    } else {
-        physis_ConfigFile::default()
-    }
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -40,32 +42,208 @@ pub extern "C" fn physis_cfg_set_value(
     key: *const c_char,
     value: *const c_char,
 ) {
-    let Some(r_key) = ffi_from_c_string(key) else {
-        return;
-    };
+    ffi_guard((), || {
+        let Some(r_key) = ffi_from_c_string(key) else {
+            return;
+        };
 
-    let Some(r_value) = ffi_from_c_string(value) else {
-        return;
-    };
+        let Some(r_value) = ffi_from_c_string(value) else {
+            return;
+        };
 
-    unsafe {
-        (*cfg.p_ptr).set_value(&r_key, &r_value);
-    }
+        CONFIG_FILES.with(cfg.handle, |cfg| cfg.set_value(&r_key, &r_value));
+    })
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_cfg_write(cfg: physis_ConfigFile) -> physis_Buffer {
-    unsafe {
+    ffi_guard(physis_Buffer::default(), || {
+        // TODO: don't hardcode Platform
+        let Some(buffer) =
+            CONFIG_FILES.with(cfg.handle, |cfg| cfg.write_to_buffer(Platform::Win32))
+        else {
+            return physis_Buffer::default();
+        };
+
+        match buffer {
+            Ok(buffer) => ffi_to_buffer(buffer),
+            Err(err) => {
+                set_last_error(
+                    physis_ErrorCode::WriteFailed,
+                    format!("Failed to write config file: {err:?}"),
+                );
+                physis_Buffer::default()
+            }
+        }
+    })
+}
+
+/// Returns the config file as JSON, in the form `{"Section": {"Key": "Value"}}` with everything in file order. The
+/// string must be freed with `physis_free_string`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_cfg_to_json(cfg: physis_ConfigFile) -> *const c_char {
+    ffi_guard(null(), || {
         // TODO: don't hardcode Platform
-        let mut buffer = (*cfg.p_ptr).write_to_buffer(Platform::Win32).unwrap();
+        let Some(buffer) =
+            CONFIG_FILES.with(cfg.handle, |cfg| cfg.write_to_buffer(Platform::Win32))
+        else {
+            return null();
+        };
+
+        let buffer = match buffer {
+            Ok(buffer) => buffer,
+            Err(err) => {
+                set_last_error(
+                    physis_ErrorCode::WriteFailed,
+                    format!("Failed to write config file: {err:?}"),
+                );
+                return null();
+            }
+        };
+
+        // Read back the written file, since that's the only way to list every section and key
+        let text = String::from_utf8_lossy(&buffer);
+
+        let mut sections = Map::new();
+        let mut current = None;
+        for line in text.lines() {
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(name) = line
+                .strip_prefix('<')
+                .and_then(|line| line.strip_suffix('>'))
+            {
+                sections.insert(name.to_string(), Value::Object(Map::new()));
+                current = Some(name.to_string());
+                continue;
+            }
+
+            let Some(Value::Object(section)) =
+                current.as_ref().and_then(|name| sections.get_mut(name))
+            else {
+                continue;
+            };
+
+            let (key, value) = line.split_once('\t').unwrap_or((line, ""));
+            section.insert(key.to_string(), Value::from(value));
+        }
+
+        ffi_to_c_string(&Value::Object(sections).to_string())
+    })
+}
+
+/// Creates a config file from JSON in the format returned by `physis_cfg_to_json`. Values can also be numbers or
+/// booleans, and are written as-is. Returns an empty config file if `json` is invalid.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_cfg_from_json(json: *const c_char) -> physis_ConfigFile {
+    ffi_guard(physis_ConfigFile::default(), || {
+        let Some(json) = ffi_from_c_string(json) else {
+            return physis_ConfigFile::default();
+        };
 
-        let leak_buffer = physis_Buffer {
-            size: buffer.len() as u32,
-            data: buffer.as_mut_ptr(),
+        let sections: Map<String, Value> = match serde_json::from_str(&json) {
+            Ok(sections) => sections,
+            Err(err) => {
+                set_last_error(
+                    physis_ErrorCode::InvalidArgument,
+                    format!("Invalid JSON: {err}"),
+                );
+                return physis_ConfigFile::default();
+            }
         };
 
-        mem::forget(buffer);
+        // Build the text file and let Physis parse it, so the result is the same as reading the real thing
+        let mut text = String::new();
+        for (name, keys) in &sections {
+            let Value::Object(keys) = keys else {
+                set_last_error(
+                    physis_ErrorCode::InvalidArgument,
+                    format!("Section {name} must be an object"),
+                );
+                return physis_ConfigFile::default();
+            };
+
+            text.push_str(&format!("<{name}>\r\n"));
+            for (key, value) in keys {
+                let value = match value {
+                    Value::String(value) => value.clone(),
+                    Value::Number(_) | Value::Bool(_) => value.to_string(),
+                    _ => {
+                        set_last_error(
+                            physis_ErrorCode::InvalidArgument,
+                            format!("{name}.{key} must be a string, number or boolean"),
+                        );
+                        return physis_ConfigFile::default();
+                    }
+                };
+
+                text.push_str(&format!("{key}\t{value}\r\n"));
+            }
+            text.push_str("\r\n");
+        }
+
+        // TODO: don't hardcode Platform
+        if let Some(cfg) = ffi_result(ConfigFile::from_existing(Platform::Win32, text.as_bytes())) {
+            physis_ConfigFile {
+                handle: CONFIG_FILES.insert(cfg),
+            }
+        } else {
+            physis_ConfigFile::default()
+        }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_cfg_free(cfg: &physis_ConfigFile) {
+    ffi_guard((), || {
+        if cfg.handle.is_null() {
+            return;
+        }
+
+        drop(CONFIG_FILES.remove(cfg.handle));
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::physis_get_last_error;
+    use crate::physis_free_string;
+    use std::ffi::{CStr, CString};
+
+    fn to_json(cfg: physis_ConfigFile) -> Value {
+        let json = physis_cfg_to_json(cfg);
+        let value =
+            serde_json::from_str(unsafe { CStr::from_ptr(json) }.to_str().unwrap()).unwrap();
+        physis_free_string(json);
+
+        value
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let json = serde_json::json!({
+            "Cutscene Settings": { "CutsceneMovieVoice": "0", "CutsceneMovieSubtitle": "1" },
+            "Display Settings": { "ScreenWidth": "1920" },
+        });
+
+        let c_json = CString::new(json.to_string()).unwrap();
+        let cfg = physis_cfg_from_json(c_json.as_ptr());
+        assert_eq!(physis_get_last_error(), physis_ErrorCode::Ok);
+
+        assert_eq!(to_json(cfg), json);
+
+        physis_cfg_free(&cfg);
+    }
+
+    #[test]
+    fn invalid_json_is_rejected() {
+        let c_json = CString::new(r#"{"Section": ["not", "an", "object"]}"#).unwrap();
+        let cfg = physis_cfg_from_json(c_json.as_ptr());
 
-        leak_buffer
+        assert!(cfg.handle.is_null());
+        assert_eq!(physis_get_last_error(), physis_ErrorCode::InvalidArgument);
     }
 }
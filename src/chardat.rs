@@ -1,10 +1,13 @@
 // SPDX-FileCopyrightText: 2024 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use crate::error::ffi_result;
+use crate::panic::ffi_guard;
 use crate::{ffi_to_c_string, physis_Buffer};
 use physis::savedata::chardat::{CharacterData, CustomizeData};
 use physis::{Platform, ReadableFile};
 use std::ffi::c_char;
+use std::ptr::null;
 use std::slice;
 
 #[repr(C)]
@@ -15,16 +18,31 @@ pub struct physis_CharacterData {
     comment: *const c_char,
 }
 
+impl Default for physis_CharacterData {
+    fn default() -> Self {
+        Self {
+            version: 0,
+            customize: CustomizeData::default(),
+            timestamp: 0,
+            comment: null(),
+        }
+    }
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_chardat_parse(buffer: physis_Buffer) -> physis_CharacterData {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(physis_CharacterData::default(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-    let chardat = CharacterData::from_existing(Platform::Win32, data).unwrap();
+        let Some(chardat) = ffi_result(CharacterData::from_existing(Platform::Win32, data)) else {
+            return physis_CharacterData::default();
+        };
 
-    physis_CharacterData {
-        version: chardat.version,
-        customize: chardat.customize,
-        timestamp: chardat.timestamp,
-        comment: ffi_to_c_string(&chardat.comment),
-    }
+        physis_CharacterData {
+            version: chardat.version,
+            customize: chardat.customize,
+            timestamp: chardat.timestamp,
+            comment: ffi_to_c_string(&chardat.comment),
+        }
+    })
 }
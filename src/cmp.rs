@@ -1,38 +1,40 @@
 // SPDX-FileCopyrightText: 2024 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use crate::error::{ffi_result, set_physis_error};
+use crate::handle::{HandleRegistry, physis_Handle};
+use crate::panic::ffi_guard;
 use crate::{ffi_to_c_string, physis_Buffer};
 use physis::Platform;
 use physis::ReadableFile;
 use physis::cmp::{CMP, RacialScalingParameters};
 use physis::race::{Race, Tribe};
+use serde_json::{Value, json};
 use std::ffi::c_char;
-use std::ptr::null_mut;
-use std::slice;
+use std::ptr::null;
+use std::{mem, slice};
+
+static CMPS: HandleRegistry<CMP> = HandleRegistry::new("CMP");
 
 #[repr(C)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 pub struct physis_CMP {
-    p_ptr: *mut CMP,
-}
-
-impl Default for physis_CMP {
-    fn default() -> Self {
-        Self { p_ptr: null_mut() }
-    }
+    handle: physis_Handle,
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_cmp_parse(platform: Platform, buffer: physis_Buffer) -> physis_CMP {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(physis_CMP::default(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-    if let Ok(cmp) = CMP::from_existing(platform, data) {
-        physis_CMP {
-            p_ptr: Box::leak(Box::new(cmp)),
+        if let Some(cmp) = ffi_result(CMP::from_existing(platform, data)) {
+            physis_CMP {
+                handle: CMPS.insert(cmp),
+            }
+        } else {
+            physis_CMP::default()
         }
-    } else {
-        physis_CMP::default()
-    }
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -41,8 +43,60 @@ pub unsafe extern "C" fn physis_cmp_get_racial_scaling_parameters(
     _: Race,
     tribe: Tribe,
 ) -> RacialScalingParameters {
-    let index = tribe as usize - 1;
-    unsafe { (&(*cmp.p_ptr).scales)[index >> 1][index & 1] }
+    ffi_guard(unsafe { mem::zeroed() }, || {
+        let index = tribe as usize - 1;
+        CMPS.with(cmp.handle, |cmp| cmp.scales[index >> 1][index & 1])
+            .unwrap_or_else(|| unsafe { mem::zeroed() })
+    })
+}
+
+fn scaling_to_json(parameters: &RacialScalingParameters) -> Value {
+    json!({
+        "male_min_size": parameters.male_min_size,
+        "male_max_size": parameters.male_max_size,
+        "male_min_tail": parameters.male_min_tail,
+        "male_max_tail": parameters.male_max_tail,
+        "female_min_size": parameters.female_min_size,
+        "female_max_size": parameters.female_max_size,
+        "female_min_tail": parameters.female_min_tail,
+        "female_max_tail": parameters.female_max_tail,
+        "bust_min_x": parameters.bust_min_x,
+        "bust_min_y": parameters.bust_min_y,
+        "bust_min_z": parameters.bust_min_z,
+        "bust_max_x": parameters.bust_max_x,
+        "bust_max_y": parameters.bust_max_y,
+        "bust_max_z": parameters.bust_max_z,
+    })
+}
+
+/// Returns the scaling parameters in `cmp` as JSON, in the form `{"scales": [[{...}, {...}], ...]}` with one pair
+/// per race, in the same order as `physis_cmp_get_racial_scaling_parameters` indexes them by tribe.
+/// The string must be freed with `physis_free_string`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_cmp_to_json(cmp: physis_CMP) -> *const c_char {
+    ffi_guard(null(), || {
+        CMPS.with(cmp.handle, |cmp| {
+            let scales: Vec<Value> = cmp
+                .scales
+                .iter()
+                .map(|tribes| Value::Array(tribes.iter().map(scaling_to_json).collect()))
+                .collect();
+
+            ffi_to_c_string(&json!({ "scales": scales }).to_string())
+        })
+        .unwrap_or(null())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_cmp_free(cmp: &physis_CMP) {
+    ffi_guard((), || {
+        if cmp.handle.is_null() {
+            return;
+        }
+
+        drop(CMPS.remove(cmp.handle));
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -50,10 +104,15 @@ pub unsafe extern "C" fn physis_cmp_debug(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> *const c_char {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(null(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-    match CMP::from_existing(platform, data) {
-        Ok(cmp) => ffi_to_c_string(&format!("{cmp:#?}")),
-        Err(err) => ffi_to_c_string(&format!("{err:#?}")),
-    }
+        match CMP::from_existing(platform, data) {
+            Ok(cmp) => ffi_to_c_string(&format!("{cmp:#?}")),
+            Err(err) => {
+                set_physis_error(&err);
+                ffi_to_c_string(&format!("{err:#?}"))
+            }
+        }
+    })
 }
@@ -1,12 +1,16 @@
 // SPDX-FileCopyrightText: 2026 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::{ffi_to_c_string, physis_Buffer};
+use crate::error::{ffi_result, set_physis_error};
+use crate::json::{ToJson, array_to_json, ffi_parsed_to_json, string_to_json};
+use crate::panic::ffi_guard;
+use crate::{ffi_free_string, ffi_to_c_string, ffi_to_vec, physis_Buffer};
 use physis::Platform;
 use physis::ReadableFile;
 use physis::cutb::{Cutscene, NodeData};
+use serde_json::{Value, json};
 use std::ffi::c_char;
-use std::ptr::null_mut;
+use std::ptr::{null, null_mut};
 use std::slice;
 
 #[repr(C)]
@@ -41,30 +45,49 @@ impl Default for physis_Cutscene {
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_cutb_parse(platform: Platform, buffer: physis_Buffer) -> physis_Cutscene {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
-
-    if let Ok(cutb) = Cutscene::from_existing(platform, data) {
-        let mut c_nodes = Vec::new();
-        for node in cutb.nodes {
-            c_nodes.push(match node.node_data {
-                NodeData::CTDS(ctds) => physis_CutsceneNode::Ctds(physis_CTDS {
-                    level_name: ffi_to_c_string(&ctds.level_name),
-                }),
-                _ => physis_CutsceneNode::Unknown,
-            })
-        }
+    ffi_guard(physis_Cutscene::default(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-        let c_cutb = physis_Cutscene {
-            num_nodes: c_nodes.len() as u32,
-            nodes: c_nodes.as_mut_ptr(),
-        };
+        if let Some(cutb) = ffi_result(Cutscene::from_existing(platform, data)) {
+            let mut c_nodes = Vec::new();
+            for node in cutb.nodes {
+                c_nodes.push(match node.node_data {
+                    NodeData::CTDS(ctds) => physis_CutsceneNode::Ctds(physis_CTDS {
+                        level_name: ffi_to_c_string(&ctds.level_name),
+                    }),
+                    _ => physis_CutsceneNode::Unknown,
+                })
+            }
 
-        std::mem::forget(c_nodes);
+            let c_cutb = physis_Cutscene {
+                num_nodes: c_nodes.len() as u32,
+                nodes: c_nodes.as_mut_ptr(),
+            };
 
-        c_cutb
-    } else {
-        physis_Cutscene::default()
-    }
+            std::mem::forget(c_nodes);
+
+            c_cutb
+        } else {
+            physis_Cutscene::default()
+        }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_cutb_free(cutb: &physis_Cutscene) {
+    ffi_guard((), || {
+        if cutb.nodes.is_null() {
+            return;
+        }
+
+        let nodes = ffi_to_vec(cutb.nodes, cutb.num_nodes);
+        for node in &nodes {
+            if let physis_CutsceneNode::Ctds(ctds) = node {
+                ffi_free_string(ctds.level_name);
+            }
+        }
+        drop(nodes);
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -72,10 +95,36 @@ pub unsafe extern "C" fn physis_cutb_debug(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> *const c_char {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(null(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+
+        match Cutscene::from_existing(platform, data) {
+            Ok(cutb) => ffi_to_c_string(&format!("{cutb:#?}")),
+            Err(err) => {
+                set_physis_error(&err);
+                ffi_to_c_string(&format!("{err:#?}"))
+            }
+        }
+    })
+}
 
-    match Cutscene::from_existing(platform, data) {
-        Ok(cutb) => ffi_to_c_string(&format!("{cutb:#?}")),
-        Err(err) => ffi_to_c_string(&format!("{err:#?}")),
+impl ToJson for physis_Cutscene {
+    fn to_json(&self) -> Value {
+        json!({
+            "nodes": array_to_json(self.nodes, self.num_nodes as usize, |node| match node {
+                physis_CutsceneNode::Ctds(ctds) => json!({
+                    "type": "Ctds",
+                    "level_name": string_to_json(ctds.level_name),
+                }),
+                physis_CutsceneNode::Unknown => json!({ "type": "Unknown" }),
+            }),
+        })
     }
 }
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_cutb_to_json(platform: Platform, buffer: physis_Buffer) -> *const c_char {
+    ffi_guard(null(), || {
+        ffi_parsed_to_json(physis_cutb_parse(platform, buffer), physis_cutb_free)
+    })
+}
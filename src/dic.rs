@@ -1,10 +1,14 @@
 // SPDX-FileCopyrightText: 2024 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::{ffi_to_c_string, physis_Buffer};
+use crate::error::{ffi_result, set_physis_error};
+use crate::json::{ToJson, array_to_json, ffi_parsed_to_json, string_to_json};
+use crate::panic::ffi_guard;
+use crate::{ffi_free_string, ffi_to_c_string, ffi_to_vec, physis_Buffer};
 use physis::Platform;
 use physis::ReadableFile;
 use physis::dic::Dictionary;
+use serde_json::{Value, json};
 use std::os::raw::c_char;
 use std::ptr::null;
 use std::{mem, slice};
@@ -30,26 +34,46 @@ pub extern "C" fn physis_dictionary_parse(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> physis_Dictionary {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(physis_Dictionary::default(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-    if let Ok(dic) = Dictionary::from_existing(platform, data) {
-        let mut c_words = vec![];
+        if let Some(dic) = ffi_result(Dictionary::from_existing(platform, data)) {
+            let mut c_words = vec![];
 
-        for word in &dic.words {
-            c_words.push(ffi_to_c_string(word));
-        }
+            for word in &dic.words {
+                c_words.push(ffi_to_c_string(word));
+            }
 
-        let mat = physis_Dictionary {
-            num_words: c_words.len() as i32,
-            words: c_words.as_ptr(),
-        };
+            let mat = physis_Dictionary {
+                num_words: c_words.len() as i32,
+                words: c_words.as_ptr(),
+            };
 
-        mem::forget(c_words);
+            mem::forget(c_words);
 
-        mat
-    } else {
-        physis_Dictionary::default()
-    }
+            mat
+        } else {
+            physis_Dictionary::default()
+        }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_dictionary_free(dictionary: &physis_Dictionary) {
+    ffi_guard((), || {
+        if dictionary.words.is_null() {
+            return;
+        }
+
+        let words = ffi_to_vec(
+            dictionary.words as *mut *const c_char,
+            dictionary.num_words as u32,
+        );
+        for word in &words {
+            ffi_free_string(*word);
+        }
+        drop(words);
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -57,10 +81,37 @@ pub unsafe extern "C" fn physis_dic_debug(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> *const c_char {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(null(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+
+        match Dictionary::from_existing(platform, data) {
+            Ok(dic) => ffi_to_c_string(&format!("{dic:#?}")),
+            Err(err) => {
+                set_physis_error(&err);
+                ffi_to_c_string(&format!("{err:#?}"))
+            }
+        }
+    })
+}
 
-    match Dictionary::from_existing(platform, data) {
-        Ok(dic) => ffi_to_c_string(&format!("{dic:#?}")),
-        Err(err) => ffi_to_c_string(&format!("{err:#?}")),
+impl ToJson for physis_Dictionary {
+    fn to_json(&self) -> Value {
+        json!({
+            "words": array_to_json(
+                self.words,
+                self.num_words as usize,
+                |word| string_to_json(*word)
+            ),
+        })
     }
 }
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_dic_to_json(platform: Platform, buffer: physis_Buffer) -> *const c_char {
+    ffi_guard(null(), || {
+        ffi_parsed_to_json(
+            physis_dictionary_parse(platform, buffer),
+            physis_dictionary_free,
+        )
+    })
+}
@@ -1,11 +1,14 @@
 // SPDX-FileCopyrightText: 2026 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use crate::error::set_physis_error;
+use crate::panic::ffi_guard;
 use crate::{ffi_to_c_string, physis_Buffer};
 use physis::Platform;
 use physis::ReadableFile;
 use physis::envb::Envb;
 use std::ffi::c_char;
+use std::ptr::null;
 use std::slice;
 
 #[unsafe(no_mangle)]
@@ -13,10 +16,15 @@ pub unsafe extern "C" fn physis_envb_debug(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> *const c_char {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(null(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-    match Envb::from_existing(platform, data) {
-        Ok(envb) => ffi_to_c_string(&format!("{envb:#?}")),
-        Err(err) => ffi_to_c_string(&format!("{err:#?}")),
-    }
+        match Envb::from_existing(platform, data) {
+            Ok(envb) => ffi_to_c_string(&format!("{envb:#?}")),
+            Err(err) => {
+                set_physis_error(&err);
+                ffi_to_c_string(&format!("{err:#?}"))
+            }
+        }
+    })
 }
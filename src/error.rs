@@ -0,0 +1,210 @@
+// SPDX-FileCopyrightText: 2026 Joshua Goins <josh@redstrate.com>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::ffi_to_c_string;
+use std::cell::RefCell;
+use std::ffi::c_char;
+use std::panic::Location;
+use std::ptr::null;
+
+/// The kind of error reported by the last libphysis call on this thread.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum physis_ErrorCode {
+    /// The last call succeeded.
+    Ok,
+    /// An argument was NULL, not valid UTF-8 or otherwise out of range.
+    InvalidArgument,
+    /// The requested file could not be found.
+    FileNotFound,
+    /// The data could not be parsed. It may be truncated, corrupt or an unsupported version.
+    ParseFailed,
+    /// The data could not be written or serialized.
+    WriteFailed,
+    /// A handle was null, already freed or otherwise stale.
+    InvalidHandle,
+    /// The data is larger than 4 GiB and doesn't fit in a 32-bit size. Use the `_large` variant of the function instead.
+    TooLarge,
+    /// libphysis or Physis panicked. This is always a bug, please report it!
+    Panic,
+    /// Some other, unclassified error.
+    Unknown,
+}
+
+struct LastError {
+    code: physis_ErrorCode,
+    message: Option<String>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<LastError> = const {
+        RefCell::new(LastError {
+            code: physis_ErrorCode::Ok,
+            message: None,
+        })
+    };
+}
+
+/// Resets the last error for this thread. Should be called at the start of every exported function.
+pub(crate) fn clear_last_error() {
+    LAST_ERROR.with_borrow_mut(|last| {
+        last.code = physis_ErrorCode::Ok;
+        last.message = None;
+    });
+}
+
+/// Returns the module of the caller as a log target, e.g. `physis::tmb` for `src/tmb.rs`. Our library is called
+/// `physis` too, so the same module filters cover both.
+#[track_caller]
+fn caller_target() -> String {
+    let file = Location::caller().file().replace('\\', "/");
+    match file
+        .strip_prefix("src/")
+        .and_then(|file| file.strip_suffix(".rs"))
+    {
+        Some("lib") | None => "physis".to_string(),
+        Some(module) => format!("physis::{}", module.replace('/', "::")),
+    }
+}
+
+/// Sets the last error for this thread, and logs it from the caller's module.
+#[track_caller]
+pub(crate) fn set_last_error(code: physis_ErrorCode, message: impl Into<String>) {
+    let message = message.into();
+
+    // Logged before it's stored, so nothing the log callback does can overwrite it
+    if log::max_level() >= log::Level::Error {
+        let target = caller_target();
+        log::error!(target: target.as_str(), "{message}");
+    }
+
+    LAST_ERROR.with_borrow_mut(|last| {
+        last.code = code;
+        last.message = Some(message);
+    });
+}
+
+/// Sets the last error for this thread from an error returned by Physis.
+#[track_caller]
+pub(crate) fn set_physis_error(err: &physis::Error) {
+    let code = match err {
+        physis::Error::FileNotFound { .. } => physis_ErrorCode::FileNotFound,
+        _ => physis_ErrorCode::ParseFailed,
+    };
+
+    set_last_error(code, format!("{err:?}"));
+}
+
+/// Converts a Physis result into an Option, recording the error if there is one.
+#[track_caller]
+pub(crate) fn ffi_result<T>(result: physis::Result<T>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(err) => {
+            set_physis_error(&err);
+            None
+        }
+    }
+}
+
+/// Returns the error code of the last libphysis call made on this thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_get_last_error() -> physis_ErrorCode {
+    LAST_ERROR.with_borrow(|last| last.code)
+}
+
+/// Returns a human-readable description of the last error on this thread, or NULL if the last call succeeded.
+/// The string must be freed with `physis_free_string`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_get_last_error_message() -> *const c_char {
+    LAST_ERROR.with_borrow(|last| match &last.message {
+        Some(message) => ffi_to_c_string(message),
+        None => null(),
+    })
+}
+
+/// Resets the last error on this thread to `Ok`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_clear_last_error() {
+    clear_last_error();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::panic::ffi_guard;
+    use crate::physis_free_string;
+    use std::ffi::CStr;
+
+    fn last_error_message() -> Option<String> {
+        let message = physis_get_last_error_message();
+        if message.is_null() {
+            return None;
+        }
+
+        let text = unsafe { CStr::from_ptr(message) }
+            .to_string_lossy()
+            .into_owned();
+        physis_free_string(message);
+
+        Some(text)
+    }
+
+    #[test]
+    fn error_is_kept_until_the_next_call() {
+        ffi_guard((), || {
+            set_last_error(physis_ErrorCode::ParseFailed, "Bad data");
+        });
+
+        assert_eq!(physis_get_last_error(), physis_ErrorCode::ParseFailed);
+        assert_eq!(last_error_message().as_deref(), Some("Bad data"));
+
+        // Reading the error doesn't reset it, only the next call does
+        assert_eq!(physis_get_last_error(), physis_ErrorCode::ParseFailed);
+
+        ffi_guard((), || {});
+        assert_eq!(physis_get_last_error(), physis_ErrorCode::Ok);
+        assert_eq!(last_error_message(), None);
+    }
+
+    #[test]
+    fn physis_errors_are_classified() {
+        let result: physis::Result<()> = Err(physis::Error::FileNotFound {
+            path: "exd/missing.exh".to_string(),
+        });
+
+        assert_eq!(ffi_result(result), None);
+        assert_eq!(physis_get_last_error(), physis_ErrorCode::FileNotFound);
+        assert!(last_error_message().unwrap().contains("exd/missing.exh"));
+    }
+
+    #[test]
+    fn errors_are_per_thread() {
+        set_last_error(physis_ErrorCode::WriteFailed, "Failed on this thread");
+
+        std::thread::spawn(|| {
+            assert_eq!(physis_get_last_error(), physis_ErrorCode::Ok);
+            set_last_error(
+                physis_ErrorCode::InvalidArgument,
+                "Failed on another thread",
+            );
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(physis_get_last_error(), physis_ErrorCode::WriteFailed);
+        assert_eq!(
+            last_error_message().as_deref(),
+            Some("Failed on this thread")
+        );
+
+        physis_clear_last_error();
+        assert_eq!(physis_get_last_error(), physis_ErrorCode::Ok);
+    }
+
+    #[test]
+    fn null_string_arguments_are_rejected() {
+        crate::cfg::physis_cfg_set_value(Default::default(), null(), null());
+        assert_eq!(physis_get_last_error(), physis_ErrorCode::InvalidArgument);
+    }
+}
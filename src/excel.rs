@@ -0,0 +1,501 @@
+// SPDX-FileCopyrightText: 2026 Joshua Goins <josh@redstrate.com>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::error::{clear_last_error, ffi_result, physis_ErrorCode, set_last_error};
+use crate::exd::{physis_ExcelRow, physis_Field};
+use crate::handle::{HandleRegistry, physis_Handle};
+use crate::panic::ffi_guard;
+use crate::resource::{
+    OverlaidSqPack, linked_sheets, physis_SqPackResource, shared_resource, to_c_row,
+};
+use crate::schema::{SCHEMAS, Schema, physis_ExcelSchema};
+use crate::{ffi_from_c_string, ffi_to_buffer, ffi_to_c_string, physis_Buffer};
+use physis::excel::{Entry, Row, Sheet};
+use physis::exd::EXD;
+use physis::exh::{ColumnDataType, EXH};
+use physis::resource::{Resource, generic_read_excel_sheet};
+use physis::{Language, ReadableFile};
+use std::collections::HashMap;
+use std::mem;
+use std::os::raw::c_char;
+use std::ptr::null;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// An Excel sheet that only reads its pages once a row in them is requested.
+pub(crate) struct LazySheet {
+    resource: OverlaidSqPack,
+    name: String,
+    language: Language,
+    exh: EXH,
+    /// The size of a row's columns, which are followed by its strings.
+    row_size: usize,
+    /// Whether each row is split into subrows, which are prefixed by their id.
+    has_subrows: bool,
+    /// Each page is read as a sheet with only that page in it.
+    pages: Vec<Option<Sheet>>,
+    /// The EXD files of pages that raw strings were read from.
+    raw_pages: Vec<Option<Vec<u8>>>,
+}
+
+/// The row kind in an EXH header for sheets with subrows.
+const EXH_SUBROWS: u8 = 2;
+
+/// Reads `N` bytes at `offset` from an Excel file, to be decoded as a big-endian integer.
+fn be_bytes<const N: usize>(data: &[u8], offset: usize) -> Option<[u8; N]> {
+    data.get(offset..offset.checked_add(N)?)?.try_into().ok()
+}
+
+/// Finds the string in `column_offset` of a subrow in an EXD page, and returns its bytes without the NUL terminator.
+fn find_raw_string(
+    page: &[u8],
+    row_id: u32,
+    subrow_id: u16,
+    column_offset: usize,
+    row_size: usize,
+    has_subrows: bool,
+) -> Option<&[u8]> {
+    // The header is followed by the row id and offset of every row in the page
+    let index_size = u32::from_be_bytes(be_bytes(page, 0x08)?) as usize;
+    let row_offset = (0..index_size / 8).find_map(|i| {
+        let entry = 0x20 + i * 8;
+        if u32::from_be_bytes(be_bytes(page, entry)?) != row_id {
+            return None;
+        }
+
+        be_bytes(page, entry + 4).map(u32::from_be_bytes)
+    })? as usize;
+
+    // Each row starts with its size and how many subrows it has
+    let subrow_count = u16::from_be_bytes(be_bytes(page, row_offset + 4)?);
+    let data_start = row_offset + 6;
+
+    let columns_start = if has_subrows {
+        if subrow_id >= subrow_count {
+            return None;
+        }
+        data_start + usize::from(subrow_id) * (row_size + 2) + 2
+    } else if subrow_id == 0 {
+        data_start
+    } else {
+        return None;
+    };
+
+    let string_offset = u32::from_be_bytes(be_bytes(page, columns_start + column_offset)?);
+    let string = page.get(columns_start + row_size + string_offset as usize..)?;
+    let end = string.iter().position(|b| *b == 0)?;
+
+    Some(&string[..end])
+}
+
+/// Sheets keyed by name. Row ids are the same in every language, so it doesn't matter which one they were opened in.
+pub(crate) type SheetCache = HashMap<String, Arc<Mutex<LazySheet>>>;
+
+static LAZY_SHEETS: HandleRegistry<Arc<Mutex<LazySheet>>> = HandleRegistry::new("LazyExcelSheet");
+
+fn lock_sheet(sheet: &Mutex<LazySheet>) -> MutexGuard<'_, LazySheet> {
+    sheet.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub(crate) fn shared_sheet(sheet: &physis_LazyExcelSheet) -> Option<Arc<Mutex<LazySheet>>> {
+    LAZY_SHEETS.with(sheet.handle, |sheet| sheet.clone())
+}
+
+impl LazySheet {
+    pub(crate) fn open(
+        mut resource: OverlaidSqPack,
+        name: &str,
+        language: Language,
+    ) -> Option<Self> {
+        let data = ffi_result(resource.read(&format!("exd/{}.exh", name.to_lowercase())))?;
+        let exh = ffi_result(EXH::from_existing(resource.platform(), &data))?;
+
+        Some(Self {
+            resource,
+            name: name.to_string(),
+            language,
+            row_size: usize::from(u16::from_be_bytes(be_bytes(&data, 0x06)?)),
+            has_subrows: data.get(0x11) == Some(&EXH_SUBROWS),
+            pages: (0..exh.pages.len()).map(|_| None).collect(),
+            raw_pages: (0..exh.pages.len()).map(|_| None).collect(),
+            exh,
+        })
+    }
+
+    pub(crate) fn column_count(&self) -> usize {
+        self.exh.column_definitions.len()
+    }
+
+    fn page_index(&self, row_id: u32) -> Option<usize> {
+        self.exh
+            .pages
+            .iter()
+            .position(|page| row_id >= page.start_id && row_id - page.start_id < page.row_count)
+    }
+
+    /// Returns the page that `row_id` would be in, reading it if needed.
+    fn page(&mut self, row_id: u32) -> Option<&Sheet> {
+        let Some(index) = self.page_index(row_id) else {
+            set_last_error(
+                physis_ErrorCode::InvalidArgument,
+                format!("Row {row_id} is not in any page of {}", self.name),
+            );
+            return None;
+        };
+
+        if self.pages[index].is_none() {
+            // Physis only reads whole sheets, so narrow the header down to this page while reading it
+            let mut pages = mem::take(&mut self.exh.pages);
+            self.exh.pages.push(pages.remove(index));
+
+            let sheet =
+                generic_read_excel_sheet(&mut self.resource, &self.exh, &self.name, self.language);
+
+            pages.insert(index, self.exh.pages.remove(0));
+            self.exh.pages = pages;
+
+            self.pages[index] = Some(ffi_result(sheet)?);
+        }
+
+        self.pages[index].as_ref()
+    }
+
+    pub(crate) fn entry(&mut self, row_id: u32) -> Option<&Entry> {
+        self.page(row_id)?.entry(row_id)
+    }
+
+    pub(crate) fn subrow(&mut self, row_id: u32, subrow_id: u16) -> Option<&Row> {
+        self.page(row_id)?.subrow(row_id, subrow_id)
+    }
+
+    /// Reads the bytes of the string in `column` of a subrow straight from its EXD page, since Physis only hands out
+    /// strings that were already decoded.
+    fn raw_string(
+        &mut self,
+        row_id: u32,
+        subrow_id: u16,
+        column: usize,
+    ) -> Result<Vec<u8>, String> {
+        let column_offset = match self.exh.column_definitions.get(column) {
+            Some(definition) if matches!(definition.data_type, ColumnDataType::String) => {
+                usize::from(definition.offset)
+            }
+            Some(_) => return Err(format!("Column {column} of {} isn't a string", self.name)),
+            None => return Err(format!("{} has no column {column}", self.name)),
+        };
+
+        let index = self
+            .page_index(row_id)
+            .ok_or_else(|| format!("Row {row_id} is not in any page of {}", self.name))?;
+
+        if self.raw_pages[index].is_none() {
+            let filename =
+                EXD::calculate_filename(&self.name, self.language, &self.exh.pages[index]);
+            let data = self
+                .resource
+                .read(&format!("exd/{}", filename.to_lowercase()))
+                .map_err(|err| format!("Failed to read {filename}: {err:?}"))?;
+
+            self.raw_pages[index] = Some(data);
+        }
+
+        let page = self.raw_pages[index].as_deref().unwrap_or_default();
+        find_raw_string(
+            page,
+            row_id,
+            subrow_id,
+            column_offset,
+            self.row_size,
+            self.has_subrows,
+        )
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| format!("Subrow {row_id}.{subrow_id} not found"))
+    }
+}
+
+#[repr(C)]
+pub struct physis_LazyExcelSheet {
+    handle: physis_Handle,
+    /// The number of columns in each row, which is needed to free them.
+    pub column_count: u32,
+    pub page_count: u32,
+}
+
+impl Default for physis_LazyExcelSheet {
+    fn default() -> Self {
+        Self {
+            handle: physis_Handle::default(),
+            column_count: 0,
+            page_count: 0,
+        }
+    }
+}
+
+/// Opens the Excel sheet `name` without reading any of its pages. They're read once a row in them is requested, so
+/// this is much cheaper than `physis_sqpack_read_excel_sheet` for large sheets. If the sheet was not found, the
+/// handle is NULL. This must be freed with `physis_excel_sheet_close`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_excel_sheet_open(
+    resource: &physis_SqPackResource,
+    name: *const c_char,
+    language: Language,
+) -> physis_LazyExcelSheet {
+    ffi_guard(physis_LazyExcelSheet::default(), || {
+        let Some(r_name) = ffi_from_c_string(name) else {
+            return physis_LazyExcelSheet::default();
+        };
+
+        let Some(shared) = shared_resource(resource) else {
+            return physis_LazyExcelSheet::default();
+        };
+
+        let Some(sheet) = LazySheet::open(shared, &r_name, language) else {
+            return physis_LazyExcelSheet::default();
+        };
+
+        physis_LazyExcelSheet {
+            column_count: sheet.column_count() as u32,
+            page_count: sheet.pages.len() as u32,
+            handle: LAZY_SHEETS.insert(Arc::new(Mutex::new(sheet))),
+        }
+    })
+}
+
+/// Returns the first subrow of `row_id`, reading the page it's in if needed. The row must be freed with
+/// `physis_free_row`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_excel_sheet_row(
+    sheet: &physis_LazyExcelSheet,
+    row_id: u32,
+) -> physis_ExcelRow {
+    physis_excel_sheet_subrow(sheet, row_id, 0)
+}
+
+/// Returns `subrow_id` of `row_id`, reading the page it's in if needed. The row must be freed with
+/// `physis_free_row`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_excel_sheet_subrow(
+    sheet: &physis_LazyExcelSheet,
+    row_id: u32,
+    subrow_id: u16,
+) -> physis_ExcelRow {
+    ffi_guard(physis_ExcelRow::default(), || {
+        let Some(shared) = shared_sheet(sheet) else {
+            return physis_ExcelRow::default();
+        };
+
+        let mut sheet = lock_sheet(&shared);
+        match sheet.subrow(row_id, subrow_id) {
+            Some(row) => to_c_row(subrow_id, row),
+            None => {
+                set_last_error(
+                    physis_ErrorCode::InvalidArgument,
+                    format!("Subrow {row_id}.{subrow_id} not found"),
+                );
+                physis_ExcelRow::default()
+            }
+        }
+    })
+}
+
+/// Returns how many subrows `row_id` has, reading the page it's in if needed. Returns 0 if the row doesn't exist.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_excel_sheet_subrow_count(
+    sheet: &physis_LazyExcelSheet,
+    row_id: u32,
+) -> u32 {
+    ffi_guard(0, || {
+        let Some(shared) = shared_sheet(sheet) else {
+            return 0;
+        };
+
+        let mut sheet = lock_sheet(&shared);
+        match sheet.entry(row_id) {
+            Some(entry) => entry.subrows.len() as u32,
+            None => 0,
+        }
+    })
+}
+
+/// Returns the bytes of the string in `column` of `subrow_id` of `row_id`, read straight from the sheet's EXD page.
+/// Unlike the strings in `physis_Field`, these haven't been decoded, so SeString macros in them are intact and can be
+/// parsed with `physis_sestring_parse`. The buffer must be freed with `physis_free_file`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_excel_sheet_raw_string(
+    sheet: &physis_LazyExcelSheet,
+    row_id: u32,
+    subrow_id: u16,
+    column: u32,
+) -> physis_Buffer {
+    ffi_guard(physis_Buffer::default(), || {
+        let Some(shared) = shared_sheet(sheet) else {
+            return physis_Buffer::default();
+        };
+
+        let result = lock_sheet(&shared).raw_string(row_id, subrow_id, column as usize);
+        match result {
+            Ok(data) => ffi_to_buffer(data),
+            Err(err) => {
+                set_last_error(physis_ErrorCode::InvalidArgument, err);
+                physis_Buffer::default()
+            }
+        }
+    })
+}
+
+/// Closes this sheet. Rows that were already returned stay valid until they are freed.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_excel_sheet_close(sheet: &physis_LazyExcelSheet) {
+    ffi_guard((), || {
+        drop(LAZY_SHEETS.remove(sheet.handle));
+    })
+}
+
+/// Where a link column points to, see `physis_excel_resolve_link`.
+#[repr(C)]
+pub struct physis_ExcelLink {
+    /// The name of the sheet, or NULL if the link couldn't be resolved. This must be freed with `physis_free_string`.
+    pub sheet: *const c_char,
+    pub row_id: u32,
+}
+
+impl Default for physis_ExcelLink {
+    fn default() -> Self {
+        Self {
+            sheet: null(),
+            row_id: 0,
+        }
+    }
+}
+
+fn field_to_int(field: &physis_Field) -> Option<i64> {
+    match field {
+        physis_Field::Int8(i) => Some(i64::from(*i)),
+        physis_Field::UInt8(i) => Some(i64::from(*i)),
+        physis_Field::Int16(i) => Some(i64::from(*i)),
+        physis_Field::UInt16(i) => Some(i64::from(*i)),
+        physis_Field::Int32(i) => Some(i64::from(*i)),
+        physis_Field::UInt32(i) => Some(i64::from(*i)),
+        physis_Field::Int64(i) => Some(*i),
+        physis_Field::UInt64(i) => i64::try_from(*i).ok(),
+        _ => None,
+    }
+}
+
+/// Returns the sheets that `column` in `columns` could point to, in the order they should be tried, and the row id.
+fn link_targets(
+    schema: &Schema,
+    columns: &[physis_Field],
+    column: &str,
+) -> Result<(Vec<String>, u32), String> {
+    let Some(link) = schema.column(column) else {
+        return Err(format!("No column called {column}"));
+    };
+
+    let row_id = columns
+        .get(link.column_index)
+        .and_then(field_to_int)
+        .and_then(|id| u32::try_from(id).ok())
+        .ok_or_else(|| format!("{column} isn't a row id"))?;
+
+    let Some(condition) = &link.condition else {
+        return Ok((link.targets.clone(), row_id));
+    };
+
+    let value = schema
+        .column(&condition.switch)
+        .and_then(|switch| columns.get(switch.column_index))
+        .and_then(field_to_int)
+        .ok_or_else(|| format!("{} isn't a number", condition.switch))?;
+
+    let targets = condition.cases.get(&value).cloned().unwrap_or_default();
+
+    Ok((targets, row_id))
+}
+
+/// Returns the sheet called `name` from the cache, opening it if needed.
+fn linked_sheet(
+    resource: &physis_SqPackResource,
+    name: &str,
+    language: Language,
+) -> Option<Arc<Mutex<LazySheet>>> {
+    let cache = linked_sheets(resource)?;
+    let mut cache = cache.lock().unwrap_or_else(PoisonError::into_inner);
+
+    if let Some(sheet) = cache.get(name) {
+        return Some(sheet.clone());
+    }
+
+    let sheet = Arc::new(Mutex::new(LazySheet::open(
+        shared_resource(resource)?,
+        name,
+        language,
+    )?));
+    cache.insert(name.to_string(), sheet.clone());
+
+    Some(sheet)
+}
+
+/// Resolves the link in `column` of a row from the schema's sheet, where `columns` are the `column_count` columns of
+/// that row. If the column can link to more than one sheet, the first one that has the row is used. Links that depend
+/// on another column, like ENpcBase.ENpcData, are followed using that column's value in the same row. If the link
+/// couldn't be resolved, `sheet` is NULL.
+///
+/// Sheets that are opened to check the row exists are kept open for later calls. They're cached by name only, since
+/// row ids are the same in every language, so `language` is only used when a sheet is opened for the first time.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_excel_resolve_link(
+    resource: &physis_SqPackResource,
+    schema: &physis_ExcelSchema,
+    columns: *const physis_Field,
+    column_count: u32,
+    column: *const c_char,
+    language: Language,
+) -> physis_ExcelLink {
+    ffi_guard(physis_ExcelLink::default(), || {
+        let Some(r_column) = ffi_from_c_string(column) else {
+            return physis_ExcelLink::default();
+        };
+
+        if columns.is_null() {
+            set_last_error(physis_ErrorCode::InvalidArgument, "columns is NULL");
+            return physis_ExcelLink::default();
+        }
+
+        let Some(targets) = SCHEMAS.with(schema.handle, |schema| {
+            let columns = unsafe { std::slice::from_raw_parts(columns, column_count as usize) };
+            link_targets(schema, columns, &r_column)
+        }) else {
+            return physis_ExcelLink::default();
+        };
+
+        let (targets, row_id) = match targets {
+            Ok(targets) => targets,
+            Err(err) => {
+                set_last_error(physis_ErrorCode::InvalidArgument, err);
+                return physis_ExcelLink::default();
+            }
+        };
+
+        for target in targets {
+            let Some(sheet) = linked_sheet(resource, &target, language) else {
+                continue;
+            };
+
+            if lock_sheet(&sheet).entry(row_id).is_some() {
+                // Earlier targets that couldn't be opened shouldn't leave an error behind
+                clear_last_error();
+
+                return physis_ExcelLink {
+                    sheet: ffi_to_c_string(&target),
+                    row_id,
+                };
+            }
+        }
+
+        set_last_error(
+            physis_ErrorCode::FileNotFound,
+            format!("{r_column} doesn't point to an existing row"),
+        );
+        physis_ExcelLink::default()
+    })
+}
@@ -1,7 +1,10 @@
 // SPDX-FileCopyrightText: 2024 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::exh::physis_EXH;
+use crate::error::set_physis_error;
+use crate::exh::{EXHS, physis_EXH};
+use crate::panic::ffi_guard;
+use crate::resource::entries_to_json;
 use crate::{ffi_from_c_string, ffi_to_c_string, physis_Buffer};
 use physis::exd::EXD;
 use physis::{Language, Platform, ReadableFile};
@@ -65,17 +68,20 @@ pub unsafe extern "C" fn physis_exd_calculate_filename(
     language: Language,
     page: c_uint,
 ) -> *const c_char {
-    unsafe {
+    ffi_guard(null(), || {
         let Some(r_name) = ffi_from_c_string(name) else {
             return null();
         };
 
-        ffi_to_c_string(&EXD::calculate_filename(
-            &r_name,
-            language,
-            &(&(*exh.p_ptr).pages)[page as usize],
-        ))
-    }
+        EXHS.with(exh.handle, |header| {
+            ffi_to_c_string(&EXD::calculate_filename(
+                &r_name,
+                language,
+                &header.pages[page as usize],
+            ))
+        })
+        .unwrap_or(null())
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -83,10 +89,35 @@ pub unsafe extern "C" fn physis_exd_debug(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> *const std::ffi::c_char {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(null(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-    match EXD::from_existing(platform, data) {
-        Ok(exd) => ffi_to_c_string(&format!("{exd:#?}")),
-        Err(err) => ffi_to_c_string(&format!("{err:#?}")),
-    }
+        match EXD::from_existing(platform, data) {
+            Ok(exd) => ffi_to_c_string(&format!("{exd:#?}")),
+            Err(err) => {
+                set_physis_error(&err);
+                ffi_to_c_string(&format!("{err:#?}"))
+            }
+        }
+    })
+}
+
+/// Returns the rows of the EXD page in `buffer` as JSON, in the same form as `physis_sqpack_excel_sheet_page_to_json`.
+/// Returns NULL if it couldn't be parsed. The string must be freed with `physis_free_string`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn physis_exd_to_json(
+    platform: Platform,
+    buffer: physis_Buffer,
+) -> *const c_char {
+    ffi_guard(null(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+
+        match EXD::from_existing(platform, data) {
+            Ok(exd) => ffi_to_c_string(&entries_to_json(&exd.entries).to_string()),
+            Err(err) => {
+                set_physis_error(&err);
+                null()
+            }
+        }
+    })
 }
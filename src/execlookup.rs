@@ -1,6 +1,8 @@
 // SPDX-FileCopyrightText: 2024 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use crate::error::{physis_ErrorCode, set_last_error};
+use crate::panic::ffi_guard;
 use crate::{ffi_from_c_string, ffi_to_c_string};
 use core::ffi::c_char;
 use core::ptr::null;
@@ -8,10 +10,19 @@ use physis::execlookup::extract_frontier_url;
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_extract_frontier_url(launcher_path: *const c_char) -> *const c_char {
-    let launcher_path = ffi_from_c_string(launcher_path).unwrap();
-    if let Some(frontier_url) = extract_frontier_url(&launcher_path) {
-        ffi_to_c_string(&frontier_url)
-    } else {
-        null()
-    }
+    ffi_guard(null(), || {
+        let Some(launcher_path) = ffi_from_c_string(launcher_path) else {
+            return null();
+        };
+
+        if let Some(frontier_url) = extract_frontier_url(&launcher_path) {
+            ffi_to_c_string(&frontier_url)
+        } else {
+            set_last_error(
+                physis_ErrorCode::ParseFailed,
+                format!("Could not find the frontier URL in {launcher_path}"),
+            );
+            null()
+        }
+    })
 }
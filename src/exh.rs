@@ -1,12 +1,18 @@
 // SPDX-FileCopyrightText: 2024 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use crate::abi::struct_size;
+use crate::error::{ffi_result, set_physis_error};
+use crate::handle::{HandleRegistry, physis_Handle};
+use crate::json::{ToJson, array_to_json, enum_to_json, ffi_parsed_to_json};
+use crate::panic::ffi_guard;
 use crate::{ffi_to_c_string, ffi_to_vec, physis_Buffer};
 use physis::ReadableFile;
 use physis::exh::{ColumnDataType, EXH};
 use physis::{Language, Platform};
+use serde_json::{Value, json};
 use std::ffi::c_char;
-use std::ptr::null_mut;
+use std::ptr::{null, null_mut};
 use std::{mem, slice};
 
 // TODO: re-use from Physis since their struct is also simple
@@ -25,10 +31,13 @@ pub struct physis_ExcelPage {
     row_count: u32,
 }
 
+pub(crate) static EXHS: HandleRegistry<EXH> = HandleRegistry::new("EXH");
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct physis_EXH {
-    pub(crate) p_ptr: *mut EXH,
+    struct_size: u32,
+    pub(crate) handle: physis_Handle,
     page_count: u32,
     pages: *mut physis_ExcelPage,
     language_count: u32,
@@ -41,7 +50,8 @@ pub struct physis_EXH {
 impl Default for physis_EXH {
     fn default() -> Self {
         Self {
-            p_ptr: null_mut(),
+            struct_size: struct_size::<physis_EXH>(),
+            handle: physis_Handle::default(),
             page_count: 0,
             pages: null_mut(),
             language_count: 0,
@@ -55,79 +65,85 @@ impl Default for physis_EXH {
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_exh_parse(platform: Platform, buffer: physis_Buffer) -> physis_EXH {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
-
-    let exh = EXH::from_existing(platform, data);
-    if exh.is_err() {
-        return physis_EXH::default();
-    }
+    ffi_guard(physis_EXH::default(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-    let exh = Box::new(exh.unwrap());
+        let Some(exh) = ffi_result(EXH::from_existing(platform, data)) else {
+            return physis_EXH::default();
+        };
 
-    let mut c_languages: Vec<Language> = vec![];
+        let mut c_languages: Vec<Language> = vec![];
 
-    for lang in &exh.languages {
-        c_languages.push(*lang);
-    }
+        for lang in &exh.languages {
+            c_languages.push(*lang);
+        }
 
-    let mut c_column_definitions: Vec<physis_ColumnDefinition> = vec![];
+        let mut c_column_definitions: Vec<physis_ColumnDefinition> = vec![];
 
-    for column in &exh.column_definitions {
-        c_column_definitions.push(physis_ColumnDefinition {
-            data_type: column.data_type,
-            offset: column.offset,
-        });
-    }
+        for column in &exh.column_definitions {
+            c_column_definitions.push(physis_ColumnDefinition {
+                data_type: column.data_type,
+                offset: column.offset,
+            });
+        }
 
-    let mut c_pages: Vec<physis_ExcelPage> = vec![];
+        let mut c_pages: Vec<physis_ExcelPage> = vec![];
 
-    for page in &exh.pages {
-        c_pages.push(physis_ExcelPage {
-            start_id: page.start_id,
-            row_count: page.row_count,
-        });
-    }
+        for page in &exh.pages {
+            c_pages.push(physis_ExcelPage {
+                start_id: page.start_id,
+                row_count: page.row_count,
+            });
+        }
 
-    let page_len = exh.pages.len() as u32;
-    let row_count = exh.header.row_count as u32;
-    let column_count = exh.column_definitions.len() as u32;
-
-    let repositories = physis_EXH {
-        p_ptr: Box::leak(exh),
-        page_count: page_len,
-        language_count: c_languages.len() as u32,
-        languages: c_languages.as_mut_ptr(),
-        column_count,
-        row_count,
-        column_definitions: c_column_definitions.as_mut_ptr(),
-        pages: c_pages.as_mut_ptr(),
-    };
-
-    mem::forget(c_languages);
-    mem::forget(c_column_definitions);
-    mem::forget(c_pages);
-
-    repositories
+        let page_len = exh.pages.len() as u32;
+        let row_count = exh.header.row_count as u32;
+        let column_count = exh.column_definitions.len() as u32;
+
+        let repositories = physis_EXH {
+            struct_size: struct_size::<physis_EXH>(),
+            handle: EXHS.insert(exh),
+            page_count: page_len,
+            language_count: c_languages.len() as u32,
+            languages: c_languages.as_mut_ptr(),
+            column_count,
+            row_count,
+            column_definitions: c_column_definitions.as_mut_ptr(),
+            pages: c_pages.as_mut_ptr(),
+        };
+
+        mem::forget(c_languages);
+        mem::forget(c_column_definitions);
+        mem::forget(c_pages);
+
+        repositories
+    })
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_exh_free(exh: &physis_EXH) {
-    if exh.p_ptr.is_null() {
-        return;
-    }
+    ffi_guard((), || {
+        if exh.handle.is_null() {
+            return;
+        }
 
-    unsafe {
-        let data = ffi_to_vec(exh.column_definitions, exh.column_count);
-        drop(data);
+        let Some(header) = EXHS.remove(exh.handle) else {
+            return;
+        };
 
-        let data = ffi_to_vec(exh.languages, exh.language_count);
-        drop(data);
+        unsafe {
+            let data = ffi_to_vec(exh.column_definitions, exh.column_count);
+            drop(data);
 
-        let data = ffi_to_vec(exh.pages, exh.page_count);
-        drop(data);
+            let data = ffi_to_vec(exh.languages, exh.language_count);
+            drop(data);
 
-        drop(Box::from_raw(exh.p_ptr));
-    }
+            let data = ffi_to_vec(exh.pages, exh.page_count);
+            drop(data);
+
+            drop(header);
+        }
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -135,10 +151,47 @@ pub unsafe extern "C" fn physis_exh_debug(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> *const c_char {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(null(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+
+        match EXH::from_existing(platform, data) {
+            Ok(exh) => ffi_to_c_string(&format!("{exh:#?}")),
+            Err(err) => {
+                set_physis_error(&err);
+                ffi_to_c_string(&format!("{err:#?}"))
+            }
+        }
+    })
+}
 
-    match EXH::from_existing(platform, data) {
-        Ok(exh) => ffi_to_c_string(&format!("{exh:#?}")),
-        Err(err) => ffi_to_c_string(&format!("{err:#?}")),
+impl ToJson for physis_EXH {
+    fn to_json(&self) -> Value {
+        json!({
+            "pages": array_to_json(self.pages, self.page_count as usize, |page| {
+                json!({
+                    "start_id": page.start_id,
+                    "row_count": page.row_count,
+                })
+            }),
+            "languages": array_to_json(self.languages, self.language_count as usize, enum_to_json),
+            "row_count": self.row_count,
+            "column_definitions": array_to_json(
+                self.column_definitions,
+                self.column_count as usize,
+                |column| {
+                    json!({
+                        "data_type": enum_to_json(&column.data_type),
+                        "offset": column.offset,
+                    })
+                }
+            ),
+        })
     }
 }
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_exh_to_json(platform: Platform, buffer: physis_Buffer) -> *const c_char {
+    ffi_guard(null(), || {
+        ffi_parsed_to_json(physis_exh_parse(platform, buffer), physis_exh_free)
+    })
+}
@@ -2,11 +2,13 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use crate::ffi_to_c_string;
+use crate::panic::ffi_guard;
 use physis::existing_dirs::{
     ExistingInstallType, find_existing_game_dirs, find_existing_user_dirs,
 };
 use std::ffi::c_char;
 use std::mem;
+use std::ptr::null_mut;
 
 /// An existing install location on disk
 #[repr(C)]
@@ -25,27 +27,38 @@ pub struct physis_ExistingGameDirectories {
     pub entries: *mut physis_ExistingGameDirectory,
 }
 
+impl Default for physis_ExistingGameDirectories {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            entries: null_mut(),
+        }
+    }
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_find_existing_game_dirs() -> physis_ExistingGameDirectories {
-    let dirs = find_existing_game_dirs();
-
-    let mut c_dirs = Vec::new();
-    for dir in dirs {
-        c_dirs.push(physis_ExistingGameDirectory {
-            path: ffi_to_c_string(&dir.path),
-            install_type: dir.install_type,
-            version: ffi_to_c_string(&dir.version),
-        });
-    }
+    ffi_guard(physis_ExistingGameDirectories::default(), || {
+        let dirs = find_existing_game_dirs();
+
+        let mut c_dirs = Vec::new();
+        for dir in dirs {
+            c_dirs.push(physis_ExistingGameDirectory {
+                path: ffi_to_c_string(&dir.path),
+                install_type: dir.install_type,
+                version: ffi_to_c_string(&dir.version),
+            });
+        }
 
-    let new_dirs = physis_ExistingGameDirectories {
-        count: c_dirs.len() as u32,
-        entries: c_dirs.as_mut_ptr(),
-    };
+        let new_dirs = physis_ExistingGameDirectories {
+            count: c_dirs.len() as u32,
+            entries: c_dirs.as_mut_ptr(),
+        };
 
-    mem::forget(c_dirs);
+        mem::forget(c_dirs);
 
-    new_dirs
+        new_dirs
+    })
 }
 
 /// An existing user directory
@@ -63,24 +76,35 @@ pub struct physis_ExistingUserDirectories {
     pub entries: *mut physis_ExistingUserDirectory,
 }
 
+impl Default for physis_ExistingUserDirectories {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            entries: null_mut(),
+        }
+    }
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_find_existing_user_dirs() -> physis_ExistingUserDirectories {
-    let dirs = find_existing_user_dirs();
-
-    let mut c_dirs = Vec::new();
-    for dir in dirs {
-        c_dirs.push(physis_ExistingUserDirectory {
-            path: ffi_to_c_string(&dir.path),
-            install_type: dir.install_type,
-        });
-    }
+    ffi_guard(physis_ExistingUserDirectories::default(), || {
+        let dirs = find_existing_user_dirs();
+
+        let mut c_dirs = Vec::new();
+        for dir in dirs {
+            c_dirs.push(physis_ExistingUserDirectory {
+                path: ffi_to_c_string(&dir.path),
+                install_type: dir.install_type,
+            });
+        }
 
-    let new_dirs = physis_ExistingUserDirectories {
-        count: c_dirs.len() as u32,
-        entries: c_dirs.as_mut_ptr(),
-    };
+        let new_dirs = physis_ExistingUserDirectories {
+            count: c_dirs.len() as u32,
+            entries: c_dirs.as_mut_ptr(),
+        };
 
-    mem::forget(c_dirs);
+        mem::forget(c_dirs);
 
-    new_dirs
+        new_dirs
+    })
 }
@@ -1,7 +1,9 @@
 // SPDX-FileCopyrightText: 2024 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::{ffi_to_c_string, physis_Buffer};
+use crate::error::ffi_result;
+use crate::panic::ffi_guard;
+use crate::{ffi_free_string, ffi_to_c_string, ffi_to_vec, physis_Buffer};
 use physis::Platform;
 use physis::ReadableFile;
 use physis::exl::EXL;
@@ -31,29 +33,51 @@ impl Default for physis_EXL {
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_exl_parse(platform: Platform, buffer: physis_Buffer) -> physis_EXL {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(physis_EXL::default(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-    if let Ok(exl) = EXL::from_existing(platform, data) {
-        let mut c_keys = vec![];
-        let mut c_values = vec![];
+        if let Some(exl) = ffi_result(EXL::from_existing(platform, data)) {
+            let mut c_keys = vec![];
+            let mut c_values = vec![];
 
-        for (key, value) in &exl.entries {
-            c_keys.push(ffi_to_c_string(key));
-            c_values.push(*value);
+            for (key, value) in &exl.entries {
+                c_keys.push(ffi_to_c_string(key));
+                c_values.push(*value);
+            }
+
+            let mat = physis_EXL {
+                version: exl.version,
+                entry_count: c_keys.len() as i32,
+                entry_keys: c_keys.as_mut_ptr(),
+                entry_values: c_values.as_mut_ptr(),
+            };
+
+            mem::forget(c_keys);
+            mem::forget(c_values);
+
+            mat
+        } else {
+            physis_EXL::default()
         }
+    })
+}
 
-        let mat = physis_EXL {
-            version: exl.version,
-            entry_count: c_keys.len() as i32,
-            entry_keys: c_keys.as_mut_ptr(),
-            entry_values: c_values.as_mut_ptr(),
-        };
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_exl_free(exl: &physis_EXL) {
+    ffi_guard((), || {
+        if exl.entry_keys.is_null() {
+            return;
+        }
 
-        mem::forget(c_keys);
-        mem::forget(c_values);
+        let keys = ffi_to_vec(exl.entry_keys as *mut *const c_char, exl.entry_count as u32);
+        for key in &keys {
+            ffi_free_string(*key);
+        }
+        drop(keys);
 
-        mat
-    } else {
-        physis_EXL::default()
-    }
+        drop(ffi_to_vec(
+            exl.entry_values as *mut i32,
+            exl.entry_count as u32,
+        ));
+    })
 }
@@ -0,0 +1,333 @@
+// SPDX-FileCopyrightText: 2026 Joshua Goins <josh@redstrate.com>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::avfx::{physis_Avfx, physis_avfx_free, physis_avfx_parse};
+use crate::cfg::{physis_ConfigFile, physis_cfg_free, physis_cfg_parse};
+use crate::cmp::{physis_CMP, physis_cmp_free, physis_cmp_parse};
+use crate::cutb::{physis_Cutscene, physis_cutb_free, physis_cutb_parse};
+use crate::dic::{physis_Dictionary, physis_dictionary_free, physis_dictionary_parse};
+use crate::error::{physis_ErrorCode, physis_get_last_error, set_last_error};
+use crate::exh::{physis_EXH, physis_exh_free, physis_exh_parse};
+use crate::exl::{physis_EXL, physis_exl_free, physis_exl_parse};
+use crate::hwc::{physis_HWC, physis_hwc_free, physis_hwc_parse};
+use crate::lcb::{physis_Lcb, physis_lcb_free, physis_lcb_parse};
+use crate::lgb::{physis_LayerGroup, physis_lgb_free, physis_lgb_parse};
+use crate::lvb::{physis_Lvb, physis_lvb_free, physis_lvb_parse};
+use crate::model::{physis_MDL, physis_mdl_free, physis_mdl_parse};
+use crate::mtrl::{physis_Material, physis_material_parse, physis_mtrl_free};
+use crate::obsb::{physis_Obsb, physis_obsb_free, physis_obsb_parse};
+use crate::panic::ffi_guard;
+use crate::pbd::{physis_PBD, physis_pbd_free, physis_pbd_parse};
+use crate::resource::{physis_SqPackResource, sqpack_read};
+use crate::scd::{physis_Scd, physis_scd_free, physis_scd_parse};
+use crate::sgb::{physis_Sgb, physis_sgb_free, physis_sgb_parse};
+use crate::shcd::{physis_SHCD, physis_shcd_free, physis_shcd_parse};
+use crate::shpk::{physis_SHPK, physis_shpk_free, physis_shpk_parse};
+use crate::skeleton::{physis_Skeleton, physis_skeleton_free, physis_skeleton_parse};
+use crate::tera::{physis_Terrain, physis_terrain_free, physis_terrain_parse};
+use crate::tex::{physis_Texture, physis_tex_free, physis_texture_parse};
+use crate::tmb::{physis_Tmb, physis_tmb_free, physis_tmb_parse};
+use crate::{ffi_size, ffi_to_buffer, physis_Buffer, physis_free_file};
+use physis::Platform;
+use std::ffi::{CStr, c_char};
+use std::path::Path;
+use std::slice;
+
+/// The kind of a game file, as detected by `physis_detect_file_type`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum physis_FileType {
+    Unknown,
+    Model,
+    Texture,
+    Material,
+    LayerGroup,
+    SharedGroup,
+    LayerVariables,
+    ExcelHeader,
+    ExcelData,
+    ExcelList,
+    ShaderPackage,
+    Shader,
+    Skeleton,
+    PreBoneDeformer,
+    Cmp,
+    Timeline,
+    Sound,
+    Vfx,
+    Cutscene,
+    Lcb,
+    Obsb,
+    HardwareCursor,
+    Terrain,
+    Dictionary,
+    Config,
+    Animation,
+    Ui,
+    Font,
+}
+
+/// Files that start with a magic we can recognize, regardless of their extension.
+const MAGICS: &[(&[u8], physis_FileType)] = &[
+    (b"LGB1", physis_FileType::LayerGroup),
+    (b"SGB1", physis_FileType::SharedGroup),
+    (b"LVB1", physis_FileType::LayerVariables),
+    (b"EXHF", physis_FileType::ExcelHeader),
+    (b"EXDF", physis_FileType::ExcelData),
+    (b"EXLT", physis_FileType::ExcelList),
+    (b"ShPk", physis_FileType::ShaderPackage),
+    (b"ShCd", physis_FileType::Shader),
+    (b"blks", physis_FileType::Skeleton),
+    (b"TMLB", physis_FileType::Timeline),
+    (b"SEDBSSCF", physis_FileType::Sound),
+    (b"LCB1", physis_FileType::Lcb),
+    (b"pap ", physis_FileType::Animation),
+    (b"uldh", physis_FileType::Ui),
+    (b"fcsv", physis_FileType::Font),
+];
+
+fn detect_from_magic(data: &[u8]) -> physis_FileType {
+    MAGICS
+        .iter()
+        .find(|(magic, _)| data.starts_with(magic))
+        .map(|(_, file_type)| *file_type)
+        .unwrap_or(physis_FileType::Unknown)
+}
+
+fn detect_from_extension(path: &str) -> physis_FileType {
+    let Some(extension) = Path::new(path).extension() else {
+        return physis_FileType::Unknown;
+    };
+
+    match extension.to_string_lossy().to_lowercase().as_str() {
+        "mdl" => physis_FileType::Model,
+        "tex" | "atex" => physis_FileType::Texture,
+        "mtrl" => physis_FileType::Material,
+        "lgb" => physis_FileType::LayerGroup,
+        "sgb" => physis_FileType::SharedGroup,
+        "lvb" => physis_FileType::LayerVariables,
+        "exh" => physis_FileType::ExcelHeader,
+        "exd" => physis_FileType::ExcelData,
+        "exl" => physis_FileType::ExcelList,
+        "shpk" => physis_FileType::ShaderPackage,
+        "shcd" => physis_FileType::Shader,
+        "sklb" => physis_FileType::Skeleton,
+        "pbd" => physis_FileType::PreBoneDeformer,
+        "cmp" => physis_FileType::Cmp,
+        "tmb" => physis_FileType::Timeline,
+        "scd" => physis_FileType::Sound,
+        "avfx" => physis_FileType::Vfx,
+        "cutb" => physis_FileType::Cutscene,
+        "lcb" => physis_FileType::Lcb,
+        "obsb" => physis_FileType::Obsb,
+        "hwc" => physis_FileType::HardwareCursor,
+        "tera" => physis_FileType::Terrain,
+        "dic" => physis_FileType::Dictionary,
+        "cfg" => physis_FileType::Config,
+        "pap" => physis_FileType::Animation,
+        "uld" => physis_FileType::Ui,
+        "fdt" => physis_FileType::Font,
+        _ => physis_FileType::Unknown,
+    }
+}
+
+fn detect_file_type(path: Option<&str>, data: &[u8]) -> physis_FileType {
+    match detect_from_magic(data) {
+        physis_FileType::Unknown => path
+            .map(detect_from_extension)
+            .unwrap_or(physis_FileType::Unknown),
+        file_type => file_type,
+    }
+}
+
+/// Guesses the type of a file from the magic at the start of `buffer`, falling back to the extension of `path` for
+/// formats that don't have one. Either can be left empty (NULL or a zero-sized buffer) if it's not known.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_detect_file_type(
+    path: *const c_char,
+    buffer: physis_Buffer,
+) -> physis_FileType {
+    ffi_guard(physis_FileType::Unknown, || {
+        let path = if path.is_null() {
+            None
+        } else {
+            Some(unsafe { CStr::from_ptr(path).to_string_lossy() })
+        };
+
+        let data = if buffer.data.is_null() {
+            &[]
+        } else {
+            unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) }
+        };
+
+        detect_file_type(path.as_deref(), data)
+    })
+}
+
+/// A file that was detected, but can't be parsed by libphysis (yet.)
+#[repr(C)]
+pub struct physis_RawFile {
+    file_type: physis_FileType,
+    buffer: physis_Buffer,
+}
+
+/// Any file returned by `physis_parse_any`. Free it with `physis_free_any`.
+#[repr(C)]
+#[allow(dead_code)]
+pub enum physis_AnyFile {
+    /// The file couldn't be read, or failed to parse.
+    None,
+    /// The file type isn't supported, but here's the raw data.
+    Raw(physis_RawFile),
+    Model(physis_MDL),
+    Texture(physis_Texture),
+    Material(physis_Material),
+    LayerGroup(physis_LayerGroup),
+    SharedGroup(physis_Sgb),
+    LayerVariables(physis_Lvb),
+    ExcelHeader(physis_EXH),
+    ExcelList(physis_EXL),
+    ShaderPackage(physis_SHPK),
+    Shader(physis_SHCD),
+    Skeleton(physis_Skeleton),
+    PreBoneDeformer(physis_PBD),
+    Cmp(physis_CMP),
+    Timeline(physis_Tmb),
+    Sound(physis_Scd),
+    Vfx(physis_Avfx),
+    Cutscene(physis_Cutscene),
+    Lcb(physis_Lcb),
+    Obsb(physis_Obsb),
+    HardwareCursor(physis_HWC),
+    Terrain(physis_Terrain),
+    Dictionary(physis_Dictionary),
+    Config(physis_ConfigFile),
+}
+
+fn parse_any(platform: Platform, file_type: physis_FileType, data: Vec<u8>) -> physis_AnyFile {
+    let Some(size) = ffi_size(data.len()) else {
+        return physis_AnyFile::None;
+    };
+
+    let buffer = physis_Buffer {
+        size,
+        data: data.as_ptr() as *mut u8,
+    };
+
+    // The parsers copy everything they need, so data is dropped afterwards
+    match file_type {
+        physis_FileType::Model => physis_AnyFile::Model(physis_mdl_parse(platform, buffer)),
+        physis_FileType::Texture => physis_AnyFile::Texture(physis_texture_parse(platform, buffer)),
+        physis_FileType::Material => {
+            physis_AnyFile::Material(physis_material_parse(platform, buffer))
+        }
+        physis_FileType::LayerGroup => {
+            physis_AnyFile::LayerGroup(physis_lgb_parse(platform, buffer))
+        }
+        physis_FileType::SharedGroup => {
+            physis_AnyFile::SharedGroup(physis_sgb_parse(platform, buffer))
+        }
+        physis_FileType::LayerVariables => {
+            physis_AnyFile::LayerVariables(physis_lvb_parse(platform, buffer))
+        }
+        physis_FileType::ExcelHeader => {
+            physis_AnyFile::ExcelHeader(physis_exh_parse(platform, buffer))
+        }
+        physis_FileType::ExcelList => physis_AnyFile::ExcelList(physis_exl_parse(platform, buffer)),
+        physis_FileType::ShaderPackage => {
+            physis_AnyFile::ShaderPackage(physis_shpk_parse(platform, buffer))
+        }
+        physis_FileType::Shader => physis_AnyFile::Shader(physis_shcd_parse(platform, buffer)),
+        physis_FileType::Skeleton => {
+            physis_AnyFile::Skeleton(physis_skeleton_parse(platform, buffer))
+        }
+        physis_FileType::PreBoneDeformer => {
+            physis_AnyFile::PreBoneDeformer(physis_pbd_parse(platform, buffer))
+        }
+        physis_FileType::Cmp => physis_AnyFile::Cmp(physis_cmp_parse(platform, buffer)),
+        physis_FileType::Timeline => physis_AnyFile::Timeline(physis_tmb_parse(platform, buffer)),
+        physis_FileType::Sound => physis_AnyFile::Sound(physis_scd_parse(platform, buffer)),
+        physis_FileType::Vfx => physis_AnyFile::Vfx(physis_avfx_parse(platform, buffer)),
+        physis_FileType::Cutscene => physis_AnyFile::Cutscene(physis_cutb_parse(platform, buffer)),
+        physis_FileType::Lcb => physis_AnyFile::Lcb(physis_lcb_parse(platform, buffer)),
+        physis_FileType::Obsb => physis_AnyFile::Obsb(physis_obsb_parse(platform, buffer)),
+        physis_FileType::HardwareCursor => {
+            physis_AnyFile::HardwareCursor(physis_hwc_parse(platform, buffer))
+        }
+        physis_FileType::Terrain => physis_AnyFile::Terrain(physis_terrain_parse(platform, buffer)),
+        physis_FileType::Dictionary => {
+            physis_AnyFile::Dictionary(physis_dictionary_parse(platform, buffer))
+        }
+        physis_FileType::Config => physis_AnyFile::Config(physis_cfg_parse(buffer)),
+        _ => physis_AnyFile::Raw(physis_RawFile {
+            file_type,
+            buffer: ffi_to_buffer(data),
+        }),
+    }
+}
+
+/// Reads the file at `path` from `resource`, detects its type and parses it with the matching `physis_*_parse`
+/// function. Files that can be detected but not parsed are returned as `Raw`. On failure, `None` is returned and the
+/// last error is set.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_parse_any(
+    resource: &physis_SqPackResource,
+    path: *const c_char,
+) -> physis_AnyFile {
+    ffi_guard(physis_AnyFile::None, || {
+        let Some(data) = sqpack_read(resource, path) else {
+            return physis_AnyFile::None;
+        };
+
+        let path = unsafe { CStr::from_ptr(path).to_string_lossy() };
+        let file_type = detect_file_type(Some(&path), &data);
+        if file_type == physis_FileType::Unknown {
+            set_last_error(
+                physis_ErrorCode::InvalidArgument,
+                format!("Could not detect the type of {path}"),
+            );
+            return physis_AnyFile::None;
+        }
+
+        let file = parse_any(resource.platform, file_type, data);
+
+        // A failed parse returns an empty object, so there's nothing to free
+        if physis_get_last_error() != physis_ErrorCode::Ok {
+            return physis_AnyFile::None;
+        }
+
+        file
+    })
+}
+
+/// Frees a file returned by `physis_parse_any`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_free_any(file: &physis_AnyFile) {
+    ffi_guard((), || match file {
+        physis_AnyFile::Raw(raw) => physis_free_file(&raw.buffer),
+        physis_AnyFile::Model(mdl) => physis_mdl_free(mdl),
+        physis_AnyFile::Texture(tex) => physis_tex_free(tex),
+        physis_AnyFile::Material(mtrl) => physis_mtrl_free(mtrl),
+        physis_AnyFile::LayerGroup(lgb) => physis_lgb_free(lgb),
+        physis_AnyFile::SharedGroup(sgb) => physis_sgb_free(sgb),
+        physis_AnyFile::LayerVariables(lvb) => physis_lvb_free(lvb),
+        physis_AnyFile::ExcelHeader(exh) => physis_exh_free(exh),
+        physis_AnyFile::ShaderPackage(shpk) => physis_shpk_free(shpk),
+        physis_AnyFile::Skeleton(skeleton) => physis_skeleton_free(skeleton),
+        physis_AnyFile::PreBoneDeformer(pbd) => physis_pbd_free(pbd),
+        physis_AnyFile::Cmp(cmp) => physis_cmp_free(cmp),
+        physis_AnyFile::Timeline(tmb) => physis_tmb_free(tmb),
+        physis_AnyFile::ExcelList(exl) => physis_exl_free(exl),
+        physis_AnyFile::Shader(shcd) => physis_shcd_free(shcd),
+        physis_AnyFile::Sound(scd) => physis_scd_free(scd),
+        physis_AnyFile::Vfx(avfx) => physis_avfx_free(avfx),
+        physis_AnyFile::Cutscene(cutb) => physis_cutb_free(cutb),
+        physis_AnyFile::Lcb(lcb) => physis_lcb_free(lcb),
+        physis_AnyFile::Obsb(obsb) => physis_obsb_free(obsb),
+        physis_AnyFile::HardwareCursor(hwc) => physis_hwc_free(hwc),
+        physis_AnyFile::Terrain(tera) => physis_terrain_free(tera),
+        physis_AnyFile::Dictionary(dic) => physis_dictionary_free(dic),
+        physis_AnyFile::Config(cfg) => physis_cfg_free(cfg),
+        physis_AnyFile::None => {}
+    })
+}
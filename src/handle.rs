@@ -0,0 +1,218 @@
+// SPDX-FileCopyrightText: 2026 Joshua Goins <josh@redstrate.com>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::error::{physis_ErrorCode, set_last_error};
+use crate::panic::ffi_guard;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// An opaque reference to an object owned by libphysis.
+///
+/// Handles are generation-checked, so using a handle after it was freed (or freeing it twice) is reported as an
+/// `InvalidHandle` error instead of corrupting memory. A zeroed handle is the null handle.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct physis_Handle {
+    index: u32,
+    generation: u32,
+}
+
+impl physis_Handle {
+    pub(crate) fn is_null(&self) -> bool {
+        self.generation == 0
+    }
+}
+
+/// Checks if `handle` is the null handle, which is what's returned when parsing or initialization fails.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_handle_is_null(handle: physis_Handle) -> bool {
+    ffi_guard(true, || handle.is_null())
+}
+
+/// Locks an object taken out of a registry. A panic while using it doesn't leave it in a state that's unsafe to keep
+/// using, since every exported function is guarded anyway.
+pub(crate) fn lock_value<T>(value: &Mutex<T>) -> MutexGuard<'_, T> {
+    value.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<Arc<Mutex<T>>>,
+}
+
+struct Slots<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+}
+
+/// Owns every object of type `T` that was handed out to C, and keeps track of which handles are still valid.
+pub(crate) struct HandleRegistry<T> {
+    name: &'static str,
+    inner: Mutex<Slots<T>>,
+}
+
+impl<T> HandleRegistry<T> {
+    pub(crate) const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            inner: Mutex::new(Slots {
+                slots: Vec::new(),
+                free: Vec::new(),
+            }),
+        }
+    }
+
+    /// Takes ownership of `value` and returns a new handle to it.
+    pub(crate) fn insert(&self, value: T) -> physis_Handle {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(index) = inner.free.pop() {
+            let slot = &mut inner.slots[index as usize];
+            slot.value = Some(Arc::new(Mutex::new(value)));
+
+            physis_Handle {
+                index,
+                generation: slot.generation,
+            }
+        } else {
+            let index = inner.slots.len() as u32;
+            inner.slots.push(Slot {
+                generation: 1,
+                value: Some(Arc::new(Mutex::new(value))),
+            });
+
+            physis_Handle {
+                index,
+                generation: 1,
+            }
+        }
+    }
+
+    /// Returns a reference to the object behind `handle`, or sets the last error and returns None if it's not valid.
+    /// The object stays alive for as long as the returned reference does, even if the handle is removed meanwhile.
+    pub(crate) fn shared(&self, handle: physis_Handle) -> Option<Arc<Mutex<T>>> {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+
+        match inner.slots.get(handle.index as usize) {
+            Some(Slot {
+                generation,
+                value: Some(value),
+            }) if *generation == handle.generation => Some(value.clone()),
+            _ => {
+                self.invalid(handle);
+                None
+            }
+        }
+    }
+
+    /// Runs `f` with the object behind `handle`. The registry itself isn't locked while `f` runs, only the object, so
+    /// other handles can still be used from other threads and a concurrent remove can't free it from under `f`. Sets
+    /// the last error and returns None if the handle is not valid.
+    pub(crate) fn with<R>(&self, handle: physis_Handle, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        let shared = self.shared(handle)?;
+        let mut value = lock_value(&shared);
+
+        Some(f(&mut value))
+    }
+
+    /// Takes the object behind `handle` back, invalidating the handle. It's dropped once every reference from `shared`
+    /// is gone. Sets the last error and returns None if the handle was already freed or never valid.
+    pub(crate) fn remove(&self, handle: physis_Handle) -> Option<Arc<Mutex<T>>> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+
+        let value = match inner.slots.get_mut(handle.index as usize) {
+            Some(slot) if slot.generation == handle.generation && slot.value.is_some() => {
+                // Skip 0 when wrapping around, since that's the null handle
+                slot.generation = slot.generation.wrapping_add(1).max(1);
+                slot.value.take()
+            }
+            _ => None,
+        };
+
+        if value.is_some() {
+            inner.free.push(handle.index);
+        } else {
+            self.invalid(handle);
+        }
+
+        value
+    }
+
+    fn invalid(&self, handle: physis_Handle) {
+        if handle.is_null() {
+            set_last_error(
+                physis_ErrorCode::InvalidHandle,
+                format!("{} handle is null", self.name),
+            );
+        } else {
+            set_last_error(
+                physis_ErrorCode::InvalidHandle,
+                format!(
+                    "{} handle {}:{} is stale or was already freed",
+                    self.name, handle.index, handle.generation
+                ),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{clear_last_error, physis_get_last_error};
+
+    #[test]
+    fn handles_are_usable_until_removed() {
+        let registry = HandleRegistry::new("Test");
+        let handle = registry.insert(5);
+
+        assert!(!handle.is_null());
+        assert_eq!(registry.with(handle, |value| *value), Some(5));
+
+        assert!(registry.remove(handle).is_some());
+        assert_eq!(physis_get_last_error(), physis_ErrorCode::Ok);
+    }
+
+    #[test]
+    fn stale_handles_are_rejected() {
+        let registry = HandleRegistry::new("Test");
+        let old = registry.insert(1);
+        registry.remove(old);
+
+        // The slot is reused, but the old handle must not reach the new value
+        let new = registry.insert(2);
+        assert_eq!(new.index, old.index);
+        assert_ne!(new.generation, old.generation);
+
+        clear_last_error();
+        assert_eq!(registry.with(old, |value| *value), None);
+        assert_eq!(physis_get_last_error(), physis_ErrorCode::InvalidHandle);
+
+        clear_last_error();
+        assert!(registry.remove(old).is_none());
+        assert_eq!(physis_get_last_error(), physis_ErrorCode::InvalidHandle);
+
+        assert_eq!(registry.with(new, |value| *value), Some(2));
+    }
+
+    #[test]
+    fn null_handles_are_rejected() {
+        let registry: HandleRegistry<u32> = HandleRegistry::new("Test");
+        registry.insert(1);
+
+        clear_last_error();
+        assert!(physis_handle_is_null(physis_Handle::default()));
+        assert!(registry.shared(physis_Handle::default()).is_none());
+        assert_eq!(physis_get_last_error(), physis_ErrorCode::InvalidHandle);
+    }
+
+    #[test]
+    fn removed_values_outlive_shared_references() {
+        let registry = HandleRegistry::new("Test");
+        let handle = registry.insert(String::from("Still here"));
+
+        let shared = registry.shared(handle).unwrap();
+        drop(registry.remove(handle));
+
+        assert_eq!(*lock_value(&shared), "Still here");
+    }
+}
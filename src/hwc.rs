@@ -1,7 +1,9 @@
 // SPDX-FileCopyrightText: 2024 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::physis_Buffer;
+use crate::error::ffi_result;
+use crate::panic::ffi_guard;
+use crate::{ffi_to_vec, physis_Buffer};
 use physis::Platform;
 use physis::ReadableFile;
 use physis::hwc::Hwc;
@@ -13,27 +15,48 @@ use std::slice;
 #[derive(Clone, Copy)]
 pub struct physis_HWC {
     rgba: *const u8,
+    rgba_size: u32,
 }
 
 impl Default for physis_HWC {
     fn default() -> Self {
-        Self { rgba: null() }
+        Self {
+            rgba: null(),
+            rgba_size: 0,
+        }
     }
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_hwc_parse(platform: Platform, buffer: physis_Buffer) -> physis_HWC {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(physis_HWC::default(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-    if let Ok(hwc) = Hwc::from_existing(platform, data) {
-        let c_hwc = physis_HWC {
-            rgba: hwc.rgba.as_ptr(),
-        };
+        if let Some(hwc) = ffi_result(Hwc::from_existing(platform, data)) {
+            let mut c_rgba = hwc.rgba;
+            c_rgba.shrink_to_fit();
 
-        mem::forget(hwc.rgba);
+            let c_hwc = physis_HWC {
+                rgba: c_rgba.as_ptr(),
+                rgba_size: c_rgba.len() as u32,
+            };
 
-        c_hwc
-    } else {
-        physis_HWC::default()
-    }
+            mem::forget(c_rgba);
+
+            c_hwc
+        } else {
+            physis_HWC::default()
+        }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_hwc_free(hwc: &physis_HWC) {
+    ffi_guard((), || {
+        if hwc.rgba.is_null() {
+            return;
+        }
+
+        drop(ffi_to_vec(hwc.rgba as *mut u8, hwc.rgba_size));
+    })
 }
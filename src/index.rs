@@ -9,12 +9,17 @@ use std::ptr::null_mut;
 use physis::Platform;
 use physis::sqpack::{Hash, SqPackIndex};
 
+use crate::error::{physis_ErrorCode, set_last_error};
+use crate::handle::{HandleRegistry, physis_Handle};
+use crate::panic::ffi_guard;
 use crate::{ffi_from_c_string, ffi_to_vec};
 
+static INDEXES: HandleRegistry<SqPackIndex> = HandleRegistry::new("IndexEntries");
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct physis_IndexEntries {
-    p_ptr: *mut SqPackIndex,
+    handle: physis_Handle,
 
     num_hashes: u32,
     hashes: *mut Hash,
@@ -23,7 +28,7 @@ pub struct physis_IndexEntries {
 impl Default for physis_IndexEntries {
     fn default() -> Self {
         Self {
-            p_ptr: null_mut(),
+            handle: physis_Handle::default(),
             num_hashes: 0,
             hashes: null_mut(),
         }
@@ -35,59 +40,82 @@ pub extern "C" fn physis_index_parse(
     platform: Platform,
     path: *const c_char,
 ) -> physis_IndexEntries {
-    let Some(r_path) = ffi_from_c_string(path) else {
-        return physis_IndexEntries::default();
-    };
+    ffi_guard(physis_IndexEntries::default(), || {
+        let Some(r_path) = ffi_from_c_string(path) else {
+            return physis_IndexEntries::default();
+        };
 
-    if let Some(idx_file) = SqPackIndex::from_existing(platform, Path::new(&r_path)) {
-        let mut c_hashes = Vec::new();
+        if let Some(idx_file) = SqPackIndex::from_existing(platform, Path::new(&r_path)) {
+            let mut c_hashes = Vec::new();
 
-        for entry in &idx_file.entries {
-            c_hashes.push(entry.hash);
-        }
+            for entry in &idx_file.entries {
+                c_hashes.push(entry.hash);
+            }
 
-        let boxed = Box::new(idx_file);
-
-        let mat = physis_IndexEntries {
-            p_ptr: Box::leak(boxed),
-            num_hashes: c_hashes.len() as u32,
-            hashes: c_hashes.as_mut_ptr(),
-        };
+            let mat = physis_IndexEntries {
+                handle: INDEXES.insert(idx_file),
+                num_hashes: c_hashes.len() as u32,
+                hashes: c_hashes.as_mut_ptr(),
+            };
 
-        mem::forget(c_hashes);
+            mem::forget(c_hashes);
 
-        mat
-    } else {
-        physis_IndexEntries::default()
-    }
+            mat
+        } else {
+            set_last_error(
+                physis_ErrorCode::ParseFailed,
+                format!("Failed to read index file {r_path}"),
+            );
+            physis_IndexEntries::default()
+        }
+    })
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_generate_partial_hash(name: *const c_char) -> u32 {
-    let Some(r_name) = ffi_from_c_string(name) else {
-        return 0;
-    };
+    ffi_guard(0, || {
+        let Some(r_name) = ffi_from_c_string(name) else {
+            return 0;
+        };
 
-    SqPackIndex::calculate_partial_hash(&r_name)
+        SqPackIndex::calculate_partial_hash(&r_name)
+    })
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_index_hash_from_offset(entries: physis_IndexEntries, offset: u64) -> Hash {
-    unsafe {
-        if let Some(hash) = (*entries.p_ptr).find_entry_from_offset(offset) {
+    ffi_guard(Hash::FullPath(0), || {
+        let Some(hash) = INDEXES.with(entries.handle, |index| index.find_entry_from_offset(offset))
+        else {
+            return Hash::FullPath(0);
+        };
+
+        if let Some(hash) = hash {
             hash
         } else {
+            set_last_error(
+                physis_ErrorCode::FileNotFound,
+                format!("No index entry at offset {offset}"),
+            );
             Hash::FullPath(0)
         }
-    }
+    })
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_index_free(index: *mut physis_IndexEntries) {
-    unsafe {
+    ffi_guard((), || unsafe {
+        if (*index).handle.is_null() {
+            return;
+        }
+
+        let Some(index_file) = INDEXES.remove((*index).handle) else {
+            return;
+        };
+
         let data = ffi_to_vec((*index).hashes, (*index).num_hashes);
         drop(data);
 
-        drop(Box::from_raw((*index).p_ptr));
-    }
+        drop(index_file);
+    })
 }
@@ -0,0 +1,210 @@
+// SPDX-FileCopyrightText: 2026 Joshua Goins <josh@redstrate.com>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::error::{physis_ErrorCode, physis_get_last_error, set_last_error};
+use crate::panic::ffi_guard;
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+/// Identifies a job queued by one of the `_async` functions. 0 means the job couldn't be queued.
+pub type physis_JobId = u64;
+
+/// How a job finished, passed to its completion callback.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum physis_JobStatus {
+    /// The job finished successfully.
+    Completed,
+    /// The job failed or panicked. `physis_get_last_error` returns the reason for the duration of the callback.
+    Failed,
+    /// The job was cancelled with `physis_job_cancel` before it started, and the result is empty.
+    Cancelled,
+}
+
+/// A queued job. The closure receives whether the job was cancelled.
+struct Job {
+    id: physis_JobId,
+    cancelled: bool,
+    run: Box<dyn FnOnce(bool) + Send>,
+}
+
+/// Wraps a job closure so it can be sent to a worker. The host is responsible for keeping the buffers, EXH and
+/// user_data it passed valid until the job's callback is called.
+struct AssertSend<T>(T);
+
+unsafe impl<T> Send for AssertSend<T> {}
+
+struct Pool {
+    queue: VecDeque<Job>,
+    /// How many workers are currently running.
+    worker_count: usize,
+    /// How many workers there should be, or 0 if it wasn't set yet.
+    target_workers: usize,
+    next_id: physis_JobId,
+}
+
+static POOL: Mutex<Pool> = Mutex::new(Pool {
+    queue: VecDeque::new(),
+    worker_count: 0,
+    target_workers: 0,
+    next_id: 1,
+});
+
+static WORK_AVAILABLE: Condvar = Condvar::new();
+
+fn lock_pool() -> MutexGuard<'static, Pool> {
+    POOL.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Spawns workers until there are as many as requested, defaulting to one per CPU.
+fn spawn_workers(pool: &mut Pool) {
+    if pool.target_workers == 0 {
+        pool.target_workers = thread::available_parallelism()
+            .map(|count| count.get())
+            .unwrap_or(1);
+    }
+
+    while pool.worker_count < pool.target_workers {
+        let spawned = thread::Builder::new()
+            .name("physis-worker".to_string())
+            .spawn(worker);
+        if spawned.is_err() {
+            break;
+        }
+
+        pool.worker_count += 1;
+    }
+}
+
+fn worker() {
+    loop {
+        let job = {
+            let mut pool = lock_pool();
+            loop {
+                // Extra workers exit once the pool was shrunk
+                if pool.worker_count > pool.target_workers {
+                    pool.worker_count -= 1;
+                    return;
+                }
+
+                if let Some(job) = pool.queue.pop_front() {
+                    break job;
+                }
+
+                pool = WORK_AVAILABLE
+                    .wait(pool)
+                    .unwrap_or_else(PoisonError::into_inner);
+            }
+        };
+
+        let Job { cancelled, run, .. } = job;
+
+        // Keeps a panic in the callback from taking the worker down with it
+        ffi_guard((), || run(cancelled));
+    }
+}
+
+/// Queues `work` to run on a worker thread, and then passes its result to `complete`. If the job is cancelled before
+/// it starts, `complete` gets the default value instead. If `work` panics, `complete` gets the default value with the
+/// `Failed` status, and the last error is `Panic`.
+///
+/// Like the exported functions, `work` has to set the last error when it fails so the right status is reported.
+pub(crate) fn spawn_job<T: Default + 'static>(
+    work: impl FnOnce() -> T + 'static,
+    complete: impl FnOnce(physis_JobId, physis_JobStatus, T) + 'static,
+) -> physis_JobId {
+    let mut pool = lock_pool();
+
+    let id = pool.next_id;
+    pool.next_id += 1;
+
+    let run = AssertSend(move |cancelled: bool| {
+        if cancelled {
+            complete(id, physis_JobStatus::Cancelled, T::default());
+            return;
+        }
+
+        // A panic in the job still has to reach the callback, or the host would wait on it forever
+        let Some(result) = ffi_guard(None, || Some(work())) else {
+            complete(id, physis_JobStatus::Failed, T::default());
+            return;
+        };
+
+        let status = if physis_get_last_error() == physis_ErrorCode::Ok {
+            physis_JobStatus::Completed
+        } else {
+            physis_JobStatus::Failed
+        };
+
+        complete(id, status, result);
+    });
+
+    pool.queue.push_back(Job {
+        id,
+        cancelled: false,
+        run: Box::new(move |cancelled| {
+            let run = run;
+            (run.0)(cancelled)
+        }),
+    });
+
+    spawn_workers(&mut pool);
+    if pool.worker_count == 0 {
+        pool.queue.pop_back();
+        set_last_error(
+            physis_ErrorCode::Unknown,
+            "Failed to start any worker threads",
+        );
+        return 0;
+    }
+
+    WORK_AVAILABLE.notify_one();
+
+    id
+}
+
+/// Cancels the job `id` if it hasn't started yet, and returns true. Its callback is still called from a worker thread,
+/// with the `Cancelled` status. Returns false if the job already started or finished, in which case it completes
+/// normally.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_job_cancel(id: physis_JobId) -> bool {
+    ffi_guard(false, || {
+        let mut pool = lock_pool();
+
+        let Some(position) = pool.queue.iter().position(|job| job.id == id) else {
+            return false;
+        };
+
+        // Moved to the front so the callback isn't held up by the rest of the queue
+        let Some(mut job) = pool.queue.remove(position) else {
+            return false;
+        };
+        job.cancelled = true;
+        pool.queue.push_front(job);
+
+        WORK_AVAILABLE.notify_one();
+
+        true
+    })
+}
+
+/// Sets how many worker threads run jobs. By default there's one per CPU, and 0 resets it to that. Shrinking the pool
+/// lets jobs that are already running finish first.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_set_job_worker_count(count: u32) {
+    ffi_guard((), || {
+        let mut pool = lock_pool();
+
+        pool.target_workers = count as usize;
+        spawn_workers(&mut pool);
+
+        WORK_AVAILABLE.notify_all();
+    })
+}
+
+/// Returns how many jobs are waiting for a worker, not counting the ones that are running.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_job_queue_length() -> u32 {
+    ffi_guard(0, || lock_pool().queue.len() as u32)
+}
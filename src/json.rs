@@ -0,0 +1,516 @@
+// SPDX-FileCopyrightText: 2026 Joshua Goins <josh@redstrate.com>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Converts the C structs returned by the `physis_*_parse` functions into JSON, for the `physis_*_to_json` functions.
+//!
+//! The JSON follows the structs in the header:
+//! * Structs become objects with the same field names. Counts and pointers are folded into one array, so
+//!   `num_layers` and `layers` become `"layers": [...]`.
+//! * Tagged unions like `physis_LayerEntry` become an object with a `"type"` key holding the variant name, plus the
+//!   fields of that variant.
+//! * Enums declared in the header become the variant name as a string.
+//! * Strings become strings, and NULL strings become `null`.
+//! * Physis enums become their variant name too, like `"Box"` for a `ModelCollisionType`. Variants that carry data
+//!   fall back to the binary encoding described below.
+//! * Physis flags become an array of the names of the flags that are set, like `["MANAGED"]`. Bits without a name
+//!   are added as one integer at the end.
+//! * Physis colors become an object with `red`, `green`, `blue` and `alpha`, plus `intensity` for `ColorIntensity`.
+//! * Other Physis types that the header passes through as-is (vertices, table rows...) become their binary encoding
+//!   from the file format: a little-endian integer if it fits in 8 bytes, otherwise an array of bytes.
+//! * Large binary payloads like texture data or shader bytecode aren't included, only their size.
+//!
+//! The `physis_*_from_json` functions read the same format back, and also accept the binary encoding for enums.
+//!
+//! Formats that Physis only exposes as opaque structs (like PAP, ULD or FDT files) only have a `physis_*_debug`
+//! function, since their fields can't be reached from here. They need serialization support in Physis first.
+
+use crate::error::{physis_ErrorCode, physis_get_last_error};
+use crate::ffi_to_c_string;
+use crate::panic::ffi_guard;
+use binrw::{BinRead, BinWrite, Endian};
+use bitflags::Flags;
+use serde_json::{Value, json};
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::ffi::{CStr, c_char};
+use std::fmt::Debug;
+use std::io::Cursor;
+use std::ptr::null;
+use std::slice;
+use std::sync::{Mutex, PoisonError};
+
+/// The version of the JSON format described above. This is bumped whenever the format changes.
+pub const PHYSIS_JSON_VERSION: u32 = 3;
+
+/// Returns the version of the JSON format produced by the `physis_*_to_json` functions.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_get_json_version() -> u32 {
+    ffi_guard(0, || PHYSIS_JSON_VERSION)
+}
+
+/// Implemented by the C structs that have a JSON representation.
+pub(crate) trait ToJson {
+    fn to_json(&self) -> Value;
+}
+
+/// Converts `parsed` into a JSON C string and then frees it with `free`. Returns NULL if parsing had failed, leaving the
+/// last error from the parse function intact.
+pub(crate) fn ffi_parsed_to_json<T: ToJson>(parsed: T, free: extern "C" fn(&T)) -> *const c_char {
+    if physis_get_last_error() != physis_ErrorCode::Ok {
+        free(&parsed);
+        return null();
+    }
+
+    let json = parsed.to_json().to_string();
+    free(&parsed);
+
+    ffi_to_c_string(&json)
+}
+
+/// Returns `ptr` as a JSON string, or `null` if it's NULL.
+pub(crate) fn string_to_json(ptr: *const c_char) -> Value {
+    if ptr.is_null() {
+        return Value::Null;
+    }
+
+    Value::from(
+        unsafe { CStr::from_ptr(ptr) }
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+/// Converts `count` elements starting at `data` into a JSON array with `f`.
+pub(crate) fn array_to_json<T>(data: *const T, count: usize, f: impl Fn(&T) -> Value) -> Value {
+    if data.is_null() || count == 0 {
+        return Value::Array(Vec::new());
+    }
+
+    let items = unsafe { slice::from_raw_parts(data, count) };
+    Value::Array(items.iter().map(f).collect())
+}
+
+/// Converts a Physis value into JSON through its binary encoding, as described at the top of this file.
+pub(crate) fn raw_to_json<T>(value: &T) -> Value
+where
+    T: BinWrite,
+    for<'a> T::Args<'a>: Default,
+{
+    let mut cursor = Cursor::new(Vec::new());
+    if value
+        .write_options(&mut cursor, Endian::Little, Default::default())
+        .is_err()
+    {
+        return Value::Null;
+    }
+
+    let bytes = cursor.into_inner();
+    if bytes.len() <= 8 {
+        let mut int = [0; 8];
+        int[..bytes.len()].copy_from_slice(&bytes);
+        Value::from(u64::from_le_bytes(int))
+    } else {
+        Value::from(bytes)
+    }
+}
+
+/// Whether `name` is what Debug prints for a variant without any data.
+fn is_variant_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Converts a Physis enum into the name of its variant, or its binary encoding if the variant has data.
+pub(crate) fn enum_to_json<T>(value: &T) -> Value
+where
+    T: BinWrite + Debug,
+    for<'a> T::Args<'a>: Default,
+{
+    let name = format!("{value:?}");
+    if is_variant_name(&name) {
+        Value::from(name)
+    } else {
+        raw_to_json(value)
+    }
+}
+
+/// Converts Physis flags into the names of the flags that are set, followed by any unnamed bits as an integer.
+pub(crate) fn flags_to_json<T>(flags: &T) -> Value
+where
+    T: Flags,
+    T::Bits: Into<u64>,
+{
+    let mut names: Vec<Value> = flags.iter_names().map(|(name, _)| name.into()).collect();
+
+    let unnamed = flags.bits().into() & !T::all().bits().into();
+    if unnamed != 0 {
+        names.push(unnamed.into());
+    }
+
+    Value::Array(names)
+}
+
+/// Converts a Physis `Color` or `ColorIntensity` into its channels, which are stored as RGBA bytes followed by the
+/// intensity as a float.
+pub(crate) fn color_to_json<T>(color: &T) -> Value
+where
+    T: BinWrite,
+    for<'a> T::Args<'a>: Default,
+{
+    let mut cursor = Cursor::new(Vec::new());
+    if color
+        .write_options(&mut cursor, Endian::Little, Default::default())
+        .is_err()
+    {
+        return Value::Null;
+    }
+
+    let bytes = cursor.into_inner();
+    let mut json = json!({
+        "red": bytes.first(),
+        "green": bytes.get(1),
+        "blue": bytes.get(2),
+        "alpha": bytes.get(3),
+    });
+
+    if let Some(intensity) = bytes.get(4..8) {
+        json["intensity"] =
+            f32::from_le_bytes([intensity[0], intensity[1], intensity[2], intensity[3]]).into();
+    }
+
+    json
+}
+
+/// The names of every variant of each enum that was read from JSON, with their binary encoding.
+static VARIANTS: Mutex<Option<HashMap<TypeId, HashMap<String, u64>>>> = Mutex::new(None);
+
+/// Returns the binary encoding of the variant of `T` called `name`. The names aren't exposed by Physis, so the first
+/// time this is called for a type, every 16-bit value (and -1) is decoded to find them.
+fn variant_encoding<T>(name: &str) -> Option<u64>
+where
+    T: BinRead + Debug + 'static,
+    for<'a> T::Args<'a>: Default,
+{
+    let mut variants = VARIANTS.lock().unwrap_or_else(PoisonError::into_inner);
+
+    let names = variants
+        .get_or_insert_with(HashMap::new)
+        .entry(TypeId::of::<T>())
+        .or_insert_with(|| {
+            let mut names = HashMap::new();
+
+            for int in (0..=u64::from(u16::MAX)).chain([u64::from(u32::MAX), u64::MAX]) {
+                let Ok(variant) = T::read_options(
+                    &mut Cursor::new(int.to_le_bytes()),
+                    Endian::Little,
+                    Default::default(),
+                ) else {
+                    continue;
+                };
+
+                let name = format!("{variant:?}");
+                if is_variant_name(&name) {
+                    names.entry(name).or_insert(int);
+                }
+            }
+
+            names
+        });
+
+    names.get(name).copied()
+}
+
+/// Reads values for the `physis_*_from_json` functions. Instead of bailing out, a missing or invalid value is replaced
+/// with a default and the first problem is remembered. That way the C struct is always complete, and can be freed
+/// with its usual free function if `finish` returns an error.
+pub(crate) struct JsonReader {
+    error: Option<String>,
+}
+
+impl JsonReader {
+    pub(crate) fn new() -> Self {
+        Self { error: None }
+    }
+
+    /// Records that `key` isn't valid, for checks the other functions can't do.
+    pub(crate) fn fail(&mut self, key: &str, expected: &str) {
+        if self.error.is_none() {
+            self.error = Some(format!("{key} must be {expected}"));
+        }
+    }
+
+    pub(crate) fn bool(&mut self, value: &Value, key: &str) -> bool {
+        value[key].as_bool().unwrap_or_else(|| {
+            self.fail(key, "a boolean");
+            false
+        })
+    }
+
+    pub(crate) fn int<T: TryFrom<i64> + Default>(&mut self, value: &Value, key: &str) -> T {
+        self.int_item(&value[key], key)
+    }
+
+    /// Like `int`, but for an element of the array `key`.
+    pub(crate) fn int_item<T: TryFrom<i64> + Default>(&mut self, value: &Value, key: &str) -> T {
+        match value.as_i64().and_then(|int| T::try_from(int).ok()) {
+            Some(int) => int,
+            None => {
+                self.fail(key, "an integer that fits its field");
+                T::default()
+            }
+        }
+    }
+
+    /// NaN and infinities are written as `null`, so that's read back as NaN.
+    pub(crate) fn f32(&mut self, value: &Value, key: &str) -> f32 {
+        match value.get(key) {
+            Some(Value::Null) => f32::NAN,
+            Some(Value::Number(float)) => float.as_f64().unwrap_or_default() as f32,
+            _ => {
+                self.fail(key, "a number");
+                0.0
+            }
+        }
+    }
+
+    pub(crate) fn f32_array<const N: usize>(&mut self, value: &Value, key: &str) -> [f32; N] {
+        self.f32_array_item(&value[key], key)
+    }
+
+    /// Like `f32_array`, but for an element of the array `key`.
+    pub(crate) fn f32_array_item<const N: usize>(&mut self, value: &Value, key: &str) -> [f32; N] {
+        let mut floats = [0.0; N];
+
+        match value.as_array() {
+            Some(values) if values.len() == N => {
+                for (float, value) in floats.iter_mut().zip(values) {
+                    *float = match value {
+                        Value::Null => f32::NAN,
+                        value => value.as_f64().unwrap_or_default() as f32,
+                    };
+                }
+            }
+            _ => self.fail(key, &format!("an array of {N} numbers")),
+        }
+
+        floats
+    }
+
+    /// Returns a new C string, which must be freed like any other string in the struct.
+    pub(crate) fn string(&mut self, value: &Value, key: &str) -> *const c_char {
+        match value[key].as_str() {
+            Some(string) if !string.contains('\0') => ffi_to_c_string(&string.to_string()),
+            _ => {
+                self.fail(key, "a string without NUL characters");
+                ffi_to_c_string(&String::new())
+            }
+        }
+    }
+
+    pub(crate) fn array<'a>(&mut self, value: &'a Value, key: &str) -> &'a [Value] {
+        match value[key].as_array() {
+            Some(values) => values,
+            None => {
+                self.fail(key, "an array");
+                &[]
+            }
+        }
+    }
+
+    /// Reads a Physis value from its binary encoding, see `raw_to_json`.
+    pub(crate) fn raw<T>(&mut self, value: &Value, key: &str) -> Option<T>
+    where
+        T: BinRead,
+        for<'a> T::Args<'a>: Default,
+    {
+        let bytes = match &value[key] {
+            Value::Number(int) => int.as_u64().map(|int| int.to_le_bytes().to_vec()),
+            Value::Array(bytes) => bytes
+                .iter()
+                .map(|byte| byte.as_u64().and_then(|byte| u8::try_from(byte).ok()))
+                .collect(),
+            _ => None,
+        };
+
+        let raw = bytes.and_then(|bytes| {
+            T::read_options(&mut Cursor::new(bytes), Endian::Little, Default::default()).ok()
+        });
+        if raw.is_none() {
+            self.fail(key, "a valid value");
+        }
+
+        raw
+    }
+
+    /// Reads a Physis enum from its variant name, see `enum_to_json`. Its binary encoding is accepted too.
+    pub(crate) fn enum_value<T>(&mut self, value: &Value, key: &str) -> Option<T>
+    where
+        T: BinRead + Debug + 'static,
+        for<'a> T::Args<'a>: Default,
+    {
+        let Some(name) = value[key].as_str() else {
+            return self.raw(value, key);
+        };
+
+        let variant = variant_encoding::<T>(name).and_then(|int| {
+            T::read_options(
+                &mut Cursor::new(int.to_le_bytes()),
+                Endian::Little,
+                Default::default(),
+            )
+            .ok()
+        });
+        if variant.is_none() {
+            self.fail(key, "the name of a variant");
+        }
+
+        variant
+    }
+
+    /// Reads a Physis `Color` or `ColorIntensity`, see `color_to_json`.
+    pub(crate) fn color<T>(&mut self, value: &Value, key: &str) -> Option<T>
+    where
+        T: BinRead,
+        for<'a> T::Args<'a>: Default,
+    {
+        let color = &value[key];
+
+        let mut bytes = Vec::new();
+        for channel in ["red", "green", "blue", "alpha"] {
+            bytes.push(self.int::<u8>(color, channel));
+        }
+        if color.get("intensity").is_some() {
+            bytes.extend_from_slice(&self.f32(color, "intensity").to_le_bytes());
+        }
+
+        let color =
+            T::read_options(&mut Cursor::new(bytes), Endian::Little, Default::default()).ok();
+        if color.is_none() {
+            self.fail(key, "a color");
+        }
+
+        color
+    }
+
+    pub(crate) fn finish(self) -> Result<(), String> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use binrw::binrw;
+
+    #[binrw]
+    #[brw(repr = u16)]
+    #[derive(Debug, PartialEq)]
+    enum Shape {
+        Box = 0,
+        Sphere = 1,
+        Capsule = 0x100,
+    }
+
+    #[binrw]
+    #[derive(Debug, PartialEq)]
+    enum Entry {
+        #[brw(magic = 1u8)]
+        Count(u16),
+    }
+
+    #[binrw]
+    #[derive(Debug, PartialEq)]
+    struct ColorIntensity {
+        red: u8,
+        green: u8,
+        blue: u8,
+        alpha: u8,
+        intensity: f32,
+    }
+
+    bitflags::bitflags! {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        struct Attributes: u32 {
+            const COMPRESSED = 1;
+            const HIDDEN = 4;
+        }
+    }
+
+    #[test]
+    fn enums_round_trip_by_name() {
+        let json = json!({ "shape": enum_to_json(&Shape::Capsule) });
+        assert_eq!(json["shape"], "Capsule");
+
+        let mut reader = JsonReader::new();
+        assert_eq!(reader.enum_value(&json, "shape"), Some(Shape::Capsule));
+        assert!(reader.finish().is_ok());
+    }
+
+    #[test]
+    fn enums_accept_their_binary_encoding() {
+        let mut reader = JsonReader::new();
+        assert_eq!(
+            reader.enum_value(&json!({ "shape": 1 }), "shape"),
+            Some(Shape::Sphere)
+        );
+        assert!(reader.finish().is_ok());
+    }
+
+    #[test]
+    fn unknown_variant_names_are_rejected() {
+        let mut reader = JsonReader::new();
+        assert_eq!(
+            reader.enum_value::<Shape>(&json!({ "shape": "Cone" }), "shape"),
+            None
+        );
+        assert!(reader.finish().is_err());
+    }
+
+    #[test]
+    fn variants_with_data_use_their_binary_encoding() {
+        let json = json!({ "entry": enum_to_json(&Entry::Count(2)) });
+        assert_eq!(json["entry"], 0x0201);
+
+        let mut reader = JsonReader::new();
+        assert_eq!(reader.enum_value(&json, "entry"), Some(Entry::Count(2)));
+        assert!(reader.finish().is_ok());
+    }
+
+    #[test]
+    fn flags_are_listed_by_name() {
+        let flags = Attributes::from_bits_retain(0b1101);
+        assert_eq!(flags_to_json(&flags), json!(["COMPRESSED", "HIDDEN", 8]));
+        assert_eq!(flags_to_json(&Attributes::empty()), json!([]));
+    }
+
+    #[test]
+    fn colors_round_trip() {
+        let color = ColorIntensity {
+            red: 255,
+            green: 128,
+            blue: 0,
+            alpha: 64,
+            intensity: 1.5,
+        };
+
+        let json = json!({ "color": color_to_json(&color) });
+        assert_eq!(
+            json["color"],
+            json!({ "red": 255, "green": 128, "blue": 0, "alpha": 64, "intensity": 1.5 })
+        );
+
+        let mut reader = JsonReader::new();
+        assert_eq!(reader.color(&json, "color"), Some(color));
+        assert!(reader.finish().is_ok());
+    }
+
+    #[test]
+    fn missing_values_are_reported() {
+        let mut reader = JsonReader::new();
+        assert_eq!(reader.int::<u32>(&json!({}), "id"), 0);
+        assert!(reader.finish().is_err());
+    }
+}
@@ -1,10 +1,13 @@
 // SPDX-FileCopyrightText: 2025 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use crate::abi::struct_size;
+use crate::json::{JsonReader, ToJson, array_to_json, color_to_json, enum_to_json, string_to_json};
 use crate::{ffi_free_string, ffi_to_c_string, ffi_to_vec};
 use physis::layer::LayerEntryData::*;
 use physis::layer::*;
 use physis::{Color, ColorIntensity};
+use serde_json::{Value, json};
 use std::os::raw::c_char;
 
 #[repr(C)]
@@ -66,6 +69,7 @@ pub struct physis_GameObjectInstanceObject {
 pub struct physis_EventObjectInstanceObject {
     pub parent_data: physis_GameObjectInstanceObject,
     pub bound_instance_id: u32,
+    pub unk1: u32,
 }
 
 #[repr(C)]
@@ -383,15 +387,19 @@ pub enum physis_LayerEntry {
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct physis_InstanceObject {
+    pub struct_size: u32,
     pub instance_id: u32,
     pub name: *const c_char,
     pub transform: Transformation,
+    /// The size of `data`, since `physis_LayerEntry` can't have a `struct_size` of its own.
+    pub data_size: u32,
     pub data: physis_LayerEntry,
 }
 
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct physis_Layer {
+    pub struct_size: u32,
     pub objects: *mut physis_InstanceObject,
     pub num_objects: u32,
     pub name: *const c_char,
@@ -480,6 +488,7 @@ pub(crate) fn convert_data(data: &LayerEntryData) -> physis_LayerEntry {
         EventObject(eobj) => physis_LayerEntry::EventObject(physis_EventObjectInstanceObject {
             parent_data: convert_gameinstanceobject(&eobj.parent_data),
             bound_instance_id: eobj.bound_instance_id,
+            unk1: eobj.unk1,
         }),
         PopRange(pop) => {
             let mut c_pos = pop.positions.clone();
@@ -687,7 +696,10 @@ pub(crate) fn convert_data(data: &LayerEntryData) -> physis_LayerEntry {
             animation_variant: weapon.animation_variant,
             visible: weapon.visible,
         }),
-        _ => physis_LayerEntry::Unknown,
+        _ => {
+            log::warn!("Skipping a layer entry type that isn't supported yet: {data:?}");
+            physis_LayerEntry::Unknown
+        }
     }
 }
 
@@ -696,9 +708,11 @@ pub(crate) fn to_c_layer(layer: &Layer) -> physis_Layer {
 
     for object in &layer.objects {
         c_objects.push(physis_InstanceObject {
+            struct_size: struct_size::<physis_InstanceObject>(),
             instance_id: object.instance_id,
             name: ffi_to_c_string(&object.name.value),
             transform: object.transform,
+            data_size: struct_size::<physis_LayerEntry>(),
             data: convert_data(&object.data),
         });
     }
@@ -723,6 +737,7 @@ pub(crate) fn to_c_layer(layer: &Layer) -> physis_Layer {
     }
 
     let layer = physis_Layer {
+        struct_size: struct_size::<physis_Layer>(),
         objects: c_objects.as_mut_ptr(),
         num_objects: c_objects.len() as u32,
         name: ffi_to_c_string(&layer.header.name.value),
@@ -752,12 +767,16 @@ pub(crate) fn free_layer(layer: &physis_Layer) {
                 ffi_free_string(bg.asset_path);
                 ffi_free_string(bg.collision_asset_path);
             }
-            physis_LayerEntry::Light(_) => {}
+            physis_LayerEntry::Light(light) => {
+                ffi_free_string(light.texture_path);
+            }
             physis_LayerEntry::Vfx(vfx) => {
                 ffi_free_string(vfx.asset_path);
             }
             physis_LayerEntry::EventObject(_) => {}
-            physis_LayerEntry::PopRange(_) => {} // TODO: free relative positions
+            physis_LayerEntry::PopRange(pop) => {
+                drop(ffi_to_vec(pop.positions, pop.position_count));
+            }
             physis_LayerEntry::EventNpc(_) => {}
             physis_LayerEntry::MapRange(_) => {}
             physis_LayerEntry::SharedGroup(sgb) => {
@@ -779,12 +798,19 @@ pub(crate) fn free_layer(layer: &physis_Layer) {
             physis_LayerEntry::Sound(sound) => {
                 ffi_free_string(sound.asset_path);
             }
-            physis_LayerEntry::CollisionBox(_) => {}
+            physis_LayerEntry::CollisionBox(collision_box) => {
+                ffi_free_string(collision_box.collision_asset_path);
+            }
             physis_LayerEntry::DoorRange(_) => {}
             physis_LayerEntry::LineVFX(_) => {}
             physis_LayerEntry::Treasure(_) => {}
             physis_LayerEntry::TargetMarker(_) => {}
-            physis_LayerEntry::ClientPath(_) => {}
+            physis_LayerEntry::ClientPath(client_path) => {
+                drop(ffi_to_vec(
+                    client_path.parent_data.control_points,
+                    client_path.parent_data.control_point_count,
+                ));
+            }
             physis_LayerEntry::CullingBox(_) => {}
             physis_LayerEntry::ClickableRange(_) => {}
             physis_LayerEntry::BattleNpc(_) => {}
@@ -805,5 +831,706 @@ pub(crate) fn free_layer(layer: &physis_Layer) {
     }
     drop(data);
 
+    let list = &layer.layer_set_referenced_list;
+    drop(ffi_to_vec(list.layer_set_ids, list.layer_set_id_count));
+
+    let referenced = ffi_to_vec(
+        layer.object_set_referenced,
+        layer.object_set_referenced_count,
+    );
+    for obsb in &referenced {
+        ffi_free_string(obsb.obsb_path);
+    }
+    drop(referenced);
+
     ffi_free_string(layer.name);
 }
+
+fn game_object_to_json(object: &physis_GameObjectInstanceObject) -> Value {
+    json!({ "base_id": object.base_id })
+}
+
+fn character_to_json(character: &physis_CharacterInstanceObject) -> Value {
+    json!({ "parent_data": game_object_to_json(&character.parent_data) })
+}
+
+fn trigger_box_to_json(trigger_box: &physis_TriggerBoxInstanceObject) -> Value {
+    json!({
+        "trigger_box_shape": enum_to_json(&trigger_box.trigger_box_shape),
+        "priority": trigger_box.priority,
+        "enabled": trigger_box.enabled,
+    })
+}
+
+fn range_to_json(range: &physis_RangeInstanceObject) -> Value {
+    json!({ "shape": enum_to_json(&range.shape) })
+}
+
+impl ToJson for physis_LayerEntry {
+    fn to_json(&self) -> Value {
+        match self {
+            physis_LayerEntry::Unknown => json!({ "type": "Unknown" }),
+            physis_LayerEntry::BgPart(bg) => json!({
+                "type": "BgPart",
+                "asset_path": string_to_json(bg.asset_path),
+                "collision_asset_path": string_to_json(bg.collision_asset_path),
+                "collision_type": enum_to_json(&bg.collision_type),
+                "collision_attributes": enum_to_json(&bg.collision_attributes),
+                "visible": bg.visible,
+                "world_light_shadow_mode": enum_to_json(&bg.world_light_shadow_mode),
+                "object_light_shadow_mode": enum_to_json(&bg.object_light_shadow_mode),
+                "fade_out_distance": bg.fade_out_distance,
+                "bounding_sphere_size": bg.bounding_sphere_size,
+            }),
+            physis_LayerEntry::Light(light) => json!({
+                "type": "Light",
+                "shape": enum_to_json(&light.shape),
+                "attenuation": light.attenuation,
+                "range": light.range,
+                "attenuation_cone_coefficient": light.attenuation_cone_coefficient,
+                "spot_angle": light.spot_angle,
+                "texture_path": string_to_json(light.texture_path),
+                "color": color_to_json(&light.color),
+                "enable_specular_highlights": light.enable_specular_highlights,
+                "enable_bg_parts_shadows": light.enable_bg_parts_shadows,
+                "enable_character_shadows": light.enable_character_shadows,
+                "shadow_plane_near": light.shadow_plane_near,
+                "flat_light_skew_angle": light.flat_light_skew_angle,
+            }),
+            physis_LayerEntry::Vfx(vfx) => json!({
+                "type": "Vfx",
+                "asset_path": string_to_json(vfx.asset_path),
+                "soft_particle_fade_range": vfx.soft_particle_fade_range,
+                "color": color_to_json(&vfx.color),
+                "active": vfx.active,
+                "unk1": vfx.unk1,
+                "unk2": vfx.unk2,
+                "fade_near_start": vfx.fade_near_start,
+                "fade_near_end": vfx.fade_near_end,
+                "fade_far_start": vfx.fade_far_start,
+                "fade_far_end": vfx.fade_far_end,
+                "z_correct": vfx.z_correct,
+                "unk3": vfx.unk3,
+            }),
+            physis_LayerEntry::EventObject(eobj) => json!({
+                "type": "EventObject",
+                "parent_data": game_object_to_json(&eobj.parent_data),
+                "bound_instance_id": eobj.bound_instance_id,
+                "unk1": eobj.unk1,
+            }),
+            physis_LayerEntry::PopRange(pop) => json!({
+                "type": "PopRange",
+                "pop_type": enum_to_json(&pop.pop_type),
+                "inner_radius_ratio": pop.inner_radius_ratio,
+                "positions": array_to_json(pop.positions, pop.position_count as usize, |position| {
+                    json!(position)
+                }),
+            }),
+            physis_LayerEntry::EventNpc(enpc) => json!({
+                "type": "EventNpc",
+                "parent_data": character_to_json(&enpc.parent_data),
+            }),
+            physis_LayerEntry::MapRange(map_range) => json!({
+                "type": "MapRange",
+                "parent_data": trigger_box_to_json(&map_range.parent_data),
+                "map": map_range.map,
+                "place_name_block": map_range.place_name_block,
+                "place_name_spot": map_range.place_name_spot,
+                "weather": map_range.weather,
+                "bgm": map_range.bgm,
+                "unk1": map_range.unk1,
+                "unk2": map_range.unk2,
+                "housing_block_id": map_range.housing_block_id,
+                "rest_bonus_effective": map_range.rest_bonus_effective,
+                "discovery_id": map_range.discovery_id,
+                "map_enabled": map_range.map_enabled,
+                "place_name_enabled": map_range.place_name_enabled,
+                "discovery_enabled": map_range.discovery_enabled,
+                "bgm_enabled": map_range.bgm_enabled,
+                "weather_enabled": map_range.weather_enabled,
+                "rest_bonus_enabled": map_range.rest_bonus_enabled,
+                "bgm_play_zone_in_only": map_range.bgm_play_zone_in_only,
+                "lift_enabled": map_range.lift_enabled,
+                "housing_enabled": map_range.housing_enabled,
+                "log_flying_height_max_err": map_range.log_flying_height_max_err,
+                "unk4": map_range.unk4,
+                "mounts_and_ornaments_disabled": map_range.mounts_and_ornaments_disabled,
+                "lalafells_only": map_range.lalafells_only,
+            }),
+            physis_LayerEntry::SharedGroup(sgb) => json!({
+                "type": "SharedGroup",
+                "asset_path": string_to_json(sgb.asset_path),
+            }),
+            physis_LayerEntry::Aetheryte(aetheryte) => json!({
+                "type": "Aetheryte",
+                "parent_data": game_object_to_json(&aetheryte.parent_data),
+                "bound_instance_id": aetheryte.bound_instance_id,
+            }),
+            physis_LayerEntry::ExitRange(exit_range) => json!({
+                "type": "ExitRange",
+                "parent_data": trigger_box_to_json(&exit_range.parent_data),
+                "exit_type": enum_to_json(&exit_range.exit_type),
+                "zone_id": exit_range.zone_id,
+                "territory_type": exit_range.territory_type,
+                "index": exit_range.index,
+                "destination_instance_id": exit_range.destination_instance_id,
+                "return_instance_id": exit_range.return_instance_id,
+                "player_running_direction": exit_range.player_running_direction,
+                "unk9c": exit_range.unk9c,
+                "unk_instance_id": exit_range.unk_instance_id,
+            }),
+            physis_LayerEntry::EventRange(event_range) => json!({
+                "type": "EventRange",
+                "parent_data": trigger_box_to_json(&event_range.parent_data),
+            }),
+            physis_LayerEntry::ChairMarker(chair_marker) => json!({
+                "type": "ChairMarker",
+                "left_enable": chair_marker.left_enable,
+                "right_enable": chair_marker.right_enable,
+                "back_enable": chair_marker.back_enable,
+                "chair_type": enum_to_json(&chair_marker.chair_type),
+            }),
+            physis_LayerEntry::PrefetchRange(prefetch_range) => json!({
+                "type": "PrefetchRange",
+                "parent_data": trigger_box_to_json(&prefetch_range.parent_data),
+                "bound_instance_id": prefetch_range.bound_instance_id,
+            }),
+            physis_LayerEntry::EnvSet(env_set) => json!({
+                "type": "EnvSet",
+                "asset_path": string_to_json(env_set.asset_path),
+                "bound_instance_id": env_set.bound_instance_id,
+                "shape": enum_to_json(&env_set.shape),
+                "is_env_map_shooting_point": env_set.is_env_map_shooting_point,
+                "priority": env_set.priority,
+                "effective_range": env_set.effective_range,
+                "interpolation_time": env_set.interpolation_time,
+                "reverb": env_set.reverb,
+                "filter": env_set.filter,
+                "sound_asset_path": string_to_json(env_set.sound_asset_path),
+            }),
+            physis_LayerEntry::EnvLocation(env_location) => json!({
+                "type": "EnvLocation",
+                "ambient_light_asset_path": string_to_json(env_location.ambient_light_asset_path),
+                "env_map_asset_path": string_to_json(env_location.env_map_asset_path),
+            }),
+            physis_LayerEntry::Sound(sound) => json!({
+                "type": "Sound",
+                "asset_path": string_to_json(sound.asset_path),
+            }),
+            physis_LayerEntry::CollisionBox(collision_box) => json!({
+                "type": "CollisionBox",
+                "parent_data": trigger_box_to_json(&collision_box.parent_data),
+                "collision_attributes": enum_to_json(&collision_box.collision_attributes),
+                "layer_mask_is_43h": collision_box.layer_mask_is_43h,
+                "collision_asset_path": string_to_json(collision_box.collision_asset_path),
+            }),
+            physis_LayerEntry::DoorRange(door_range) => json!({
+                "type": "DoorRange",
+                "parent_data": range_to_json(&door_range.parent_data),
+            }),
+            physis_LayerEntry::LineVFX(line_vfx) => json!({
+                "type": "LineVFX",
+                "line_style": enum_to_json(&line_vfx.line_style),
+            }),
+            physis_LayerEntry::Treasure(treasure) => json!({
+                "type": "Treasure",
+                "parent_data": game_object_to_json(&treasure.parent_data),
+            }),
+            physis_LayerEntry::TargetMarker(target_marker) => json!({
+                "type": "TargetMarker",
+                "target_market_type": enum_to_json(&target_marker.target_market_type),
+            }),
+            physis_LayerEntry::ClientPath(client_path) => json!({
+                "type": "ClientPath",
+                "parent_data": {
+                    "control_points": array_to_json(
+                        client_path.parent_data.control_points,
+                        client_path.parent_data.control_point_count as usize,
+                        |point| {
+                            json!({
+                                "position": point.position,
+                                "point_id": point.point_id,
+                                "select": point.select,
+                            })
+                        }
+                    ),
+                },
+                "unk1": client_path.unk1,
+                "unk2": client_path.unk2,
+                "unk3": client_path.unk3,
+            }),
+            physis_LayerEntry::CullingBox(_) => json!({ "type": "CullingBox" }),
+            physis_LayerEntry::ClickableRange(clickable_range) => json!({
+                "type": "ClickableRange",
+                "parent_data": range_to_json(&clickable_range.parent_data),
+            }),
+            physis_LayerEntry::BattleNpc(bnpc) => json!({
+                "type": "BattleNpc",
+                "parent_data": character_to_json(&bnpc.parent_data),
+                "name_id": bnpc.name_id,
+            }),
+            physis_LayerEntry::Decal(decal) => json!({
+                "type": "Decal",
+                "asset_path": string_to_json(decal.asset_path),
+            }),
+            physis_LayerEntry::VolumetricCloud(cloud) => json!({
+                "type": "VolumetricCloud",
+                "asset_path": string_to_json(cloud.asset_path),
+                "color": color_to_json(&cloud.color),
+                "active": cloud.active,
+            }),
+            physis_LayerEntry::ShowHideRange(collider) => json!({
+                "type": "ShowHideRange",
+                "parent_data": trigger_box_to_json(&collider.parent_data),
+            }),
+            physis_LayerEntry::EventEffectRange(collider) => json!({
+                "type": "EventEffectRange",
+                "parent_data": trigger_box_to_json(&collider.parent_data),
+            }),
+            physis_LayerEntry::WaterRange(collider) => json!({
+                "type": "WaterRange",
+                "parent_data": trigger_box_to_json(&collider.parent_data),
+                "enabled": collider.enabled,
+                "unk2": collider.unk2,
+            }),
+            physis_LayerEntry::GameContentsRange(collider) => json!({
+                "type": "GameContentsRange",
+                "parent_data": trigger_box_to_json(&collider.parent_data),
+            }),
+            physis_LayerEntry::FateRange(range) => json!({
+                "type": "FateRange",
+                "parent_data": range_to_json(&range.parent_data),
+                "fate_layout_label_id": range.fate_layout_label_id,
+            }),
+            physis_LayerEntry::SphereCastRange() => json!({ "type": "SphereCastRange" }),
+            physis_LayerEntry::Weapon(weapon) => json!({
+                "type": "Weapon",
+                "weapon_id": weapon.weapon_id,
+                "animation_variant": weapon.animation_variant,
+                "visible": weapon.visible,
+            }),
+        }
+    }
+}
+
+impl ToJson for physis_InstanceObject {
+    fn to_json(&self) -> Value {
+        json!({
+            "instance_id": self.instance_id,
+            "name": string_to_json(self.name),
+            "transform": {
+                "translation": self.transform.translation,
+                "rotation": self.transform.rotation,
+                "scale": self.transform.scale,
+            },
+            "data": self.data.to_json(),
+        })
+    }
+}
+
+impl ToJson for physis_Layer {
+    fn to_json(&self) -> Value {
+        let list = &self.layer_set_referenced_list;
+
+        json!({
+            "objects": array_to_json(self.objects, self.num_objects as usize, ToJson::to_json),
+            "name": string_to_json(self.name),
+            "id": self.id,
+            "festival_id": self.festival_id,
+            "festival_phase_id": self.festival_phase_id,
+            "layer_set_referenced_list": {
+                "referenced_type": enum_to_json(&list.referenced_type),
+                "layer_set_ids": array_to_json(list.layer_set_ids, list.layer_set_id_count as usize, |id| {
+                    json!(id)
+                }),
+            },
+            "visible": self.visible,
+            "object_set_referenced": array_to_json(
+                self.object_set_referenced,
+                self.object_set_referenced_count as usize,
+                |referenced| {
+                    json!({
+                        "asset_type": enum_to_json(&referenced.asset_type),
+                        "instance_id": referenced.instance_id,
+                        "obsb_path": string_to_json(referenced.obsb_path),
+                    })
+                }
+            ),
+        })
+    }
+}
+
+fn game_object_from_json(
+    reader: &mut JsonReader,
+    value: &Value,
+) -> physis_GameObjectInstanceObject {
+    physis_GameObjectInstanceObject {
+        base_id: reader.int(value, "base_id"),
+    }
+}
+
+fn character_from_json(reader: &mut JsonReader, value: &Value) -> physis_CharacterInstanceObject {
+    physis_CharacterInstanceObject {
+        parent_data: game_object_from_json(reader, &value["parent_data"]),
+    }
+}
+
+fn trigger_box_from_json(
+    reader: &mut JsonReader,
+    value: &Value,
+) -> Option<physis_TriggerBoxInstanceObject> {
+    Some(physis_TriggerBoxInstanceObject {
+        trigger_box_shape: reader.enum_value(value, "trigger_box_shape")?,
+        priority: reader.int(value, "priority"),
+        enabled: reader.bool(value, "enabled"),
+    })
+}
+
+fn range_from_json(reader: &mut JsonReader, value: &Value) -> Option<physis_RangeInstanceObject> {
+    Some(physis_RangeInstanceObject {
+        shape: reader.enum_value(value, "shape")?,
+    })
+}
+
+/// Every fallible value is read before any string is allocated, so returning None early doesn't leak anything.
+fn entry_from_json(reader: &mut JsonReader, value: &Value) -> Option<physis_LayerEntry> {
+    let parent_data = &value["parent_data"];
+
+    Some(match value["type"].as_str().unwrap_or_default() {
+        "Unknown" => physis_LayerEntry::Unknown,
+        "BgPart" => {
+            let collision_type = reader.enum_value(value, "collision_type")?;
+            let collision_attributes = reader.enum_value(value, "collision_attributes")?;
+            let world_light_shadow_mode = reader.enum_value(value, "world_light_shadow_mode")?;
+            let object_light_shadow_mode = reader.enum_value(value, "object_light_shadow_mode")?;
+
+            physis_LayerEntry::BgPart(physis_BgPartInstanceObject {
+                asset_path: reader.string(value, "asset_path"),
+                collision_asset_path: reader.string(value, "collision_asset_path"),
+                collision_type,
+                collision_attributes,
+                visible: reader.bool(value, "visible"),
+                world_light_shadow_mode,
+                object_light_shadow_mode,
+                fade_out_distance: reader.f32(value, "fade_out_distance"),
+                bounding_sphere_size: reader.f32(value, "bounding_sphere_size"),
+            })
+        }
+        "Light" => {
+            let shape = reader.enum_value(value, "shape")?;
+            let color = reader.color(value, "color")?;
+
+            physis_LayerEntry::Light(physis_LightInstanceObject {
+                shape,
+                attenuation: reader.f32(value, "attenuation"),
+                range: reader.f32(value, "range"),
+                attenuation_cone_coefficient: reader.f32(value, "attenuation_cone_coefficient"),
+                spot_angle: reader.f32(value, "spot_angle"),
+                texture_path: reader.string(value, "texture_path"),
+                color,
+                enable_specular_highlights: reader.bool(value, "enable_specular_highlights"),
+                enable_bg_parts_shadows: reader.bool(value, "enable_bg_parts_shadows"),
+                enable_character_shadows: reader.bool(value, "enable_character_shadows"),
+                shadow_plane_near: reader.f32(value, "shadow_plane_near"),
+                flat_light_skew_angle: reader.f32_array(value, "flat_light_skew_angle"),
+            })
+        }
+        "Vfx" => {
+            let color = reader.color(value, "color")?;
+
+            physis_LayerEntry::Vfx(physis_VfxInstanceObject {
+                asset_path: reader.string(value, "asset_path"),
+                soft_particle_fade_range: reader.f32(value, "soft_particle_fade_range"),
+                color,
+                active: reader.bool(value, "active"),
+                unk1: reader.bool(value, "unk1"),
+                unk2: reader.bool(value, "unk2"),
+                fade_near_start: reader.f32(value, "fade_near_start"),
+                fade_near_end: reader.f32(value, "fade_near_end"),
+                fade_far_start: reader.f32(value, "fade_far_start"),
+                fade_far_end: reader.f32(value, "fade_far_end"),
+                z_correct: reader.f32(value, "z_correct"),
+                unk3: reader.f32(value, "unk3"),
+            })
+        }
+        "EventObject" => physis_LayerEntry::EventObject(physis_EventObjectInstanceObject {
+            parent_data: game_object_from_json(reader, parent_data),
+            bound_instance_id: reader.int(value, "bound_instance_id"),
+            unk1: reader.int(value, "unk1"),
+        }),
+        "PopRange" => {
+            let pop_type = reader.enum_value(value, "pop_type")?;
+
+            let mut positions = Vec::new();
+            for position in reader.array(value, "positions") {
+                positions.push(reader.f32_array_item(position, "positions"));
+            }
+
+            let pop = physis_LayerEntry::PopRange(physis_PopRangeInstanceObject {
+                pop_type,
+                inner_radius_ratio: reader.f32(value, "inner_radius_ratio"),
+                position_count: positions.len() as u32,
+                positions: positions.as_mut_ptr(),
+            });
+
+            std::mem::forget(positions);
+
+            pop
+        }
+        "EventNpc" => physis_LayerEntry::EventNpc(physis_EventNpcInstanceObject {
+            parent_data: character_from_json(reader, parent_data),
+        }),
+        "MapRange" => physis_LayerEntry::MapRange(physis_MapRangeInstanceObject {
+            parent_data: trigger_box_from_json(reader, parent_data)?,
+            map: reader.int(value, "map"),
+            place_name_block: reader.int(value, "place_name_block"),
+            place_name_spot: reader.int(value, "place_name_spot"),
+            weather: reader.int(value, "weather"),
+            bgm: reader.int(value, "bgm"),
+            unk1: reader.int(value, "unk1"),
+            unk2: reader.int(value, "unk2"),
+            housing_block_id: reader.int(value, "housing_block_id"),
+            rest_bonus_effective: reader.bool(value, "rest_bonus_effective"),
+            discovery_id: reader.int(value, "discovery_id"),
+            map_enabled: reader.bool(value, "map_enabled"),
+            place_name_enabled: reader.bool(value, "place_name_enabled"),
+            discovery_enabled: reader.bool(value, "discovery_enabled"),
+            bgm_enabled: reader.bool(value, "bgm_enabled"),
+            weather_enabled: reader.bool(value, "weather_enabled"),
+            rest_bonus_enabled: reader.bool(value, "rest_bonus_enabled"),
+            bgm_play_zone_in_only: reader.bool(value, "bgm_play_zone_in_only"),
+            lift_enabled: reader.bool(value, "lift_enabled"),
+            housing_enabled: reader.bool(value, "housing_enabled"),
+            log_flying_height_max_err: reader.bool(value, "log_flying_height_max_err"),
+            unk4: reader.bool(value, "unk4"),
+            mounts_and_ornaments_disabled: reader.bool(value, "mounts_and_ornaments_disabled"),
+            lalafells_only: reader.bool(value, "lalafells_only"),
+        }),
+        "SharedGroup" => physis_LayerEntry::SharedGroup(physis_SharedGroupInstanceObject {
+            asset_path: reader.string(value, "asset_path"),
+        }),
+        "Aetheryte" => physis_LayerEntry::Aetheryte(physis_AetheryteInstanceObject {
+            parent_data: game_object_from_json(reader, parent_data),
+            bound_instance_id: reader.int(value, "bound_instance_id"),
+            unk1: reader.int(value, "unk1"),
+        }),
+        "ExitRange" => physis_LayerEntry::ExitRange(physis_ExitRangeInstanceObject {
+            parent_data: trigger_box_from_json(reader, parent_data)?,
+            exit_type: reader.enum_value(value, "exit_type")?,
+            zone_id: reader.int(value, "zone_id"),
+            territory_type: reader.int(value, "territory_type"),
+            index: reader.int(value, "index"),
+            destination_instance_id: reader.int(value, "destination_instance_id"),
+            return_instance_id: reader.int(value, "return_instance_id"),
+            player_running_direction: reader.f32(value, "player_running_direction"),
+            unk9c: reader.int(value, "unk9c"),
+            unk_instance_id: reader.int(value, "unk_instance_id"),
+        }),
+        "EventRange" => physis_LayerEntry::EventRange(physis_EventRangeInstanceObject {
+            parent_data: trigger_box_from_json(reader, parent_data)?,
+        }),
+        "ChairMarker" => physis_LayerEntry::ChairMarker(physis_ChairMarkerInstanceObject {
+            left_enable: reader.bool(value, "left_enable"),
+            right_enable: reader.bool(value, "right_enable"),
+            back_enable: reader.bool(value, "back_enable"),
+            chair_type: reader.enum_value(value, "chair_type")?,
+        }),
+        "PrefetchRange" => physis_LayerEntry::PrefetchRange(physis_PrefetchRangeInstanceObject {
+            parent_data: trigger_box_from_json(reader, parent_data)?,
+            bound_instance_id: reader.int(value, "bound_instance_id"),
+        }),
+        "EnvSet" => {
+            let shape = reader.enum_value(value, "shape")?;
+
+            physis_LayerEntry::EnvSet(physis_EnvSetInstanceObject {
+                asset_path: reader.string(value, "asset_path"),
+                bound_instance_id: reader.int(value, "bound_instance_id"),
+                shape,
+                is_env_map_shooting_point: reader.bool(value, "is_env_map_shooting_point"),
+                priority: reader.int(value, "priority"),
+                effective_range: reader.f32(value, "effective_range"),
+                interpolation_time: reader.int(value, "interpolation_time"),
+                reverb: reader.f32(value, "reverb"),
+                filter: reader.f32(value, "filter"),
+                sound_asset_path: reader.string(value, "sound_asset_path"),
+            })
+        }
+        "EnvLocation" => physis_LayerEntry::EnvLocation(physis_EnvLocationObject {
+            ambient_light_asset_path: reader.string(value, "ambient_light_asset_path"),
+            env_map_asset_path: reader.string(value, "env_map_asset_path"),
+        }),
+        "Sound" => physis_LayerEntry::Sound(physis_SoundInstanceObject {
+            asset_path: reader.string(value, "asset_path"),
+        }),
+        "CollisionBox" => {
+            let trigger_box = trigger_box_from_json(reader, parent_data)?;
+            let collision_attributes = reader.enum_value(value, "collision_attributes")?;
+
+            physis_LayerEntry::CollisionBox(physis_CollisionBoxInstanceObject {
+                parent_data: trigger_box,
+                collision_attributes,
+                layer_mask_is_43h: reader.bool(value, "layer_mask_is_43h"),
+                collision_asset_path: reader.string(value, "collision_asset_path"),
+            })
+        }
+        "DoorRange" => physis_LayerEntry::DoorRange(physis_DoorRangeInstanceObject {
+            parent_data: range_from_json(reader, parent_data)?,
+        }),
+        "LineVFX" => physis_LayerEntry::LineVFX(physis_LineVFXInstanceObject {
+            line_style: reader.enum_value(value, "line_style")?,
+        }),
+        "Treasure" => physis_LayerEntry::Treasure(physis_TreasureInstanceObject {
+            parent_data: game_object_from_json(reader, parent_data),
+        }),
+        "TargetMarker" => physis_LayerEntry::TargetMarker(physis_TargetMarkerInstanceObject {
+            target_market_type: reader.enum_value(value, "target_market_type")?,
+        }),
+        "ClientPath" => {
+            let mut points = Vec::new();
+            for point in reader.array(parent_data, "control_points") {
+                points.push(physis_PathControlPoint {
+                    position: reader.f32_array(point, "position"),
+                    point_id: reader.int(point, "point_id"),
+                    select: reader.bool(point, "select"),
+                });
+            }
+
+            let client_path = physis_LayerEntry::ClientPath(physis_ClientPathInstanceObject {
+                parent_data: physis_PathInstanceObject {
+                    control_point_count: points.len() as u32,
+                    control_points: points.as_mut_ptr(),
+                },
+                unk1: reader.bool(value, "unk1"),
+                unk2: reader.bool(value, "unk2"),
+                unk3: reader.bool(value, "unk3"),
+            });
+
+            std::mem::forget(points);
+
+            client_path
+        }
+        "CullingBox" => physis_LayerEntry::CullingBox(physis_CullingBoxInstanceObject {}),
+        "ClickableRange" => {
+            physis_LayerEntry::ClickableRange(physis_ClickableRangeInstanceObject {
+                parent_data: range_from_json(reader, parent_data)?,
+            })
+        }
+        "BattleNpc" => physis_LayerEntry::BattleNpc(physis_BattleNpcInstanceObject {
+            parent_data: character_from_json(reader, parent_data),
+            name_id: reader.int(value, "name_id"),
+        }),
+        "Decal" => physis_LayerEntry::Decal(physis_DecalInstanceObject {
+            asset_path: reader.string(value, "asset_path"),
+        }),
+        "VolumetricCloud" => {
+            let color = reader.color(value, "color")?;
+
+            physis_LayerEntry::VolumetricCloud(physis_VolumetricCloudInstanceObject {
+                asset_path: reader.string(value, "asset_path"),
+                color,
+                active: reader.bool(value, "active"),
+            })
+        }
+        "ShowHideRange" => physis_LayerEntry::ShowHideRange(physis_ShowHideRangeInstanceObject {
+            parent_data: trigger_box_from_json(reader, parent_data)?,
+        }),
+        "EventEffectRange" => {
+            physis_LayerEntry::EventEffectRange(physis_EventEffectRangeInstanceObject {
+                parent_data: trigger_box_from_json(reader, parent_data)?,
+            })
+        }
+        "WaterRange" => physis_LayerEntry::WaterRange(physis_WaterRangeInstanceObject {
+            parent_data: trigger_box_from_json(reader, parent_data)?,
+            enabled: reader.bool(value, "enabled"),
+            unk2: reader.bool(value, "unk2"),
+        }),
+        "GameContentsRange" => {
+            physis_LayerEntry::GameContentsRange(physis_GameContentsRangeInstanceObject {
+                parent_data: trigger_box_from_json(reader, parent_data)?,
+            })
+        }
+        "FateRange" => physis_LayerEntry::FateRange(physis_FateRangeInstanceObject {
+            parent_data: range_from_json(reader, parent_data)?,
+            fate_layout_label_id: reader.int(value, "fate_layout_label_id"),
+        }),
+        "SphereCastRange" => physis_LayerEntry::SphereCastRange(),
+        "Weapon" => physis_LayerEntry::Weapon(physis_WeaponInstanceObject {
+            weapon_id: reader.int(value, "weapon_id"),
+            animation_variant: reader.int(value, "animation_variant"),
+            visible: reader.bool(value, "visible"),
+        }),
+        _ => {
+            reader.fail("type", "a layer entry type");
+            return None;
+        }
+    })
+}
+
+fn object_from_json(reader: &mut JsonReader, value: &Value) -> physis_InstanceObject {
+    let transform = &value["transform"];
+
+    physis_InstanceObject {
+        struct_size: struct_size::<physis_InstanceObject>(),
+        instance_id: reader.int(value, "instance_id"),
+        name: reader.string(value, "name"),
+        transform: Transformation {
+            translation: reader.f32_array(transform, "translation"),
+            rotation: reader.f32_array(transform, "rotation"),
+            scale: reader.f32_array(transform, "scale"),
+        },
+        data_size: struct_size::<physis_LayerEntry>(),
+        data: entry_from_json(reader, &value["data"]).unwrap_or(physis_LayerEntry::Unknown),
+    }
+}
+
+/// Creates a layer from JSON in the format of `physis_Layer`'s `ToJson`. Returns None without allocating anything if the
+/// layer can't be represented at all, otherwise see `JsonReader` for how errors are handled.
+pub(crate) fn layer_from_json(reader: &mut JsonReader, value: &Value) -> Option<physis_Layer> {
+    let list = &value["layer_set_referenced_list"];
+    let referenced_type = reader.enum_value(list, "referenced_type")?;
+
+    let mut objects = Vec::new();
+    for object in reader.array(value, "objects") {
+        objects.push(object_from_json(reader, object));
+    }
+
+    let mut layer_set_ids = Vec::new();
+    for id in reader.array(list, "layer_set_ids") {
+        layer_set_ids.push(reader.int_item(id, "layer_set_ids"));
+    }
+
+    let mut object_set_referenced = Vec::new();
+    for referenced in reader.array(value, "object_set_referenced") {
+        if let Some(asset_type) = reader.enum_value(referenced, "asset_type") {
+            object_set_referenced.push(physis_ObjectSetReferenced {
+                asset_type,
+                instance_id: reader.int(referenced, "instance_id"),
+                obsb_path: reader.string(referenced, "obsb_path"),
+            });
+        }
+    }
+
+    let layer = physis_Layer {
+        struct_size: struct_size::<physis_Layer>(),
+        objects: objects.as_mut_ptr(),
+        num_objects: objects.len() as u32,
+        name: reader.string(value, "name"),
+        id: reader.int(value, "id"),
+        festival_id: reader.int(value, "festival_id"),
+        festival_phase_id: reader.int(value, "festival_phase_id"),
+        layer_set_referenced_list: physis_LayerSetReferencedList {
+            referenced_type,
+            layer_set_id_count: layer_set_ids.len() as u32,
+            layer_set_ids: layer_set_ids.as_mut_ptr(),
+        },
+        visible: reader.bool(value, "visible"),
+        object_set_referenced_count: object_set_referenced.len() as u32,
+        object_set_referenced: object_set_referenced.as_mut_ptr(),
+    };
+
+    std::mem::forget(objects);
+    std::mem::forget(layer_set_ids);
+    std::mem::forget(object_set_referenced);
+
+    Some(layer)
+}
@@ -1,12 +1,16 @@
 // SPDX-FileCopyrightText: 2026 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::{ffi_to_c_string, physis_Buffer};
+use crate::error::{ffi_result, set_physis_error};
+use crate::json::{ToJson, array_to_json, ffi_parsed_to_json, raw_to_json};
+use crate::panic::ffi_guard;
+use crate::{ffi_to_c_string, ffi_to_vec, physis_Buffer};
 use physis::Platform;
 use physis::ReadableFile;
 use physis::lcb::{Lcb, LccEntry};
+use serde_json::{Value, json};
 use std::ffi::c_char;
-use std::ptr::null_mut;
+use std::ptr::{null, null_mut};
 use std::{mem, slice};
 
 #[repr(C)]
@@ -34,34 +38,51 @@ impl Default for physis_Lcb {
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_lcb_parse(platform: Platform, buffer: physis_Buffer) -> physis_Lcb {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(physis_Lcb::default(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-    if let Ok(lcb) = Lcb::from_existing(platform, data) {
-        let mut c_lccs = Vec::new();
-        for lcc in &lcb.lccs {
-            let mut c_entries = lcc.entries.clone();
+        if let Some(lcb) = ffi_result(Lcb::from_existing(platform, data)) {
+            let mut c_lccs = Vec::new();
+            for lcc in &lcb.lccs {
+                let mut c_entries = lcc.entries.clone();
 
-            let c_lcc = physis_Lcc {
-                num_entries: c_entries.len() as u32,
-                entries: c_entries.as_mut_ptr(),
+                let c_lcc = physis_Lcc {
+                    num_entries: c_entries.len() as u32,
+                    entries: c_entries.as_mut_ptr(),
+                };
+
+                mem::forget(c_entries);
+
+                c_lccs.push(c_lcc);
+            }
+
+            let c_lcb = physis_Lcb {
+                lcc_count: c_lccs.len() as u32,
+                lccs: c_lccs.as_mut_ptr(),
             };
 
-            mem::forget(c_entries);
+            mem::forget(c_lccs);
 
-            c_lccs.push(c_lcc);
+            c_lcb
+        } else {
+            physis_Lcb::default()
         }
+    })
+}
 
-        let c_lcb = physis_Lcb {
-            lcc_count: c_lccs.len() as u32,
-            lccs: c_lccs.as_mut_ptr(),
-        };
-
-        mem::forget(c_lccs);
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_lcb_free(lcb: &physis_Lcb) {
+    ffi_guard((), || {
+        if lcb.lccs.is_null() {
+            return;
+        }
 
-        c_lcb
-    } else {
-        physis_Lcb::default()
-    }
+        let lccs = ffi_to_vec(lcb.lccs, lcb.lcc_count);
+        for lcc in &lccs {
+            drop(ffi_to_vec(lcc.entries, lcc.num_entries));
+        }
+        drop(lccs);
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -69,10 +90,34 @@ pub unsafe extern "C" fn physis_lcb_debug(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> *const c_char {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(null(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+
+        match Lcb::from_existing(platform, data) {
+            Ok(lcb) => ffi_to_c_string(&format!("{lcb:#?}")),
+            Err(err) => {
+                set_physis_error(&err);
+                ffi_to_c_string(&format!("{err:#?}"))
+            }
+        }
+    })
+}
 
-    match Lcb::from_existing(platform, data) {
-        Ok(lcb) => ffi_to_c_string(&format!("{lcb:#?}")),
-        Err(err) => ffi_to_c_string(&format!("{err:#?}")),
+impl ToJson for physis_Lcb {
+    fn to_json(&self) -> Value {
+        json!({
+            "lccs": array_to_json(self.lccs, self.lcc_count as usize, |lcc| {
+                json!({
+                    "entries": array_to_json(lcc.entries, lcc.num_entries as usize, raw_to_json),
+                })
+            }),
+        })
     }
 }
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_lcb_to_json(platform: Platform, buffer: physis_Buffer) -> *const c_char {
+    ffi_guard(null(), || {
+        ffi_parsed_to_json(physis_lcb_parse(platform, buffer), physis_lcb_free)
+    })
+}
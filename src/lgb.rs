@@ -1,11 +1,18 @@
 // SPDX-FileCopyrightText: 2026 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use crate::abi::struct_size;
+use crate::error::{ffi_result, physis_ErrorCode, set_last_error, set_physis_error};
+use crate::job::{physis_JobId, physis_JobStatus, spawn_job};
+use crate::json::{JsonReader, ToJson, array_to_json, ffi_parsed_to_json, string_to_json};
 use crate::layer::{
-    free_layer, physis_GameObjectInstanceObject, physis_InstanceObject, physis_Layer,
-    physis_LayerEntry, to_c_layer,
+    free_layer, layer_from_json, physis_GameObjectInstanceObject, physis_InstanceObject,
+    physis_Layer, physis_LayerEntry, to_c_layer,
+};
+use crate::panic::ffi_guard;
+use crate::{
+    ffi_free_string, ffi_from_c_string, ffi_to_buffer, ffi_to_c_string, ffi_to_vec, physis_Buffer,
 };
-use crate::{ffi_from_c_string, ffi_to_c_string, ffi_to_vec, physis_Buffer};
 use physis::ReadableFile;
 use physis::layer::{
     BgPartInstanceObject, EventObjectInstanceObject, GameObjectInstanceObject, InstanceObject,
@@ -14,8 +21,9 @@ use physis::layer::{
 };
 use physis::lgb::{LayerChunk, Lgb};
 use physis::{Platform, WritableFile};
-use std::ffi::c_char;
-use std::ptr::null_mut;
+use serde_json::{Value, json};
+use std::ffi::{c_char, c_void};
+use std::ptr::{null, null_mut};
 use std::{mem, slice};
 
 #[repr(C)]
@@ -30,6 +38,7 @@ pub struct physis_LayerChunk {
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct physis_LayerGroup {
+    struct_size: u32,
     chunks: *mut physis_LayerChunk,
     num_chunks: u32,
 }
@@ -37,6 +46,7 @@ pub struct physis_LayerGroup {
 impl Default for physis_LayerGroup {
     fn default() -> Self {
         Self {
+            struct_size: struct_size::<physis_LayerGroup>(),
             chunks: null_mut(),
             num_chunks: 0,
         }
@@ -45,21 +55,146 @@ impl Default for physis_LayerGroup {
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_lgb_parse(platform: Platform, buffer: physis_Buffer) -> physis_LayerGroup {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(physis_LayerGroup::default(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-    if let Ok(lgb) = Lgb::from_existing(platform, data) {
-        let mut c_chunks = vec![];
+        if let Some(lgb) = ffi_result(Lgb::from_existing(platform, data)) {
+            let mut c_chunks = vec![];
 
-        for chunk in &lgb.chunks {
-            let mut c_layers = vec![];
+            for chunk in &lgb.chunks {
+                let mut c_layers = vec![];
+
+                for layer in &chunk.layers {
+                    c_layers.push(to_c_layer(layer));
+                }
 
-            for layer in &chunk.layers {
-                c_layers.push(to_c_layer(layer));
+                c_chunks.push(physis_LayerChunk {
+                    layer_group_id: chunk.layer_group_id,
+                    name: ffi_to_c_string(&chunk.name),
+                    layers: c_layers.as_mut_ptr(),
+                    num_layers: c_layers.len() as u32,
+                });
+
+                std::mem::forget(c_layers);
+            }
+
+            let lgb = physis_LayerGroup {
+                struct_size: struct_size::<physis_LayerGroup>(),
+                chunks: c_chunks.as_mut_ptr(),
+                num_chunks: c_chunks.len() as u32,
+            };
+
+            std::mem::forget(c_chunks);
+
+            lgb
+        } else {
+            physis_LayerGroup::default()
+        }
+    })
+}
+
+/// Called when an asynchronous LGB parse finishes. The result must be freed with `physis_lgb_free`.
+pub type physis_LayerGroupCallback = extern "C" fn(
+    user_data: *mut c_void,
+    job: physis_JobId,
+    status: physis_JobStatus,
+    layer_group: physis_LayerGroup,
+);
+
+/// Same as `physis_lgb_parse`, but parses on a worker thread and then calls `callback` from there. `buffer` must stay valid
+/// until then. Returns the job id, or 0 if it couldn't be queued.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_lgb_parse_async(
+    platform: Platform,
+    buffer: physis_Buffer,
+    callback: physis_LayerGroupCallback,
+    user_data: *mut c_void,
+) -> physis_JobId {
+    ffi_guard(0, || {
+        spawn_job(
+            move || physis_lgb_parse(platform, buffer),
+            move |job, status, layer_group| callback(user_data, job, status, layer_group),
+        )
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn physis_lgb_debug(
+    platform: Platform,
+    buffer: physis_Buffer,
+) -> *const c_char {
+    ffi_guard(null(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+
+        match Lgb::from_existing(platform, data) {
+            Ok(lgb) => ffi_to_c_string(&format!("{lgb:#?}")),
+            Err(err) => {
+                set_physis_error(&err);
+                ffi_to_c_string(&format!("{err:#?}"))
+            }
+        }
+    })
+}
+
+impl ToJson for physis_LayerGroup {
+    fn to_json(&self) -> Value {
+        json!({
+            "chunks": array_to_json(self.chunks, self.num_chunks as usize, |chunk| {
+                json!({
+                    "layer_group_id": chunk.layer_group_id,
+                    "name": string_to_json(chunk.name),
+                    "layers": array_to_json(
+                        chunk.layers,
+                        chunk.num_layers as usize,
+                        ToJson::to_json
+                    ),
+                })
+            }),
+        })
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_lgb_to_json(platform: Platform, buffer: physis_Buffer) -> *const c_char {
+    ffi_guard(null(), || {
+        ffi_parsed_to_json(physis_lgb_parse(platform, buffer), physis_lgb_free)
+    })
+}
+
+/// Creates a layer group from JSON in the format returned by `physis_lgb_to_json`, to be written with
+/// `physis_lgb_write_to_buffer`. Returns an empty layer group if `json` is invalid.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_lgb_from_json(json: *const c_char) -> physis_LayerGroup {
+    ffi_guard(physis_LayerGroup::default(), || {
+        let Some(json) = ffi_from_c_string(json) else {
+            return physis_LayerGroup::default();
+        };
+
+        let value: Value = match serde_json::from_str(&json) {
+            Ok(value) => value,
+            Err(err) => {
+                set_last_error(
+                    physis_ErrorCode::InvalidArgument,
+                    format!("Invalid JSON: {err}"),
+                );
+                return physis_LayerGroup::default();
+            }
+        };
+
+        let mut reader = JsonReader::new();
+
+        let mut c_chunks = vec![];
+        for chunk in reader.array(&value, "chunks") {
+            let mut c_layers = vec![];
+            for layer in reader.array(chunk, "layers") {
+                if let Some(layer) = layer_from_json(&mut reader, layer) {
+                    c_layers.push(layer);
+                }
             }
 
             c_chunks.push(physis_LayerChunk {
-                layer_group_id: chunk.layer_group_id,
-                name: ffi_to_c_string(&chunk.name),
+                layer_group_id: reader.int(chunk, "layer_group_id"),
+                name: reader.string(chunk, "name"),
                 layers: c_layers.as_mut_ptr(),
                 num_layers: c_layers.len() as u32,
             });
@@ -68,46 +203,45 @@ pub extern "C" fn physis_lgb_parse(platform: Platform, buffer: physis_Buffer) ->
         }
 
         let lgb = physis_LayerGroup {
+            struct_size: struct_size::<physis_LayerGroup>(),
             chunks: c_chunks.as_mut_ptr(),
             num_chunks: c_chunks.len() as u32,
         };
 
         std::mem::forget(c_chunks);
 
-        lgb
-    } else {
-        physis_LayerGroup::default()
-    }
-}
-
-#[unsafe(no_mangle)]
-pub unsafe extern "C" fn physis_lgb_debug(
-    platform: Platform,
-    buffer: physis_Buffer,
-) -> *const c_char {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+        if let Err(err) = reader.finish() {
+            physis_lgb_free(&lgb);
+            set_last_error(
+                physis_ErrorCode::InvalidArgument,
+                format!("Invalid layer group: {err}"),
+            );
+            return physis_LayerGroup::default();
+        }
 
-    match Lgb::from_existing(platform, data) {
-        Ok(lgb) => ffi_to_c_string(&format!("{lgb:#?}")),
-        Err(err) => ffi_to_c_string(&format!("{err:#?}")),
-    }
+        lgb
+    })
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_lgb_free(lgb: &physis_LayerGroup) {
-    if lgb.chunks.is_null() {
-        return;
-    }
+    ffi_guard((), || {
+        if lgb.chunks.is_null() {
+            return;
+        }
 
-    let data = ffi_to_vec(lgb.chunks, lgb.num_chunks);
-    for chunk in &data {
-        let data = ffi_to_vec(chunk.layers, chunk.num_layers);
-        for layer in &data {
-            free_layer(layer);
+        let data = ffi_to_vec(lgb.chunks, lgb.num_chunks);
+        for chunk in &data {
+            ffi_free_string(chunk.name);
+
+            let data = ffi_to_vec(chunk.layers, chunk.num_layers);
+            for layer in &data {
+                free_layer(layer);
+            }
+            drop(data);
         }
         drop(data);
-    }
-    drop(data);
+    })
 }
 
 fn to_rust_parent_data_game(
@@ -118,12 +252,17 @@ fn to_rust_parent_data_game(
     }
 }
 
-fn to_rust_object(object: &physis_InstanceObject) -> InstanceObject {
+/// Reads a string field of a C struct, or explains which one is invalid.
+fn to_rust_string(ptr: *const c_char, field: &str) -> Result<String, String> {
+    ffi_from_c_string(ptr).ok_or_else(|| format!("{field} is NULL or isn't valid UTF-8"))
+}
+
+/// Converts a C instance object back, or returns an error if it's invalid or its type can't be written yet.
+fn to_rust_object(object: &physis_InstanceObject) -> Result<InstanceObject, String> {
     let data = match object.data {
         physis_LayerEntry::BgPart(bg) => LayerEntryData::BgPart(BgPartInstanceObject {
-            asset_path: ffi_from_c_string(bg.asset_path).unwrap().as_str().into(),
-            collision_asset_path: ffi_from_c_string(bg.collision_asset_path)
-                .unwrap()
+            asset_path: to_rust_string(bg.asset_path, "asset_path")?.as_str().into(),
+            collision_asset_path: to_rust_string(bg.collision_asset_path, "collision_asset_path")?
                 .as_str()
                 .into(),
             collision_type: bg.collision_type,
@@ -138,93 +277,227 @@ fn to_rust_object(object: &physis_InstanceObject) -> InstanceObject {
         physis_LayerEntry::Light(light) => LayerEntryData::Light(LightInstanceObject {
             shape: light.shape,
             color: light.color,
+            attenuation: light.attenuation,
+            range: light.range,
+            attenuation_cone_coefficient: light.attenuation_cone_coefficient,
+            spot_angle: light.spot_angle,
+            texture_path: to_rust_string(light.texture_path, "texture_path")?
+                .as_str()
+                .into(),
+            enable_specular_highlights: light.enable_specular_highlights,
+            enable_bg_part_shadows: light.enable_bg_parts_shadows,
+            enable_character_shadows: light.enable_character_shadows,
+            shadow_plane_near: light.shadow_plane_near,
+            flat_light_skew_angle: light.flat_light_skew_angle,
             ..Default::default()
         }),
         physis_LayerEntry::Vfx(vfx) => LayerEntryData::Vfx(VfxInstanceObject {
-            asset_path: ffi_from_c_string(vfx.asset_path).unwrap().as_str().into(),
+            asset_path: to_rust_string(vfx.asset_path, "asset_path")?
+                .as_str()
+                .into(),
+            soft_particle_fade_range: vfx.soft_particle_fade_range,
+            color: vfx.color,
+            active: vfx.active,
+            unk1: vfx.unk1,
+            unk2: vfx.unk2,
+            fade_near_start: vfx.fade_near_start,
+            fade_near_end: vfx.fade_near_end,
+            fade_far_start: vfx.fade_far_start,
+            fade_far_end: vfx.fade_far_end,
+            z_correct: vfx.z_correct,
+            unk3: vfx.unk3,
             ..Default::default()
         }),
         physis_LayerEntry::EventObject(eobj) => {
             LayerEntryData::EventObject(EventObjectInstanceObject {
                 parent_data: to_rust_parent_data_game(eobj.parent_data),
                 bound_instance_id: eobj.bound_instance_id,
-                unk1: 0, // TODO
+                unk1: eobj.unk1,
             })
         }
         physis_LayerEntry::SharedGroup(sgb) => LayerEntryData::SharedGroup(SharedGroupInstance {
-            asset_path: ffi_from_c_string(sgb.asset_path).unwrap().as_str().into(),
+            asset_path: to_rust_string(sgb.asset_path, "asset_path")?
+                .as_str()
+                .into(),
             ..Default::default()
         }),
-        _ => LayerEntryData::Unknown,
+        physis_LayerEntry::Unknown => LayerEntryData::Unknown,
+        _ => {
+            return Err(format!(
+                "{} layer entries can't be written yet",
+                object.data.to_json()["type"].as_str().unwrap_or_default()
+            ));
+        }
     };
 
-    InstanceObject {
+    Ok(InstanceObject {
         instance_id: object.instance_id,
-        name: ffi_from_c_string(object.name).unwrap().as_str().into(),
+        name: to_rust_string(object.name, "name")?.as_str().into(),
         transform: object.transform,
         data,
-    }
+    })
 }
 
-unsafe fn to_rust_layer(layer: &physis_Layer) -> Layer {
+unsafe fn to_rust_layer(layer: &physis_Layer) -> Result<Layer, String> {
     unsafe {
         let mut objects = Vec::new();
         for i in 0..layer.num_objects {
-            objects.push(to_rust_object(&*layer.objects.add(i as usize)));
+            let object = &*layer.objects.add(i as usize);
+            objects.push(to_rust_object(object).map_err(|err| {
+                format!("Object {} of layer {}: {err}", object.instance_id, layer.id)
+            })?);
         }
 
-        Layer {
-            header: LayerHeader {
-                layer_id: layer.id,
-                name: ffi_from_c_string(layer.name).unwrap().as_str().into(),
-                festival_id: layer.festival_id,
-                festival_phase_id: layer.festival_phase_id,
-                ..Default::default()
-            },
-            objects,
+        let mut header = LayerHeader {
+            layer_id: layer.id,
+            name: to_rust_string(layer.name, "name")
+                .map_err(|err| format!("Layer {}: {err}", layer.id))?
+                .as_str()
+                .into(),
+            festival_id: layer.festival_id,
+            festival_phase_id: layer.festival_phase_id,
+            visible: layer.visible,
+            ..Default::default()
+        };
+
+        let list = &layer.layer_set_referenced_list;
+        header.layer_set_referenced_list.referenced_type = list.referenced_type;
+        if list.layer_set_id_count > 0 {
+            header.layer_set_referenced_list.layer_set_ids =
+                slice::from_raw_parts(list.layer_set_ids, list.layer_set_id_count as usize)
+                    .to_vec();
+        }
+
+        for i in 0..layer.object_set_referenced_count {
+            let referenced = &*layer.object_set_referenced.add(i as usize);
+
+            header.object_set_referenced.push(Default::default());
+            let obsb = header.object_set_referenced.last_mut().unwrap();
+            obsb.asset_type = referenced.asset_type;
+            obsb.instance_id = referenced.instance_id;
+            obsb.obsb_path = to_rust_string(referenced.obsb_path, "obsb_path")
+                .map_err(|err| format!("Layer {}: {err}", layer.id))?
+                .as_str()
+                .into();
         }
+
+        Ok(Layer { header, objects })
     }
 }
 
-unsafe fn to_rust_chunk(chunk: &physis_LayerChunk) -> LayerChunk {
+unsafe fn to_rust_chunk(chunk: &physis_LayerChunk) -> Result<LayerChunk, String> {
     unsafe {
         let mut layers = Vec::new();
         for i in 0..chunk.num_layers {
-            layers.push(to_rust_layer(&*chunk.layers.add(i as usize)));
+            layers.push(to_rust_layer(&*chunk.layers.add(i as usize))?);
         }
 
-        LayerChunk {
+        Ok(LayerChunk {
             layer_group_id: chunk.layer_group_id,
-            name: ffi_from_c_string(chunk.name).unwrap(),
+            name: to_rust_string(chunk.name, "name")
+                .map_err(|err| format!("Chunk {}: {err}", chunk.layer_group_id))?,
             layers,
-        }
+        })
     }
 }
 
+/// Writes `layer_group` to a buffer. Only BgPart, Light, Vfx, EventObject and SharedGroup entries can be written, so
+/// if there are any others, or a string is NULL, nothing is written and the last error is set to InvalidArgument.
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_lgb_write_to_buffer(
     platform: Platform,
     layer_group: physis_LayerGroup,
 ) -> physis_Buffer {
-    unsafe {
+    ffi_guard(physis_Buffer::default(), || unsafe {
         let mut chunks = Vec::new();
         for i in 0..layer_group.num_chunks {
-            chunks.push(to_rust_chunk(&*layer_group.chunks.add(i as usize)));
+            match to_rust_chunk(&*layer_group.chunks.add(i as usize)) {
+                Ok(chunk) => chunks.push(chunk),
+                Err(err) => {
+                    set_last_error(
+                        physis_ErrorCode::InvalidArgument,
+                        format!("Invalid layer group: {err}"),
+                    );
+                    return physis_Buffer::default();
+                }
+            }
         }
 
         let lgb = Lgb { chunks };
 
-        if let Ok(mut d) = lgb.write_to_buffer(platform) {
-            let b = physis_Buffer {
-                size: d.len() as u32,
-                data: d.as_mut_ptr(),
-            };
+        match lgb.write_to_buffer(platform) {
+            Ok(d) => ffi_to_buffer(d),
+            Err(err) => {
+                set_last_error(
+                    physis_ErrorCode::WriteFailed,
+                    format!("Failed to write LGB: {err:?}"),
+                );
+                physis_Buffer::default()
+            }
+        }
+    })
+}
 
-            mem::forget(d);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::physis_get_last_error;
+    use crate::{physis_free_file, physis_free_string};
+    use std::ffi::{CStr, CString};
 
-            return b;
-        }
+    fn from_json(json: &Value) -> physis_LayerGroup {
+        let c_json = CString::new(json.to_string()).unwrap();
+        physis_lgb_from_json(c_json.as_ptr())
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let lgb = from_json(&json!({
+            "chunks": [{
+                "layer_group_id": 3,
+                "name": "bg",
+                "layers": [{
+                    "objects": [],
+                    "name": "Terrain",
+                    "id": 7,
+                    "festival_id": 0,
+                    "festival_phase_id": 0,
+                    "layer_set_referenced_list": {
+                        "referenced_type": 0,
+                        "layer_set_ids": [1, 2],
+                    },
+                    "visible": true,
+                    "object_set_referenced": [],
+                }],
+            }],
+        }));
+        assert_eq!(physis_get_last_error(), physis_ErrorCode::Ok);
+        let json = lgb.to_json();
+
+        // Converting the JSON back again has to give the same layer group
+        let copy = from_json(&json);
+        assert_eq!(copy.to_json(), json);
+        physis_lgb_free(&copy);
+
+        // And so does writing it with Physis and parsing it again
+        let buffer = physis_lgb_write_to_buffer(Platform::Win32, lgb);
+        assert_eq!(physis_get_last_error(), physis_ErrorCode::Ok);
+
+        let written = physis_lgb_to_json(Platform::Win32, buffer);
+        let written_json: Value =
+            serde_json::from_str(unsafe { CStr::from_ptr(written) }.to_str().unwrap()).unwrap();
+        assert_eq!(written_json, json);
+
+        physis_free_string(written);
+        physis_free_file(&buffer);
+        physis_lgb_free(&lgb);
+    }
+
+    #[test]
+    fn invalid_json_is_rejected() {
+        let lgb = from_json(&json!({ "chunks": [{ "layer_group_id": "bg" }] }));
 
-        physis_Buffer::default()
+        assert!(lgb.chunks.is_null());
+        assert_eq!(physis_get_last_error(), physis_ErrorCode::InvalidArgument);
     }
 }
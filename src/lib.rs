@@ -1,19 +1,31 @@
 // SPDX-FileCopyrightText: 2024 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use crate::error::{physis_ErrorCode, set_last_error};
+use crate::panic::ffi_guard;
 use physis::TerritoryIntendedUse;
 use std::ffi::{CStr, CString};
 use std::fs::read;
+use std::io::ErrorKind;
 use std::mem;
 use std::os::raw::c_char;
 use std::ptr::{null, null_mut};
 
-/// Convert from a C string to a proper Rust string
+/// Convert from a C string to a proper Rust string. Sets the last error if `ptr` is NULL or not valid UTF-8.
 fn ffi_from_c_string(ptr: *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        set_last_error(physis_ErrorCode::InvalidArgument, "String argument is NULL");
+        return None;
+    }
+
     unsafe {
         if let Ok(str) = CStr::from_ptr(ptr as *mut c_char).to_str() {
             Some(str.to_string())
         } else {
+            set_last_error(
+                physis_ErrorCode::InvalidArgument,
+                "String argument is not valid UTF-8",
+            );
             None
         }
     }
@@ -34,6 +46,53 @@ fn ffi_to_vec<T>(ptr: *mut T, count: u32) -> Vec<T> {
     unsafe { Vec::from_raw_parts(ptr, count as usize, count as usize) }
 }
 
+/// Convert from a C vector with a 64-bit length to a Rust Vec
+fn ffi_to_large_vec<T>(ptr: *mut T, count: usize) -> Vec<T> {
+    unsafe { Vec::from_raw_parts(ptr, count, count) }
+}
+
+/// Convert a length to a 32-bit size. Sets the last error and returns None if it doesn't fit.
+fn ffi_size(len: usize) -> Option<u32> {
+    match u32::try_from(len) {
+        Ok(size) => Some(size),
+        Err(_) => {
+            set_last_error(
+                physis_ErrorCode::TooLarge,
+                format!("{len} bytes doesn't fit in a 32-bit size"),
+            );
+            None
+        }
+    }
+}
+
+/// Hand ownership of `data` to C. Sets the last error and returns an empty buffer if it's too large.
+fn ffi_to_buffer(mut data: Vec<u8>) -> physis_Buffer {
+    let Some(size) = ffi_size(data.len()) else {
+        return physis_Buffer::default();
+    };
+
+    let buffer = physis_Buffer {
+        size,
+        data: data.as_mut_ptr(),
+    };
+
+    mem::forget(data);
+
+    buffer
+}
+
+/// Hand ownership of `data` to C.
+fn ffi_to_large_buffer(mut data: Vec<u8>) -> physis_LargeBuffer {
+    let buffer = physis_LargeBuffer {
+        size: data.len(),
+        data: data.as_mut_ptr(),
+    };
+
+    mem::forget(data);
+
+    buffer
+}
+
 /// Free a C string
 fn ffi_free_string(ptr: *const c_char) {
     unsafe {
@@ -44,17 +103,27 @@ fn ffi_free_string(ptr: *const c_char) {
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_get_physis_version() -> *const c_char {
-    ffi_to_c_string(&physis::PHYSIS_VERSION.to_string())
+    ffi_guard(null(), || {
+        ffi_to_c_string(&physis::PHYSIS_VERSION.to_string())
+    })
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_get_libphysis_version() -> *const c_char {
-    ffi_to_c_string(&env!("CARGO_PKG_VERSION").to_string())
+    ffi_guard(null(), || {
+        ffi_to_c_string(&env!("CARGO_PKG_VERSION").to_string())
+    })
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_free_string(string: *const c_char) {
-    ffi_free_string(string);
+    ffi_guard((), || {
+        if string.is_null() {
+            return;
+        }
+
+        ffi_free_string(string);
+    })
 }
 
 #[repr(C)]
@@ -73,40 +142,98 @@ impl Default for physis_Buffer {
     }
 }
 
-#[unsafe(no_mangle)]
-pub extern "C" fn physis_read_file(path: *const c_char) -> physis_Buffer {
-    let Some(r_path) = ffi_from_c_string(path) else {
-        return physis_Buffer::default();
-    };
+/// Like `physis_Buffer`, but with a 64-bit size for data larger than 4 GiB.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct physis_LargeBuffer {
+    size: usize,
+    data: *mut u8,
+}
 
-    let Ok(mut f) = read(r_path) else {
-        return physis_Buffer::default();
-    };
+impl Default for physis_LargeBuffer {
+    fn default() -> Self {
+        Self {
+            size: 0,
+            data: null_mut(),
+        }
+    }
+}
 
-    let buf = physis_Buffer {
-        size: f.len() as u32,
-        data: f.as_mut_ptr(),
-    };
+fn read_file(path: *const c_char) -> Option<Vec<u8>> {
+    let r_path = ffi_from_c_string(path)?;
+
+    match read(&r_path) {
+        Ok(f) => Some(f),
+        Err(err) => {
+            let code = if err.kind() == ErrorKind::NotFound {
+                physis_ErrorCode::FileNotFound
+            } else {
+                physis_ErrorCode::Unknown
+            };
+            set_last_error(code, format!("Failed to read {r_path}: {err}"));
+            None
+        }
+    }
+}
 
-    mem::forget(f);
+/// Reads the file at `path`. Files larger than 4 GiB are rejected with `TooLarge`, use `physis_read_file_large` for those.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_read_file(path: *const c_char) -> physis_Buffer {
+    ffi_guard(physis_Buffer::default(), || {
+        read_file(path).map(ffi_to_buffer).unwrap_or_default()
+    })
+}
 
-    buf
+/// Reads the file at `path`. The buffer must be freed with `physis_free_large_buffer`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_read_file_large(path: *const c_char) -> physis_LargeBuffer {
+    ffi_guard(physis_LargeBuffer::default(), || {
+        read_file(path).map(ffi_to_large_buffer).unwrap_or_default()
+    })
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_free_file(buffer: &physis_Buffer) {
-    if buffer.data.is_null() {
-        return;
-    }
+    ffi_guard((), || {
+        if buffer.data.is_null() {
+            return;
+        }
 
-    let bytes = ffi_to_vec(buffer.data, buffer.size);
-    drop(bytes);
+        let bytes = ffi_to_vec(buffer.data, buffer.size);
+        drop(bytes);
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_free_large_buffer(buffer: &physis_LargeBuffer) {
+    ffi_guard((), || {
+        if buffer.data.is_null() {
+            return;
+        }
+
+        let bytes = ffi_to_large_vec(buffer.data, buffer.size);
+        drop(bytes);
+    })
 }
 
 // Dummy function to export TerritoryIntendedUse because... cbindgen
 #[unsafe(no_mangle)]
 pub extern "C" fn _physis_dummy_tiu(_: TerritoryIntendedUse) {}
 
+mod error;
+
+mod panic;
+
+mod handle;
+
+mod abi;
+
+mod allocator;
+
+mod logging;
+
+mod job;
+
 mod bootdata;
 
 mod model;
@@ -216,3 +343,17 @@ mod gzd;
 mod spm;
 
 mod cldb;
+
+mod filetype;
+
+mod json;
+
+mod pathdb;
+
+mod modpack;
+
+mod excel;
+
+mod schema;
+
+mod sestring;
@@ -0,0 +1,225 @@
+// SPDX-FileCopyrightText: 2026 Joshua Goins <josh@redstrate.com>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::ffi_from_c_string;
+use crate::panic::ffi_guard;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::ffi::{CString, c_char, c_void};
+use std::sync::{Mutex, Once};
+
+/// How verbose logging should be. Each level includes every level above it.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum physis_LogLevel {
+    /// Nothing is logged.
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<physis_LogLevel> for LevelFilter {
+    fn from(level: physis_LogLevel) -> Self {
+        match level {
+            physis_LogLevel::Off => LevelFilter::Off,
+            physis_LogLevel::Error => LevelFilter::Error,
+            physis_LogLevel::Warn => LevelFilter::Warn,
+            physis_LogLevel::Info => LevelFilter::Info,
+            physis_LogLevel::Debug => LevelFilter::Debug,
+            physis_LogLevel::Trace => LevelFilter::Trace,
+        }
+    }
+}
+
+impl From<log::Level> for physis_LogLevel {
+    fn from(level: log::Level) -> Self {
+        match level {
+            log::Level::Error => physis_LogLevel::Error,
+            log::Level::Warn => physis_LogLevel::Warn,
+            log::Level::Info => physis_LogLevel::Info,
+            log::Level::Debug => physis_LogLevel::Debug,
+            log::Level::Trace => physis_LogLevel::Trace,
+        }
+    }
+}
+
+/// Called for every log message that passes the filter. `target` is the libphysis module that logged it (e.g.
+/// `physis::tmb`, since the library is named physis) and both strings are only valid for the duration of the call.
+pub type physis_LogCallback = extern "C" fn(
+    user_data: *mut c_void,
+    level: physis_LogLevel,
+    target: *const c_char,
+    message: *const c_char,
+);
+
+struct LogState {
+    callback: Option<(physis_LogCallback, *mut c_void)>,
+    level: LevelFilter,
+    /// Overrides for specific modules, the longest matching prefix wins.
+    modules: Vec<(String, LevelFilter)>,
+}
+
+// user_data is never touched on our side, only passed back to the callback
+unsafe impl Send for LogState {}
+
+impl LogState {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .filter(|(module, _)| {
+                target == module
+                    || (target.starts_with(module.as_str())
+                        && target[module.len()..].starts_with("::"))
+            })
+            .max_by_key(|(module, _)| module.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(self.level)
+    }
+
+    /// The most verbose level anything could be logged at, so the log crate can skip the rest early.
+    fn max_level(&self) -> LevelFilter {
+        if self.callback.is_none() {
+            return LevelFilter::Off;
+        }
+
+        self.modules
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.level, LevelFilter::max)
+    }
+}
+
+static LOG_STATE: Mutex<LogState> = Mutex::new(LogState {
+    callback: None,
+    level: LevelFilter::Off,
+    modules: Vec::new(),
+});
+
+static INSTALL_LOGGER: Once = Once::new();
+
+/// Forwards log records to the host's callback.
+struct BridgeLogger;
+
+static LOGGER: BridgeLogger = BridgeLogger;
+
+impl Log for BridgeLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let Ok(state) = LOG_STATE.lock() else {
+            return false;
+        };
+
+        state.callback.is_some() && metadata.level() <= state.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        // Copied out so the callback is free to call back into libphysis
+        let callback = {
+            let Ok(state) = LOG_STATE.lock() else {
+                return;
+            };
+
+            if record.level() > state.level_for(record.target()) {
+                return;
+            }
+
+            state.callback
+        };
+
+        let Some((callback, user_data)) = callback else {
+            return;
+        };
+
+        let (Ok(target), Ok(message)) = (
+            CString::new(record.target()),
+            CString::new(record.args().to_string()),
+        ) else {
+            return;
+        };
+
+        callback(
+            user_data,
+            record.level().into(),
+            target.as_ptr(),
+            message.as_ptr(),
+        );
+    }
+
+    fn flush(&self) {}
+}
+
+fn update_max_level(state: &LogState) {
+    INSTALL_LOGGER.call_once(|| {
+        // This only fails if the host already linked in another Rust logger, which we can't do anything about
+        let _ = log::set_logger(&LOGGER);
+    });
+
+    log::set_max_level(state.max_level());
+}
+
+/// Sets a callback that receives log messages at `level` or more severe, with `user_data` passed along. Pass NULL to
+/// remove it.
+///
+/// Only messages from libphysis itself are forwarded. Every error reported through `physis_get_last_error` is logged at
+/// `Error` from the module that reported it, and data that libphysis can't convert yet (like unknown layer entry types,
+/// TMB nodes or SCD codecs) is logged at `Warn`. The Physis crate doesn't use the log crate, so anything it would warn
+/// about internally never reaches this callback.
+///
+/// The callback can be called while libphysis holds a lock on the object being used, so it shouldn't call back into
+/// libphysis.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_set_log_callback(
+    level: physis_LogLevel,
+    callback: Option<physis_LogCallback>,
+    user_data: *mut c_void,
+) {
+    ffi_guard((), || {
+        let Ok(mut state) = LOG_STATE.lock() else {
+            return;
+        };
+
+        state.callback = callback.map(|callback| (callback, user_data));
+        state.level = level.into();
+
+        update_max_level(&state);
+    })
+}
+
+/// Overrides the log level for the libphysis `module` and its submodules (e.g. `physis::layer`), regardless of the
+/// level passed to `physis_set_log_callback`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_set_log_module_level(module: *const c_char, level: physis_LogLevel) {
+    ffi_guard((), || {
+        let Some(module) = ffi_from_c_string(module) else {
+            return;
+        };
+
+        let Ok(mut state) = LOG_STATE.lock() else {
+            return;
+        };
+
+        let level = level.into();
+        if let Some((_, existing)) = state.modules.iter_mut().find(|(m, _)| *m == module) {
+            *existing = level;
+        } else {
+            state.modules.push((module, level));
+        }
+
+        update_max_level(&state);
+    })
+}
+
+/// Removes every override set by `physis_set_log_module_level`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_clear_log_module_levels() {
+    ffi_guard((), || {
+        let Ok(mut state) = LOG_STATE.lock() else {
+            return;
+        };
+
+        state.modules.clear();
+
+        update_max_level(&state);
+    })
+}
@@ -1,13 +1,17 @@
 // SPDX-FileCopyrightText: 2026 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use crate::error::{ffi_result, set_physis_error};
+use crate::json::{ToJson, array_to_json, ffi_parsed_to_json};
+use crate::panic::ffi_guard;
 use crate::scn::{drop_section, physis_ScnSection, to_c_section};
 use crate::{ffi_to_c_string, ffi_to_vec, physis_Buffer};
 use physis::Platform;
 use physis::ReadableFile;
 use physis::lvb::Lvb;
+use serde_json::{Value, json};
 use std::ffi::c_char;
-use std::ptr::null_mut;
+use std::ptr::{null, null_mut};
 use std::slice;
 
 #[repr(C)]
@@ -27,26 +31,28 @@ impl Default for physis_Lvb {
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_lvb_parse(platform: Platform, buffer: physis_Buffer) -> physis_Lvb {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(physis_Lvb::default(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-    if let Ok(lvb) = Lvb::from_existing(platform, data) {
-        let mut c_sections = Vec::new();
+        if let Some(lvb) = ffi_result(Lvb::from_existing(platform, data)) {
+            let mut c_sections = Vec::new();
 
-        for section in &lvb.sections {
-            c_sections.push(to_c_section(section))
-        }
+            for section in &lvb.sections {
+                c_sections.push(to_c_section(section))
+            }
 
-        let lvb = physis_Lvb {
-            section_count: c_sections.len() as u32,
-            sections: c_sections.as_mut_ptr(),
-        };
+            let lvb = physis_Lvb {
+                section_count: c_sections.len() as u32,
+                sections: c_sections.as_mut_ptr(),
+            };
 
-        std::mem::forget(c_sections);
+            std::mem::forget(c_sections);
 
-        lvb
-    } else {
-        physis_Lvb::default()
-    }
+            lvb
+        } else {
+            physis_Lvb::default()
+        }
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -54,23 +60,45 @@ pub unsafe extern "C" fn physis_lvb_debug(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> *const c_char {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(null(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-    match Lvb::from_existing(platform, data) {
-        Ok(lvb) => ffi_to_c_string(&format!("{lvb:#?}")),
-        Err(err) => ffi_to_c_string(&format!("{err:#?}")),
+        match Lvb::from_existing(platform, data) {
+            Ok(lvb) => ffi_to_c_string(&format!("{lvb:#?}")),
+            Err(err) => {
+                set_physis_error(&err);
+                ffi_to_c_string(&format!("{err:#?}"))
+            }
+        }
+    })
+}
+
+impl ToJson for physis_Lvb {
+    fn to_json(&self) -> Value {
+        json!({
+            "sections": array_to_json(self.sections, self.section_count as usize, ToJson::to_json),
+        })
     }
 }
 
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_lvb_to_json(platform: Platform, buffer: physis_Buffer) -> *const c_char {
+    ffi_guard(null(), || {
+        ffi_parsed_to_json(physis_lvb_parse(platform, buffer), physis_lvb_free)
+    })
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_lvb_free(lvb: &physis_Lvb) {
-    if lvb.sections.is_null() {
-        return;
-    }
+    ffi_guard((), || {
+        if lvb.sections.is_null() {
+            return;
+        }
 
-    let data = ffi_to_vec(lvb.sections, lvb.section_count);
-    for section in &data {
-        drop_section(section);
-    }
-    drop(data);
+        let data = ffi_to_vec(lvb.sections, lvb.section_count);
+        for section in &data {
+            drop_section(section);
+        }
+        drop(data);
+    })
 }
@@ -3,17 +3,27 @@
 
 use physis::Platform;
 use physis::model::NewShapeValue;
-use std::os::raw::c_char;
-use std::ptr::null_mut;
-use std::ptr::slice_from_raw_parts;
+use std::os::raw::{c_char, c_void};
+use std::ptr::{null, null_mut, slice_from_raw_parts};
 use std::{mem, slice};
 
-use crate::{ffi_free_string, ffi_to_c_string, ffi_to_vec, physis_Buffer};
+use crate::abi::struct_size;
+use crate::error::{ffi_result, physis_ErrorCode, set_last_error, set_physis_error};
+use crate::handle::{HandleRegistry, physis_Handle};
+use crate::job::{physis_JobId, physis_JobStatus, spawn_job};
+use crate::json::{
+    JsonReader, ToJson, array_to_json, ffi_parsed_to_json, raw_to_json, string_to_json,
+};
+use crate::panic::ffi_guard;
+use crate::{
+    ffi_free_string, ffi_from_c_string, ffi_to_buffer, ffi_to_c_string, ffi_to_vec, physis_Buffer,
+};
 use physis::model::vertex_declarations::VertexElement;
 use physis::model::vertex_declarations::VertexType;
 use physis::model::vertex_declarations::get_vertex_type_size;
 use physis::model::{BoundingBox, MDL, SubMesh, Vertex};
 use physis::{ReadableFile, WritableFile};
+use serde_json::{Value, json};
 
 #[repr(C)]
 pub struct physis_Part {
@@ -52,9 +62,12 @@ pub struct physis_LOD {
     model_lod_range: f32,
 }
 
+static MODELS: HandleRegistry<MDL> = HandleRegistry::new("MDL");
+
 #[repr(C)]
 pub struct physis_MDL {
-    p_ptr: *mut MDL,
+    struct_size: u32,
+    handle: physis_Handle,
     num_lod: u32,
     lods: *mut physis_LOD,
     num_affected_bones: u32,
@@ -68,7 +81,8 @@ pub struct physis_MDL {
 impl Default for physis_MDL {
     fn default() -> Self {
         Self {
-            p_ptr: null_mut(),
+            struct_size: struct_size::<physis_MDL>(),
+            handle: physis_Handle::default(),
             num_lod: 0,
             lods: null_mut(),
             num_affected_bones: 0,
@@ -83,64 +97,94 @@ impl Default for physis_MDL {
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_mdl_parse(platform: Platform, buffer: physis_Buffer) -> physis_MDL {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(physis_MDL::default(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-    let Ok(mdl_d) = MDL::from_existing(platform, data) else {
-        return physis_MDL::default();
-    };
+        let Some(mdl) = ffi_result(MDL::from_existing(platform, data)) else {
+            return physis_MDL::default();
+        };
 
-    let mdl = Box::new(mdl_d);
+        let mut c_lods: Vec<physis_LOD> = physis_mdl_update_vertices(&mdl);
 
-    let mut c_lods: Vec<physis_LOD> = physis_mdl_update_vertices(&mdl);
+        let mut c_bone_names = vec![];
 
-    let mut c_bone_names = vec![];
+        for bone_name in &mdl.affected_bone_names {
+            c_bone_names.push(ffi_to_c_string(bone_name));
+        }
 
-    for bone_name in &mdl.affected_bone_names {
-        c_bone_names.push(ffi_to_c_string(bone_name));
-    }
+        let mut c_material_names = vec![];
 
-    let mut c_material_names = vec![];
+        for bone_name in &mdl.material_names {
+            c_material_names.push(ffi_to_c_string(bone_name));
+        }
 
-    for bone_name in &mdl.material_names {
-        c_material_names.push(ffi_to_c_string(bone_name));
-    }
+        let bounding_box = mdl.model_data.bounding_box;
+        let model_clip_out_of_distance = mdl.model_data.header.model_clip_out_of_distance;
+
+        let mdl = physis_MDL {
+            struct_size: struct_size::<physis_MDL>(),
+            handle: MODELS.insert(mdl),
+            num_lod: c_lods.len() as u32,
+            lods: c_lods.as_mut_ptr(),
+            num_affected_bones: c_bone_names.len() as u32,
+            affected_bone_names: c_bone_names.as_mut_ptr(),
+            num_material_names: c_material_names.len() as u32,
+            material_names: c_material_names.as_mut_ptr(),
+            bounding_box,
+            model_clip_out_of_distance,
+        };
+
+        mem::forget(c_bone_names);
+        mem::forget(c_material_names);
+        mem::forget(c_lods);
 
-    let bounding_box = mdl.model_data.bounding_box;
-    let model_clip_out_of_distance = mdl.model_data.header.model_clip_out_of_distance;
-
-    let mdl = physis_MDL {
-        p_ptr: Box::leak(mdl),
-        num_lod: c_lods.len() as u32,
-        lods: c_lods.as_mut_ptr(),
-        num_affected_bones: c_bone_names.len() as u32,
-        affected_bone_names: c_bone_names.as_mut_ptr(),
-        num_material_names: c_material_names.len() as u32,
-        material_names: c_material_names.as_mut_ptr(),
-        bounding_box,
-        model_clip_out_of_distance,
-    };
-
-    mem::forget(c_bone_names);
-    mem::forget(c_material_names);
-    mem::forget(c_lods);
-
-    mdl
+        mdl
+    })
 }
 
+/// Called when an asynchronous MDL parse finishes. The result must be freed with `physis_mdl_free`.
+pub type physis_MDLCallback = extern "C" fn(
+    user_data: *mut c_void,
+    job: physis_JobId,
+    status: physis_JobStatus,
+    mdl: physis_MDL,
+);
+
+/// Same as `physis_mdl_parse`, but parses on a worker thread and then calls `callback` from there. `buffer` must stay valid
+/// until then. Returns the job id, or 0 if it couldn't be queued.
 #[unsafe(no_mangle)]
-pub extern "C" fn physis_mdl_write(platform: Platform, mdl: &physis_MDL) -> physis_Buffer {
-    unsafe {
-        let mut buffer = (*mdl.p_ptr).write_to_buffer(platform).unwrap();
+pub extern "C" fn physis_mdl_parse_async(
+    platform: Platform,
+    buffer: physis_Buffer,
+    callback: physis_MDLCallback,
+    user_data: *mut c_void,
+) -> physis_JobId {
+    ffi_guard(0, || {
+        spawn_job(
+            move || physis_mdl_parse(platform, buffer),
+            move |job, status, mdl| callback(user_data, job, status, mdl),
+        )
+    })
+}
 
-        let leak_buffer = physis_Buffer {
-            size: buffer.len() as u32,
-            data: buffer.as_mut_ptr(),
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_mdl_write(platform: Platform, mdl: &physis_MDL) -> physis_Buffer {
+    ffi_guard(physis_Buffer::default(), || {
+        let Some(buffer) = MODELS.with(mdl.handle, |model| model.write_to_buffer(platform)) else {
+            return physis_Buffer::default();
         };
 
-        mem::forget(buffer);
-
-        leak_buffer
-    }
+        match buffer {
+            Ok(buffer) => ffi_to_buffer(buffer),
+            Err(err) => {
+                set_last_error(
+                    physis_ErrorCode::WriteFailed,
+                    format!("Failed to write model: {err:?}"),
+                );
+                physis_Buffer::default()
+            }
+        }
+    })
 }
 
 fn physis_mdl_update_vertices(mdl: &MDL) -> Vec<physis_LOD> {
@@ -221,80 +265,10 @@ fn physis_mdl_update_vertices(mdl: &MDL) -> Vec<physis_LOD> {
     c_lods
 }
 
-#[unsafe(no_mangle)]
-pub extern "C" fn physis_mdl_replace_vertices(
-    mdl: *mut physis_MDL,
-    lod_index: u32,
-    part_index: u32,
-    num_vertices: u32,
-    vertices_ptr: *const Vertex,
-    num_indices: u32,
-    indices_ptr: *const u16,
-    num_submeshes: u32,
-    submeshes_ptr: *const SubMesh,
-) {
-    unsafe {
-        (*(*mdl).p_ptr).replace_vertices(
-            lod_index as usize,
-            part_index as usize,
-            &*std::ptr::slice_from_raw_parts(vertices_ptr, num_vertices as usize),
-            &*std::ptr::slice_from_raw_parts(indices_ptr, num_indices as usize),
-            &*std::ptr::slice_from_raw_parts(submeshes_ptr, num_submeshes as usize),
-        );
-
-        // We need to update the C version of these LODs as well
-        let mut new_lods = physis_mdl_update_vertices((*mdl).p_ptr.as_ref().unwrap());
-
-        (*mdl).lods = new_lods.as_mut_ptr();
-
-        mem::forget(new_lods);
-    }
-}
-
-#[unsafe(no_mangle)]
-pub extern "C" fn physis_mdl_remove_shape_meshes(mdl: *mut physis_MDL) {
-    unsafe {
-        (*(*mdl).p_ptr).remove_shape_meshes();
-    }
-}
-
-#[unsafe(no_mangle)]
-pub extern "C" fn physis_mdl_add_shape_mesh(
-    mdl: *mut physis_MDL,
-    lod_index: u32,
-    shape_index: u32,
-    shape_mesh_index: u32,
-    part_index: u32,
-    num_shape_values: u32,
-    shape_values: *const NewShapeValue,
-) {
+/// Frees LODs created by `physis_mdl_update_vertices`.
+unsafe fn free_lods(lods: *mut physis_LOD, num_lod: u32) {
     unsafe {
-        (*(*mdl).p_ptr).add_shape_mesh(
-            lod_index as usize,
-            shape_index as usize,
-            shape_mesh_index as usize,
-            part_index as usize,
-            &*slice_from_raw_parts(shape_values, num_shape_values as usize),
-        );
-
-        // We need to update the C version of these LODs as well
-        let mut new_lods = physis_mdl_update_vertices((*mdl).p_ptr.as_ref().unwrap());
-
-        (*mdl).lods = new_lods.as_mut_ptr();
-
-        mem::forget(new_lods);
-    }
-}
-
-#[unsafe(no_mangle)]
-pub extern "C" fn physis_get_vertex_type_size(vertex_type: VertexType) -> usize {
-    get_vertex_type_size(vertex_type)
-}
-
-#[unsafe(no_mangle)]
-pub extern "C" fn physis_mdl_free(mdl: &physis_MDL) {
-    unsafe {
-        let lods = ffi_to_vec(mdl.lods, mdl.num_lod);
+        let lods = ffi_to_vec(lods, num_lod);
         for lod in &lods {
             let parts = ffi_to_vec(lod.parts, lod.num_parts);
             for part in &parts {
@@ -336,6 +310,223 @@ pub extern "C" fn physis_mdl_free(mdl: &physis_MDL) {
             drop(vertex_elements);
         }
         drop(lods);
+    }
+}
+
+/// Swaps the LODs of `mdl` for `lods`, and frees the old ones.
+unsafe fn replace_lods(mdl: *mut physis_MDL, mut lods: Vec<physis_LOD>) {
+    unsafe {
+        free_lods((*mdl).lods, (*mdl).num_lod);
+
+        (*mdl).num_lod = lods.len() as u32;
+        (*mdl).lods = lods.as_mut_ptr();
+    }
+
+    mem::forget(lods);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_mdl_replace_vertices(
+    mdl: *mut physis_MDL,
+    lod_index: u32,
+    part_index: u32,
+    num_vertices: u32,
+    vertices_ptr: *const Vertex,
+    num_indices: u32,
+    indices_ptr: *const u16,
+    num_submeshes: u32,
+    submeshes_ptr: *const SubMesh,
+) {
+    ffi_guard((), || {
+        let Some(new_lods) = MODELS.with(unsafe { (*mdl).handle }, |model| unsafe {
+            model.replace_vertices(
+                lod_index as usize,
+                part_index as usize,
+                &*std::ptr::slice_from_raw_parts(vertices_ptr, num_vertices as usize),
+                &*std::ptr::slice_from_raw_parts(indices_ptr, num_indices as usize),
+                &*std::ptr::slice_from_raw_parts(submeshes_ptr, num_submeshes as usize),
+            );
+
+            // We need to update the C version of these LODs as well
+            physis_mdl_update_vertices(model)
+        }) else {
+            return;
+        };
+
+        unsafe { replace_lods(mdl, new_lods) };
+    })
+}
+
+fn vertex_from_json(reader: &mut JsonReader, value: &Value, existing: Option<&Vertex>) -> Vertex {
+    let mut vertex = existing.cloned().unwrap_or_default();
+    vertex.position = reader.f32_array(value, "position");
+    vertex.uv0 = reader.f32_array(value, "uv0");
+    vertex.uv1 = reader.f32_array(value, "uv1");
+    vertex.normal = reader.f32_array(value, "normal");
+    vertex.bitangent = reader.f32_array(value, "bitangent");
+    vertex.color = reader.f32_array(value, "color");
+    vertex.bone_weight = reader.f32_array(value, "bone_weight");
+
+    match value["bone_id"].as_array() {
+        Some(ids) if ids.len() == vertex.bone_id.len() => {
+            for (id, value) in vertex.bone_id.iter_mut().zip(ids) {
+                *id = reader.int_item(value, "bone_id");
+            }
+        }
+        _ => reader.fail("bone_id", "an array of 4 integers"),
+    }
+
+    vertex
+}
+
+/// Replaces the geometry of `mdl` with JSON in the format returned by `physis_mdl_to_json`, like calling
+/// `physis_mdl_replace_vertices` for every part. Only the vertices, indices and submesh ranges are read, and the LODs,
+/// parts and submeshes must line up with the ones already in the model. Returns false and leaves the model unchanged if
+/// `json` is invalid.
+///
+/// This only updates geometry. Materials, bones, shapes and everything else in `json` are ignored, so they have to be
+/// changed through their own functions.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_mdl_update_from_json(mdl: *mut physis_MDL, json: *const c_char) -> bool {
+    ffi_guard(false, || {
+        let Some(json) = ffi_from_c_string(json) else {
+            return false;
+        };
+
+        let value: Value = match serde_json::from_str(&json) {
+            Ok(value) => value,
+            Err(err) => {
+                set_last_error(
+                    physis_ErrorCode::InvalidArgument,
+                    format!("Invalid JSON: {err}"),
+                );
+                return false;
+            }
+        };
+
+        let new_lods = MODELS.with(unsafe { (*mdl).handle }, |model| {
+            let mut reader = JsonReader::new();
+            let mut geometry = Vec::new();
+
+            let lods = reader.array(&value, "lods");
+            if lods.len() != model.lods.len() {
+                reader.fail("lods", "as long as the model's LODs");
+            }
+
+            for (lod_index, (lod, lod_value)) in model.lods.iter().zip(lods).enumerate() {
+                let parts = reader.array(lod_value, "parts");
+                if parts.len() != lod.parts.len() {
+                    reader.fail("parts", "as long as the LOD's parts");
+                }
+
+                for (part_index, (part, part_value)) in lod.parts.iter().zip(parts).enumerate() {
+                    let mut vertices = Vec::new();
+                    for (i, vertex) in reader.array(part_value, "vertices").iter().enumerate() {
+                        vertices.push(vertex_from_json(&mut reader, vertex, part.vertices.get(i)));
+                    }
+
+                    let mut indices = Vec::new();
+                    for index in reader.array(part_value, "indices") {
+                        indices.push(reader.int_item(index, "indices"));
+                    }
+
+                    let submesh_values = reader.array(part_value, "submeshes");
+                    if submesh_values.len() != part.submeshes.len() {
+                        reader.fail("submeshes", "as long as the part's submeshes");
+                    }
+
+                    let mut submeshes = Vec::new();
+                    for (submesh, submesh_value) in part.submeshes.iter().zip(submesh_values) {
+                        let mut submesh = submesh.clone();
+                        submesh.index_count = reader.int(submesh_value, "index_count");
+                        submesh.index_offset = reader.int(submesh_value, "index_offset");
+                        submeshes.push(submesh);
+                    }
+
+                    geometry.push((lod_index, part_index, vertices, indices, submeshes));
+                }
+            }
+
+            if let Err(err) = reader.finish() {
+                set_last_error(
+                    physis_ErrorCode::InvalidArgument,
+                    format!("Invalid model: {err}"),
+                );
+                return None;
+            }
+
+            for (lod_index, part_index, vertices, indices, submeshes) in geometry {
+                model.replace_vertices(lod_index, part_index, &vertices, &indices, &submeshes);
+            }
+
+            // We need to update the C version of these LODs as well
+            Some(physis_mdl_update_vertices(model))
+        });
+
+        let Some(new_lods) = new_lods.flatten() else {
+            return false;
+        };
+
+        unsafe { replace_lods(mdl, new_lods) };
+
+        true
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_mdl_remove_shape_meshes(mdl: *mut physis_MDL) {
+    ffi_guard((), || unsafe {
+        MODELS.with((*mdl).handle, |model| model.remove_shape_meshes());
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_mdl_add_shape_mesh(
+    mdl: *mut physis_MDL,
+    lod_index: u32,
+    shape_index: u32,
+    shape_mesh_index: u32,
+    part_index: u32,
+    num_shape_values: u32,
+    shape_values: *const NewShapeValue,
+) {
+    ffi_guard((), || {
+        let Some(new_lods) = MODELS.with(unsafe { (*mdl).handle }, |model| unsafe {
+            model.add_shape_mesh(
+                lod_index as usize,
+                shape_index as usize,
+                shape_mesh_index as usize,
+                part_index as usize,
+                &*slice_from_raw_parts(shape_values, num_shape_values as usize),
+            );
+
+            // We need to update the C version of these LODs as well
+            physis_mdl_update_vertices(model)
+        }) else {
+            return;
+        };
+
+        unsafe { replace_lods(mdl, new_lods) };
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_get_vertex_type_size(vertex_type: VertexType) -> usize {
+    ffi_guard(0, || get_vertex_type_size(vertex_type))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_mdl_free(mdl: &physis_MDL) {
+    ffi_guard((), || unsafe {
+        if mdl.handle.is_null() {
+            return;
+        }
+
+        let Some(model) = MODELS.remove(mdl.handle) else {
+            return;
+        };
+
+        free_lods(mdl.lods, mdl.num_lod);
 
         let affected_bone_names = ffi_to_vec(mdl.affected_bone_names, mdl.num_affected_bones);
         for name in &affected_bone_names {
@@ -349,8 +540,8 @@ pub extern "C" fn physis_mdl_free(mdl: &physis_MDL) {
         }
         drop(material_names);
 
-        drop(Box::from_raw(mdl.p_ptr));
-    }
+        drop(model);
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -358,10 +549,102 @@ pub unsafe extern "C" fn physis_mdl_debug(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> *const c_char {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(null(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+
+        match MDL::from_existing(platform, data) {
+            Ok(mdl) => ffi_to_c_string(&format!("{mdl:#?}")),
+            Err(err) => {
+                set_physis_error(&err);
+                ffi_to_c_string(&format!("{err:#?}"))
+            }
+        }
+    })
+}
+
+fn vertex_to_json(vertex: &Vertex) -> Value {
+    json!({
+        "position": vertex.position,
+        "uv0": vertex.uv0,
+        "uv1": vertex.uv1,
+        "normal": vertex.normal,
+        "bitangent": vertex.bitangent,
+        "color": vertex.color,
+        "bone_weight": vertex.bone_weight,
+        "bone_id": vertex.bone_id,
+    })
+}
+
+impl ToJson for physis_Part {
+    fn to_json(&self) -> Value {
+        let vertex_count = self.num_vertices as usize;
+
+        json!({
+            "vertices": array_to_json(self.vertices, vertex_count, vertex_to_json),
+            "streams": (0..self.num_streams).map(|i| unsafe {
+                json!({
+                    "size": *self.stream_sizes.add(i),
+                    "stride": *self.stream_strides.add(i),
+                })
+            }).collect::<Vec<_>>(),
+            "indices": array_to_json(self.indices, self.num_indices as usize, |index| json!(index)),
+            "material_index": self.material_index,
+            "submeshes": array_to_json(self.submeshes, self.num_submeshes as usize, |submesh| {
+                json!({
+                    "index_count": submesh.index_count,
+                    "index_offset": submesh.index_offset,
+                })
+            }),
+            "shapes": array_to_json(self.shapes, self.num_shapes as usize, |shape| {
+                json!({
+                    "name": string_to_json(shape.name),
+                    "morphed_vertices": array_to_json(
+                        shape.morphed_vertices,
+                        vertex_count,
+                        vertex_to_json
+                    ),
+                })
+            }),
+        })
+    }
+}
 
-    match MDL::from_existing(platform, data) {
-        Ok(mdl) => ffi_to_c_string(&format!("{mdl:#?}")),
-        Err(err) => ffi_to_c_string(&format!("{err:#?}")),
+impl ToJson for physis_MDL {
+    fn to_json(&self) -> Value {
+        json!({
+            "lods": array_to_json(self.lods, self.num_lod as usize, |lod| {
+                json!({
+                    "vertex_elements": array_to_json(
+                        lod.vertex_elements,
+                        lod.num_vertex_elements as usize,
+                        raw_to_json
+                    ),
+                    "parts": array_to_json(lod.parts, lod.num_parts as usize, ToJson::to_json),
+                    "model_lod_range": lod.model_lod_range,
+                })
+            }),
+            "affected_bone_names": array_to_json(
+                self.affected_bone_names,
+                self.num_affected_bones as usize,
+                |name| string_to_json(*name)
+            ),
+            "material_names": array_to_json(
+                self.material_names,
+                self.num_material_names as usize,
+                |name| string_to_json(*name)
+            ),
+            "bounding_box": {
+                "min": self.bounding_box.min,
+                "max": self.bounding_box.max,
+            },
+            "model_clip_out_of_distance": self.model_clip_out_of_distance,
+        })
     }
 }
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_mdl_to_json(platform: Platform, buffer: physis_Buffer) -> *const c_char {
+    ffi_guard(null(), || {
+        ffi_parsed_to_json(physis_mdl_parse(platform, buffer), physis_mdl_free)
+    })
+}
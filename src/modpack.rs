@@ -0,0 +1,581 @@
+// SPDX-FileCopyrightText: 2026 Joshua Goins <josh@redstrate.com>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::error::{physis_ErrorCode, set_last_error};
+use crate::panic::ffi_guard;
+use crate::resource::{physis_CustomResource, register_modpack, with_modpack};
+use crate::{ffi_free_string, ffi_from_c_string, ffi_to_c_string, ffi_to_vec};
+use physis::Platform;
+use physis::sqpack::SqPackData;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read};
+use std::mem;
+use std::os::raw::c_char;
+use std::ptr::{null, null_mut};
+use zip::ZipArchive;
+
+/// Where the data of a file in a mod pack is stored.
+#[derive(Clone)]
+enum ModFileSource {
+    /// A loose file in the archive, used by Penumbra.
+    Archive(String),
+    /// A compressed SqPack entry in the TTMPD.mpd blob, used by TexTools.
+    Mpd { offset: u64, size: u64 },
+}
+
+struct ModFile {
+    game_path: String,
+    source: ModFileSource,
+}
+
+struct ModOption {
+    name: String,
+    description: String,
+    files: Vec<ModFile>,
+}
+
+struct ModGroup {
+    name: String,
+    /// Whether more than one option can be selected at once.
+    multi: bool,
+    options: Vec<ModOption>,
+    selected: Vec<bool>,
+}
+
+/// A Penumbra (.pmp) or TexTools (.ttmp2) mod pack.
+pub(crate) struct ModPack {
+    name: String,
+    author: String,
+    version: String,
+    description: String,
+    /// Files that are always applied, no matter what options are selected.
+    default_files: Vec<ModFile>,
+    groups: Vec<ModGroup>,
+    archive: ZipArchive<BufReader<File>>,
+    /// Lowercase archive paths to their real names, since Penumbra doesn't care about case.
+    archive_names: HashMap<String, String>,
+}
+
+fn json_string(value: &Value, key: &str) -> String {
+    value[key].as_str().unwrap_or_default().to_string()
+}
+
+/// Reads a Penumbra `Files` object, which maps game paths to paths in the archive.
+fn pmp_files(files: &Value) -> Vec<ModFile> {
+    let Some(files) = files.as_object() else {
+        return Vec::new();
+    };
+
+    files
+        .iter()
+        .filter_map(|(game_path, archive_path)| {
+            Some(ModFile {
+                game_path: game_path.to_lowercase(),
+                source: ModFileSource::Archive(archive_path.as_str()?.replace('\\', "/")),
+            })
+        })
+        .collect()
+}
+
+/// Reads a TexTools mod entry, which points into the mpd blob.
+fn ttmp_file(entry: &Value) -> Option<ModFile> {
+    Some(ModFile {
+        game_path: entry["FullPath"].as_str()?.to_lowercase(),
+        source: ModFileSource::Mpd {
+            offset: entry["ModOffset"].as_u64()?,
+            size: entry["ModSize"].as_u64()?,
+        },
+    })
+}
+
+fn find_file<'a>(files: &'a [ModFile], path: &str) -> Option<&'a ModFileSource> {
+    files
+        .iter()
+        .find(|file| file.game_path == path)
+        .map(|file| &file.source)
+}
+
+/// Single choice groups always have exactly one option selected.
+fn fix_single_selection(group: &mut ModGroup) {
+    if group.multi || group.options.is_empty() {
+        return;
+    }
+
+    let selected = group.selected.iter().position(|s| *s).unwrap_or(0);
+    group.selected = (0..group.options.len()).map(|i| i == selected).collect();
+}
+
+impl ModPack {
+    fn open(path: &str) -> Result<Self, String> {
+        let file = File::open(path).map_err(|err| format!("Failed to open {path}: {err}"))?;
+        let archive = ZipArchive::new(BufReader::new(file))
+            .map_err(|err| format!("{path} isn't a valid mod pack: {err}"))?;
+
+        let archive_names = archive
+            .file_names()
+            .map(|name| (name.to_lowercase(), name.to_string()))
+            .collect();
+
+        let mut modpack = Self {
+            name: String::new(),
+            author: String::new(),
+            version: String::new(),
+            description: String::new(),
+            default_files: Vec::new(),
+            groups: Vec::new(),
+            archive,
+            archive_names,
+        };
+
+        if modpack.archive_names.contains_key("meta.json") {
+            modpack.read_pmp()?;
+        } else if modpack.archive_names.contains_key("ttmpl.mpl") {
+            modpack.read_ttmp()?;
+        } else {
+            return Err(format!("{path} isn't a Penumbra or TexTools mod pack"));
+        }
+
+        for group in &mut modpack.groups {
+            fix_single_selection(group);
+        }
+
+        Ok(modpack)
+    }
+
+    fn read_archive_file(&mut self, name: &str) -> Option<Vec<u8>> {
+        let real_name = self.archive_names.get(&name.to_lowercase())?;
+        let mut file = self.archive.by_name(real_name).ok()?;
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).ok()?;
+
+        Some(data)
+    }
+
+    /// Reads `size` bytes at `offset` from the TTMPD.mpd blob, without loading the rest of it into memory.
+    fn read_mpd_range(&mut self, offset: u64, size: u64) -> Option<Vec<u8>> {
+        offset.checked_add(size)?;
+
+        let real_name = self.archive_names.get("ttmpd.mpd")?;
+        let mut file = self.archive.by_name(real_name).ok()?;
+
+        // The entry may be compressed, so skip ahead by reading instead of seeking
+        if io::copy(&mut file.by_ref().take(offset), &mut io::sink()).ok()? != offset {
+            return None;
+        }
+
+        let mut data = Vec::new();
+        file.take(size).read_to_end(&mut data).ok()?;
+
+        (data.len() as u64 == size).then_some(data)
+    }
+
+    fn read_json(&mut self, name: &str) -> Result<Value, String> {
+        let data = self
+            .read_archive_file(name)
+            .ok_or_else(|| format!("{name} is missing from the mod pack"))?;
+
+        // Some tools write a BOM
+        let text = String::from_utf8_lossy(&data);
+        serde_json::from_str(text.trim_start_matches('\u{feff}'))
+            .map_err(|err| format!("{name} isn't valid JSON: {err}"))
+    }
+
+    fn read_pmp(&mut self) -> Result<(), String> {
+        let meta = self.read_json("meta.json")?;
+        self.name = json_string(&meta, "Name");
+        self.author = json_string(&meta, "Author");
+        self.version = json_string(&meta, "Version");
+        self.description = json_string(&meta, "Description");
+
+        if self.archive_names.contains_key("default_mod.json") {
+            let default_mod = self.read_json("default_mod.json")?;
+            self.default_files = pmp_files(&default_mod["Files"]);
+        }
+
+        // Groups are stored as group_001_name.json, so sorting them keeps their order
+        let mut group_names: Vec<String> = self
+            .archive_names
+            .keys()
+            .filter(|name| name.starts_with("group_") && name.ends_with(".json"))
+            .cloned()
+            .collect();
+        group_names.sort();
+
+        for group_name in group_names {
+            let group = self.read_json(&group_name)?;
+
+            let multi = group["Type"].as_str() == Some("Multi");
+            let default_settings = group["DefaultSettings"].as_u64().unwrap_or_default();
+
+            let options: Vec<ModOption> = group["Options"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|option| ModOption {
+                    name: json_string(option, "Name"),
+                    description: json_string(option, "Description"),
+                    files: pmp_files(&option["Files"]),
+                })
+                .collect();
+
+            // Multi groups store a bitmask of the selected options, and single ones the index
+            let selected = (0..options.len())
+                .map(|i| {
+                    if multi {
+                        i < 64 && default_settings & (1 << i) != 0
+                    } else {
+                        i as u64 == default_settings
+                    }
+                })
+                .collect();
+
+            self.groups.push(ModGroup {
+                name: json_string(&group, "Name"),
+                multi,
+                options,
+                selected,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn read_ttmp(&mut self) -> Result<(), String> {
+        let data = self
+            .read_archive_file("ttmpl.mpl")
+            .ok_or("TTMPL.mpl is missing from the mod pack")?;
+        let text = String::from_utf8_lossy(&data);
+        let text = text.trim_start_matches('\u{feff}');
+
+        // Old mod packs have one JSON object per line, each of them a file
+        let Ok(Value::Object(mpl)) = serde_json::from_str::<Value>(text) else {
+            self.default_files = text
+                .lines()
+                .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+                .filter_map(|entry| ttmp_file(&entry))
+                .collect();
+            return Ok(());
+        };
+
+        let mpl = Value::Object(mpl);
+        self.name = json_string(&mpl, "Name");
+        self.author = json_string(&mpl, "Author");
+        self.version = json_string(&mpl, "Version");
+        self.description = json_string(&mpl, "Description");
+
+        self.default_files = mpl["SimpleModsList"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(ttmp_file)
+            .collect();
+
+        for page in mpl["ModPackPages"].as_array().into_iter().flatten() {
+            for group in page["ModGroups"].as_array().into_iter().flatten() {
+                let mut options = Vec::new();
+                let mut selected = Vec::new();
+
+                for option in group["OptionList"].as_array().into_iter().flatten() {
+                    options.push(ModOption {
+                        name: json_string(option, "Name"),
+                        description: json_string(option, "Description"),
+                        files: option["ModsJsons"]
+                            .as_array()
+                            .into_iter()
+                            .flatten()
+                            .filter_map(ttmp_file)
+                            .collect(),
+                    });
+                    selected.push(option["IsChecked"].as_bool().unwrap_or_default());
+                }
+
+                self.groups.push(ModGroup {
+                    name: json_string(group, "GroupName"),
+                    multi: group["SelectionType"].as_str() == Some("Multi"),
+                    options,
+                    selected,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finds where `path` is stored, taking the selected options into account. Later groups and options take
+    /// priority, and the default files have the lowest.
+    fn find(&self, path: &str) -> Option<&ModFileSource> {
+        let path = path.to_lowercase();
+
+        for group in self.groups.iter().rev() {
+            for (option, selected) in group.options.iter().zip(&group.selected).rev() {
+                if !selected {
+                    continue;
+                }
+
+                if let Some(source) = find_file(&option.files, &path) {
+                    return Some(source);
+                }
+            }
+        }
+
+        find_file(&self.default_files, &path)
+    }
+
+    /// Lists the files and subdirectories in `directory` that are replaced by the selected options.
+    pub(crate) fn list_directory(&self, directory: &str) -> Option<Vec<String>> {
+        let prefix = format!("{}/", directory.trim_end_matches('/').to_lowercase());
+
+        let selected_files = self.groups.iter().flat_map(|group| {
+            group
+                .options
+                .iter()
+                .zip(&group.selected)
+                .filter(|(_, selected)| **selected)
+                .flat_map(|(option, _)| &option.files)
+        });
+
+        let mut entries: Vec<String> = self
+            .default_files
+            .iter()
+            .chain(selected_files)
+            .filter_map(|file| file.game_path.strip_prefix(&prefix))
+            .map(|rest| rest.split('/').next().unwrap_or(rest).to_string())
+            .collect();
+
+        entries.sort();
+        entries.dedup();
+
+        (!entries.is_empty()).then_some(entries)
+    }
+
+    pub(crate) fn exists(&self, path: &str) -> bool {
+        self.find(path).is_some()
+    }
+
+    pub(crate) fn read(&mut self, path: &str) -> Option<Vec<u8>> {
+        match self.find(path)?.clone() {
+            ModFileSource::Archive(name) => self.read_archive_file(&name),
+            ModFileSource::Mpd { offset, size } => {
+                let entry = self.read_mpd_range(offset, size)?;
+
+                SqPackData::read_from_reader(&mut Cursor::new(entry), Platform::Win32).ok()
+            }
+        }
+    }
+}
+
+#[repr(C)]
+pub struct physis_ModPackOption {
+    pub name: *const c_char,
+    pub description: *const c_char,
+    pub selected: bool,
+    /// The game paths this option replaces.
+    pub file_count: u32,
+    pub files: *mut *const c_char,
+}
+
+#[repr(C)]
+pub struct physis_ModPackGroup {
+    pub name: *const c_char,
+    /// Whether more than one option can be selected at once.
+    pub multi: bool,
+    pub option_count: u32,
+    pub options: *mut physis_ModPackOption,
+}
+
+#[repr(C)]
+pub struct physis_ModPackInfo {
+    pub name: *const c_char,
+    pub author: *const c_char,
+    pub version: *const c_char,
+    pub description: *const c_char,
+    /// The game paths that are always replaced, no matter what options are selected.
+    pub default_file_count: u32,
+    pub default_files: *mut *const c_char,
+    pub group_count: u32,
+    pub groups: *mut physis_ModPackGroup,
+}
+
+impl Default for physis_ModPackInfo {
+    fn default() -> Self {
+        Self {
+            name: null(),
+            author: null(),
+            version: null(),
+            description: null(),
+            default_file_count: 0,
+            default_files: null_mut(),
+            group_count: 0,
+            groups: null_mut(),
+        }
+    }
+}
+
+fn to_c_paths(files: &[ModFile]) -> (u32, *mut *const c_char) {
+    let mut c_files: Vec<*const c_char> = files
+        .iter()
+        .map(|file| ffi_to_c_string(&file.game_path))
+        .collect();
+
+    let paths = (c_files.len() as u32, c_files.as_mut_ptr());
+
+    mem::forget(c_files);
+
+    paths
+}
+
+fn free_c_paths(count: u32, paths: *mut *const c_char) {
+    let data = ffi_to_vec(paths, count);
+
+    for path in &data {
+        ffi_free_string(*path);
+    }
+
+    drop(data)
+}
+
+/// Opens a Penumbra (.pmp) or TexTools (.ttmp2) mod pack as a custom resource, so the files it replaces can be read
+/// with the `physis_custom_*` functions. Only the options that are selected by default are applied, which can be
+/// changed with `physis_modpack_select_option`. File swaps and metadata manipulations aren't supported.
+///
+/// To preview a mod against the game, add it to an overlay with `physis_overlay_add_custom` on top of the game data.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_modpack_open(path: *const c_char) -> physis_CustomResource {
+    ffi_guard(physis_CustomResource::default(), || {
+        let Some(r_path) = ffi_from_c_string(path) else {
+            return physis_CustomResource::default();
+        };
+
+        match ModPack::open(&r_path) {
+            Ok(modpack) => register_modpack(modpack),
+            Err(err) => {
+                set_last_error(physis_ErrorCode::ParseFailed, err);
+                physis_CustomResource::default()
+            }
+        }
+    })
+}
+
+/// Returns the name, groups and options of a mod pack. This must be freed with `physis_modpack_free_info`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_modpack_get_info(modpack: &physis_CustomResource) -> physis_ModPackInfo {
+    ffi_guard(physis_ModPackInfo::default(), || {
+        with_modpack(modpack, |modpack| {
+            let mut c_groups = Vec::new();
+            for group in &modpack.groups {
+                let mut c_options = Vec::new();
+                for (option, selected) in group.options.iter().zip(&group.selected) {
+                    let (file_count, files) = to_c_paths(&option.files);
+
+                    c_options.push(physis_ModPackOption {
+                        name: ffi_to_c_string(&option.name),
+                        description: ffi_to_c_string(&option.description),
+                        selected: *selected,
+                        file_count,
+                        files,
+                    });
+                }
+
+                c_groups.push(physis_ModPackGroup {
+                    name: ffi_to_c_string(&group.name),
+                    multi: group.multi,
+                    option_count: c_options.len() as u32,
+                    options: c_options.as_mut_ptr(),
+                });
+
+                mem::forget(c_options);
+            }
+
+            let (default_file_count, default_files) = to_c_paths(&modpack.default_files);
+
+            let info = physis_ModPackInfo {
+                name: ffi_to_c_string(&modpack.name),
+                author: ffi_to_c_string(&modpack.author),
+                version: ffi_to_c_string(&modpack.version),
+                description: ffi_to_c_string(&modpack.description),
+                default_file_count,
+                default_files,
+                group_count: c_groups.len() as u32,
+                groups: c_groups.as_mut_ptr(),
+            };
+
+            mem::forget(c_groups);
+
+            info
+        })
+        .unwrap_or_default()
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_modpack_free_info(info: &physis_ModPackInfo) {
+    ffi_guard((), || {
+        if info.name.is_null() {
+            return;
+        }
+
+        let groups = ffi_to_vec(info.groups, info.group_count);
+        for group in &groups {
+            let options = ffi_to_vec(group.options, group.option_count);
+            for option in &options {
+                ffi_free_string(option.name);
+                ffi_free_string(option.description);
+                free_c_paths(option.file_count, option.files);
+            }
+            drop(options);
+
+            ffi_free_string(group.name);
+        }
+        drop(groups);
+
+        free_c_paths(info.default_file_count, info.default_files);
+
+        ffi_free_string(info.name);
+        ffi_free_string(info.author);
+        ffi_free_string(info.version);
+        ffi_free_string(info.description);
+    })
+}
+
+/// Selects or deselects an option in a mod pack. Selecting an option in a single choice group deselects the others,
+/// and its selected option can't be deselected. Returns false if the group or option doesn't exist.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_modpack_select_option(
+    modpack: &physis_CustomResource,
+    group: u32,
+    option: u32,
+    selected: bool,
+) -> bool {
+    ffi_guard(false, || {
+        with_modpack(modpack, |modpack| {
+            let Some(group) = modpack.groups.get_mut(group as usize) else {
+                set_last_error(
+                    physis_ErrorCode::InvalidArgument,
+                    format!("The mod pack has no group {group}"),
+                );
+                return false;
+            };
+
+            let option = option as usize;
+            if option >= group.options.len() {
+                set_last_error(
+                    physis_ErrorCode::InvalidArgument,
+                    format!("{} has no option {option}", group.name),
+                );
+                return false;
+            }
+
+            if group.multi {
+                group.selected[option] = selected;
+            } else if selected {
+                group.selected = (0..group.options.len()).map(|i| i == option).collect();
+            }
+
+            true
+        })
+        .unwrap_or_default()
+    })
+}
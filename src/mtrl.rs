@@ -1,6 +1,10 @@
 // SPDX-FileCopyrightText: 2024 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use crate::abi::struct_size;
+use crate::error::{ffi_result, set_physis_error};
+use crate::json::{ToJson, array_to_json, ffi_parsed_to_json, raw_to_json, string_to_json};
+use crate::panic::ffi_guard;
 use crate::{ffi_free_string, ffi_to_c_string, ffi_to_vec, physis_Buffer};
 use physis::Platform;
 use physis::ReadableFile;
@@ -9,6 +13,7 @@ use physis::mtrl::Material;
 use physis::mtrl::Sampler;
 use physis::mtrl::ShaderKey;
 use physis::mtrl::{ColorTable, DawntrailColorTableRow, LegacyColorTableRow};
+use serde_json::{Value, json};
 use std::os::raw::c_char;
 use std::ptr::{null, null_mut};
 use std::{mem, slice};
@@ -48,6 +53,7 @@ impl Default for physis_DawntrailColorTable {
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct physis_Material {
+    struct_size: u32,
     shpk_name: *const c_char,
     num_textures: u32,
     textures: *mut *const c_char,
@@ -64,6 +70,7 @@ pub struct physis_Material {
 impl Default for physis_Material {
     fn default() -> Self {
         Self {
+            struct_size: struct_size::<physis_Material>(),
             shpk_name: null(),
             num_textures: 0,
             textures: null_mut(),
@@ -84,78 +91,81 @@ pub extern "C" fn physis_material_parse(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> physis_Material {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(physis_Material::default(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-    if let Ok(material) = Material::from_existing(platform, data) {
-        let mut c_strings = vec![];
+        if let Some(material) = ffi_result(Material::from_existing(platform, data)) {
+            let mut c_strings = vec![];
 
-        for tex in &material.texture_paths {
-            c_strings.push(ffi_to_c_string(tex));
-        }
+            for tex in &material.texture_paths {
+                c_strings.push(ffi_to_c_string(tex));
+            }
+
+            let mut shader_keys = material.shader_keys.clone();
+            let mut constants = material.constants.clone();
+            let mut samplers = material.samplers.clone();
+            let mut legacy_rows = vec![];
+            let mut dawntrail_rows = vec![];
 
-        let mut shader_keys = material.shader_keys.clone();
-        let mut constants = material.constants.clone();
-        let mut samplers = material.samplers.clone();
-        let mut legacy_rows = vec![];
-        let mut dawntrail_rows = vec![];
-
-        let legacy_color_table = match &material.color_table {
-            Some(ColorTable::LegacyColorTable(data)) => {
-                legacy_rows.clone_from(&data.rows);
-
-                physis_LegacyColorTable {
-                    num_rows: legacy_rows.len() as u32,
-                    rows: if legacy_rows.is_empty() {
-                        null_mut()
-                    } else {
-                        legacy_rows.as_mut_ptr()
-                    },
+            let legacy_color_table = match &material.color_table {
+                Some(ColorTable::LegacyColorTable(data)) => {
+                    legacy_rows.clone_from(&data.rows);
+
+                    physis_LegacyColorTable {
+                        num_rows: legacy_rows.len() as u32,
+                        rows: if legacy_rows.is_empty() {
+                            null_mut()
+                        } else {
+                            legacy_rows.as_mut_ptr()
+                        },
+                    }
                 }
-            }
-            _ => physis_LegacyColorTable::default(),
-        };
-
-        let dawntrail_color_table = match &material.color_table {
-            Some(ColorTable::DawntrailColorTable(data)) => {
-                dawntrail_rows.clone_from(&data.rows);
-
-                physis_DawntrailColorTable {
-                    num_rows: dawntrail_rows.len() as u32,
-                    rows: if dawntrail_rows.is_empty() {
-                        null_mut()
-                    } else {
-                        dawntrail_rows.as_mut_ptr()
-                    },
+                _ => physis_LegacyColorTable::default(),
+            };
+
+            let dawntrail_color_table = match &material.color_table {
+                Some(ColorTable::DawntrailColorTable(data)) => {
+                    dawntrail_rows.clone_from(&data.rows);
+
+                    physis_DawntrailColorTable {
+                        num_rows: dawntrail_rows.len() as u32,
+                        rows: if dawntrail_rows.is_empty() {
+                            null_mut()
+                        } else {
+                            dawntrail_rows.as_mut_ptr()
+                        },
+                    }
                 }
-            }
-            _ => physis_DawntrailColorTable::default(),
-        };
-
-        let mat = physis_Material {
-            shpk_name: ffi_to_c_string(&material.shader_package_name),
-            num_textures: c_strings.len() as u32,
-            textures: c_strings.as_mut_ptr(),
-            num_shader_keys: shader_keys.len() as u32,
-            shader_keys: shader_keys.as_mut_ptr(),
-            num_constants: constants.len() as u32,
-            constants: constants.as_mut_ptr(),
-            num_samplers: samplers.len() as u32,
-            samplers: samplers.as_mut_ptr(),
-            legacy_color_table,
-            dawntrail_color_table,
-        };
-
-        mem::forget(c_strings);
-        mem::forget(shader_keys);
-        mem::forget(constants);
-        mem::forget(samplers);
-        mem::forget(legacy_rows);
-        mem::forget(dawntrail_rows);
-
-        mat
-    } else {
-        physis_Material::default()
-    }
+                _ => physis_DawntrailColorTable::default(),
+            };
+
+            let mat = physis_Material {
+                struct_size: struct_size::<physis_Material>(),
+                shpk_name: ffi_to_c_string(&material.shader_package_name),
+                num_textures: c_strings.len() as u32,
+                textures: c_strings.as_mut_ptr(),
+                num_shader_keys: shader_keys.len() as u32,
+                shader_keys: shader_keys.as_mut_ptr(),
+                num_constants: constants.len() as u32,
+                constants: constants.as_mut_ptr(),
+                num_samplers: samplers.len() as u32,
+                samplers: samplers.as_mut_ptr(),
+                legacy_color_table,
+                dawntrail_color_table,
+            };
+
+            mem::forget(c_strings);
+            mem::forget(shader_keys);
+            mem::forget(constants);
+            mem::forget(samplers);
+            mem::forget(legacy_rows);
+            mem::forget(dawntrail_rows);
+
+            mat
+        } else {
+            physis_Material::default()
+        }
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -163,50 +173,102 @@ pub unsafe extern "C" fn physis_mtrl_debug(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> *const c_char {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(null(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+
+        match Material::from_existing(platform, data) {
+            Ok(mtrl) => ffi_to_c_string(&format!("{mtrl:#?}")),
+            Err(err) => {
+                set_physis_error(&err);
+                ffi_to_c_string(&format!("{err:#?}"))
+            }
+        }
+    })
+}
 
-    match Material::from_existing(platform, data) {
-        Ok(mtrl) => ffi_to_c_string(&format!("{mtrl:#?}")),
-        Err(err) => ffi_to_c_string(&format!("{err:#?}")),
+impl ToJson for physis_Material {
+    fn to_json(&self) -> Value {
+        json!({
+            "shpk_name": string_to_json(self.shpk_name),
+            "textures": array_to_json(self.textures, self.num_textures as usize, |texture| {
+                string_to_json(*texture)
+            }),
+            "shader_keys": array_to_json(
+                self.shader_keys,
+                self.num_shader_keys as usize,
+                raw_to_json
+            ),
+            "constants": array_to_json(self.constants, self.num_constants as usize, |constant| {
+                json!({
+                    "id": constant.id,
+                    "num_values": constant.num_values,
+                    "values": constant.values,
+                })
+            }),
+            "samplers": array_to_json(self.samplers, self.num_samplers as usize, raw_to_json),
+            "legacy_color_table": {
+                "rows": array_to_json(
+                    self.legacy_color_table.rows,
+                    self.legacy_color_table.num_rows as usize,
+                    raw_to_json
+                ),
+            },
+            "dawntrail_color_table": {
+                "rows": array_to_json(
+                    self.dawntrail_color_table.rows,
+                    self.dawntrail_color_table.num_rows as usize,
+                    raw_to_json
+                ),
+            },
+        })
     }
 }
 
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_mtrl_to_json(platform: Platform, buffer: physis_Buffer) -> *const c_char {
+    ffi_guard(null(), || {
+        ffi_parsed_to_json(physis_material_parse(platform, buffer), physis_mtrl_free)
+    })
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_mtrl_free(mtrl: &physis_Material) {
-    if mtrl.shpk_name.is_null() {
-        return;
-    }
+    ffi_guard((), || {
+        if mtrl.shpk_name.is_null() {
+            return;
+        }
 
-    if !mtrl.dawntrail_color_table.rows.is_null() {
-        let data = ffi_to_vec(
-            mtrl.dawntrail_color_table.rows,
-            mtrl.dawntrail_color_table.num_rows,
-        );
-        drop(data);
-    }
+        if !mtrl.dawntrail_color_table.rows.is_null() {
+            let data = ffi_to_vec(
+                mtrl.dawntrail_color_table.rows,
+                mtrl.dawntrail_color_table.num_rows,
+            );
+            drop(data);
+        }
 
-    if !mtrl.legacy_color_table.rows.is_null() {
-        let data = ffi_to_vec(
-            mtrl.legacy_color_table.rows,
-            mtrl.legacy_color_table.num_rows,
-        );
-        drop(data);
-    }
+        if !mtrl.legacy_color_table.rows.is_null() {
+            let data = ffi_to_vec(
+                mtrl.legacy_color_table.rows,
+                mtrl.legacy_color_table.num_rows,
+            );
+            drop(data);
+        }
 
-    let data = ffi_to_vec(mtrl.samplers, mtrl.num_samplers);
-    drop(data);
+        let data = ffi_to_vec(mtrl.samplers, mtrl.num_samplers);
+        drop(data);
 
-    let data = ffi_to_vec(mtrl.constants, mtrl.num_constants);
-    drop(data);
+        let data = ffi_to_vec(mtrl.constants, mtrl.num_constants);
+        drop(data);
 
-    let data = ffi_to_vec(mtrl.shader_keys, mtrl.num_shader_keys);
-    drop(data);
+        let data = ffi_to_vec(mtrl.shader_keys, mtrl.num_shader_keys);
+        drop(data);
 
-    let data = ffi_to_vec(mtrl.textures, mtrl.num_textures);
-    for texture in &data {
-        ffi_free_string(*texture);
-    }
-    drop(data);
+        let data = ffi_to_vec(mtrl.textures, mtrl.num_textures);
+        for texture in &data {
+            ffi_free_string(*texture);
+        }
+        drop(data);
 
-    ffi_free_string(mtrl.shpk_name);
+        ffi_free_string(mtrl.shpk_name);
+    })
 }
@@ -3,11 +3,15 @@
 
 #![allow(unused)] // cbindgen madness
 
-use crate::{ffi_to_c_string, physis_Buffer};
+use crate::error::{ffi_result, set_physis_error};
+use crate::json::{ToJson, array_to_json, ffi_parsed_to_json};
+use crate::panic::ffi_guard;
+use crate::{ffi_to_c_string, ffi_to_vec, physis_Buffer};
 use physis::Platform;
 use physis::ReadableFile;
 use physis::envs::EnvTimelineElement;
 use physis::obsb::Obsb;
+use serde_json::{Value, json};
 use std::ffi::c_char;
 use std::ptr::{null, null_mut};
 use std::slice;
@@ -57,72 +61,103 @@ pub struct physis_EnvObjectVisibility {
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_obsb_parse(platform: Platform, buffer: physis_Buffer) -> physis_Obsb {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
-
-    if let Ok(obsb) = Obsb::from_existing(platform, data) {
-        let mut c_envss = Vec::new();
-        for env in &obsb.envs {
-            let mut c_sections = Vec::new();
-            for section in &env.sections {
-                let mut c_timelines = Vec::new();
-                for timeline in &section.timelines {
-                    let c_timeline = match &timeline.data {
-                        EnvTimelineElement::ObjectVisibility(elements) => {
-                            let mut c_points = Vec::new();
-                            for element in elements {
-                                c_points.push(physis_EnvObjectVisibility {
-                                    time: element.time,
-                                    visible: element.visible,
-                                });
+    ffi_guard(physis_Obsb::default(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+
+        if let Some(obsb) = ffi_result(Obsb::from_existing(platform, data)) {
+            let mut c_envss = Vec::new();
+            for env in &obsb.envs {
+                let mut c_sections = Vec::new();
+                for section in &env.sections {
+                    let mut c_timelines = Vec::new();
+                    for timeline in &section.timelines {
+                        let c_timeline = match &timeline.data {
+                            EnvTimelineElement::ObjectVisibility(elements) => {
+                                let mut c_points = Vec::new();
+                                for element in elements {
+                                    c_points.push(physis_EnvObjectVisibility {
+                                        time: element.time,
+                                        visible: element.visible,
+                                    });
+                                }
+
+                                let c_element = physis_EnvTimelineElement::ObjectVisibility {
+                                    point_count: c_points.len() as u32,
+                                    points: c_points.as_mut_ptr(),
+                                };
+
+                                std::mem::forget(c_points);
+
+                                c_element
                             }
+                            _ => physis_EnvTimelineElement::Unknown,
+                        };
 
-                            let c_element = physis_EnvTimelineElement::ObjectVisibility {
-                                point_count: c_points.len() as u32,
-                                points: c_points.as_mut_ptr(),
-                            };
+                        c_timelines.push(c_timeline);
+                    }
 
-                            std::mem::forget(c_points);
-
-                            c_element
-                        }
-                        _ => physis_EnvTimelineElement::Unknown,
+                    let c_section = physis_EnvChildSection {
+                        owner_id: section.owner_id,
+                        timeline_count: c_timelines.len() as u32,
+                        timelines: c_timelines.as_mut_ptr(),
                     };
 
-                    c_timelines.push(c_timeline);
+                    std::mem::forget(c_timelines);
+
+                    c_sections.push(c_section);
                 }
 
-                let c_section = physis_EnvChildSection {
-                    owner_id: section.owner_id,
-                    timeline_count: c_timelines.len() as u32,
-                    timelines: c_timelines.as_mut_ptr(),
+                let c_envs = physis_Envs {
+                    section_count: c_sections.len() as u32,
+                    sections: c_sections.as_mut_ptr(),
                 };
 
-                std::mem::forget(c_timelines);
+                std::mem::forget(c_sections);
 
-                c_sections.push(c_section);
+                c_envss.push(c_envs);
             }
 
-            let c_envs = physis_Envs {
-                section_count: c_sections.len() as u32,
-                sections: c_sections.as_mut_ptr(),
+            let c_obsb = physis_Obsb {
+                envs_count: c_envss.len() as u32,
+                envs: c_envss.as_mut_ptr(),
             };
 
-            std::mem::forget(c_sections);
+            std::mem::forget(c_envss);
 
-            c_envss.push(c_envs);
+            return c_obsb;
         }
 
-        let c_obsb = physis_Obsb {
-            envs_count: c_envss.len() as u32,
-            envs: c_envss.as_mut_ptr(),
-        };
-
-        std::mem::forget(c_envss);
+        physis_Obsb::default()
+    })
+}
 
-        return c_obsb;
-    }
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_obsb_free(obsb: &physis_Obsb) {
+    ffi_guard((), || {
+        if obsb.envs.is_null() {
+            return;
+        }
 
-    physis_Obsb::default()
+        let envss = ffi_to_vec(obsb.envs, obsb.envs_count);
+        for envs in &envss {
+            let sections = ffi_to_vec(envs.sections, envs.section_count);
+            for section in &sections {
+                let timelines = ffi_to_vec(section.timelines, section.timeline_count);
+                for timeline in &timelines {
+                    if let physis_EnvTimelineElement::ObjectVisibility {
+                        point_count,
+                        points,
+                    } = timeline
+                    {
+                        drop(ffi_to_vec(*points, *point_count));
+                    }
+                }
+                drop(timelines);
+            }
+            drop(sections);
+        }
+        drop(envss);
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -130,10 +165,57 @@ pub unsafe extern "C" fn physis_obsb_debug(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> *const c_char {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(null(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+
+        match Obsb::from_existing(platform, data) {
+            Ok(obsb) => ffi_to_c_string(&format!("{obsb:#?}")),
+            Err(err) => {
+                set_physis_error(&err);
+                ffi_to_c_string(&format!("{err:#?}"))
+            }
+        }
+    })
+}
 
-    match Obsb::from_existing(platform, data) {
-        Ok(obsb) => ffi_to_c_string(&format!("{obsb:#?}")),
-        Err(err) => ffi_to_c_string(&format!("{err:#?}")),
+impl ToJson for physis_Obsb {
+    fn to_json(&self) -> Value {
+        json!({
+            "envs": array_to_json(self.envs, self.envs_count as usize, |envs| {
+                json!({
+                    "sections": array_to_json(envs.sections, envs.section_count as usize, |section| {
+                        json!({
+                            "owner_id": section.owner_id,
+                            "timelines": array_to_json(
+                                section.timelines,
+                                section.timeline_count as usize,
+                                |timeline| match timeline {
+                                    physis_EnvTimelineElement::ObjectVisibility {
+                                        point_count,
+                                        points,
+                                    } => json!({
+                                        "type": "ObjectVisibility",
+                                        "points": array_to_json(*points, *point_count as usize, |point| {
+                                            json!({
+                                                "time": point.time,
+                                                "visible": point.visible,
+                                            })
+                                        }),
+                                    }),
+                                    physis_EnvTimelineElement::Unknown => json!({ "type": "Unknown" }),
+                                }
+                            ),
+                        })
+                    }),
+                })
+            }),
+        })
     }
 }
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_obsb_to_json(platform: Platform, buffer: physis_Buffer) -> *const c_char {
+    ffi_guard(null(), || {
+        ffi_parsed_to_json(physis_obsb_parse(platform, buffer), physis_obsb_free)
+    })
+}
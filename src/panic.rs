@@ -0,0 +1,130 @@
+// SPDX-FileCopyrightText: 2026 Joshua Goins <josh@redstrate.com>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::error::{clear_last_error, physis_ErrorCode, set_last_error};
+use std::any::Any;
+use std::ffi::{CString, c_char, c_void};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::Mutex;
+
+/// Called when a panic is caught at the FFI boundary. `message` is only valid for the duration of the call.
+pub type physis_PanicCallback = extern "C" fn(user_data: *mut c_void, message: *const c_char);
+
+struct PanicHandler {
+    callback: physis_PanicCallback,
+    user_data: *mut c_void,
+}
+
+// The host is responsible for making user_data safe to use from any thread
+unsafe impl Send for PanicHandler {}
+
+static PANIC_HANDLER: Mutex<Option<PanicHandler>> = Mutex::new(None);
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Unknown panic".to_string()
+    }
+}
+
+/// Runs `f`, catching any panic so it doesn't unwind across the C ABI. If a panic happens, the last error is set,
+/// the host's panic callback is called and `default` is returned instead.
+///
+/// This also clears the last error beforehand, so every exported function should go through here.
+pub(crate) fn ffi_guard<T>(default: T, f: impl FnOnce() -> T) -> T {
+    clear_last_error();
+
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(value) => value,
+        Err(payload) => {
+            let message = panic_message(payload.as_ref());
+
+            // Copied out so the callback is free to call back into libphysis
+            let handler = PANIC_HANDLER
+                .lock()
+                .ok()
+                .and_then(|handler| handler.as_ref().map(|h| (h.callback, h.user_data)));
+
+            if let Some((callback, user_data)) = handler
+                && let Ok(c_message) = CString::new(message.as_bytes())
+            {
+                callback(user_data, c_message.as_ptr());
+            }
+
+            set_last_error(physis_ErrorCode::Panic, message);
+
+            default
+        }
+    }
+}
+
+/// Sets a callback that's called whenever a panic is caught, with `user_data` passed along. Pass NULL to remove it.
+/// The function that panicked still returns its usual failure value, and `physis_get_last_error` returns `Panic`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_set_panic_callback(
+    callback: Option<physis_PanicCallback>,
+    user_data: *mut c_void,
+) {
+    ffi_guard((), || {
+        let Ok(mut handler) = PANIC_HANDLER.lock() else {
+            return;
+        };
+
+        *handler = callback.map(|callback| PanicHandler {
+            callback,
+            user_data,
+        });
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{physis_get_last_error, physis_get_last_error_message};
+    use crate::physis_free_string;
+    use std::ffi::CStr;
+    use std::ptr::null_mut;
+
+    static CAUGHT: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    extern "C" fn record_panic(_: *mut c_void, message: *const c_char) {
+        let message = unsafe { CStr::from_ptr(message) }.to_string_lossy();
+        CAUGHT.lock().unwrap().push(message.into_owned());
+    }
+
+    #[test]
+    fn panics_are_caught() {
+        physis_set_panic_callback(Some(record_panic), null_mut());
+
+        let value = ffi_guard(1, || -> i32 { panic!("Something went wrong") });
+
+        physis_set_panic_callback(None, null_mut());
+
+        assert_eq!(value, 1);
+        assert_eq!(physis_get_last_error(), physis_ErrorCode::Panic);
+        assert!(
+            CAUGHT
+                .lock()
+                .unwrap()
+                .contains(&"Something went wrong".to_string())
+        );
+    }
+
+    #[test]
+    fn formatted_panic_messages_are_kept() {
+        let count = 3;
+        ffi_guard((), || panic!("Expected {count} items"));
+
+        assert_eq!(physis_get_last_error(), physis_ErrorCode::Panic);
+
+        let message = physis_get_last_error_message();
+        assert_eq!(
+            unsafe { CStr::from_ptr(message) }.to_str(),
+            Ok("Expected 3 items")
+        );
+        physis_free_string(message);
+    }
+}
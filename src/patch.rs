@@ -1,8 +1,12 @@
 // SPDX-FileCopyrightText: 2025 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::{ffi_free_string, ffi_from_c_string, ffi_to_c_string, ffi_to_vec};
-use physis::patch::{ChunkType, SqpkOperation, SqpkTargetInfo, ZiPatch};
+use crate::error::{physis_ErrorCode, set_last_error};
+use crate::panic::ffi_guard;
+use crate::{
+    ffi_free_string, ffi_from_c_string, ffi_size, ffi_to_c_string, ffi_to_large_vec, ffi_to_vec,
+};
+use physis::patch::{ChunkType, SqpkAddData, SqpkOperation, SqpkTargetInfo, ZiPatch};
 use std::ffi::c_char;
 use std::ptr::{null, null_mut};
 
@@ -11,18 +15,26 @@ pub extern "C" fn physis_patch_apply(
     data_dir: *const c_char,
     patch_path: *const c_char,
 ) -> *const c_char {
-    let Some(data_dir) = ffi_from_c_string(data_dir) else {
-        return null();
-    };
+    ffi_guard(null(), || {
+        let Some(data_dir) = ffi_from_c_string(data_dir) else {
+            return null();
+        };
 
-    let Some(patch_path) = ffi_from_c_string(patch_path) else {
-        return null();
-    };
+        let Some(patch_path) = ffi_from_c_string(patch_path) else {
+            return null();
+        };
 
-    match ZiPatch::apply(&data_dir, &patch_path) {
-        Ok(()) => null(),
-        Err(err) => ffi_to_c_string(&format!("{err:#?}")),
-    }
+        match ZiPatch::apply(&data_dir, &patch_path) {
+            Ok(()) => null(),
+            Err(err) => {
+                set_last_error(
+                    physis_ErrorCode::WriteFailed,
+                    format!("Failed to apply {patch_path}: {err:?}"),
+                );
+                ffi_to_c_string(&format!("{err:#?}"))
+            }
+        }
+    })
 }
 
 #[repr(C)]
@@ -37,6 +49,19 @@ pub struct physis_ZiPatchSqpkAddData {
     block_data: *mut u8,
 }
 
+/// Like `physis_ZiPatchSqpkAddData`, but with a 64-bit size for blocks larger than 4 GiB.
+#[repr(C)]
+pub struct physis_ZiPatchSqpkAddDataLarge {
+    main_id: u16,
+    sub_id: u16,
+    file_id: u32,
+
+    block_offset: u64,
+
+    block_data_size: usize,
+    block_data: *mut u8,
+}
+
 #[repr(C)]
 pub struct physis_SqpkFileOperationData {
     path: *const c_char,
@@ -51,11 +76,25 @@ pub enum physis_ZiPatchSqpkOperation {
     Unknown,
 }
 
+#[repr(C)]
+#[allow(dead_code)]
+pub enum physis_ZiPatchSqpkOperationLarge {
+    AddData(physis_ZiPatchSqpkAddDataLarge),
+    FileOperation(physis_SqpkFileOperationData),
+    TargetInfo(SqpkTargetInfo),
+    Unknown,
+}
+
 #[repr(C)]
 pub struct physis_ZiPatchSqpkChunk {
     operation: physis_ZiPatchSqpkOperation,
 }
 
+#[repr(C)]
+pub struct physis_ZiPatchSqpkChunkLarge {
+    operation: physis_ZiPatchSqpkOperationLarge,
+}
+
 #[repr(C)]
 #[allow(dead_code)]
 pub enum physis_ZiPatchChunkType {
@@ -63,11 +102,23 @@ pub enum physis_ZiPatchChunkType {
     Unknown,
 }
 
+#[repr(C)]
+#[allow(dead_code)]
+pub enum physis_ZiPatchChunkTypeLarge {
+    Sqpk(physis_ZiPatchSqpkChunkLarge),
+    Unknown,
+}
+
 #[repr(C)]
 pub struct physis_ZiPatchChunk {
     chunk_type: physis_ZiPatchChunkType,
 }
 
+#[repr(C)]
+pub struct physis_ZiPatchChunkLarge {
+    chunk_type: physis_ZiPatchChunkTypeLarge,
+}
+
 #[repr(C)]
 pub struct physis_ZiPatch {
     num_chunks: u32,
@@ -83,63 +134,177 @@ impl Default for physis_ZiPatch {
     }
 }
 
+/// Like `physis_ZiPatch`, but the data of each `AddData` operation has a 64-bit size.
+#[repr(C)]
+pub struct physis_ZiPatchLarge {
+    num_chunks: u32,
+    chunks: *mut physis_ZiPatchChunkLarge,
+}
+
+impl Default for physis_ZiPatchLarge {
+    fn default() -> Self {
+        Self {
+            num_chunks: 0,
+            chunks: null_mut(),
+        }
+    }
+}
+
+fn list_operations(patch_path: *const c_char) -> Option<ZiPatch> {
+    let patch_path = ffi_from_c_string(patch_path)?;
+
+    match ZiPatch::list_operations(&patch_path) {
+        Ok(patch) => Some(patch),
+        Err(err) => {
+            set_last_error(
+                physis_ErrorCode::ParseFailed,
+                format!("Failed to parse {patch_path}: {err:?}"),
+            );
+            None
+        }
+    }
+}
+
+fn convert_file_operation(path: &String) -> physis_SqpkFileOperationData {
+    physis_SqpkFileOperationData {
+        path: ffi_to_c_string(path),
+    }
+}
+
+/// Lists every chunk in the patch at `patch_path`. If any block of data is larger than 4 GiB, this fails with
+/// `TooLarge` and `physis_patch_parse_large` has to be used instead.
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_patch_parse(patch_path: *const c_char) -> physis_ZiPatch {
-    let Some(patch_path) = ffi_from_c_string(patch_path) else {
-        return physis_ZiPatch::default();
-    };
+    ffi_guard(physis_ZiPatch::default(), || {
+        let Some(patch) = list_operations(patch_path) else {
+            return physis_ZiPatch::default();
+        };
 
-    let Ok(patch) = ZiPatch::list_operations(&patch_path) else {
-        return physis_ZiPatch::default();
-    };
+        // Check every block up front, so nothing has to be cleaned up halfway through
+        for chunk in &patch.chunks {
+            if let ChunkType::Sqpk(sqpk) = &chunk.chunk_type
+                && let SqpkOperation::AddData(add_data) = &sqpk.operation
+                && ffi_size(add_data.block_data.len()).is_none()
+            {
+                return physis_ZiPatch::default();
+            }
+        }
 
-    let mut c_chunks = Vec::new();
-    for chunk in &patch.chunks {
-        let chunk_type = match &chunk.chunk_type {
-            ChunkType::Sqpk(sqpk) => physis_ZiPatchChunkType::Sqpk(physis_ZiPatchSqpkChunk {
-                operation: match &sqpk.operation {
-                    SqpkOperation::AddData(add_data) => {
-                        let mut c_data = add_data.block_data.clone();
+        let mut c_chunks = Vec::new();
+        for chunk in &patch.chunks {
+            let chunk_type = match &chunk.chunk_type {
+                ChunkType::Sqpk(sqpk) => physis_ZiPatchChunkType::Sqpk(physis_ZiPatchSqpkChunk {
+                    operation: match &sqpk.operation {
+                        SqpkOperation::AddData(add_data) => {
+                            let c_add_data = convert_add_data(add_data);
 
-                        let c_add_data =
                             physis_ZiPatchSqpkOperation::AddData(physis_ZiPatchSqpkAddData {
-                                main_id: add_data.main_id,
-                                sub_id: add_data.sub_id,
-                                file_id: add_data.file_id,
-                                block_offset: add_data.block_offset,
-                                block_data_size: c_data.len() as u32,
-                                block_data: c_data.as_mut_ptr(),
-                            });
+                                main_id: c_add_data.main_id,
+                                sub_id: c_add_data.sub_id,
+                                file_id: c_add_data.file_id,
+                                block_offset: c_add_data.block_offset,
+                                block_data_size: c_add_data.block_data_size as u32,
+                                block_data: c_add_data.block_data,
+                            })
+                        }
+                        SqpkOperation::FileOperation(fop) => {
+                            physis_ZiPatchSqpkOperation::FileOperation(convert_file_operation(
+                                &fop.path,
+                            ))
+                        }
+                        SqpkOperation::TargetInfo(target_info) => {
+                            physis_ZiPatchSqpkOperation::TargetInfo(target_info.clone())
+                        }
+                        _ => {
+                            log::debug!("Skipping an unknown SQPK operation");
+                            physis_ZiPatchSqpkOperation::Unknown
+                        }
+                    },
+                }),
+                _ => physis_ZiPatchChunkType::Unknown,
+            };
 
-                        std::mem::forget(c_data);
+            c_chunks.push(physis_ZiPatchChunk { chunk_type });
+        }
 
-                        c_add_data
-                    }
-                    SqpkOperation::FileOperation(fop) => {
-                        physis_ZiPatchSqpkOperation::FileOperation(physis_SqpkFileOperationData {
-                            path: ffi_to_c_string(&fop.path),
-                        })
-                    }
-                    SqpkOperation::TargetInfo(target_info) => {
-                        physis_ZiPatchSqpkOperation::TargetInfo(target_info.clone())
-                    }
-                    _ => physis_ZiPatchSqpkOperation::Unknown,
-                },
-            }),
-            _ => physis_ZiPatchChunkType::Unknown,
+        let c_patch = physis_ZiPatch {
+            num_chunks: c_chunks.len() as u32,
+            chunks: c_chunks.as_mut_ptr(),
         };
 
-        c_chunks.push(physis_ZiPatchChunk { chunk_type });
-    }
+        std::mem::forget(c_chunks);
 
-    let c_patch = physis_ZiPatch {
-        num_chunks: c_chunks.len() as u32,
-        chunks: c_chunks.as_mut_ptr(),
+        c_patch
+    })
+}
+
+fn convert_add_data(add_data: &SqpkAddData) -> physis_ZiPatchSqpkAddDataLarge {
+    let mut c_data = add_data.block_data.clone();
+
+    let c_add_data = physis_ZiPatchSqpkAddDataLarge {
+        main_id: add_data.main_id,
+        sub_id: add_data.sub_id,
+        file_id: add_data.file_id,
+        block_offset: add_data.block_offset,
+        block_data_size: c_data.len(),
+        block_data: c_data.as_mut_ptr(),
     };
 
-    std::mem::forget(c_chunks);
+    std::mem::forget(c_data);
 
-    c_patch
+    c_add_data
+}
+
+/// Same as `physis_patch_parse`, but for patches with blocks of data larger than 4 GiB. The patch must be freed with
+/// `physis_patch_free_large`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_patch_parse_large(patch_path: *const c_char) -> physis_ZiPatchLarge {
+    ffi_guard(physis_ZiPatchLarge::default(), || {
+        let Some(patch) = list_operations(patch_path) else {
+            return physis_ZiPatchLarge::default();
+        };
+
+        let mut c_chunks = Vec::new();
+        for chunk in &patch.chunks {
+            let chunk_type = match &chunk.chunk_type {
+                ChunkType::Sqpk(sqpk) => {
+                    physis_ZiPatchChunkTypeLarge::Sqpk(physis_ZiPatchSqpkChunkLarge {
+                        operation: match &sqpk.operation {
+                            SqpkOperation::AddData(add_data) => {
+                                physis_ZiPatchSqpkOperationLarge::AddData(convert_add_data(
+                                    add_data,
+                                ))
+                            }
+                            SqpkOperation::FileOperation(fop) => {
+                                physis_ZiPatchSqpkOperationLarge::FileOperation(
+                                    convert_file_operation(&fop.path),
+                                )
+                            }
+                            SqpkOperation::TargetInfo(target_info) => {
+                                physis_ZiPatchSqpkOperationLarge::TargetInfo(target_info.clone())
+                            }
+                            _ => {
+                                log::debug!("Skipping an unknown SQPK operation");
+                                physis_ZiPatchSqpkOperationLarge::Unknown
+                            }
+                        },
+                    })
+                }
+                _ => physis_ZiPatchChunkTypeLarge::Unknown,
+            };
+
+            c_chunks.push(physis_ZiPatchChunkLarge { chunk_type });
+        }
+
+        let c_patch = physis_ZiPatchLarge {
+            num_chunks: c_chunks.len() as u32,
+            chunks: c_chunks.as_mut_ptr(),
+        };
+
+        std::mem::forget(c_chunks);
+
+        c_patch
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -149,17 +314,18 @@ pub extern "C" fn physis_patch_index_path(
     sub_id: u16,
     file_id: u32,
 ) -> *const c_char {
-    ffi_to_c_string(
-        &ZiPatch::index_path(&sqpk_target_info, main_id, sub_id, file_id)
-            .to_str()
-            .unwrap()
-            .to_string(),
-    )
+    ffi_guard(null(), || {
+        ffi_to_c_string(
+            &ZiPatch::index_path(&sqpk_target_info, main_id, sub_id, file_id)
+                .to_string_lossy()
+                .to_string(),
+        )
+    })
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_patch_free(patch: *mut physis_ZiPatch) {
-    unsafe {
+    ffi_guard((), || unsafe {
         let data = ffi_to_vec((*patch).chunks, (*patch).num_chunks);
         for chunk in &data {
             match &chunk.chunk_type {
@@ -178,5 +344,30 @@ pub extern "C" fn physis_patch_free(patch: *mut physis_ZiPatch) {
             }
         }
         drop(data);
-    }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_patch_free_large(patch: *mut physis_ZiPatchLarge) {
+    ffi_guard((), || unsafe {
+        let data = ffi_to_vec((*patch).chunks, (*patch).num_chunks);
+        for chunk in &data {
+            match &chunk.chunk_type {
+                physis_ZiPatchChunkTypeLarge::Sqpk(sqpk) => match &sqpk.operation {
+                    physis_ZiPatchSqpkOperationLarge::AddData(add_data) => {
+                        let block_data =
+                            ffi_to_large_vec(add_data.block_data, add_data.block_data_size);
+                        drop(block_data);
+                    }
+                    physis_ZiPatchSqpkOperationLarge::FileOperation(fop) => {
+                        ffi_free_string(fop.path);
+                    }
+                    physis_ZiPatchSqpkOperationLarge::TargetInfo(_) => {}
+                    physis_ZiPatchSqpkOperationLarge::Unknown => {}
+                },
+                physis_ZiPatchChunkTypeLarge::Unknown => {}
+            }
+        }
+        drop(data);
+    })
 }
@@ -7,6 +7,7 @@ use std::ptr::null;
 
 use physis::patchlist::{PatchList, PatchListType};
 
+use crate::panic::ffi_guard;
 use crate::{ffi_from_c_string, ffi_to_c_string};
 
 #[repr(C)]
@@ -46,42 +47,44 @@ pub extern "C" fn physis_parse_patchlist(
     patch_type: PatchListType,
     encoded: *const c_char,
 ) -> physis_PatchList {
-    if let Some(r_path) = ffi_from_c_string(encoded) {
-        let patch_list = PatchList::from_string(patch_type, &r_path);
-
-        let mut c_patches = vec![];
-
-        for entry in &patch_list.patches {
-            let mut c_hashes = vec![];
-
-            for hash in &entry.hashes {
-                c_hashes.push(ffi_to_c_string(hash));
+    ffi_guard(physis_PatchList::default(), || {
+        if let Some(r_path) = ffi_from_c_string(encoded) {
+            let patch_list = PatchList::from_string(patch_type, &r_path);
+
+            let mut c_patches = vec![];
+
+            for entry in &patch_list.patches {
+                let mut c_hashes = vec![];
+
+                for hash in &entry.hashes {
+                    c_hashes.push(ffi_to_c_string(hash));
+                }
+
+                c_patches.push(physis_PatchEntry {
+                    url: ffi_to_c_string(&entry.url),
+                    version: ffi_to_c_string(&entry.version),
+                    hash_count: c_hashes.len() as u64,
+                    hashes: c_hashes.as_mut_ptr(),
+                    hash_block_size: entry.hash_block_size,
+                    length: entry.length,
+                    size_on_disk: entry.size_on_disk,
+                });
+
+                mem::forget(c_hashes);
             }
 
-            c_patches.push(physis_PatchEntry {
-                url: ffi_to_c_string(&entry.url),
-                version: ffi_to_c_string(&entry.version),
-                hash_count: c_hashes.len() as u64,
-                hashes: c_hashes.as_mut_ptr(),
-                hash_block_size: entry.hash_block_size,
-                length: entry.length,
-                size_on_disk: entry.size_on_disk,
-            });
-
-            mem::forget(c_hashes);
-        }
+            let pl = physis_PatchList {
+                patch_length: patch_list.patch_length,
+                num_entries: c_patches.len() as i32,
+                entries: c_patches.as_mut_ptr(),
+                total_size_downloaded: patch_list.total_size_downloaded(),
+            };
 
-        let pl = physis_PatchList {
-            patch_length: patch_list.patch_length,
-            num_entries: c_patches.len() as i32,
-            entries: c_patches.as_mut_ptr(),
-            total_size_downloaded: patch_list.total_size_downloaded(),
-        };
+            mem::forget(c_patches);
 
-        mem::forget(c_patches);
-
-        return pl;
-    }
+            return pl;
+        }
 
-    physis_PatchList::default()
+        physis_PatchList::default()
+    })
 }
@@ -0,0 +1,181 @@
+// SPDX-FileCopyrightText: 2026 Joshua Goins <josh@redstrate.com>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::error::{physis_ErrorCode, set_last_error};
+use crate::panic::ffi_guard;
+use crate::{ffi_from_c_string, ffi_to_c_string};
+use physis::sqpack::{Hash, SqPackIndex};
+use std::collections::HashMap;
+use std::os::raw::c_char;
+use std::ptr::null;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Known game paths, looked up by both of the hashes SqPack indexes use.
+struct PathDb {
+    paths: Vec<String>,
+    /// Index2 files hash the whole path.
+    full: HashMap<u32, usize>,
+    /// Index files hash the directory and filename separately, keyed as (directory, filename).
+    split: HashMap<(u32, u32), usize>,
+}
+
+static PATH_DB: Mutex<Option<PathDb>> = Mutex::new(None);
+
+fn lock_db() -> MutexGuard<'static, Option<PathDb>> {
+    PATH_DB.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl PathDb {
+    fn new() -> Self {
+        Self {
+            paths: Vec::new(),
+            full: HashMap::new(),
+            split: HashMap::new(),
+        }
+    }
+
+    /// Adds `path` and returns true, or false if it was already known.
+    fn add(&mut self, path: &str) -> bool {
+        let path = path.trim().to_lowercase();
+        if path.is_empty() {
+            return false;
+        }
+
+        let full = SqPackIndex::calculate_partial_hash(&path);
+        if self
+            .full
+            .get(&full)
+            .is_some_and(|existing| self.paths[*existing] == path)
+        {
+            return false;
+        }
+
+        let (directory, filename) = path.rsplit_once('/').unwrap_or(("", &path));
+        let split = (
+            SqPackIndex::calculate_partial_hash(directory),
+            SqPackIndex::calculate_partial_hash(filename),
+        );
+
+        let index = self.paths.len();
+        self.paths.push(path);
+        self.full.insert(full, index);
+        self.split.insert(split, index);
+
+        true
+    }
+
+    fn resolve(&self, hash: &Hash) -> Option<&str> {
+        let index = match hash {
+            Hash::FullPath(full) => self.full.get(full),
+            Hash::SplitPath { name, path } => self.split.get(&(*path, *name)),
+        }?;
+
+        Some(&self.paths[*index])
+    }
+}
+
+/// Looks up `hash` in the path database, for filling in paths elsewhere.
+pub(crate) fn resolve_path(hash: &Hash) -> Option<String> {
+    lock_db().as_ref()?.resolve(hash).map(str::to_string)
+}
+
+/// Adds every path in the text file at `path` to the path database, one per line. Empty lines and lines starting
+/// with `#` are skipped. Returns how many paths were new, or 0 and sets the last error if the file couldn't be read.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_pathdb_load(path: *const c_char) -> u32 {
+    ffi_guard(0, || {
+        let Some(r_path) = ffi_from_c_string(path) else {
+            return 0;
+        };
+
+        let text = match std::fs::read_to_string(&r_path) {
+            Ok(text) => text,
+            Err(err) => {
+                set_last_error(
+                    physis_ErrorCode::FileNotFound,
+                    format!("Failed to read path list {r_path}: {err}"),
+                );
+                return 0;
+            }
+        };
+
+        let mut db = lock_db();
+        let db = db.get_or_insert_with(PathDb::new);
+
+        text.lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter(|line| db.add(line))
+            .count() as u32
+    })
+}
+
+/// Adds a newly discovered path to the path database. Returns false if it was already known.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_pathdb_add(path: *const c_char) -> bool {
+    ffi_guard(false, || {
+        let Some(r_path) = ffi_from_c_string(path) else {
+            return false;
+        };
+
+        lock_db().get_or_insert_with(PathDb::new).add(&r_path)
+    })
+}
+
+/// Returns the path that `hash` was calculated from, or NULL if it's not in the path database. The string must be
+/// freed with `physis_free_string`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_pathdb_resolve(hash: Hash) -> *const c_char {
+    ffi_guard(null(), || match resolve_path(&hash) {
+        Some(path) => ffi_to_c_string(&path),
+        None => null(),
+    })
+}
+
+/// Returns how many paths are in the path database.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_pathdb_count() -> u32 {
+    ffi_guard(0, || {
+        lock_db()
+            .as_ref()
+            .map(|db| db.paths.len() as u32)
+            .unwrap_or_default()
+    })
+}
+
+/// Saves every path in the path database to `path`, sorted and one per line, so it can be loaded again with
+/// `physis_pathdb_load`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_pathdb_save(path: *const c_char) -> bool {
+    ffi_guard(false, || {
+        let Some(r_path) = ffi_from_c_string(path) else {
+            return false;
+        };
+
+        let mut paths = lock_db()
+            .as_ref()
+            .map(|db| db.paths.clone())
+            .unwrap_or_default();
+        paths.sort();
+
+        let mut text = paths.join("\n");
+        text.push('\n');
+
+        if let Err(err) = std::fs::write(&r_path, text) {
+            set_last_error(
+                physis_ErrorCode::WriteFailed,
+                format!("Failed to write path list {r_path}: {err}"),
+            );
+            return false;
+        }
+
+        true
+    })
+}
+
+/// Removes every path from the path database.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_pathdb_clear() {
+    ffi_guard((), || {
+        *lock_db() = None;
+    })
+}
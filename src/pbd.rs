@@ -1,37 +1,38 @@
 // SPDX-FileCopyrightText: 2024 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use crate::error::{ffi_result, physis_ErrorCode, set_last_error, set_physis_error};
+use crate::handle::{HandleRegistry, lock_value, physis_Handle};
+use crate::panic::ffi_guard;
 use crate::{ffi_to_c_string, physis_Buffer};
 use physis::Platform;
 use physis::ReadableFile;
 use physis::pbd::PreBoneDeformer;
 use std::os::raw::c_char;
-use std::ptr::null_mut;
+use std::ptr::{null, null_mut};
 use std::{mem, slice};
 
+static PBDS: HandleRegistry<PreBoneDeformer> = HandleRegistry::new("PBD");
+
 #[repr(C)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 pub struct physis_PBD {
-    p_ptr: *mut PreBoneDeformer,
-}
-
-impl Default for physis_PBD {
-    fn default() -> Self {
-        Self { p_ptr: null_mut() }
-    }
+    handle: physis_Handle,
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_pbd_parse(platform: Platform, buffer: physis_Buffer) -> physis_PBD {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(physis_PBD::default(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-    if let Ok(pbd) = PreBoneDeformer::from_existing(platform, data) {
-        physis_PBD {
-            p_ptr: Box::leak(Box::new(pbd)),
+        if let Some(pbd) = ffi_result(PreBoneDeformer::from_existing(platform, data)) {
+            physis_PBD {
+                handle: PBDS.insert(pbd),
+            }
+        } else {
+            physis_PBD::default()
         }
-    } else {
-        physis_PBD::default()
-    }
+    })
 }
 
 #[repr(C)]
@@ -63,8 +64,13 @@ pub extern "C" fn physis_pbd_get_deform_matrix(
     from_body_id: u16,
     to_body_id: u16,
 ) -> physis_PreBoneDeformMatrices {
-    unsafe {
-        if let Some(prebd) = (*pbd.p_ptr).get_deform_matrices(from_body_id, to_body_id) {
+    ffi_guard(physis_PreBoneDeformMatrices::default(), || {
+        let Some(shared) = PBDS.shared(pbd.handle) else {
+            return physis_PreBoneDeformMatrices::default();
+        };
+        let pbd = lock_value(&shared);
+
+        if let Some(prebd) = pbd.get_deform_matrices(from_body_id, to_body_id) {
             let mut c_bones = vec![];
 
             for bone in &prebd.bones {
@@ -83,9 +89,13 @@ pub extern "C" fn physis_pbd_get_deform_matrix(
 
             mat
         } else {
+            set_last_error(
+                physis_ErrorCode::InvalidArgument,
+                format!("No deform matrices from {from_body_id} to {to_body_id}"),
+            );
             physis_PreBoneDeformMatrices::default()
         }
-    }
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -93,21 +103,26 @@ pub unsafe extern "C" fn physis_pbd_debug(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> *const c_char {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
-
-    match PreBoneDeformer::from_existing(platform, data) {
-        Ok(pbd) => ffi_to_c_string(&format!("{pbd:#?}")),
-        Err(err) => ffi_to_c_string(&format!("{err:#?}")),
-    }
+    ffi_guard(null(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+
+        match PreBoneDeformer::from_existing(platform, data) {
+            Ok(pbd) => ffi_to_c_string(&format!("{pbd:#?}")),
+            Err(err) => {
+                set_physis_error(&err);
+                ffi_to_c_string(&format!("{err:#?}"))
+            }
+        }
+    })
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_pbd_free(pbd: &physis_PBD) {
-    if pbd.p_ptr.is_null() {
-        return;
-    }
+    ffi_guard((), || {
+        if pbd.handle.is_null() {
+            return;
+        }
 
-    unsafe {
-        drop(Box::from_raw(pbd.p_ptr));
-    }
+        drop(PBDS.remove(pbd.handle));
+    })
 }
@@ -1,11 +1,21 @@
 // SPDX-FileCopyrightText: 2024 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use crate::error::{physis_ErrorCode, set_last_error};
+use crate::panic::ffi_guard;
 use physis::race::{Gender, Race, Tribe, get_race_id, get_supported_tribes};
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_get_race_code(race: Race, tribe: Tribe, gender: Gender) -> i32 {
-    get_race_id(race, tribe, gender).unwrap()
+    ffi_guard(0, || {
+        get_race_id(race, tribe, gender).unwrap_or_else(|| {
+            set_last_error(
+                physis_ErrorCode::InvalidArgument,
+                "This race, tribe and gender combination doesn't exist",
+            );
+            0
+        })
+    })
 }
 
 #[repr(C)]
@@ -13,9 +23,19 @@ pub struct physis_SupportedTribes {
     subraces: [Tribe; 2],
 }
 
+impl Default for physis_SupportedTribes {
+    fn default() -> Self {
+        Self {
+            subraces: [Tribe::Midlander, Tribe::Highlander],
+        }
+    }
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_get_supported_tribes(race: Race) -> physis_SupportedTribes {
-    physis_SupportedTribes {
-        subraces: get_supported_tribes(race),
-    }
+    ffi_guard(physis_SupportedTribes::default(), || {
+        physis_SupportedTribes {
+            subraces: get_supported_tribes(race),
+        }
+    })
 }
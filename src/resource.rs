@@ -1,27 +1,66 @@
 // SPDX-FileCopyrightText: 2026 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use crate::abi::struct_size;
+use crate::error::{ffi_result, physis_ErrorCode, set_last_error};
+use crate::excel::SheetCache;
 use crate::exd::{physis_ExcelEntry, physis_ExcelRow, physis_Field};
-use crate::exh::physis_EXH;
-use crate::{ffi_free_string, ffi_from_c_string, ffi_to_c_string, ffi_to_vec, physis_Buffer};
+use crate::exh::{EXHS, physis_EXH};
+use crate::handle::{HandleRegistry, lock_value, physis_Handle};
+use crate::job::{physis_JobId, physis_JobStatus, spawn_job};
+use crate::modpack::ModPack;
+use crate::panic::ffi_guard;
+use crate::pathdb::resolve_path;
+use crate::sqpack::{
+    FileStat, IndexCache, SqPackStream, expansion_id, platform_name, repository_for_path,
+    write_file,
+};
+use crate::{
+    ffi_free_string, ffi_from_c_string, ffi_to_buffer, ffi_to_c_string, ffi_to_large_buffer,
+    ffi_to_vec, physis_Buffer, physis_LargeBuffer,
+};
 use physis::excel::Field;
 use physis::excel::Row;
 use physis::excel::{Entry, Sheet};
+use physis::exl::EXL;
 use physis::repository::RepositoryType;
 use physis::resource::{
     RepairAction, Resource, SqPackRelease, SqPackResource, generic_read_excel_sheet,
 };
-use physis::sqpack::Hash;
-use physis::{Language, Platform};
-use std::ffi::{CStr, c_void};
+use physis::sqpack::{Hash, SqPackIndex};
+use physis::{Language, Platform, ReadableFile};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::ffi::{CStr, CString, c_void};
+use std::io::{Read, Seek, SeekFrom};
 use std::mem;
 use std::os::raw::{c_char, c_uint};
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
 use std::ptr::{null, null_mut};
+use std::slice;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+static RESOURCES: HandleRegistry<LoadedSqPack> = HandleRegistry::new("SqPackResource");
+static SHEETS: HandleRegistry<LoadedSheet> = HandleRegistry::new("ExcelSheet");
+static CUSTOM_RESOURCES: HandleRegistry<CustomResource> = HandleRegistry::new("CustomResource");
+static STREAMS: HandleRegistry<SqPackStream> = HandleRegistry::new("SqPackStream");
+
+/// A resource along with the directory it was loaded from, which Physis doesn't expose.
+struct LoadedSqPack {
+    sqpack: Arc<Mutex<SqPackResource>>,
+    /// Used to find files when reading them, so reads don't have to lock `sqpack`.
+    indexes: Arc<Mutex<IndexCache>>,
+    game_directory: String,
+    /// Sheets opened while resolving links, see `physis_excel_resolve_link`.
+    linked_sheets: Arc<Mutex<SheetCache>>,
+    /// Takes priority over the game data, see `physis_sqpack_set_overlay`.
+    overlay: Option<Arc<Mutex<CustomResource>>>,
+}
 
 #[repr(C)]
 pub struct physis_SqPackResource {
-    p_ptr: *mut SqPackResource,
+    struct_size: u32,
+    handle: physis_Handle,
     pub platform: Platform,
     pub release: SqPackRelease,
 }
@@ -29,7 +68,8 @@ pub struct physis_SqPackResource {
 impl Default for physis_SqPackResource {
     fn default() -> Self {
         Self {
-            p_ptr: null_mut(),
+            struct_size: struct_size::<physis_SqPackResource>(),
+            handle: physis_Handle::default(),
             platform: Platform::Win32,
             release: SqPackRelease::Retail,
         }
@@ -37,32 +77,134 @@ impl Default for physis_SqPackResource {
 }
 
 /// Initializes a new SqPackResource structure.
+///
+/// The resource is thread-safe, and one handle can be shared between threads. Reading files only locks the resource
+/// while looking them up in the index files, so several threads can read and decompress files at the same time.
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_sqpack_initialize(path: *const c_char) -> physis_SqPackResource {
-    let Some(r_path) = ffi_from_c_string(path) else {
-        return physis_SqPackResource::default();
-    };
+    ffi_guard(physis_SqPackResource::default(), || {
+        let Some(r_path) = ffi_from_c_string(path) else {
+            return physis_SqPackResource::default();
+        };
+
+        let resource = SqPackResource::from_existing(&r_path);
+
+        let platform = resource.platform();
+        let release = resource.release;
+
+        physis_SqPackResource {
+            struct_size: struct_size::<physis_SqPackResource>(),
+            handle: RESOURCES.insert(LoadedSqPack {
+                sqpack: Arc::new(Mutex::new(resource)),
+                indexes: Arc::new(Mutex::new(IndexCache::new(&r_path, platform))),
+                game_directory: r_path,
+                linked_sheets: Arc::default(),
+                overlay: None,
+            }),
+            platform,
+            release,
+        }
+    })
+}
+
+/// Returns the resource behind `resource`. This is reference counted, so it stays alive even if another thread frees
+/// the handle while it's still being read from.
+pub(crate) fn shared_sqpack(
+    resource: &physis_SqPackResource,
+) -> Option<Arc<Mutex<SqPackResource>>> {
+    RESOURCES.with(resource.handle, |loaded| loaded.sqpack.clone())
+}
+
+fn shared_indexes(resource: &physis_SqPackResource) -> Option<Arc<Mutex<IndexCache>>> {
+    RESOURCES.with(resource.handle, |loaded| loaded.indexes.clone())
+}
+
+pub(crate) fn linked_sheets(resource: &physis_SqPackResource) -> Option<Arc<Mutex<SheetCache>>> {
+    RESOURCES.with(resource.handle, |loaded| loaded.linked_sheets.clone())
+}
 
-    let resource = SqPackResource::from_existing(&r_path);
+fn game_directory(resource: &physis_SqPackResource) -> Option<String> {
+    RESOURCES.with(resource.handle, |loaded| loaded.game_directory.clone())
+}
+
+fn shared_overlay(resource: &physis_SqPackResource) -> Option<Arc<Mutex<CustomResource>>> {
+    RESOURCES
+        .with(resource.handle, |loaded| loaded.overlay.clone())
+        .flatten()
+}
+
+/// Reads `path` from `overlay`, or returns None if there's no overlay or it doesn't have the file. The overlay is only
+/// locked while reading, so its layers can lock the resource it's attached to.
+fn read_overlay(
+    overlay: Option<&Mutex<CustomResource>>,
+    path: &str,
+) -> Option<physis::Result<physis::ByteBuffer>> {
+    let mut overlay = lock_value(overlay?);
+
+    overlay.exists(path).then(|| overlay.read(path))
+}
+
+/// A resource with its overlay on top, for reading through Physis. Files are found through the same index cache as
+/// `physis_sqpack_read`, so each read only locks the cache while looking the file up.
+pub(crate) struct OverlaidSqPack {
+    sqpack: Arc<Mutex<SqPackResource>>,
+    indexes: Arc<Mutex<IndexCache>>,
+    overlay: Option<Arc<Mutex<CustomResource>>>,
+}
 
-    let platform = resource.platform();
-    let release = resource.release;
+impl OverlaidSqPack {
+    pub(crate) fn platform(&self) -> Platform {
+        lock_value(&self.indexes).platform()
+    }
+}
+
+impl Resource for OverlaidSqPack {
+    fn read(&mut self, path: &str) -> physis::Result<physis::ByteBuffer> {
+        if let Some(result) = read_overlay(self.overlay.as_deref(), path) {
+            return result;
+        }
 
-    let boxed = Box::new(resource);
+        let entry = lock_value(&self.indexes).locate(path);
+        match entry.map(|entry| entry.map(|entry| entry.read())) {
+            Ok(Some(Ok(data))) => Ok(data),
+            Ok(None) => Err(physis::Error::FileNotFound {
+                path: path.to_string(),
+            }),
+            // Only Physis can create its own errors, so it reads the file again to report what went wrong
+            Ok(Some(Err(_))) | Err(_) => lock_sqpack(&self.sqpack).read(path),
+        }
+    }
 
-    physis_SqPackResource {
-        p_ptr: Box::leak(boxed),
-        platform,
-        release,
+    fn exists(&mut self, path: &str) -> bool {
+        self.overlay
+            .as_deref()
+            .is_some_and(|overlay| lock_value(overlay).exists(path))
+            || lock_value(&self.indexes)
+                .locate(path)
+                .is_ok_and(|entry| entry.is_some())
     }
 }
 
-/// Frees this SqPackResource.
+/// Returns the resource behind `resource` with its overlay, like `shared_sqpack`.
+pub(crate) fn shared_resource(resource: &physis_SqPackResource) -> Option<OverlaidSqPack> {
+    RESOURCES.with(resource.handle, |loaded| OverlaidSqPack {
+        sqpack: loaded.sqpack.clone(),
+        indexes: loaded.indexes.clone(),
+        overlay: loaded.overlay.clone(),
+    })
+}
+
+pub(crate) fn lock_sqpack(sqpack: &Mutex<SqPackResource>) -> MutexGuard<'_, SqPackResource> {
+    // A panic while reading doesn't leave the resource in a state that's unsafe to keep using
+    sqpack.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Frees this SqPackResource. If another thread is still using it, it's only freed once they're done.
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_sqpack_free(resource: &physis_SqPackResource) {
-    unsafe {
-        drop(Box::from_raw(resource.p_ptr));
-    }
+    ffi_guard((), || {
+        drop(RESOURCES.remove(resource.handle));
+    })
 }
 
 /// Checks if the file at `path` exists.
@@ -71,39 +213,171 @@ pub extern "C" fn physis_sqpack_exists(
     resource: &physis_SqPackResource,
     path: *const c_char,
 ) -> bool {
-    if let Some(r_path) = ffi_from_c_string(path) {
-        unsafe { (*resource.p_ptr).exists(&r_path) }
-    } else {
-        false
+    ffi_guard(false, || {
+        let Some(mut resource) = shared_resource(resource) else {
+            return false;
+        };
+
+        if let Some(r_path) = ffi_from_c_string(path) {
+            resource.exists(&r_path)
+        } else {
+            false
+        }
+    })
+}
+
+/// Only finding the file is done under the lock, so reading and decompressing it can happen on several threads at once.
+/// Files in `overlay` are read from there instead.
+fn read_file(
+    indexes: &Mutex<IndexCache>,
+    overlay: Option<&Mutex<CustomResource>>,
+    path: &str,
+) -> Option<Vec<u8>> {
+    if let Some(result) = read_overlay(overlay, path) {
+        return ffi_result(result);
+    }
+
+    let entry = lock_value(indexes).locate(path);
+
+    let result = match entry {
+        Ok(Some(entry)) => entry
+            .read()
+            .map_err(|err| (physis_ErrorCode::ParseFailed, err)),
+        Ok(None) => Err((
+            physis_ErrorCode::FileNotFound,
+            format!("Could not find {path}"),
+        )),
+        Err(err) => Err((physis_ErrorCode::ParseFailed, err)),
+    };
+
+    match result {
+        Ok(data) => Some(data),
+        Err((code, err)) => {
+            set_last_error(code, err);
+            None
+        }
     }
 }
 
+/// Like `read_file`, only finding the file is done under the lock. Errors come with the code they should be reported as.
+fn stat_file(
+    indexes: &Mutex<IndexCache>,
+    path: &str,
+) -> Result<FileStat, (physis_ErrorCode, String)> {
+    let entry = lock_value(indexes)
+        .locate(path)
+        .map_err(|err| (physis_ErrorCode::ParseFailed, err))?;
+
+    entry
+        .ok_or_else(|| {
+            (
+                physis_ErrorCode::FileNotFound,
+                format!("Could not find {path}"),
+            )
+        })?
+        .stat()
+        .map_err(|err| (physis_ErrorCode::ParseFailed, err))
+}
+
+pub(crate) fn sqpack_read(
+    resource: &physis_SqPackResource,
+    path: *const c_char,
+) -> Option<Vec<u8>> {
+    let indexes = shared_indexes(resource)?;
+    let overlay = shared_overlay(resource);
+    let path = ffi_from_c_string(path)?;
+
+    read_file(&indexes, overlay.as_deref(), &path)
+}
+
+/// Called when an asynchronous read finishes. The buffer must be freed with `physis_free_file`.
+pub type physis_ReadCallback = extern "C" fn(
+    user_data: *mut c_void,
+    job: physis_JobId,
+    status: physis_JobStatus,
+    buffer: physis_Buffer,
+);
+
 /// Extracts the raw game file from `path`, and puts it in `data` with `size` length. If the path was not found,
-/// `size` is 0 and `data` is NULL.
+/// `size` is 0 and `data` is NULL. Files larger than 4 GiB are rejected with `TooLarge`, use `physis_sqpack_read_large`
+/// for those.
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_sqpack_read(
     resource: &physis_SqPackResource,
     path: *const c_char,
 ) -> physis_Buffer {
-    unsafe {
-        if let Ok(mut d) = (*resource.p_ptr).read(CStr::from_ptr(path).to_string_lossy().as_ref()) {
-            let b = physis_Buffer {
-                size: d.len() as u32,
-                data: d.as_mut_ptr(),
-            };
+    ffi_guard(physis_Buffer::default(), || {
+        sqpack_read(resource, path)
+            .map(ffi_to_buffer)
+            .unwrap_or_default()
+    })
+}
 
-            mem::forget(d);
+/// Same as `physis_sqpack_read`, but for files of any size. The buffer must be freed with `physis_free_large_buffer`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_sqpack_read_large(
+    resource: &physis_SqPackResource,
+    path: *const c_char,
+) -> physis_LargeBuffer {
+    ffi_guard(physis_LargeBuffer::default(), || {
+        sqpack_read(resource, path)
+            .map(ffi_to_large_buffer)
+            .unwrap_or_default()
+    })
+}
 
-            b
-        } else {
-            physis_Buffer::default()
+/// Same as `physis_sqpack_read`, but reads the file on a worker thread and then calls `callback` from there. Returns
+/// the job id, or 0 if it couldn't be queued.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_sqpack_read_async(
+    resource: &physis_SqPackResource,
+    path: *const c_char,
+    callback: physis_ReadCallback,
+    user_data: *mut c_void,
+) -> physis_JobId {
+    ffi_guard(0, || {
+        let Some(indexes) = shared_indexes(resource) else {
+            return 0;
+        };
+        let overlay = shared_overlay(resource);
+        let Some(path) = ffi_from_c_string(path) else {
+            return 0;
+        };
+
+        spawn_job(
+            move || {
+                read_file(&indexes, overlay.as_deref(), &path)
+                    .map(ffi_to_buffer)
+                    .unwrap_or_default()
+            },
+            move |job, status, buffer| callback(user_data, job, status, buffer),
+        )
+    })
+}
+
+/// A sheet handed out as a `physis_ExcelSheet`.
+struct LoadedSheet {
+    sheet: Sheet,
+    /// Where each row is in `physis_ExcelSheet.pages`, as (page index, entry index).
+    rows: HashMap<u32, (usize, usize)>,
+}
+
+impl LoadedSheet {
+    fn new(sheet: Sheet) -> Self {
+        let mut rows = HashMap::new();
+        for (page_index, page) in sheet.pages.iter().enumerate() {
+            for (entry_index, entry) in page.entries.iter().enumerate() {
+                rows.insert(entry.id, (page_index, entry_index));
+            }
         }
+
+        Self { sheet, rows }
     }
 }
 
 #[repr(C)]
 pub struct physis_ExcelSheetPage {
-    p_ptr: *mut Sheet,
+    sheet: physis_Handle,
     page_index: u32,
     pub entry_count: c_uint,
     pub entries: *mut physis_ExcelEntry,
@@ -115,7 +389,7 @@ pub extern "C" fn physis_excel_page_free_rows(
     page: &physis_ExcelSheetPage,
     rows: &physis_ExcelEntry,
 ) {
-    unsafe {
+    ffi_guard((), || unsafe {
         let data = Vec::from_raw_parts(
             rows.subrows,
             rows.subrow_count as usize,
@@ -139,12 +413,13 @@ pub extern "C" fn physis_excel_page_free_rows(
         }
 
         drop(data);
-    }
+    })
 }
 
 #[repr(C)]
 pub struct physis_ExcelSheet {
-    p_ptr: *mut Sheet,
+    struct_size: u32,
+    handle: physis_Handle,
     page_count: u32,
     pages: *mut physis_ExcelSheetPage,
 }
@@ -152,7 +427,8 @@ pub struct physis_ExcelSheet {
 impl Default for physis_ExcelSheet {
     fn default() -> Self {
         Self {
-            p_ptr: null_mut(),
+            struct_size: struct_size::<physis_ExcelSheet>(),
+            handle: physis_Handle::default(),
             page_count: 0,
             pages: null_mut(),
         }
@@ -177,23 +453,44 @@ fn to_c_entry(row_id: u32, entry: &Entry) -> physis_ExcelEntry {
     rows
 }
 
-fn to_c_row(subrow_id: u16, row: &Row) -> physis_ExcelRow {
+fn to_c_field(field: &Field) -> physis_Field {
+    match field {
+        Field::String(s) => physis_Field::String(ffi_to_c_string(s)),
+        Field::Bool(b) => physis_Field::Bool(*b),
+        Field::Int8(i) => physis_Field::Int8(*i),
+        Field::UInt8(i) => physis_Field::UInt8(*i),
+        Field::Int16(i) => physis_Field::Int16(*i),
+        Field::UInt16(i) => physis_Field::UInt16(*i),
+        Field::Int32(i) => physis_Field::Int32(*i),
+        Field::UInt32(i) => physis_Field::UInt32(*i),
+        Field::Float32(i) => physis_Field::Float32(*i),
+        Field::Int64(i) => physis_Field::Int64(*i),
+        Field::UInt64(i) => physis_Field::UInt64(*i),
+    }
+}
+
+/// Returns None if `field` is a NULL string.
+fn to_rust_field(field: &physis_Field) -> Option<Field> {
+    Some(match field {
+        physis_Field::String(val) => Field::String(ffi_from_c_string(*val)?),
+        physis_Field::Bool(val) => Field::Bool(*val),
+        physis_Field::Int8(val) => Field::Int8(*val),
+        physis_Field::UInt8(val) => Field::UInt8(*val),
+        physis_Field::Int16(val) => Field::Int16(*val),
+        physis_Field::UInt16(val) => Field::UInt16(*val),
+        physis_Field::Int32(val) => Field::Int32(*val),
+        physis_Field::UInt32(val) => Field::UInt32(*val),
+        physis_Field::Float32(val) => Field::Float32(*val),
+        physis_Field::Int64(val) => Field::Int64(*val),
+        physis_Field::UInt64(val) => Field::UInt64(*val),
+    })
+}
+
+pub(crate) fn to_c_row(subrow_id: u16, row: &Row) -> physis_ExcelRow {
     let mut c_col_data: Vec<physis_Field> = Vec::new();
 
     for col_data in &row.columns {
-        match &col_data {
-            Field::String(s) => c_col_data.push(physis_Field::String(ffi_to_c_string(s))),
-            Field::Bool(b) => c_col_data.push(physis_Field::Bool(*b)),
-            Field::Int8(i) => c_col_data.push(physis_Field::Int8(*i)),
-            Field::UInt8(i) => c_col_data.push(physis_Field::UInt8(*i)),
-            Field::Int16(i) => c_col_data.push(physis_Field::Int16(*i)),
-            Field::UInt16(i) => c_col_data.push(physis_Field::UInt16(*i)),
-            Field::Int32(i) => c_col_data.push(physis_Field::Int32(*i)),
-            Field::UInt32(i) => c_col_data.push(physis_Field::UInt32(*i)),
-            Field::Float32(i) => c_col_data.push(physis_Field::Float32(*i)),
-            Field::Int64(i) => c_col_data.push(physis_Field::Int64(*i)),
-            Field::UInt64(i) => c_col_data.push(physis_Field::UInt64(*i)),
-        }
+        c_col_data.push(to_c_field(col_data));
     }
 
     let row = physis_ExcelRow {
@@ -213,53 +510,110 @@ pub unsafe extern "C" fn physis_sqpack_read_excel_sheet(
     exh: &physis_EXH,
     language: Language,
 ) -> physis_ExcelSheet {
-    unsafe {
+    ffi_guard(physis_ExcelSheet::default(), || {
         let Some(r_name) = ffi_from_c_string(name) else {
             return physis_ExcelSheet::default();
         };
 
-        if let Ok(exd) = (*resource.p_ptr).read_excel_sheet(&*exh.p_ptr, &r_name, language) {
-            let exd = Box::new(exd);
-            let pages = exd.pages.clone();
-            let p_ptr = Box::leak(exd);
+        let Some(mut resource) = shared_resource(resource) else {
+            return physis_ExcelSheet::default();
+        };
+
+        read_excel_sheet(&mut resource, &r_name, exh.handle, language)
+    })
+}
 
-            let mut c_pages = Vec::new();
-            for (i, page) in pages.iter().enumerate() {
-                let mut c_entries = Vec::new();
+/// Called when an asynchronous excel sheet read finishes. The sheet must be freed with `physis_sqpack_free_excel_sheet`.
+pub type physis_ExcelSheetCallback = extern "C" fn(
+    user_data: *mut c_void,
+    job: physis_JobId,
+    status: physis_JobStatus,
+    sheet: physis_ExcelSheet,
+);
 
-                for row in &page.entries {
-                    c_entries.push(to_c_entry(row.id, row));
-                }
+/// Same as `physis_sqpack_read_excel_sheet`, but reads the sheet on a worker thread and then calls `callback` from
+/// there. `exh` must not be freed until then. Returns the job id, or 0 if it couldn't be queued.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_sqpack_read_excel_sheet_async(
+    resource: &physis_SqPackResource,
+    name: *const c_char,
+    exh: &physis_EXH,
+    language: Language,
+    callback: physis_ExcelSheetCallback,
+    user_data: *mut c_void,
+) -> physis_JobId {
+    ffi_guard(0, || {
+        let Some(r_name) = ffi_from_c_string(name) else {
+            return 0;
+        };
 
-                let page = physis_ExcelSheetPage {
-                    p_ptr,
-                    page_index: i as u32,
-                    column_count: (*exh.p_ptr).column_definitions.len() as c_uint,
-                    entry_count: page.entries.len() as u32,
-                    entries: c_entries.as_mut_ptr(),
-                };
+        let Some(mut resource) = shared_resource(resource) else {
+            return 0;
+        };
+
+        let exh = exh.handle;
 
-                mem::forget(c_entries);
+        spawn_job(
+            move || read_excel_sheet(&mut resource, &r_name, exh, language),
+            move |job, status, sheet| callback(user_data, job, status, sheet),
+        )
+    })
+}
+
+fn read_excel_sheet<R: Resource>(
+    resource: &mut R,
+    name: &str,
+    exh: physis_Handle,
+    language: Language,
+) -> physis_ExcelSheet {
+    let Some(header) = EXHS.shared(exh) else {
+        return physis_ExcelSheet::default();
+    };
+    let header = lock_value(&header);
+
+    // Only hold the lock while reading, converting the sheet doesn't need the resource
+    let exd = generic_read_excel_sheet(resource, &header, name, language);
+
+    if let Some(exd) = ffi_result(exd) {
+        let pages = exd.pages.clone();
+        let handle = SHEETS.insert(LoadedSheet::new(exd));
+
+        let mut c_pages = Vec::new();
+        for (i, page) in pages.iter().enumerate() {
+            let mut c_entries = Vec::new();
 
-                c_pages.push(page);
+            for row in &page.entries {
+                c_entries.push(to_c_entry(row.id, row));
             }
 
-            let exd = physis_ExcelSheet {
-                p_ptr,
-                page_count: c_pages.len() as u32,
-                pages: c_pages.as_mut_ptr(),
+            let page = physis_ExcelSheetPage {
+                sheet: handle,
+                page_index: i as u32,
+                column_count: header.column_definitions.len() as c_uint,
+                entry_count: page.entries.len() as u32,
+                entries: c_entries.as_mut_ptr(),
             };
 
-            mem::forget(c_pages);
+            mem::forget(c_entries);
 
-            exd
-        } else {
-            physis_ExcelSheet::default()
+            c_pages.push(page);
         }
+
+        let exd = physis_ExcelSheet {
+            struct_size: struct_size::<physis_ExcelSheet>(),
+            handle,
+            page_count: c_pages.len() as u32,
+            pages: c_pages.as_mut_ptr(),
+        };
+
+        mem::forget(c_pages);
+
+        exd
+    } else {
+        physis_ExcelSheet::default()
     }
 }
 
-// TODO: is there some way to de-duplicate these functions?
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn physis_custom_read_excel_sheet(
     resource: &physis_CustomResource,
@@ -267,54 +621,24 @@ pub unsafe extern "C" fn physis_custom_read_excel_sheet(
     exh: &physis_EXH,
     language: Language,
 ) -> physis_ExcelSheet {
-    unsafe {
+    ffi_guard(physis_ExcelSheet::default(), || {
         let Some(r_name) = ffi_from_c_string(name) else {
             return physis_ExcelSheet::default();
         };
 
-        if let Ok(exd) =
-            generic_read_excel_sheet(&mut *resource.p_ptr, &*exh.p_ptr, &r_name, language)
-        {
-            let exd = Box::new(exd);
-            let pages = exd.pages.clone();
-            let p_ptr = Box::leak(exd);
-
-            let mut c_pages = Vec::new();
-            for (i, page) in pages.iter().enumerate() {
-                let mut c_entries = Vec::new();
-
-                for row in &page.entries {
-                    c_entries.push(to_c_entry(row.id, row));
-                }
-
-                let page = physis_ExcelSheetPage {
-                    p_ptr,
-                    page_index: i as u32,
-                    column_count: (*exh.p_ptr).column_definitions.len() as c_uint,
-                    entry_count: page.entries.len() as u32,
-                    entries: c_entries.as_mut_ptr(),
-                };
-
-                mem::forget(c_entries);
-
-                c_pages.push(page);
-            }
-
-            let exd = physis_ExcelSheet {
-                p_ptr,
-                page_count: c_pages.len() as u32,
-                pages: c_pages.as_mut_ptr(),
-            };
-
-            mem::forget(c_pages);
+        let Some(custom) = CUSTOM_RESOURCES.shared(resource.handle) else {
+            return physis_ExcelSheet::default();
+        };
 
-            exd
-        } else {
-            physis_ExcelSheet::default()
-        }
-    }
+        read_excel_sheet(&mut *lock_value(&custom), &r_name, exh.handle, language)
+    })
 }
 
+/// Sets column `column_index` of `row_id`.`subrow_id` in `page` to `new_field`. Strings are copied, so `new_field` is
+/// still owned by the caller afterwards.
+///
+/// Does nothing and sets the last error to InvalidArgument if `column_index` is out of bounds, or `new_field` is a NULL
+/// string.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn physis_sqpack_update_excel_sheet_page(
     page: &mut physis_ExcelSheetPage,
@@ -323,44 +647,65 @@ pub unsafe extern "C" fn physis_sqpack_update_excel_sheet_page(
     column_index: usize,
     new_field: &physis_Field,
 ) {
-    unsafe {
-        for i in 0..page.entry_count {
-            let entry = page.entries.add(i as usize);
-            if (*entry).row_id == row_id {
-                for j in 0..(*entry).subrow_count {
-                    let subrow = (*entry).subrows.add(j as usize);
-                    if (*subrow).subrow_id == subrow_id {
-                        // Update the C++ model
-                        *(*subrow).columns.add(column_index) = (*new_field).clone();
-                        // Then update the Rust model
-                        if let Some(entry) = (*page.p_ptr).entry_mut(row_id) {
-                            for (id, subrow) in &mut entry.subrows {
-                                if *id == subrow_id {
-                                    let old_field = &mut subrow.columns[column_index];
-                                    *old_field = match new_field {
-                                        physis_Field::String(val) => {
-                                            Field::String(ffi_from_c_string(*val).unwrap())
-                                        }
-                                        physis_Field::Bool(val) => Field::Bool(*val),
-                                        physis_Field::Int8(val) => Field::Int8(*val),
-                                        physis_Field::UInt8(val) => Field::UInt8(*val),
-                                        physis_Field::Int16(val) => Field::Int16(*val),
-                                        physis_Field::UInt16(val) => Field::UInt16(*val),
-                                        physis_Field::Int32(val) => Field::Int32(*val),
-                                        physis_Field::UInt32(val) => Field::UInt32(*val),
-                                        physis_Field::Float32(val) => Field::Float32(*val),
-                                        physis_Field::Int64(val) => Field::Int64(*val),
-                                        physis_Field::UInt64(val) => Field::UInt64(*val),
-                                    };
+    ffi_guard((), || {
+        if column_index >= page.column_count as usize {
+            set_last_error(
+                physis_ErrorCode::InvalidArgument,
+                format!(
+                    "Column {column_index} is out of bounds, the page has {} columns",
+                    page.column_count
+                ),
+            );
+            return;
+        }
+
+        let Some(field) = to_rust_field(new_field) else {
+            set_last_error(
+                physis_ErrorCode::InvalidArgument,
+                "The new field is a NULL string",
+            );
+            return;
+        };
+
+        let Some(sheet) = SHEETS.shared(page.sheet) else {
+            return;
+        };
+        let mut sheet = lock_value(&sheet);
+
+        unsafe {
+            for i in 0..page.entry_count {
+                let entry = page.entries.add(i as usize);
+                if (*entry).row_id == row_id {
+                    for j in 0..(*entry).subrow_count {
+                        let subrow = (*entry).subrows.add(j as usize);
+                        if (*subrow).subrow_id == subrow_id {
+                            // Update the C++ model, the old string was allocated by us
+                            let c_field = (*subrow).columns.add(column_index);
+                            if let physis_Field::String(s) = &*c_field
+                                && !s.is_null()
+                            {
+                                ffi_free_string(*s);
+                            }
+                            c_field.write(to_c_field(&field));
+
+                            // Then update the Rust model
+                            if let Some(entry) = sheet.sheet.entry_mut(row_id) {
+                                for (id, subrow) in &mut entry.subrows {
+                                    if *id == subrow_id
+                                        && let Some(old_field) =
+                                            subrow.columns.get_mut(column_index)
+                                    {
+                                        *old_field = field.clone();
+                                    }
                                 }
                             }
+                            break;
                         }
-                        break;
                     }
                 }
             }
         }
-    }
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -368,114 +713,550 @@ pub unsafe extern "C" fn physis_sqpack_write_sheet_page_to_buffer(
     page: &mut physis_ExcelSheetPage,
     exh: &physis_EXH,
 ) -> physis_Buffer {
-    unsafe {
-        if let Some(mut d) =
-            (&(*page.p_ptr)).pages[page.page_index as usize].write_to_buffer(&*exh.p_ptr)
-        {
-            let b = physis_Buffer {
-                size: d.len() as u32,
-                data: d.as_mut_ptr(),
-            };
-
-            mem::forget(d);
+    ffi_guard(physis_Buffer::default(), || {
+        let Some(sheet) = SHEETS.shared(page.sheet) else {
+            return physis_Buffer::default();
+        };
+        let Some(header) = EXHS.shared(exh.handle) else {
+            return physis_Buffer::default();
+        };
+        let (sheet, header) = (lock_value(&sheet), lock_value(&header));
 
-            return b;
+        if let Some(d) = sheet.sheet.pages[page.page_index as usize].write_to_buffer(&header) {
+            return ffi_to_buffer(d);
         }
 
+        set_last_error(
+            physis_ErrorCode::WriteFailed,
+            format!("Failed to write page {}", page.page_index),
+        );
         physis_Buffer::default()
+    })
+}
+
+fn field_to_json(field: &Field) -> Value {
+    match field {
+        Field::String(s) => Value::from(s.as_str()),
+        Field::Bool(b) => Value::from(*b),
+        Field::Int8(i) => Value::from(*i),
+        Field::UInt8(i) => Value::from(*i),
+        Field::Int16(i) => Value::from(*i),
+        Field::UInt16(i) => Value::from(*i),
+        Field::Int32(i) => Value::from(*i),
+        Field::UInt32(i) => Value::from(*i),
+        Field::Float32(i) => Value::from(*i),
+        Field::Int64(i) => Value::from(*i),
+        Field::UInt64(i) => Value::from(*i),
+    }
+}
+
+/// Returns `entries` as JSON, in the form `[{"row_id": 0, "subrows": [{"subrow_id": 0, "columns": [...]}]}]`.
+pub(crate) fn entries_to_json(entries: &[Entry]) -> Value {
+    let mut rows = Vec::new();
+    for entry in entries {
+        let mut subrows = Vec::new();
+        for (subrow_id, row) in &entry.subrows {
+            subrows.push(json!({
+                "subrow_id": subrow_id,
+                "columns": row.columns.iter().map(field_to_json).collect::<Vec<_>>(),
+            }));
+        }
+
+        rows.push(json!({
+            "row_id": entry.id,
+            "subrows": subrows,
+        }));
     }
+
+    Value::Array(rows)
 }
 
+/// Converts `value` to the same type as `existing`, since the column types are fixed by the EXH.
+fn field_from_json(existing: &Field, value: &Value) -> Option<Field> {
+    Some(match existing {
+        Field::String(_) => Field::String(value.as_str()?.to_string()),
+        Field::Bool(_) => Field::Bool(value.as_bool()?),
+        Field::Int8(_) => Field::Int8(value.as_i64()?.try_into().ok()?),
+        Field::UInt8(_) => Field::UInt8(value.as_u64()?.try_into().ok()?),
+        Field::Int16(_) => Field::Int16(value.as_i64()?.try_into().ok()?),
+        Field::UInt16(_) => Field::UInt16(value.as_u64()?.try_into().ok()?),
+        Field::Int32(_) => Field::Int32(value.as_i64()?.try_into().ok()?),
+        Field::UInt32(_) => Field::UInt32(value.as_u64()?.try_into().ok()?),
+        Field::Float32(_) => Field::Float32(value.as_f64()? as f32),
+        Field::Int64(_) => Field::Int64(value.as_i64()?),
+        Field::UInt64(_) => Field::UInt64(value.as_u64()?),
+    })
+}
+
+/// Returns the rows of `page` as JSON, in the form `[{"row_id": 0, "subrows": [{"subrow_id": 0, "columns": [...]}]}]`.
+/// The string must be freed with `physis_free_string`.
 #[unsafe(no_mangle)]
-pub extern "C" fn physis_sqpack_free_excel_sheet(sheet: &physis_ExcelSheet) {
-    if sheet.p_ptr.is_null() {
-        return;
+pub unsafe extern "C" fn physis_sqpack_excel_sheet_page_to_json(
+    page: &physis_ExcelSheetPage,
+) -> *const c_char {
+    ffi_guard(null(), || {
+        let Some(sheet) = SHEETS.shared(page.sheet) else {
+            return null();
+        };
+        let sheet = lock_value(&sheet);
+
+        ffi_to_c_string(
+            &entries_to_json(&sheet.sheet.pages[page.page_index as usize].entries).to_string(),
+        )
+    })
+}
+
+/// Checks every row in `json` against `entries`, and returns the fields to change as (row, subrow, column, field).
+fn excel_page_changes_from_json(
+    entries: &[Entry],
+    json: &str,
+) -> Result<Vec<(u32, u16, usize, Field)>, String> {
+    let json_entries: Vec<Value> =
+        serde_json::from_str(json).map_err(|err| format!("Invalid JSON: {err}"))?;
+
+    let mut changes = Vec::new();
+    for json_entry in &json_entries {
+        let row_id = json_entry["row_id"]
+            .as_u64()
+            .and_then(|id| u32::try_from(id).ok())
+            .ok_or("Every row needs a valid row_id")?;
+
+        let Some(entry) = entries.iter().find(|entry| entry.id == row_id) else {
+            return Err(format!("Row {row_id} isn't in this page"));
+        };
+
+        for json_subrow in json_entry["subrows"].as_array().into_iter().flatten() {
+            let subrow_id = json_subrow["subrow_id"]
+                .as_u64()
+                .and_then(|id| u16::try_from(id).ok())
+                .ok_or_else(|| format!("Every subrow of row {row_id} needs a valid subrow_id"))?;
+
+            let mut columns = None;
+            for (id, row) in &entry.subrows {
+                if *id == subrow_id {
+                    columns = Some(&row.columns);
+                }
+            }
+            let Some(columns) = columns else {
+                return Err(format!("Row {row_id} has no subrow {subrow_id}"));
+            };
+
+            let Some(values) = json_subrow["columns"].as_array() else {
+                return Err(format!("Row {row_id}.{subrow_id} is missing its columns"));
+            };
+            if values.len() != columns.len() {
+                return Err(format!(
+                    "Row {row_id}.{subrow_id} has {} columns, but the sheet has {}",
+                    values.len(),
+                    columns.len()
+                ));
+            }
+
+            for (column, (existing, value)) in columns.iter().zip(values).enumerate() {
+                let Some(field) = field_from_json(existing, value) else {
+                    return Err(format!(
+                        "Column {column} of row {row_id}.{subrow_id} doesn't fit in {existing:?}"
+                    ));
+                };
+
+                changes.push((row_id, subrow_id, column, field));
+            }
+        }
     }
 
-    unsafe {
-        let data = ffi_to_vec(sheet.pages, sheet.page_count);
-        for page in &data {
-            let data = ffi_to_vec(page.entries, page.entry_count);
-            for entry in &data {
-                let data = ffi_to_vec(entry.subrows, entry.subrow_count);
-                for subrow in &data {
-                    let data = ffi_to_vec(subrow.columns, page.column_count);
-                    for column in &data {
-                        if let physis_Field::String(string) = &column {
-                            ffi_free_string(*string)
+    Ok(changes)
+}
+
+/// Updates `page` from JSON in the format returned by `physis_sqpack_excel_sheet_page_to_json`, so it can be written
+/// with `physis_sqpack_write_sheet_page_to_buffer`. Rows are matched by their ids, and rows missing from `json` are
+/// left alone. Adding or removing rows isn't supported.
+///
+/// If any value doesn't fit its column, nothing is changed and false is returned.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn physis_sqpack_update_excel_sheet_page_from_json(
+    page: &mut physis_ExcelSheetPage,
+    json: *const c_char,
+) -> bool {
+    ffi_guard(false, || unsafe {
+        let Some(json) = ffi_from_c_string(json) else {
+            return false;
+        };
+
+        let Some(sheet) = SHEETS.shared(page.sheet) else {
+            return false;
+        };
+        let mut sheet = lock_value(&sheet);
+        let Some(rust_page) = sheet.sheet.pages.get_mut(page.page_index as usize) else {
+            set_last_error(
+                physis_ErrorCode::InvalidArgument,
+                format!("Page {} isn't in this sheet", page.page_index),
+            );
+            return false;
+        };
+
+        let changes = match excel_page_changes_from_json(&rust_page.entries, &json) {
+            Ok(changes) => changes,
+            Err(err) => {
+                set_last_error(physis_ErrorCode::InvalidArgument, err);
+                return false;
+            }
+        };
+
+        // The C columns are only column_count long, even if the Rust rows somehow have more
+        if let Some((row_id, subrow_id, column, _)) = changes
+            .iter()
+            .find(|(_, _, column, _)| *column >= page.column_count as usize)
+        {
+            set_last_error(
+                physis_ErrorCode::InvalidArgument,
+                format!(
+                    "Column {column} of row {row_id}.{subrow_id} is out of bounds, the page has {} columns",
+                    page.column_count
+                ),
+            );
+            return false;
+        }
+
+        for (row_id, subrow_id, column, field) in changes {
+            // Update the C++ model
+            for i in 0..page.entry_count {
+                let entry = page.entries.add(i as usize);
+                if (*entry).row_id != row_id {
+                    continue;
+                }
+
+                for j in 0..(*entry).subrow_count {
+                    let subrow = (*entry).subrows.add(j as usize);
+                    if (*subrow).subrow_id == subrow_id {
+                        let c_field = (*subrow).columns.add(column);
+                        if let physis_Field::String(s) = &*c_field
+                            && !s.is_null()
+                        {
+                            ffi_free_string(*s);
+                        }
+                        c_field.write(to_c_field(&field));
+                    }
+                }
+            }
+
+            // Then update the Rust model
+            for entry in &mut rust_page.entries {
+                if entry.id != row_id {
+                    continue;
+                }
+
+                for (id, row) in &mut entry.subrows {
+                    if *id == subrow_id {
+                        row.columns[column] = field.clone();
+                    }
+                }
+            }
+        }
+
+        true
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_sqpack_free_excel_sheet(sheet: &physis_ExcelSheet) {
+    ffi_guard((), || {
+        if sheet.handle.is_null() {
+            return;
+        }
+
+        let Some(excel_sheet) = SHEETS.remove(sheet.handle) else {
+            return;
+        };
+
+        unsafe {
+            let data = ffi_to_vec(sheet.pages, sheet.page_count);
+            for page in &data {
+                let data = ffi_to_vec(page.entries, page.entry_count);
+                for entry in &data {
+                    let data = ffi_to_vec(entry.subrows, entry.subrow_count);
+                    for subrow in &data {
+                        let data = ffi_to_vec(subrow.columns, page.column_count);
+                        for column in &data {
+                            if let physis_Field::String(string) = &column {
+                                ffi_free_string(*string)
+                            }
                         }
+                        drop(data);
                     }
                     drop(data);
                 }
                 drop(data);
             }
             drop(data);
-        }
-        drop(data);
 
-        drop(Box::from_raw(sheet.p_ptr));
-    }
+            drop(excel_sheet);
+        }
+    })
 }
 
+/// Returns a copy of `row_id`, which must be freed with `physis_free_row`. Use `physis_excel_find_row` to read it
+/// without copying.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn physis_excel_get_row(
     sheet: &physis_ExcelSheet,
     row_id: u32,
 ) -> physis_ExcelRow {
-    unsafe {
-        if let Some(row) = (*sheet.p_ptr).row(row_id) {
-            return to_c_row(0, row);
-        }
-    }
-
-    physis_ExcelRow::default()
+    unsafe { physis_excel_get_subrow(sheet, row_id, 0) }
 }
 
+/// Returns a copy of `row_id`.`subrow_id`, which must be freed with `physis_free_row`. Use `physis_excel_find_subrow`
+/// to read it without copying.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn physis_excel_get_subrow(
     sheet: &physis_ExcelSheet,
     row_id: u32,
     subrow_id: u16,
 ) -> physis_ExcelRow {
-    unsafe {
-        if let Some(row) = (*sheet.p_ptr).subrow(row_id, subrow_id) {
-            return to_c_row(subrow_id, row);
+    ffi_guard(physis_ExcelRow::default(), || {
+        // Copies the converted columns, so there's no need to convert the row from the sheet again
+        let view = physis_excel_find_subrow(sheet, row_id, subrow_id);
+        if view.columns.is_null() {
+            return physis_ExcelRow::default();
         }
-    }
 
-    physis_ExcelRow::default()
+        let columns = unsafe { slice::from_raw_parts(view.columns, view.column_count as usize) };
+        let mut c_columns: Vec<physis_Field> = columns
+            .iter()
+            .map(|field| match field {
+                physis_Field::String(s) if !s.is_null() => {
+                    physis_Field::String(unsafe { CStr::from_ptr(*s) }.to_owned().into_raw())
+                }
+                field => field.clone(),
+            })
+            .collect();
+
+        let row = physis_ExcelRow {
+            subrow_id,
+            columns: c_columns.as_mut_ptr(),
+        };
+
+        mem::forget(c_columns);
+
+        row
+    })
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_free_row(row: &physis_ExcelRow, column_count: u32) {
-    if row.columns.is_null() {
-        return;
-    }
+    ffi_guard((), || {
+        if row.columns.is_null() {
+            return;
+        }
 
-    let data = ffi_to_vec(row.columns, column_count);
+        let data = ffi_to_vec(row.columns, column_count);
 
-    for field in &data {
-        if let physis_Field::String(string) = *field {
-            ffi_free_string(string)
+        for field in &data {
+            if let physis_Field::String(string) = *field {
+                ffi_free_string(string)
+            }
         }
-    }
 
-    drop(data)
+        drop(data)
+    })
 }
 
-// TODO: not final API, this sucks
+/// Returns how many subrows `row_id` has, or 0 if the row doesn't exist.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn physis_excel_get_subrow_count(
     sheet: &physis_ExcelSheet,
     row_id: u32,
 ) -> usize {
+    ffi_guard(0, || match find_entry(sheet, row_id) {
+        Some(entry) => entry.subrow_count as usize,
+        None => {
+            set_last_error(
+                physis_ErrorCode::InvalidArgument,
+                format!("Row {row_id} not found"),
+            );
+            0
+        }
+    })
+}
+
+/// Looks up the converted entry for `row_id` in `sheet`.
+fn find_entry(sheet: &physis_ExcelSheet, row_id: u32) -> Option<&physis_ExcelEntry> {
+    let (page_index, entry_index) =
+        SHEETS.with(sheet.handle, |loaded| loaded.rows.get(&row_id).copied())??;
+
+    if page_index >= sheet.page_count as usize {
+        return None;
+    }
+
     unsafe {
-        if let Some(row) = (*sheet.p_ptr).entry(row_id) {
-            return row.subrows.len();
+        let page = &*sheet.pages.add(page_index);
+        if entry_index >= page.entry_count as usize {
+            return None;
+        }
+
+        Some(&*page.entries.add(entry_index))
+    }
+}
+
+/// A subrow that points into the `physis_ExcelSheet` it came from. It must not be freed, and stays valid until the
+/// sheet is freed.
+#[repr(C)]
+pub struct physis_ExcelRowView {
+    pub row_id: u32,
+    pub subrow_id: u16,
+    /// How many subrows the row has in total.
+    pub subrow_count: u32,
+    pub column_count: u32,
+    pub columns: *const physis_Field,
+}
+
+impl Default for physis_ExcelRowView {
+    fn default() -> Self {
+        Self {
+            row_id: 0,
+            subrow_id: 0,
+            subrow_count: 0,
+            column_count: 0,
+            columns: null(),
+        }
+    }
+}
+
+fn to_row_view(
+    entry: &physis_ExcelEntry,
+    subrow: &physis_ExcelRow,
+    column_count: u32,
+) -> physis_ExcelRowView {
+    physis_ExcelRowView {
+        row_id: entry.row_id,
+        subrow_id: subrow.subrow_id,
+        subrow_count: entry.subrow_count,
+        column_count,
+        columns: subrow.columns,
+    }
+}
+
+/// Same as `physis_excel_get_subrow`, but returns a view into `sheet` instead of a copy, and looks the row up in
+/// constant time. If the subrow was not found, `columns` is NULL.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_excel_find_subrow(
+    sheet: &physis_ExcelSheet,
+    row_id: u32,
+    subrow_id: u16,
+) -> physis_ExcelRowView {
+    ffi_guard(physis_ExcelRowView::default(), || {
+        let subrow = find_entry(sheet, row_id).and_then(|entry| {
+            let subrows =
+                unsafe { slice::from_raw_parts(entry.subrows, entry.subrow_count as usize) };
+            subrows
+                .iter()
+                .find(|subrow| subrow.subrow_id == subrow_id)
+                .map(|subrow| (entry, subrow))
+        });
+
+        let Some((entry, subrow)) = subrow else {
+            set_last_error(
+                physis_ErrorCode::InvalidArgument,
+                format!("Subrow {row_id}.{subrow_id} not found"),
+            );
+            return physis_ExcelRowView::default();
+        };
+
+        // Every page of a sheet has the same columns
+        let column_count = unsafe { (*sheet.pages).column_count };
+
+        to_row_view(entry, subrow, column_count)
+    })
+}
+
+/// Same as `physis_excel_get_row`, but returns a view into `sheet` instead of a copy, and looks the row up in
+/// constant time. If the row was not found, `columns` is NULL.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_excel_find_row(
+    sheet: &physis_ExcelSheet,
+    row_id: u32,
+) -> physis_ExcelRowView {
+    physis_excel_find_subrow(sheet, row_id, 0)
+}
+
+/// Walks over every subrow of every row in a sheet, see `physis_excel_iterate`.
+#[repr(C)]
+pub struct physis_ExcelRowIterator {
+    /// Checked on every step, so the pages are never read after the sheet is freed.
+    sheet: physis_Handle,
+    page_count: u32,
+    pages: *const physis_ExcelSheetPage,
+    page_index: u32,
+    entry_index: u32,
+    subrow_index: u32,
+}
+
+impl Default for physis_ExcelRowIterator {
+    fn default() -> Self {
+        Self {
+            sheet: physis_Handle::default(),
+            page_count: 0,
+            pages: null(),
+            page_index: 0,
+            entry_index: 0,
+            subrow_index: 0,
         }
     }
+}
+
+/// Returns an iterator over every subrow in `sheet`, in the order they're stored. It doesn't need to be freed. Once the
+/// sheet is freed, the iterator stops and sets the last error to InvalidHandle.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_excel_iterate(sheet: &physis_ExcelSheet) -> physis_ExcelRowIterator {
+    ffi_guard(physis_ExcelRowIterator::default(), || {
+        physis_ExcelRowIterator {
+            sheet: sheet.handle,
+            page_count: sheet.page_count,
+            pages: sheet.pages,
+            ..Default::default()
+        }
+    })
+}
+
+/// Puts the next subrow of `iterator` into `row` and returns true, or returns false once every subrow was visited.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_excel_iterator_next(
+    iterator: &mut physis_ExcelRowIterator,
+    row: &mut physis_ExcelRowView,
+) -> bool {
+    ffi_guard(false, || {
+        if iterator.pages.is_null() {
+            return false;
+        }
+
+        // Keeps the sheet alive while the row is found
+        let Some(_sheet) = SHEETS.shared(iterator.sheet) else {
+            return false;
+        };
+
+        let pages = unsafe { slice::from_raw_parts(iterator.pages, iterator.page_count as usize) };
+        while let Some(page) = pages.get(iterator.page_index as usize) {
+            let entries = unsafe { slice::from_raw_parts(page.entries, page.entry_count as usize) };
+
+            while let Some(entry) = entries.get(iterator.entry_index as usize) {
+                let subrows =
+                    unsafe { slice::from_raw_parts(entry.subrows, entry.subrow_count as usize) };
+
+                if let Some(subrow) = subrows.get(iterator.subrow_index as usize) {
+                    iterator.subrow_index += 1;
+                    *row = to_row_view(entry, subrow, page.column_count);
+                    return true;
+                }
 
-    0
+                iterator.entry_index += 1;
+                iterator.subrow_index = 0;
+            }
+
+            iterator.page_index += 1;
+            iterator.entry_index = 0;
+        }
+
+        false
+    })
 }
 
 #[repr(C)]
@@ -485,49 +1266,74 @@ pub struct physis_SheetNames {
     names: *mut *const c_char,
 }
 
+impl Default for physis_SheetNames {
+    fn default() -> Self {
+        Self {
+            name_count: 0,
+            names: null_mut(),
+        }
+    }
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_sqpack_get_all_sheet_names(
     resource: &physis_SqPackResource,
 ) -> physis_SheetNames {
-    let mut c_repo_names = vec![];
+    ffi_guard(physis_SheetNames::default(), || {
+        let Some(mut resource) = shared_resource(resource) else {
+            return physis_SheetNames::default();
+        };
 
-    unsafe {
-        for name in (*resource.p_ptr).get_all_sheet_names().unwrap() {
-            c_repo_names.push(ffi_to_c_string(&name));
+        // Read the list like any other file, so an overlay can add sheets to it
+        let Some(data) = ffi_result(resource.read("exd/root.exl")) else {
+            return physis_SheetNames::default();
+        };
+        let Some(exl) = ffi_result(EXL::from_existing(resource.platform(), &data)) else {
+            return physis_SheetNames::default();
+        };
+
+        let mut c_repo_names = vec![];
+
+        for (name, _) in &exl.entries {
+            c_repo_names.push(ffi_to_c_string(name));
         }
-    }
 
-    let repositories = physis_SheetNames {
-        name_count: c_repo_names.len() as u32,
-        names: c_repo_names.as_mut_ptr(),
-    };
+        let repositories = physis_SheetNames {
+            name_count: c_repo_names.len() as u32,
+            names: c_repo_names.as_mut_ptr(),
+        };
 
-    mem::forget(c_repo_names);
+        mem::forget(c_repo_names);
 
-    repositories
+        repositories
+    })
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_sqpack_free_all_sheet_names(names: physis_SheetNames) {
-    let data = ffi_to_vec(names.names, names.name_count);
+    ffi_guard((), || {
+        let data = ffi_to_vec(names.names, names.name_count);
 
-    for name in &data {
-        ffi_free_string(*name);
-    }
+        for name in &data {
+            ffi_free_string(*name);
+        }
 
-    drop(data)
+        drop(data)
+    })
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_sqpack_free_repositories(repositories: physis_Repositories) {
-    let data = ffi_to_vec(repositories.repositories, repositories.repositories_count);
+    ffi_guard((), || {
+        let data = ffi_to_vec(repositories.repositories, repositories.repositories_count);
 
-    for repository in &data {
-        ffi_free_string(repository.version);
-        ffi_free_string(repository.name);
-    }
+        for repository in &data {
+            ffi_free_string(repository.version);
+            ffi_free_string(repository.name);
+        }
 
-    drop(data)
+        drop(data)
+    })
 }
 
 /// Extracts the raw game file from `hash` and `index_path`, and puts it in `data` with `size` length. If the path was not found,
@@ -538,23 +1344,22 @@ pub extern "C" fn physis_sqpack_read_from_hash(
     index_path: *const c_char,
     hash: Hash,
 ) -> physis_Buffer {
-    unsafe {
-        if let Ok(mut d) = (*resource.p_ptr).read_from_hash(
-            Path::new(CStr::from_ptr(index_path).to_string_lossy().as_ref()),
-            hash,
-        ) {
-            let b = physis_Buffer {
-                size: d.len() as u32,
-                data: d.as_mut_ptr(),
-            };
+    ffi_guard(physis_Buffer::default(), || {
+        let Some(index_path) = ffi_from_c_string(index_path) else {
+            return physis_Buffer::default();
+        };
 
-            mem::forget(d);
+        let Some(shared) = shared_sqpack(resource) else {
+            return physis_Buffer::default();
+        };
+        let mut sqpack = lock_sqpack(&shared);
 
-            b
+        if let Some(d) = ffi_result(sqpack.read_from_hash(Path::new(&index_path), hash)) {
+            ffi_to_buffer(d)
         } else {
             physis_Buffer::default()
         }
-    }
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -562,174 +1367,1379 @@ pub extern "C" fn physis_sqpack_find_offset(
     resource: &physis_SqPackResource,
     path: *const c_char,
 ) -> u64 {
-    unsafe {
-        (*resource.p_ptr)
-            .find_offset(CStr::from_ptr(path).to_string_lossy().as_ref())
-            .unwrap_or_default()
-    }
+    ffi_guard(0, || {
+        let Some(path) = ffi_from_c_string(path) else {
+            return 0;
+        };
+
+        let Some(shared) = shared_sqpack(resource) else {
+            return 0;
+        };
+        let mut sqpack = lock_sqpack(&shared);
+
+        let offset = sqpack.find_offset(&path).unwrap_or_default();
+
+        if offset == 0 {
+            set_last_error(
+                physis_ErrorCode::FileNotFound,
+                format!("Could not find {path}"),
+            );
+        }
+
+        offset
+    })
 }
 
+/// How a file is stored in a dat file.
 #[repr(C)]
-pub struct physis_Repository {
-    name: *const c_char,
-    repository_type: RepositoryType,
-    version: *const c_char,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum physis_SqPackFileType {
+    Unknown,
+    /// A placeholder without any data.
+    Empty,
+    /// Compressed in blocks, used by most files.
+    Standard,
+    /// Models, which are split up by vertex and index buffers.
+    Model,
+    /// Textures, which are split up by mip level.
+    Texture,
 }
 
+/// Information about how a file is stored, see `physis_sqpack_stat`.
 #[repr(C)]
-#[derive(Clone, Copy)]
-pub struct physis_Repositories {
-    repositories_count: u32,
-    repositories: *mut physis_Repository,
+pub struct physis_SqPackFileStat {
+    pub file_type: physis_SqPackFileType,
+    /// How much space the file takes up in the dat file, including its headers.
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    pub block_count: u32,
+    /// The repository the file is in, e.g. `ffxiv` or `ex1`.
+    pub repository: *const c_char,
+    /// The path of the dat file the data is stored in.
+    pub dat_path: *const c_char,
+    pub data_file_id: u8,
+    /// The offset of the data in the dat file.
+    pub offset: u64,
 }
 
+impl Default for physis_SqPackFileStat {
+    fn default() -> Self {
+        Self {
+            file_type: physis_SqPackFileType::Unknown,
+            compressed_size: 0,
+            uncompressed_size: 0,
+            block_count: 0,
+            repository: null(),
+            dat_path: null(),
+            data_file_id: 0,
+            offset: 0,
+        }
+    }
+}
+
+/// Returns how the file at `path` is stored, by only reading its headers. If the path was not found, `file_type` is
+/// `Unknown` and the strings are NULL. This must be freed with `physis_sqpack_free_stat`.
 #[unsafe(no_mangle)]
-pub extern "C" fn physis_sqpack_get_repositories(
+pub extern "C" fn physis_sqpack_stat(
     resource: &physis_SqPackResource,
-) -> physis_Repositories {
-    let mut c_repositories: Vec<physis_Repository> = Vec::new();
+    path: *const c_char,
+) -> physis_SqPackFileStat {
+    ffi_guard(physis_SqPackFileStat::default(), || {
+        let Some(r_path) = ffi_from_c_string(path) else {
+            return physis_SqPackFileStat::default();
+        };
 
-    unsafe {
-        for repository in &(*resource.p_ptr).repositories {
-            let ver = match &repository.version {
-                Some(x) => ffi_to_c_string(x),
-                None => null(),
-            };
+        let Some(indexes) = shared_indexes(resource) else {
+            return physis_SqPackFileStat::default();
+        };
 
-            c_repositories.push(physis_Repository {
-                name: ffi_to_c_string(&repository.name),
-                repository_type: repository.repo_type,
-                version: ver,
-            });
+        match stat_file(&indexes, &r_path) {
+            Ok(stat) => physis_SqPackFileStat {
+                file_type: match stat.file_type {
+                    1 => physis_SqPackFileType::Empty,
+                    2 => physis_SqPackFileType::Standard,
+                    3 => physis_SqPackFileType::Model,
+                    4 => physis_SqPackFileType::Texture,
+                    _ => physis_SqPackFileType::Unknown,
+                },
+                compressed_size: stat.compressed_size,
+                uncompressed_size: stat.uncompressed_size,
+                block_count: stat.block_count,
+                repository: ffi_to_c_string(&stat.repository),
+                dat_path: ffi_to_c_string(&stat.dat_path),
+                data_file_id: stat.data_file_id,
+                offset: stat.offset,
+            },
+            Err((code, err)) => {
+                set_last_error(code, err);
+                physis_SqPackFileStat::default()
+            }
         }
-    }
-
-    let repositories = physis_Repositories {
-        repositories_count: c_repositories.len() as u32,
-        repositories: c_repositories.as_mut_ptr(),
-    };
+    })
+}
 
-    mem::forget(c_repositories);
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_sqpack_free_stat(stat: &physis_SqPackFileStat) {
+    ffi_guard((), || {
+        if stat.repository.is_null() {
+            return;
+        }
 
-    repositories
+        ffi_free_string(stat.repository);
+        ffi_free_string(stat.dat_path);
+    })
 }
 
 #[repr(C)]
-#[derive(Clone, Copy)]
-pub struct physis_RepairActions {
-    action_count: u32,
-    repositories: *const *const c_char,
-    actions: *const RepairAction,
+pub struct physis_SqPackStream {
+    handle: physis_Handle,
+    /// The uncompressed size of the file.
+    pub size: u64,
 }
 
-#[unsafe(no_mangle)]
-pub extern "C" fn physis_sqpack_needs_repair(
-    resource: &physis_SqPackResource,
-) -> physis_RepairActions {
-    unsafe {
-        if let Some(repairs) = (*resource.p_ptr).needs_repair() {
-            let mut c_repositories = vec![];
-            let mut c_actions = vec![];
+impl Default for physis_SqPackStream {
+    fn default() -> Self {
+        Self {
+            handle: physis_Handle::default(),
+            size: 0,
+        }
+    }
+}
 
-            for (repository, action) in repairs {
-                c_repositories.push(ffi_to_c_string(&repository.name));
-                c_actions.push(action);
+fn open_stream(resource: &physis_SqPackResource, path: &str) -> Option<SqPackStream> {
+    let indexes = shared_indexes(resource)?;
+    let overlay = shared_overlay(resource);
+
+    // Files from the overlay aren't compressed, or are read whole by the layer anyway
+    if let Some(result) = read_overlay(overlay.as_deref(), path) {
+        return ffi_result(result).map(SqPackStream::from_memory);
+    }
+
+    if let Ok(stat) = stat_file(&indexes, path)
+        && stat.can_stream()
+    {
+        match SqPackStream::open(&stat) {
+            Ok(stream) => return Some(stream),
+            Err(err) => {
+                set_last_error(physis_ErrorCode::ParseFailed, err);
+                return None;
             }
+        }
+    }
 
-            let actions = physis_RepairActions {
-                action_count: c_actions.len() as u32,
-                repositories: c_repositories.as_ptr(),
-                actions: c_actions.as_ptr(),
-            };
+    // Models are read whole
+    let data = read_file(&indexes, None, path)?;
 
-            mem::forget(c_repositories);
-            mem::forget(c_actions);
+    Some(SqPackStream::from_memory(data))
+}
 
-            actions
-        } else {
-            physis_RepairActions {
-                action_count: 0,
-                repositories: null(),
-                actions: null(),
+/// Opens the file at `path` for reading parts of it with `physis_sqpack_stream_read`, without decompressing the
+/// whole file. If the path was not found, the handle is NULL. This must be freed with `physis_sqpack_close_stream`.
+///
+/// Standard files and textures are decompressed block by block as they're read. Models are stored as separate vertex
+/// and index buffer sections, so they're decompressed whole when the stream is opened and kept in memory until it's
+/// closed, just like `physis_sqpack_read` would. Files from an overlay are read whole too.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_sqpack_open_stream(
+    resource: &physis_SqPackResource,
+    path: *const c_char,
+) -> physis_SqPackStream {
+    ffi_guard(physis_SqPackStream::default(), || {
+        let Some(r_path) = ffi_from_c_string(path) else {
+            return physis_SqPackStream::default();
+        };
+
+        match open_stream(resource, &r_path) {
+            Some(stream) => physis_SqPackStream {
+                size: stream.size(),
+                handle: STREAMS.insert(stream),
+            },
+            None => physis_SqPackStream::default(),
+        }
+    })
+}
+
+/// Reads up to `size` bytes from `stream` starting at `offset` in the uncompressed file. The buffer is shorter if
+/// it goes past the end of the file, and empty if `offset` is past the end. The buffer must be freed with
+/// `physis_free_file`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_sqpack_stream_read(
+    stream: &physis_SqPackStream,
+    offset: u64,
+    size: u32,
+) -> physis_Buffer {
+    ffi_guard(physis_Buffer::default(), || {
+        // Only this stream is locked while decompressing, so other streams can be read from other threads at once
+        let Some(result) =
+            STREAMS.with(stream.handle, |stream| stream.read(offset, u64::from(size)))
+        else {
+            return physis_Buffer::default();
+        };
+
+        match result {
+            Ok(data) => ffi_to_buffer(data),
+            Err(err) => {
+                set_last_error(physis_ErrorCode::ParseFailed, err);
+                physis_Buffer::default()
             }
         }
-    }
+    })
 }
 
 #[unsafe(no_mangle)]
-pub extern "C" fn physis_sqpack_repair(resource: &physis_SqPackResource) -> bool {
-    unsafe {
-        if let Some(repairs) = (*resource.p_ptr).needs_repair() {
-            (*resource.p_ptr).perform_repair(&repairs).is_ok()
-        } else {
-            true
+pub extern "C" fn physis_sqpack_close_stream(stream: &physis_SqPackStream) {
+    ffi_guard((), || {
+        drop(STREAMS.remove(stream.handle));
+    })
+}
+
+/// A file found in a SqPack index.
+#[repr(C)]
+pub struct physis_SqPackFile {
+    pub hash: Hash,
+    /// Index into `physis_SqPackFileList.index_paths` of the index this file was found in.
+    pub index: u32,
+    /// The dat file the data is stored in, e.g. 1 for `.dat1`.
+    pub data_file_id: u8,
+    /// The offset of the data in the dat file.
+    pub offset: u64,
+    /// The path of the file if it's in the path database (see `physis_pathdb_load`), or NULL.
+    pub path: *const c_char,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct physis_SqPackFileList {
+    pub file_count: u32,
+    pub files: *mut physis_SqPackFile,
+    pub index_count: u32,
+    /// The paths of every index that was read, which can be passed to `physis_sqpack_read_from_hash`.
+    pub index_paths: *mut *const c_char,
+}
+
+impl Default for physis_SqPackFileList {
+    fn default() -> Self {
+        Self {
+            file_count: 0,
+            files: null_mut(),
+            index_count: 0,
+            index_paths: null_mut(),
         }
     }
 }
 
-#[derive(Clone)]
-struct CustomResource {
-    user_data: *mut c_void,
-    read_func: extern "C" fn(*mut c_void, *const c_char) -> physis_Buffer,
-    exists_func: extern "C" fn(*mut c_void, *const c_char) -> bool,
-}
+/// Lists every file in `category` (e.g. 0x04 for `chara`) of `repository` (e.g. `ffxiv` or `ex1`), across all of its
+/// index chunks for the resource's platform. Index2 files aren't read, since they only contain the same files again.
+/// Fails if any of the index files can't be read, instead of returning a partial list. The list must be freed with
+/// `physis_sqpack_free_file_list`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_sqpack_list_files(
+    resource: &physis_SqPackResource,
+    repository: *const c_char,
+    category: u8,
+) -> physis_SqPackFileList {
+    ffi_guard(physis_SqPackFileList::default(), || {
+        let Some(r_repository) = ffi_from_c_string(repository) else {
+            return physis_SqPackFileList::default();
+        };
 
-// Erase safety woohoo
-unsafe impl Sync for CustomResource {}
-unsafe impl Send for CustomResource {}
+        let Some(expansion) = expansion_id(&r_repository) else {
+            set_last_error(
+                physis_ErrorCode::InvalidArgument,
+                format!("{r_repository} isn't a valid repository name"),
+            );
+            return physis_SqPackFileList::default();
+        };
 
-impl Resource for CustomResource {
-    fn read(&mut self, path: &str) -> physis::Result<physis::ByteBuffer> {
-        // TODO: free string
-        let c_path = ffi_to_c_string(&path.to_string());
-        let data = (self.read_func)(self.user_data, c_path);
-        if data.size == 0 {
-            return Err(physis::Error::FileNotFound {
-                path: path.to_string(),
-            });
+        let Some(shared) = shared_sqpack(resource) else {
+            return physis_SqPackFileList::default();
+        };
+        let Some(game_directory) = game_directory(resource) else {
+            return physis_SqPackFileList::default();
+        };
+        let platform = lock_sqpack(&shared).platform();
+
+        let repository_dir = Path::new(&game_directory)
+            .join("sqpack")
+            .join(&r_repository);
+
+        // Index files are named like 040100.win32.index, where the last two digits are the chunk
+        let prefix = format!("{category:02x}{expansion:02x}");
+        let suffix = format!(".{}.index", platform_name(platform));
+        let mut index_paths: Vec<(u8, String)> = Vec::new();
+        if let Ok(dir) = std::fs::read_dir(&repository_dir) {
+            for entry in dir.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let Some(chunk) = name
+                    .strip_prefix(&prefix)
+                    .and_then(|rest| rest.strip_suffix(&suffix))
+                    .filter(|chunk| chunk.len() == 2)
+                    .and_then(|chunk| u8::from_str_radix(chunk, 16).ok())
+                else {
+                    continue;
+                };
+
+                index_paths.push((chunk, entry.path().to_string_lossy().to_string()));
+            }
+        }
+        index_paths.sort();
+
+        if index_paths.is_empty() {
+            set_last_error(
+                physis_ErrorCode::FileNotFound,
+                format!("No index files for category {category:02x} in {r_repository}"),
+            );
+            return physis_SqPackFileList::default();
         }
 
-        // This ensures the callee still owns the memory and we don't accidentally free it
-        let original = ffi_to_vec(data.data, data.size);
-        let clone = original.clone();
-        std::mem::forget(original);
+        // Read every index before allocating anything, so a broken one doesn't leave a partial list behind
+        let mut indexes = Vec::new();
+        for (_, index_path) in &index_paths {
+            let Some(index) = SqPackIndex::from_existing(platform, Path::new(index_path)) else {
+                set_last_error(
+                    physis_ErrorCode::ParseFailed,
+                    format!("Failed to read index file {index_path}"),
+                );
+                return physis_SqPackFileList::default();
+            };
 
-        Ok(clone)
-    }
+            indexes.push((index_path, index));
+        }
 
-    fn exists(&mut self, path: &str) -> bool {
-        // TODO: free string
-        (self.exists_func)(self.user_data, ffi_to_c_string(&path.to_string()))
-    }
-}
+        let mut c_files = Vec::new();
+        let mut c_index_paths = Vec::new();
+        for (index_path, index) in &indexes {
+            for entry in &index.entries {
+                c_files.push(physis_SqPackFile {
+                    hash: entry.hash,
+                    index: c_index_paths.len() as u32,
+                    data_file_id: entry.data_file_id,
+                    offset: entry.offset,
+                    path: resolve_path(&entry.hash).map_or(null(), |path| ffi_to_c_string(&path)),
+                });
+            }
 
-#[repr(C)]
-pub struct physis_CustomResource {
-    p_ptr: *mut CustomResource,
+            c_index_paths.push(ffi_to_c_string(index_path));
+        }
+
+        let list = physis_SqPackFileList {
+            file_count: c_files.len() as u32,
+            files: c_files.as_mut_ptr(),
+            index_count: c_index_paths.len() as u32,
+            index_paths: c_index_paths.as_mut_ptr(),
+        };
+
+        mem::forget(c_files);
+        mem::forget(c_index_paths);
+
+        list
+    })
 }
 
 #[unsafe(no_mangle)]
-pub extern "C" fn physis_custom_initialize(
-    user_data: *mut c_void,
-    read_func: extern "C" fn(*mut c_void, *const c_char) -> physis_Buffer,
-    exists_func: extern "C" fn(*mut c_void, *const c_char) -> bool,
-) -> physis_CustomResource {
-    let resource = CustomResource {
-        user_data,
-        read_func,
-        exists_func,
-    };
+pub extern "C" fn physis_sqpack_free_file_list(list: &physis_SqPackFileList) {
+    ffi_guard((), || {
+        if list.files.is_null() {
+            return;
+        }
 
-    let boxed = Box::new(resource);
+        let files = ffi_to_vec(list.files, list.file_count);
+        for file in &files {
+            if !file.path.is_null() {
+                ffi_free_string(file.path);
+            }
+        }
+        drop(files);
 
-    physis_CustomResource {
-        p_ptr: Box::leak(boxed),
-    }
+        let index_paths = ffi_to_vec(list.index_paths, list.index_count);
+        for path in &index_paths {
+            ffi_free_string(*path);
+        }
+        drop(index_paths);
+    })
 }
 
-#[unsafe(no_mangle)]
-pub extern "C" fn physis_custom_free(resource: *mut physis_CustomResource) {
-    unsafe {
-        drop(Box::from_raw((*resource).p_ptr));
+fn sqpack_write_file(
+    resource: &physis_SqPackResource,
+    repository: Option<String>,
+    path: *const c_char,
+    buffer: physis_Buffer,
+) -> bool {
+    let Some(r_path) = ffi_from_c_string(path) else {
+        return false;
+    };
+
+    let Some(shared) = shared_sqpack(resource) else {
+        return false;
+    };
+    let Some(indexes) = shared_indexes(resource) else {
+        return false;
+    };
+    let Some(game_directory) = game_directory(resource) else {
+        return false;
+    };
+
+    // Held while writing, so nothing reads the files halfway through
+    let mut sqpack = lock_sqpack(&shared);
+    let mut indexes = lock_value(&indexes);
+    let platform = sqpack.platform();
+    if platform == Platform::PS3 {
+        set_last_error(
+            physis_ErrorCode::InvalidArgument,
+            "PS3 game data can't be written to, since it's big-endian",
+        );
+        return false;
+    }
+
+    let repository = repository.unwrap_or_else(|| repository_for_path(&r_path));
+    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+
+    if let Err(err) = write_file(&game_directory, &repository, &r_path, data, platform) {
+        set_last_error(physis_ErrorCode::WriteFailed, err);
+        return false;
+    }
+
+    // The indexes are cached, so they have to be read again to see the new file
+    *sqpack = SqPackResource::from_existing(&game_directory);
+    indexes.clear();
+
+    true
+}
+
+/// Compresses `buffer` and writes it into the game data as `path`, so it can be read like any other file. The
+/// repository is picked from the path like the game does, e.g. `bg/ex1/...` goes into `ex1`. Missing repositories,
+/// categories, dat and index files are created. Returns false and sets the last error if it couldn't be written.
+///
+/// This modifies the game data in place, so make a backup first. PS3 game data can't be written to.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_sqpack_write_file(
+    resource: &physis_SqPackResource,
+    path: *const c_char,
+    buffer: physis_Buffer,
+) -> bool {
+    ffi_guard(false, || sqpack_write_file(resource, None, path, buffer))
+}
+
+/// Same as `physis_sqpack_write_file`, but writes into `repository` (e.g. `ffxiv` or `ex6`) instead.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_sqpack_write_file_to_repository(
+    resource: &physis_SqPackResource,
+    repository: *const c_char,
+    path: *const c_char,
+    buffer: physis_Buffer,
+) -> bool {
+    ffi_guard(false, || {
+        let Some(r_repository) = ffi_from_c_string(repository) else {
+            return false;
+        };
+
+        sqpack_write_file(resource, Some(r_repository), path, buffer)
+    })
+}
+
+#[repr(C)]
+pub struct physis_Repository {
+    name: *const c_char,
+    repository_type: RepositoryType,
+    version: *const c_char,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct physis_Repositories {
+    repositories_count: u32,
+    repositories: *mut physis_Repository,
+}
+
+impl Default for physis_Repositories {
+    fn default() -> Self {
+        Self {
+            repositories_count: 0,
+            repositories: null_mut(),
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_sqpack_get_repositories(
+    resource: &physis_SqPackResource,
+) -> physis_Repositories {
+    ffi_guard(physis_Repositories::default(), || {
+        let Some(shared) = shared_sqpack(resource) else {
+            return physis_Repositories::default();
+        };
+        let mut sqpack = lock_sqpack(&shared);
+
+        let mut c_repositories: Vec<physis_Repository> = Vec::new();
+
+        for repository in &sqpack.repositories {
+            let ver = match &repository.version {
+                Some(x) => ffi_to_c_string(x),
+                None => null(),
+            };
+
+            c_repositories.push(physis_Repository {
+                name: ffi_to_c_string(&repository.name),
+                repository_type: repository.repo_type,
+                version: ver,
+            });
+        }
+
+        let repositories = physis_Repositories {
+            repositories_count: c_repositories.len() as u32,
+            repositories: c_repositories.as_mut_ptr(),
+        };
+
+        mem::forget(c_repositories);
+
+        repositories
+    })
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct physis_RepairActions {
+    action_count: u32,
+    repositories: *const *const c_char,
+    actions: *const RepairAction,
+}
+
+impl Default for physis_RepairActions {
+    fn default() -> Self {
+        Self {
+            action_count: 0,
+            repositories: null(),
+            actions: null(),
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_sqpack_needs_repair(
+    resource: &physis_SqPackResource,
+) -> physis_RepairActions {
+    ffi_guard(physis_RepairActions::default(), || {
+        let Some(shared) = shared_sqpack(resource) else {
+            return physis_RepairActions::default();
+        };
+        let mut sqpack = lock_sqpack(&shared);
+
+        if let Some(repairs) = sqpack.needs_repair() {
+            let mut c_repositories = vec![];
+            let mut c_actions = vec![];
+
+            for (repository, action) in repairs {
+                c_repositories.push(ffi_to_c_string(&repository.name));
+                c_actions.push(action);
+            }
+
+            let actions = physis_RepairActions {
+                action_count: c_actions.len() as u32,
+                repositories: c_repositories.as_ptr(),
+                actions: c_actions.as_ptr(),
+            };
+
+            mem::forget(c_repositories);
+            mem::forget(c_actions);
+
+            actions
+        } else {
+            physis_RepairActions::default()
+        }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_sqpack_repair(resource: &physis_SqPackResource) -> bool {
+    ffi_guard(false, || {
+        let Some(shared) = shared_sqpack(resource) else {
+            return false;
+        };
+        let mut sqpack = lock_sqpack(&shared);
+
+        if let Some(repairs) = sqpack.needs_repair() {
+            match sqpack.perform_repair(&repairs) {
+                Ok(_) => true,
+                Err(err) => {
+                    set_last_error(
+                        physis_ErrorCode::WriteFailed,
+                        format!("Failed to repair: {err:?}"),
+                    );
+                    false
+                }
+            }
+        } else {
+            true
+        }
+    })
+}
+
+/// Called by `list_directory` for each entry in a directory. `name` is only valid for the duration of the call.
+pub type physis_DirectoryEntryCallback = extern "C" fn(context: *mut c_void, name: *const c_char);
+
+/// The callbacks of a custom resource. Every path passed to them is only valid for the duration of the call.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct physis_CustomResourceVTable {
+    /// Must be set to `sizeof(physis_CustomResourceVTable)`. A vtable from an older header that ends before some of the
+    /// optional fields is accepted, and the missing fields are treated as NULL.
+    pub struct_size: u32,
+    /// Reads the whole file at `path`, or returns a buffer with NULL `data` if it doesn't exist. An empty file is a
+    /// buffer with a `size` of 0 and non-NULL `data`.
+    pub read: extern "C" fn(user_data: *mut c_void, path: *const c_char) -> physis_Buffer,
+    /// Checks if the file at `path` exists.
+    pub exists: extern "C" fn(user_data: *mut c_void, path: *const c_char) -> bool,
+    /// Optional, called with every buffer returned by `read` and `read_range` once it was copied, including empty ones.
+    /// If this is NULL, the host keeps ownership of them.
+    pub free_buffer: Option<extern "C" fn(user_data: *mut c_void, buffer: physis_Buffer)>,
+    /// Optional, calls `callback` with `context` for each file and subdirectory in the directory at `path`, and
+    /// returns false if it doesn't exist.
+    pub list_directory: Option<
+        extern "C" fn(
+            user_data: *mut c_void,
+            path: *const c_char,
+            callback: physis_DirectoryEntryCallback,
+            context: *mut c_void,
+        ) -> bool,
+    >,
+    /// Optional, reads `size` bytes starting at `offset` in the file at `path`. If this is NULL, the whole file is read
+    /// instead.
+    pub read_range: Option<
+        extern "C" fn(
+            user_data: *mut c_void,
+            path: *const c_char,
+            offset: u64,
+            size: u64,
+        ) -> physis_Buffer,
+    >,
+}
+
+/// The size of a vtable up to and including `exists`, the last field that isn't optional.
+const MIN_VTABLE_SIZE: usize = mem::offset_of!(physis_CustomResourceVTable, free_buffer);
+
+/// Copies the first `struct_size` bytes of the host's vtable, leaving any later fields as None. Returns None if the
+/// vtable is too small to have the required fields.
+fn copy_vtable(vtable: *const physis_CustomResourceVTable) -> Option<physis_CustomResourceVTable> {
+    let size = unsafe { (*vtable).struct_size } as usize;
+    if size < MIN_VTABLE_SIZE {
+        return None;
+    }
+
+    // A zeroed Option of a function pointer is None, and `read` and `exists` are always copied over
+    let mut copy = mem::MaybeUninit::<physis_CustomResourceVTable>::zeroed();
+    unsafe {
+        std::ptr::copy_nonoverlapping(
+            vtable as *const u8,
+            copy.as_mut_ptr() as *mut u8,
+            size.min(size_of::<physis_CustomResourceVTable>()),
+        );
+
+        Some(copy.assume_init())
+    }
+}
+
+/// A resource backed by the host's callbacks.
+#[derive(Clone, Copy)]
+struct CallbackResource {
+    user_data: *mut c_void,
+    vtable: physis_CustomResourceVTable,
+}
+
+// Erase safety woohoo
+unsafe impl Sync for CallbackResource {}
+unsafe impl Send for CallbackResource {}
+
+/// Collects the entries passed to a `physis_DirectoryEntryCallback`, where `context` is a `Vec<String>`.
+extern "C" fn collect_directory_entry(context: *mut c_void, name: *const c_char) {
+    if let Some(name) = ffi_from_c_string(name) {
+        unsafe { (*(context as *mut Vec<String>)).push(name) };
+    }
+}
+
+impl CallbackResource {
+    /// Copies a buffer returned by the host, and then gives it back. Returns None if its data is NULL.
+    fn take_buffer(&self, buffer: physis_Buffer) -> Option<Vec<u8>> {
+        let data = if buffer.data.is_null() {
+            None
+        } else if buffer.size == 0 {
+            Some(Vec::new())
+        } else {
+            Some(unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) }.to_vec())
+        };
+
+        if let Some(free_buffer) = self.vtable.free_buffer {
+            free_buffer(self.user_data, buffer);
+        }
+
+        data
+    }
+
+    fn read_range(&self, path: &str, offset: u64, size: u64) -> Option<Vec<u8>> {
+        let c_path = CString::new(path).ok()?;
+
+        match self.vtable.read_range {
+            Some(read_range) => {
+                self.take_buffer(read_range(self.user_data, c_path.as_ptr(), offset, size))
+            }
+            None => {
+                let data = self.take_buffer((self.vtable.read)(self.user_data, c_path.as_ptr()))?;
+                slice_range(&data, offset, size)
+            }
+        }
+    }
+
+    fn list_directory(&self, path: &str) -> Option<Vec<String>> {
+        let list_directory = self.vtable.list_directory?;
+        let c_path = CString::new(path).ok()?;
+
+        let mut entries: Vec<String> = Vec::new();
+        list_directory(
+            self.user_data,
+            c_path.as_ptr(),
+            collect_directory_entry,
+            &mut entries as *mut Vec<String> as *mut c_void,
+        )
+        .then_some(entries)
+    }
+}
+
+impl Resource for CallbackResource {
+    fn read(&mut self, path: &str) -> physis::Result<physis::ByteBuffer> {
+        CString::new(path)
+            .ok()
+            .and_then(|c_path| {
+                self.take_buffer((self.vtable.read)(self.user_data, c_path.as_ptr()))
+            })
+            .ok_or_else(|| physis::Error::FileNotFound {
+                path: path.to_string(),
+            })
+    }
+
+    fn exists(&mut self, path: &str) -> bool {
+        let Ok(c_path) = CString::new(path) else {
+            return false;
+        };
+
+        (self.vtable.exists)(self.user_data, c_path.as_ptr())
+    }
+}
+
+/// Returns `size` bytes of `data` starting at `offset`, or None if that's out of bounds.
+fn slice_range(data: &[u8], offset: u64, size: u64) -> Option<Vec<u8>> {
+    let start = usize::try_from(offset).ok()?;
+    let end = start.checked_add(usize::try_from(size).ok()?)?;
+
+    data.get(start..end).map(<[u8]>::to_vec)
+}
+
+/// Joins `path` onto `directory`, or returns None if it could point outside of it. Only plain relative paths are
+/// allowed, without any `..`, root or drive components.
+fn join_relative(directory: &Path, path: &str) -> Option<PathBuf> {
+    let relative = Path::new(path);
+
+    relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        .then(|| directory.join(relative))
+}
+
+/// One source of files in an overlay.
+enum OverlayLayer {
+    /// Loose files, where paths are relative to the directory.
+    Directory(PathBuf),
+    /// Shared with the original handle, so it stays usable even if that's freed first. Its own overlay is left out, so
+    /// overlays can't end up reading from themselves.
+    SqPack(OverlaidSqPack),
+    Callbacks(CallbackResource),
+    ModPack(Arc<Mutex<ModPack>>),
+}
+
+fn lock_modpack(modpack: &Mutex<ModPack>) -> MutexGuard<'_, ModPack> {
+    modpack.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn read_modpack(modpack: &Mutex<ModPack>, path: &str) -> physis::Result<physis::ByteBuffer> {
+    lock_modpack(modpack)
+        .read(path)
+        .ok_or_else(|| physis::Error::FileNotFound {
+            path: path.to_string(),
+        })
+}
+
+impl OverlayLayer {
+    fn read_range(&mut self, path: &str, offset: u64, size: u64) -> Option<Vec<u8>> {
+        match self {
+            OverlayLayer::Directory(directory) => {
+                let mut file = std::fs::File::open(join_relative(directory, path)?).ok()?;
+                file.seek(SeekFrom::Start(offset)).ok()?;
+
+                let mut data = vec![0; usize::try_from(size).ok()?];
+                file.read_exact(&mut data).ok()?;
+
+                Some(data)
+            }
+            OverlayLayer::Callbacks(callbacks) => callbacks.read_range(path, offset, size),
+            _ => slice_range(&self.read(path).ok()?, offset, size),
+        }
+    }
+
+    /// Returns None if this layer doesn't support listing directories, or it doesn't have `path`.
+    fn list_directory(&mut self, path: &str) -> Option<Vec<String>> {
+        match self {
+            OverlayLayer::Directory(directory) => Some(
+                std::fs::read_dir(join_relative(directory, path)?)
+                    .ok()?
+                    .flatten()
+                    .map(|entry| entry.file_name().to_string_lossy().to_string())
+                    .collect(),
+            ),
+            OverlayLayer::SqPack(_) => None,
+            OverlayLayer::Callbacks(callbacks) => callbacks.list_directory(path),
+            OverlayLayer::ModPack(modpack) => lock_modpack(modpack).list_directory(path),
+        }
+    }
+
+    fn read(&mut self, path: &str) -> physis::Result<physis::ByteBuffer> {
+        match self {
+            OverlayLayer::Directory(directory) => join_relative(directory, path)
+                .and_then(|path| std::fs::read(path).ok())
+                .ok_or_else(|| physis::Error::FileNotFound {
+                    path: path.to_string(),
+                }),
+            OverlayLayer::SqPack(sqpack) => sqpack.read(path),
+            OverlayLayer::Callbacks(callbacks) => callbacks.read(path),
+            OverlayLayer::ModPack(modpack) => read_modpack(modpack, path),
+        }
+    }
+
+    fn exists(&mut self, path: &str) -> bool {
+        match self {
+            OverlayLayer::Directory(directory) => {
+                join_relative(directory, path).is_some_and(|path| path.is_file())
+            }
+            OverlayLayer::SqPack(sqpack) => sqpack.exists(path),
+            OverlayLayer::Callbacks(callbacks) => callbacks.exists(path),
+            OverlayLayer::ModPack(modpack) => lock_modpack(modpack).exists(path),
+        }
+    }
+}
+
+enum CustomResource {
+    Callbacks(CallbackResource),
+    /// Layers added later take priority.
+    Overlay(Vec<OverlayLayer>),
+    /// Shared so it can also be added to overlays.
+    ModPack(Arc<Mutex<ModPack>>),
+}
+
+impl CustomResource {
+    fn read_range(&mut self, path: &str, offset: u64, size: u64) -> Option<Vec<u8>> {
+        match self {
+            CustomResource::Callbacks(callbacks) => callbacks.read_range(path, offset, size),
+            CustomResource::Overlay(layers) => layers
+                .iter_mut()
+                .rev()
+                .find(|layer| layer.exists(path))?
+                .read_range(path, offset, size),
+            CustomResource::ModPack(modpack) => {
+                slice_range(&lock_modpack(modpack).read(path)?, offset, size)
+            }
+        }
+    }
+
+    /// Overlays list the entries of every layer that supports it, without duplicates.
+    fn list_directory(&mut self, path: &str) -> Option<Vec<String>> {
+        match self {
+            CustomResource::Callbacks(callbacks) => callbacks.list_directory(path),
+            CustomResource::Overlay(layers) => {
+                let mut found = false;
+                let mut entries = Vec::new();
+                for layer in layers.iter_mut() {
+                    if let Some(layer_entries) = layer.list_directory(path) {
+                        found = true;
+                        entries.extend(layer_entries);
+                    }
+                }
+
+                entries.sort();
+                entries.dedup();
+
+                found.then_some(entries)
+            }
+            CustomResource::ModPack(modpack) => lock_modpack(modpack).list_directory(path),
+        }
+    }
+
+    /// Returns the index of the layer that `path` would be read from, if this is an overlay and any of them has it.
+    fn find_layer(&mut self, path: &str) -> Option<usize> {
+        let CustomResource::Overlay(layers) = self else {
+            return None;
+        };
+
+        layers.iter_mut().rposition(|layer| layer.exists(path))
+    }
+}
+
+impl Resource for CustomResource {
+    fn read(&mut self, path: &str) -> physis::Result<physis::ByteBuffer> {
+        match self {
+            CustomResource::Callbacks(callbacks) => callbacks.read(path),
+            CustomResource::Overlay(layers) => {
+                let Some(layer) = layers.iter_mut().rev().find(|layer| layer.exists(path)) else {
+                    return Err(physis::Error::FileNotFound {
+                        path: path.to_string(),
+                    });
+                };
+
+                layer.read(path)
+            }
+            CustomResource::ModPack(modpack) => read_modpack(modpack, path),
+        }
+    }
+
+    fn exists(&mut self, path: &str) -> bool {
+        match self {
+            CustomResource::Callbacks(callbacks) => callbacks.exists(path),
+            CustomResource::Overlay(layers) => layers.iter_mut().any(|layer| layer.exists(path)),
+            CustomResource::ModPack(modpack) => lock_modpack(modpack).exists(path),
+        }
+    }
+}
+
+#[repr(C)]
+pub struct physis_CustomResource {
+    handle: physis_Handle,
+}
+
+impl Default for physis_CustomResource {
+    fn default() -> Self {
+        Self {
+            handle: physis_Handle::default(),
+        }
+    }
+}
+
+/// Creates a custom resource from `read_func` and `exists_func`. The host keeps ownership of the buffers returned by
+/// `read_func`, use `physis_custom_initialize_v2` to free them or support listing directories.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_custom_initialize(
+    user_data: *mut c_void,
+    read_func: extern "C" fn(*mut c_void, *const c_char) -> physis_Buffer,
+    exists_func: extern "C" fn(*mut c_void, *const c_char) -> bool,
+) -> physis_CustomResource {
+    ffi_guard(physis_CustomResource::default(), || {
+        let resource = CustomResource::Callbacks(CallbackResource {
+            user_data,
+            vtable: physis_CustomResourceVTable {
+                struct_size: struct_size::<physis_CustomResourceVTable>(),
+                read: read_func,
+                exists: exists_func,
+                free_buffer: None,
+                list_directory: None,
+                read_range: None,
+            },
+        });
+
+        physis_CustomResource {
+            handle: CUSTOM_RESOURCES.insert(resource),
+        }
+    })
+}
+
+/// Creates a custom resource from the callbacks in `vtable`, with `user_data` passed to each of them. The vtable is
+/// copied, so it doesn't have to outlive this call.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_custom_initialize_v2(
+    user_data: *mut c_void,
+    vtable: *const physis_CustomResourceVTable,
+) -> physis_CustomResource {
+    ffi_guard(physis_CustomResource::default(), || {
+        if vtable.is_null() {
+            set_last_error(physis_ErrorCode::InvalidArgument, "The vtable is NULL");
+            return physis_CustomResource::default();
+        }
+
+        let Some(vtable) = copy_vtable(vtable) else {
+            set_last_error(
+                physis_ErrorCode::InvalidArgument,
+                format!("The vtable must be at least {MIN_VTABLE_SIZE} bytes"),
+            );
+            return physis_CustomResource::default();
+        };
+
+        let resource = CustomResource::Callbacks(CallbackResource { user_data, vtable });
+
+        physis_CustomResource {
+            handle: CUSTOM_RESOURCES.insert(resource),
+        }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_custom_free(resource: *mut physis_CustomResource) {
+    ffi_guard((), || unsafe {
+        if resource.is_null() {
+            return;
+        }
+
+        drop(CUSTOM_RESOURCES.remove((*resource).handle));
+    })
+}
+
+/// Reads the file at `path` from a custom or overlay resource. If the path was not found, `size` is 0 and `data` is
+/// NULL.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_custom_read(
+    resource: &physis_CustomResource,
+    path: *const c_char,
+) -> physis_Buffer {
+    ffi_guard(physis_Buffer::default(), || {
+        let Some(r_path) = ffi_from_c_string(path) else {
+            return physis_Buffer::default();
+        };
+
+        CUSTOM_RESOURCES
+            .with(resource.handle, |custom| ffi_result(custom.read(&r_path)))
+            .flatten()
+            .map(ffi_to_buffer)
+            .unwrap_or_default()
+    })
+}
+
+/// Checks if the file at `path` exists in a custom or overlay resource.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_custom_exists(
+    resource: &physis_CustomResource,
+    path: *const c_char,
+) -> bool {
+    ffi_guard(false, || {
+        let Some(r_path) = ffi_from_c_string(path) else {
+            return false;
+        };
+
+        CUSTOM_RESOURCES
+            .with(resource.handle, |custom| custom.exists(&r_path))
+            .unwrap_or(false)
+    })
+}
+
+/// Creates an empty overlay resource, which reads each file from the highest priority layer that has it. Layers are
+/// added with the `physis_overlay_add_*` functions, and each one takes priority over the ones added before it. So
+/// add the base game first, and then any mods on top.
+///
+/// It's a custom resource, so it can be used with the `physis_custom_*` functions and freed with `physis_custom_free`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_overlay_initialize() -> physis_CustomResource {
+    ffi_guard(physis_CustomResource::default(), || physis_CustomResource {
+        handle: CUSTOM_RESOURCES.insert(CustomResource::Overlay(Vec::new())),
+    })
+}
+
+/// Adds `layer` to `overlay`, and returns its index or -1 on failure.
+fn add_overlay_layer(overlay: &physis_CustomResource, layer: OverlayLayer) -> i32 {
+    CUSTOM_RESOURCES
+        .with(overlay.handle, |custom| {
+            let CustomResource::Overlay(layers) = custom else {
+                set_last_error(
+                    physis_ErrorCode::InvalidArgument,
+                    "This custom resource isn't an overlay",
+                );
+                return -1;
+            };
+
+            layers.push(layer);
+            layers.len() as i32 - 1
+        })
+        .unwrap_or(-1)
+}
+
+/// Adds a directory of loose files as a layer, e.g. `chara/equipment/e0001/model/c0101e0001_top.mdl` is read from
+/// that path inside `directory`. Returns the index of the layer, or -1 if it couldn't be added.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_overlay_add_directory(
+    overlay: &physis_CustomResource,
+    directory: *const c_char,
+) -> i32 {
+    ffi_guard(-1, || {
+        let Some(r_directory) = ffi_from_c_string(directory) else {
+            return -1;
+        };
+
+        if !Path::new(&r_directory).is_dir() {
+            set_last_error(
+                physis_ErrorCode::FileNotFound,
+                format!("{r_directory} isn't a directory"),
+            );
+            return -1;
+        }
+
+        add_overlay_layer(overlay, OverlayLayer::Directory(PathBuf::from(r_directory)))
+    })
+}
+
+/// Adds `resource` as a layer. The overlay keeps its own reference, so `resource` can be freed separately. Returns
+/// the index of the layer, or -1 if it couldn't be added.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_overlay_add_sqpack(
+    overlay: &physis_CustomResource,
+    resource: &physis_SqPackResource,
+) -> i32 {
+    ffi_guard(-1, || {
+        let Some(shared) = RESOURCES.with(resource.handle, |loaded| OverlaidSqPack {
+            sqpack: loaded.sqpack.clone(),
+            indexes: loaded.indexes.clone(),
+            overlay: None,
+        }) else {
+            return -1;
+        };
+
+        add_overlay_layer(overlay, OverlayLayer::SqPack(shared))
+    })
+}
+
+/// Adds a custom resource or mod pack as a layer. Custom resources use the same callbacks and user data, and mod packs
+/// are shared so selecting options still affects the overlay. Overlays can't be nested. Returns the index of the
+/// layer, or -1 if it couldn't be added.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_overlay_add_custom(
+    overlay: &physis_CustomResource,
+    resource: &physis_CustomResource,
+) -> i32 {
+    ffi_guard(-1, || {
+        let layer = CUSTOM_RESOURCES.with(resource.handle, |custom| match custom {
+            CustomResource::Callbacks(callbacks) => Some(OverlayLayer::Callbacks(*callbacks)),
+            CustomResource::ModPack(modpack) => Some(OverlayLayer::ModPack(modpack.clone())),
+            CustomResource::Overlay(_) => None,
+        });
+
+        match layer {
+            Some(Some(layer)) => add_overlay_layer(overlay, layer),
+            Some(None) => {
+                set_last_error(
+                    physis_ErrorCode::InvalidArgument,
+                    "Overlays can't be added to other overlays",
+                );
+                -1
+            }
+            None => -1,
+        }
+    })
+}
+
+/// Returns the index of the layer that `path` would be read from, or -1 if none of them have it.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_overlay_find_layer(
+    overlay: &physis_CustomResource,
+    path: *const c_char,
+) -> i32 {
+    ffi_guard(-1, || {
+        let Some(r_path) = ffi_from_c_string(path) else {
+            return -1;
+        };
+
+        CUSTOM_RESOURCES
+            .with(overlay.handle, |custom| custom.find_layer(&r_path))
+            .flatten()
+            .map(|layer| layer as i32)
+            .unwrap_or(-1)
+    })
+}
+
+/// Puts the files of `overlay` over the game data of `resource`, so they take priority everywhere a file is read by
+/// its path: `physis_sqpack_exists`, the `physis_sqpack_read*` functions, excel sheets (including lazy sheets and
+/// links), streams and `physis_parse_any`. `overlay` can be any custom resource, usually one from
+/// `physis_overlay_initialize`. The resource keeps its own reference, so `overlay` can be freed separately. Pass NULL
+/// to remove the overlay again. Returns false if either handle isn't valid.
+///
+/// Functions that describe the dat and index files themselves, like `physis_sqpack_stat`,
+/// `physis_sqpack_find_offset`, `physis_sqpack_read_from_hash` and `physis_sqpack_list_files`, only see the game data.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_sqpack_set_overlay(
+    resource: &physis_SqPackResource,
+    overlay: *const physis_CustomResource,
+) -> bool {
+    ffi_guard(false, || {
+        let overlay = if overlay.is_null() {
+            None
+        } else {
+            let Some(shared) = CUSTOM_RESOURCES.shared(unsafe { (*overlay).handle }) else {
+                return false;
+            };
+            Some(shared)
+        };
+
+        RESOURCES
+            .with(resource.handle, |loaded| {
+                loaded.overlay = overlay;
+
+                // Sheets opened for links could have come from the old overlay
+                lock_value(&loaded.linked_sheets).clear();
+            })
+            .is_some()
+    })
+}
+
+pub(crate) fn register_modpack(modpack: ModPack) -> physis_CustomResource {
+    physis_CustomResource {
+        handle: CUSTOM_RESOURCES.insert(CustomResource::ModPack(Arc::new(Mutex::new(modpack)))),
+    }
+}
+
+/// Runs `f` with the mod pack behind `resource`, or sets the last error if it's not a mod pack.
+pub(crate) fn with_modpack<R>(
+    resource: &physis_CustomResource,
+    f: impl FnOnce(&mut ModPack) -> R,
+) -> Option<R> {
+    let modpack = CUSTOM_RESOURCES.with(resource.handle, |custom| match custom {
+        CustomResource::ModPack(modpack) => Some(modpack.clone()),
+        _ => None,
+    })?;
+
+    let Some(modpack) = modpack else {
+        set_last_error(
+            physis_ErrorCode::InvalidArgument,
+            "This custom resource isn't a mod pack",
+        );
+        return None;
+    };
+
+    Some(f(&mut lock_modpack(&modpack)))
+}
+
+/// Reads `size` bytes starting at `offset` in the file at `path`. If the file doesn't exist or is too short, `size`
+/// is 0 and `data` is NULL.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_custom_read_range(
+    resource: &physis_CustomResource,
+    path: *const c_char,
+    offset: u64,
+    size: u64,
+) -> physis_Buffer {
+    ffi_guard(physis_Buffer::default(), || {
+        let Some(r_path) = ffi_from_c_string(path) else {
+            return physis_Buffer::default();
+        };
+
+        let Some(data) = CUSTOM_RESOURCES.with(resource.handle, |custom| {
+            custom.read_range(&r_path, offset, size)
+        }) else {
+            return physis_Buffer::default();
+        };
+
+        match data {
+            Some(data) => ffi_to_buffer(data),
+            None => {
+                set_last_error(
+                    physis_ErrorCode::FileNotFound,
+                    format!("Could not read {size} bytes at {offset} from {r_path}"),
+                );
+                physis_Buffer::default()
+            }
+        }
+    })
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct physis_DirectoryListing {
+    entry_count: u32,
+    entries: *mut *const c_char,
+}
+
+impl Default for physis_DirectoryListing {
+    fn default() -> Self {
+        Self {
+            entry_count: 0,
+            entries: null_mut(),
+        }
+    }
+}
+
+/// Lists the files and subdirectories in the directory at `path`, e.g. `chara/equipment`. Custom resources need a
+/// `list_directory` callback for this, and overlays combine every layer that supports it (SqPack layers don't.)
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_custom_list_directory(
+    resource: &physis_CustomResource,
+    path: *const c_char,
+) -> physis_DirectoryListing {
+    ffi_guard(physis_DirectoryListing::default(), || {
+        let Some(r_path) = ffi_from_c_string(path) else {
+            return physis_DirectoryListing::default();
+        };
+
+        let Some(entries) =
+            CUSTOM_RESOURCES.with(resource.handle, |custom| custom.list_directory(&r_path))
+        else {
+            return physis_DirectoryListing::default();
+        };
+
+        let Some(entries) = entries else {
+            set_last_error(
+                physis_ErrorCode::FileNotFound,
+                format!("Could not list {r_path}"),
+            );
+            return physis_DirectoryListing::default();
+        };
+
+        let mut c_entries: Vec<*const c_char> = entries.iter().map(ffi_to_c_string).collect();
+
+        let listing = physis_DirectoryListing {
+            entry_count: c_entries.len() as u32,
+            entries: c_entries.as_mut_ptr(),
+        };
+
+        mem::forget(c_entries);
+
+        listing
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_free_directory_listing(listing: &physis_DirectoryListing) {
+    ffi_guard((), || {
+        if listing.entries.is_null() {
+            return;
+        }
+
+        let data = ffi_to_vec(listing.entries, listing.entry_count);
+
+        for entry in &data {
+            ffi_free_string(*entry);
+        }
+
+        drop(data)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_paths_stay_in_the_directory() {
+        let directory = Path::new("/mods/overlay");
+
+        assert_eq!(
+            join_relative(directory, "chara/equipment/e0001.tex"),
+            Some(directory.join("chara/equipment/e0001.tex"))
+        );
+        assert_eq!(
+            join_relative(directory, "./exd/root.exl"),
+            Some(directory.join("exd/root.exl"))
+        );
+    }
+
+    #[test]
+    fn paths_leaving_the_directory_are_rejected() {
+        let directory = Path::new("/mods/overlay");
+
+        for path in [
+            "../secret.txt",
+            "chara/../../secret.txt",
+            "chara/..",
+            "/etc/passwd",
+        ] {
+            assert_eq!(join_relative(directory, path), None, "{path}");
+        }
     }
 }
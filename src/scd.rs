@@ -1,13 +1,17 @@
 // SPDX-FileCopyrightText: 2026 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::{ffi_to_c_string, physis_Buffer};
+use crate::error::{ffi_result, set_physis_error};
+use crate::json::{ToJson, array_to_json, enum_to_json, ffi_parsed_to_json};
+use crate::panic::ffi_guard;
+use crate::{ffi_to_c_string, ffi_to_vec, physis_Buffer};
 use physis::Platform;
 use physis::ReadableFile;
 use physis::scd::Scd;
 use physis::scd::{AudioData, AudioFormat};
+use serde_json::{Value, json};
 use std::ffi::c_char;
-use std::ptr::null_mut;
+use std::ptr::{null, null_mut};
 use std::slice;
 
 #[repr(C)]
@@ -34,40 +38,63 @@ pub struct physis_ScdAudio {
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_scd_parse(platform: Platform, buffer: physis_Buffer) -> physis_Scd {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
-
-    if let Ok(scd) = Scd::from_existing(platform, data) {
-        let mut c_audios = Vec::new();
-        for audio in &scd.audios {
-            let mut c_data;
-            match &audio.data {
-                AudioData::Empty => c_data = Vec::default(),
-                AudioData::OggVorbis { data, .. } => c_data = data.clone(),
-                AudioData::Unknown { data, .. } => c_data = data.clone(),
+    ffi_guard(physis_Scd::default(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+
+        if let Some(scd) = ffi_result(Scd::from_existing(platform, data)) {
+            let mut c_audios = Vec::new();
+            for audio in &scd.audios {
+                let mut c_data;
+                match &audio.data {
+                    AudioData::Empty => c_data = Vec::default(),
+                    AudioData::OggVorbis { data, .. } => c_data = data.clone(),
+                    AudioData::Unknown { data, .. } => {
+                        log::warn!(
+                            "Unsupported SCD codec {:?}, passing the audio data through as-is",
+                            audio.format
+                        );
+                        c_data = data.clone()
+                    }
+                }
+
+                let c_audio = physis_ScdAudio {
+                    format: audio.format,
+                    data_size: c_data.len() as u32,
+                    data: c_data.as_mut_ptr(),
+                };
+
+                c_audios.push(c_audio);
+
+                std::mem::forget(c_data);
             }
 
-            let c_audio = physis_ScdAudio {
-                format: audio.format,
-                data_size: c_data.len() as u32,
-                data: c_data.as_mut_ptr(),
+            let c_scd = physis_Scd {
+                audio_count: c_audios.len() as u32,
+                audios: c_audios.as_mut_ptr(),
             };
 
-            c_audios.push(c_audio);
+            std::mem::forget(c_audios);
 
-            std::mem::forget(c_data);
+            c_scd
+        } else {
+            physis_Scd::default()
         }
+    })
+}
 
-        let c_scd = physis_Scd {
-            audio_count: c_audios.len() as u32,
-            audios: c_audios.as_mut_ptr(),
-        };
-
-        std::mem::forget(c_audios);
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_scd_free(scd: &physis_Scd) {
+    ffi_guard((), || {
+        if scd.audios.is_null() {
+            return;
+        }
 
-        c_scd
-    } else {
-        physis_Scd::default()
-    }
+        let audios = ffi_to_vec(scd.audios, scd.audio_count);
+        for audio in &audios {
+            drop(ffi_to_vec(audio.data, audio.data_size));
+        }
+        drop(audios);
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -75,10 +102,35 @@ pub unsafe extern "C" fn physis_scd_debug(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> *const c_char {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(null(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+
+        match Scd::from_existing(platform, data) {
+            Ok(scd) => ffi_to_c_string(&format!("{scd:#?}")),
+            Err(err) => {
+                set_physis_error(&err);
+                ffi_to_c_string(&format!("{err:#?}"))
+            }
+        }
+    })
+}
 
-    match Scd::from_existing(platform, data) {
-        Ok(scd) => ffi_to_c_string(&format!("{scd:#?}")),
-        Err(err) => ffi_to_c_string(&format!("{err:#?}")),
+impl ToJson for physis_Scd {
+    fn to_json(&self) -> Value {
+        json!({
+            "audios": array_to_json(self.audios, self.audio_count as usize, |audio| {
+                json!({
+                    "format": enum_to_json(&audio.format),
+                    "data_size": audio.data_size,
+                })
+            }),
+        })
     }
 }
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_scd_to_json(platform: Platform, buffer: physis_Buffer) -> *const c_char {
+    ffi_guard(null(), || {
+        ffi_parsed_to_json(physis_scd_parse(platform, buffer), physis_scd_free)
+    })
+}
@@ -0,0 +1,579 @@
+// SPDX-FileCopyrightText: 2026 Joshua Goins <josh@redstrate.com>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Names for Excel sheet columns, loaded from [EXDSchema](https://github.com/xivdev/EXDSchema) definitions.
+//!
+//! Fields in a schema map onto the sheet's columns sorted by offset, so column indices can be looked up by name even
+//! if they're reordered between game versions. Arrays are flattened, so element 2 of `BaseParam` is named
+//! `BaseParam[2]` and a nested field is named like `Unknown[1].Value`.
+
+use crate::error::{physis_ErrorCode, set_last_error};
+use crate::exd::physis_Field;
+use crate::exh::{EXHS, physis_EXH};
+use crate::handle::{HandleRegistry, physis_Handle};
+use crate::panic::ffi_guard;
+use crate::{ffi_free_string, ffi_from_c_string, ffi_to_c_string, ffi_to_vec};
+use physis::exh::EXH;
+use serde_yaml_ng::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+use std::os::raw::c_char;
+use std::ptr::{null, null_mut};
+
+/// Link targets that depend on the value of another column.
+pub(crate) struct LinkCondition {
+    /// The name of the column to switch on.
+    pub(crate) switch: String,
+    /// Sorted by case, so the targets are always listed in the same order.
+    pub(crate) cases: BTreeMap<i64, Vec<String>>,
+}
+
+pub(crate) struct SchemaColumn {
+    pub(crate) name: String,
+    /// Index into the sheet's columns, as listed in its EXH.
+    pub(crate) column_index: usize,
+    /// Sheets that this column is a row id in.
+    pub(crate) targets: Vec<String>,
+    pub(crate) condition: Option<LinkCondition>,
+}
+
+pub(crate) struct Schema {
+    pub(crate) display_field: Option<String>,
+    pub(crate) columns: Vec<SchemaColumn>,
+    by_name: HashMap<String, usize>,
+    /// The number of elements in each array, keyed by its flattened name.
+    arrays: HashMap<String, u32>,
+}
+
+pub(crate) static SCHEMAS: HandleRegistry<Schema> = HandleRegistry::new("ExcelSchema");
+
+/// A field from the schema with its arrays expanded, before it's matched up with a column.
+struct FlatField {
+    name: String,
+    targets: Vec<String>,
+    condition: Option<LinkCondition>,
+}
+
+fn parse_targets(value: Option<&Value>) -> Vec<String> {
+    value
+        .and_then(Value::as_sequence)
+        .map(|targets| {
+            targets
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn parse_condition(value: &Value) -> Option<LinkCondition> {
+    let switch = value.get("switch")?.as_str()?.to_string();
+    let cases = value
+        .get("cases")?
+        .as_mapping()?
+        .iter()
+        .filter_map(|(case, targets)| Some((case.as_i64()?, parse_targets(Some(targets)))))
+        .collect();
+
+    Some(LinkCondition { switch, cases })
+}
+
+fn flatten_fields(
+    fields: &[Value],
+    prefix: &str,
+    arrays: &mut HashMap<String, u32>,
+    flat: &mut Vec<FlatField>,
+) -> Result<(), String> {
+    for (i, field) in fields.iter().enumerate() {
+        let name = match field.get("name").and_then(Value::as_str) {
+            Some(name) if prefix.is_empty() => name.to_string(),
+            Some(name) => format!("{prefix}.{name}"),
+            // Arrays of a single unnamed field, like BaseParam[2]
+            None if !prefix.is_empty() && fields.len() == 1 => prefix.to_string(),
+            None if prefix.is_empty() => format!("Unknown{}", flat.len()),
+            None => format!("{prefix}.Unknown{i}"),
+        };
+
+        match field.get("type").and_then(Value::as_str) {
+            Some("array") => {
+                let count = field.get("count").and_then(Value::as_u64).unwrap_or(1) as u32;
+                arrays.insert(name.clone(), count);
+
+                // An array without fields is an array of plain columns
+                let default_fields = [Value::Mapping(Default::default())];
+                let sub_fields = match field.get("fields").and_then(Value::as_sequence) {
+                    Some(sub_fields) => sub_fields.as_slice(),
+                    None => &default_fields,
+                };
+
+                for element in 0..count {
+                    flatten_fields(sub_fields, &format!("{name}[{element}]"), arrays, flat)?;
+                }
+            }
+            Some("link") => flat.push(FlatField {
+                name,
+                targets: parse_targets(field.get("targets")),
+                condition: field.get("condition").and_then(parse_condition),
+            }),
+            Some("scalar" | "icon" | "modelId" | "color") | None => flat.push(FlatField {
+                name,
+                targets: Vec::new(),
+                condition: None,
+            }),
+            Some(other) => return Err(format!("Unknown field type {other} for {name}")),
+        }
+    }
+
+    Ok(())
+}
+
+impl Schema {
+    /// Parses the schema in `yaml` and matches its fields up with the columns of `exh`.
+    pub(crate) fn parse(yaml: &str, exh: &EXH) -> Result<Self, String> {
+        let root: Value = serde_yaml_ng::from_str(yaml)
+            .map_err(|err| format!("Failed to parse schema: {err}"))?;
+
+        let name = root
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let display_field = root
+            .get("displayField")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+
+        let fields = root
+            .get("fields")
+            .and_then(Value::as_sequence)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        let mut arrays = HashMap::new();
+        let mut flat = Vec::new();
+        flatten_fields(fields, "", &mut arrays, &mut flat)?;
+
+        if flat.len() != exh.column_definitions.len() {
+            return Err(format!(
+                "Schema for {name} has {} columns, but the sheet has {}",
+                flat.len(),
+                exh.column_definitions.len()
+            ));
+        }
+
+        // Packed bools share an offset, and are ordered by their bit
+        let mut sorted: Vec<usize> = (0..exh.column_definitions.len()).collect();
+        sorted.sort_by_key(|i| {
+            let column = &exh.column_definitions[*i];
+            (column.offset, column.data_type as u16)
+        });
+
+        let columns: Vec<SchemaColumn> = flat
+            .into_iter()
+            .zip(sorted)
+            .map(|(field, column_index)| SchemaColumn {
+                name: field.name,
+                column_index,
+                targets: field.targets,
+                condition: field.condition,
+            })
+            .collect();
+
+        let by_name = columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| (column.name.clone(), i))
+            .collect();
+
+        Ok(Self {
+            display_field,
+            columns,
+            by_name,
+            arrays,
+        })
+    }
+
+    pub(crate) fn column(&self, name: &str) -> Option<&SchemaColumn> {
+        self.by_name.get(name).map(|i| &self.columns[*i])
+    }
+
+    pub(crate) fn column_count(&self) -> usize {
+        self.columns.len()
+    }
+}
+
+#[repr(C)]
+pub struct physis_ExcelSchema {
+    pub(crate) handle: physis_Handle,
+    pub column_count: u32,
+}
+
+impl Default for physis_ExcelSchema {
+    fn default() -> Self {
+        Self {
+            handle: physis_Handle::default(),
+            column_count: 0,
+        }
+    }
+}
+
+/// Parses the EXDSchema definition in `yaml`, for the sheet described by `exh`. If it couldn't be parsed or doesn't
+/// match the sheet, the handle is NULL. This must be freed with `physis_excel_schema_free`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_excel_schema_parse(
+    yaml: *const c_char,
+    exh: &physis_EXH,
+) -> physis_ExcelSchema {
+    ffi_guard(physis_ExcelSchema::default(), || {
+        let Some(r_yaml) = ffi_from_c_string(yaml) else {
+            return physis_ExcelSchema::default();
+        };
+
+        let Some(schema) = EXHS.with(exh.handle, |exh| Schema::parse(&r_yaml, exh)) else {
+            return physis_ExcelSchema::default();
+        };
+
+        match schema {
+            Ok(schema) => physis_ExcelSchema {
+                column_count: schema.column_count() as u32,
+                handle: SCHEMAS.insert(schema),
+            },
+            Err(err) => {
+                set_last_error(physis_ErrorCode::ParseFailed, err);
+                physis_ExcelSchema::default()
+            }
+        }
+    })
+}
+
+/// Same as `physis_excel_schema_parse`, but reads the definition from the file at `path`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_excel_schema_load(
+    path: *const c_char,
+    exh: &physis_EXH,
+) -> physis_ExcelSchema {
+    ffi_guard(physis_ExcelSchema::default(), || {
+        let Some(r_path) = ffi_from_c_string(path) else {
+            return physis_ExcelSchema::default();
+        };
+
+        let yaml = match std::fs::read_to_string(&r_path) {
+            Ok(yaml) => yaml,
+            Err(err) => {
+                set_last_error(
+                    physis_ErrorCode::FileNotFound,
+                    format!("Failed to read schema {r_path}: {err}"),
+                );
+                return physis_ExcelSchema::default();
+            }
+        };
+
+        let Some(schema) = EXHS.with(exh.handle, |exh| Schema::parse(&yaml, exh)) else {
+            return physis_ExcelSchema::default();
+        };
+
+        match schema {
+            Ok(schema) => physis_ExcelSchema {
+                column_count: schema.column_count() as u32,
+                handle: SCHEMAS.insert(schema),
+            },
+            Err(err) => {
+                set_last_error(physis_ErrorCode::ParseFailed, err);
+                physis_ExcelSchema::default()
+            }
+        }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_excel_schema_free(schema: &physis_ExcelSchema) {
+    ffi_guard((), || {
+        drop(SCHEMAS.remove(schema.handle));
+    })
+}
+
+/// Returns the index of the column called `name`, which can be used to index into a row's columns. Returns -1 if
+/// the schema has no such column.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_excel_schema_column_index(
+    schema: &physis_ExcelSchema,
+    name: *const c_char,
+) -> i32 {
+    ffi_guard(-1, || {
+        let Some(r_name) = ffi_from_c_string(name) else {
+            return -1;
+        };
+
+        SCHEMAS
+            .with(schema.handle, |schema| {
+                schema
+                    .column(&r_name)
+                    .map(|column| column.column_index as i32)
+            })
+            .flatten()
+            .unwrap_or(-1)
+    })
+}
+
+/// Returns the index of the column that best describes a row, like its name. Returns -1 if the schema doesn't have
+/// one.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_excel_schema_display_column_index(schema: &physis_ExcelSchema) -> i32 {
+    ffi_guard(-1, || {
+        SCHEMAS
+            .with(schema.handle, |schema| {
+                let display_field = schema.display_field.as_deref()?;
+                schema
+                    .column(display_field)
+                    .map(|column| column.column_index as i32)
+            })
+            .flatten()
+            .unwrap_or(-1)
+    })
+}
+
+/// Returns the number of elements in the array called `name`, or 0 if the schema has no such array.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_excel_schema_array_count(
+    schema: &physis_ExcelSchema,
+    name: *const c_char,
+) -> u32 {
+    ffi_guard(0, || {
+        let Some(r_name) = ffi_from_c_string(name) else {
+            return 0;
+        };
+
+        SCHEMAS
+            .with(schema.handle, |schema| schema.arrays.get(&r_name).copied())
+            .flatten()
+            .unwrap_or_default()
+    })
+}
+
+/// A column in a schema, see `physis_excel_schema_get_column`.
+#[repr(C)]
+pub struct physis_ExcelSchemaColumn {
+    pub name: *const c_char,
+    /// Index into the row's columns, or -1 if the column wasn't found.
+    pub column_index: i32,
+    /// The sheets this column links to.
+    pub target_count: u32,
+    pub targets: *mut *const c_char,
+    /// If the sheet that's linked to depends on another column, this is its name. Otherwise it's NULL.
+    pub switch_column: *const c_char,
+}
+
+impl Default for physis_ExcelSchemaColumn {
+    fn default() -> Self {
+        Self {
+            name: null(),
+            column_index: -1,
+            target_count: 0,
+            targets: null_mut(),
+            switch_column: null(),
+        }
+    }
+}
+
+/// Returns the column called `name`, including what it links to. This must be freed with
+/// `physis_excel_schema_free_column`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_excel_schema_get_column(
+    schema: &physis_ExcelSchema,
+    name: *const c_char,
+) -> physis_ExcelSchemaColumn {
+    ffi_guard(physis_ExcelSchemaColumn::default(), || {
+        let Some(r_name) = ffi_from_c_string(name) else {
+            return physis_ExcelSchemaColumn::default();
+        };
+
+        SCHEMAS
+            .with(schema.handle, |schema| {
+                let column = schema.column(&r_name)?;
+
+                // Conditional links can point to any of their cases
+                let mut targets: Vec<&String> = column.targets.iter().collect();
+                if let Some(condition) = &column.condition {
+                    for target in condition.cases.values().flatten() {
+                        if !targets.contains(&target) {
+                            targets.push(target);
+                        }
+                    }
+                }
+                let mut c_targets: Vec<*const c_char> =
+                    targets.into_iter().map(ffi_to_c_string).collect();
+
+                let c_column = physis_ExcelSchemaColumn {
+                    name: ffi_to_c_string(&column.name),
+                    column_index: column.column_index as i32,
+                    target_count: c_targets.len() as u32,
+                    targets: c_targets.as_mut_ptr(),
+                    switch_column: column
+                        .condition
+                        .as_ref()
+                        .map(|condition| ffi_to_c_string(&condition.switch))
+                        .unwrap_or(null()),
+                };
+
+                mem::forget(c_targets);
+
+                Some(c_column)
+            })
+            .flatten()
+            .unwrap_or_default()
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_excel_schema_free_column(column: &physis_ExcelSchemaColumn) {
+    ffi_guard((), || {
+        if column.name.is_null() {
+            return;
+        }
+
+        ffi_free_string(column.name);
+        if !column.switch_column.is_null() {
+            ffi_free_string(column.switch_column);
+        }
+
+        let targets = ffi_to_vec(column.targets, column.target_count);
+        for target in &targets {
+            ffi_free_string(*target);
+        }
+    })
+}
+
+/// Looks up the field called `name` in `columns`, which are the `column_count` columns of a row from the schema's
+/// sheet.
+fn named_field<'a>(
+    schema: &physis_ExcelSchema,
+    columns: *const physis_Field,
+    column_count: u32,
+    name: *const c_char,
+) -> Option<&'a physis_Field> {
+    let r_name = ffi_from_c_string(name)?;
+
+    if columns.is_null() {
+        set_last_error(physis_ErrorCode::InvalidArgument, "columns is NULL");
+        return None;
+    }
+
+    let index = SCHEMAS
+        .with(schema.handle, |schema| {
+            schema.column(&r_name).map(|column| column.column_index)
+        })
+        .flatten();
+
+    let Some(index) = index else {
+        set_last_error(
+            physis_ErrorCode::InvalidArgument,
+            format!("No column called {r_name}"),
+        );
+        return None;
+    };
+
+    if index >= column_count as usize {
+        set_last_error(
+            physis_ErrorCode::InvalidArgument,
+            format!("{r_name} is column {index}, but the row only has {column_count} columns"),
+        );
+        return None;
+    }
+
+    Some(unsafe { &*columns.add(index) })
+}
+
+/// Returns the field called `name` in the `column_count` `columns` of a row, as an integer. Returns false if there's no
+/// such column or it isn't an integer or bool.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_excel_schema_get_int(
+    schema: &physis_ExcelSchema,
+    columns: *const physis_Field,
+    column_count: u32,
+    name: *const c_char,
+    value: &mut i64,
+) -> bool {
+    ffi_guard(false, || {
+        let int = match named_field(schema, columns, column_count, name) {
+            Some(physis_Field::Bool(b)) => i64::from(*b),
+            Some(physis_Field::Int8(i)) => i64::from(*i),
+            Some(physis_Field::UInt8(i)) => i64::from(*i),
+            Some(physis_Field::Int16(i)) => i64::from(*i),
+            Some(physis_Field::UInt16(i)) => i64::from(*i),
+            Some(physis_Field::Int32(i)) => i64::from(*i),
+            Some(physis_Field::UInt32(i)) => i64::from(*i),
+            Some(physis_Field::Int64(i)) => *i,
+            Some(physis_Field::UInt64(i)) => *i as i64,
+            _ => return false,
+        };
+
+        *value = int;
+        true
+    })
+}
+
+/// Returns the field called `name` in the `column_count` `columns` of a row, as a float. Returns false if there's no
+/// such column or it isn't a number.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_excel_schema_get_float(
+    schema: &physis_ExcelSchema,
+    columns: *const physis_Field,
+    column_count: u32,
+    name: *const c_char,
+    value: &mut f32,
+) -> bool {
+    ffi_guard(false, || {
+        let float = match named_field(schema, columns, column_count, name) {
+            Some(physis_Field::Float32(f)) => *f,
+            Some(physis_Field::Int8(i)) => f32::from(*i),
+            Some(physis_Field::UInt8(i)) => f32::from(*i),
+            Some(physis_Field::Int16(i)) => f32::from(*i),
+            Some(physis_Field::UInt16(i)) => f32::from(*i),
+            Some(physis_Field::Int32(i)) => *i as f32,
+            Some(physis_Field::UInt32(i)) => *i as f32,
+            _ => return false,
+        };
+
+        *value = float;
+        true
+    })
+}
+
+/// Returns the field called `name` in the `column_count` `columns` of a row, as a bool. Returns false if there's no
+/// such column or it isn't a bool.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_excel_schema_get_bool(
+    schema: &physis_ExcelSchema,
+    columns: *const physis_Field,
+    column_count: u32,
+    name: *const c_char,
+    value: &mut bool,
+) -> bool {
+    ffi_guard(false, || {
+        match named_field(schema, columns, column_count, name) {
+            Some(physis_Field::Bool(b)) => {
+                *value = *b;
+                true
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Returns the field called `name` in the `column_count` `columns` of a row, as a string. This points into the row and
+/// must not be freed. Returns NULL if there's no such column or it isn't a string.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_excel_schema_get_string(
+    schema: &physis_ExcelSchema,
+    columns: *const physis_Field,
+    column_count: u32,
+    name: *const c_char,
+) -> *const c_char {
+    ffi_guard(null(), || {
+        match named_field(schema, columns, column_count, name) {
+            Some(physis_Field::String(s)) => *s,
+            _ => null(),
+        }
+    })
+}
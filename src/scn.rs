@@ -1,11 +1,13 @@
 // SPDX-FileCopyrightText: 2026 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use crate::json::{ToJson, array_to_json, raw_to_json, string_to_json};
 use crate::layer::{free_layer, physis_Layer, to_c_layer};
 use crate::tmb::{physis_Tmb, physis_tmb_free, to_c_tmb};
 use crate::{ffi_free_string, ffi_to_c_string, ffi_to_vec};
 use physis::scn::ScnSGActionControllerDescriptor;
 use physis::scn::{ScnLayerGroup, ScnSection, ScnTimeline, ScnTimelineInstance};
+use serde_json::{Value, json};
 use std::ffi::c_char;
 
 #[repr(C)]
@@ -247,3 +249,75 @@ pub(crate) fn drop_section(section: &physis_ScnSection) {
     }
     drop(data);
 }
+
+impl ToJson for physis_ScnSection {
+    fn to_json(&self) -> Value {
+        json!({
+            "layer_groups": array_to_json(self.layer_groups, self.num_layer_groups as usize, |group| {
+                json!({
+                    "layer_group_id": group.layer_group_id,
+                    "name": string_to_json(group.name),
+                    "layers": array_to_json(
+                        group.layers,
+                        group.layer_count as usize,
+                        ToJson::to_json
+                    ),
+                })
+            }),
+            "general": {
+                "bg_path": string_to_json(self.general.bg_path),
+                "lcb_path": string_to_json(self.general.lcb_path),
+                "svb_path": string_to_json(self.general.svb_path),
+            },
+            "timelines": {
+                "timelines": array_to_json(
+                    self.timelines.timelines,
+                    self.timelines.timeline_count as usize,
+                    |timeline| {
+                        json!({
+                            "sub_id": timeline.sub_id,
+                            "animation_type": string_to_json(timeline.animation_type),
+                            "tmb": timeline.tmb.to_json(),
+                            "instances": array_to_json(
+                                timeline.instances,
+                                timeline.instance_count as usize,
+                                raw_to_json
+                            ),
+                        })
+                    }
+                ),
+            },
+            "layer_sets": {
+                "layer_sets": array_to_json(
+                    self.layer_sets.layer_sets,
+                    self.layer_sets.layer_set_count as usize,
+                    |layer_set| {
+                        json!({
+                            "id": layer_set.id,
+                            "territory_type_id": layer_set.territory_type_id,
+                            "content_finder_condition_id": layer_set.content_finder_condition_id,
+                        })
+                    }
+                ),
+            },
+            "lgb_paths": array_to_json(self.lgb_paths, self.num_lgb_paths as usize, |path| {
+                string_to_json(*path)
+            }),
+            "action_descriptors": {
+                "descriptors": array_to_json(
+                    self.action_descriptors.descriptors,
+                    self.action_descriptors.descriptor_count as usize,
+                    raw_to_json
+                ),
+            },
+            "env_spaces": array_to_json(self.env_spaces, self.num_env_spaces as usize, |env_space| {
+                json!({
+                    "envb_path": string_to_json(env_space.envb_path),
+                    "index": env_space.index,
+                    "env_location_instance_id": env_space.env_location_instance_id,
+                    "essb_path": string_to_json(env_space.essb_path),
+                })
+            }),
+        })
+    }
+}
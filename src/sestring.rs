@@ -0,0 +1,551 @@
+// SPDX-FileCopyrightText: 2026 Joshua Goins <josh@redstrate.com>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Parses SeStrings, the rich text format used by Excel string columns.
+//!
+//! A SeString is UTF-8 text with macros mixed in. Each macro starts with 0x02, followed by its code, the length of
+//! its payload and the payload, and ends with 0x03. The payload is a list of expressions, which are integers,
+//! placeholders like the player's gender, comparisons, or more SeStrings.
+
+use crate::error::{physis_ErrorCode, set_last_error};
+use crate::exd::physis_Field;
+use crate::panic::ffi_guard;
+use crate::{ffi_free_string, ffi_to_c_string, ffi_to_vec, physis_Buffer};
+use std::ffi::CStr;
+use std::mem;
+use std::os::raw::c_char;
+use std::ptr::{null, null_mut};
+use std::slice;
+
+const MACRO_START: u8 = 0x02;
+const MACRO_END: u8 = 0x03;
+
+/// Strings can contain expressions that contain strings, so limit how deep that can go.
+const MAX_DEPTH: usize = 64;
+
+pub(crate) enum Payload {
+    Text(String),
+    Macro { code: u8, args: Vec<Expression> },
+}
+
+pub(crate) enum Expression {
+    Integer(u32),
+    /// A value that's only known at runtime, like the current time or a color from the stack.
+    Placeholder(u8),
+    /// Reads a parameter, like `lnum1` for the first local number.
+    Unary(u8, Box<Expression>),
+    /// A comparison between two expressions.
+    Binary(u8, Box<Expression>, Box<Expression>),
+    String(Vec<Payload>),
+}
+
+fn macro_name(code: u8) -> &'static str {
+    match code {
+        0x06 => "setresettime",
+        0x07 => "settime",
+        0x08 => "if",
+        0x09 => "switch",
+        0x0A => "pcname",
+        0x0B => "ifpcgender",
+        0x0C => "ifpcname",
+        0x0D => "josa",
+        0x0E => "josaro",
+        0x0F => "ifself",
+        0x10 => "br",
+        0x11 => "wait",
+        0x12 => "icon",
+        0x13 => "color",
+        0x14 => "edgecolor",
+        0x15 => "shadowcolor",
+        0x16 => "softhyphen",
+        0x17 => "key",
+        0x18 => "scale",
+        0x19 => "bold",
+        0x1A => "italic",
+        0x1B => "edge",
+        0x1C => "shadow",
+        0x1D => "nbsp",
+        0x1E => "icon2",
+        0x1F => "hyphen",
+        0x20 => "num",
+        0x21 => "hex",
+        0x22 => "kilo",
+        0x23 => "byte",
+        0x24 => "sec",
+        0x25 => "time",
+        0x26 => "float",
+        0x27 => "link",
+        0x28 => "sheet",
+        0x29 => "string",
+        0x2A => "caps",
+        0x2B => "head",
+        0x2C => "split",
+        0x2D => "headall",
+        0x2E => "fixed",
+        0x2F => "lower",
+        0x30 => "janoun",
+        0x31 => "ennoun",
+        0x32 => "denoun",
+        0x33 => "frnoun",
+        0x34 => "chnoun",
+        0x40 => "lowerhead",
+        0x48 => "colortype",
+        0x49 => "edgecolortype",
+        0x4A => "ruby",
+        0x50 => "digit",
+        0x51 => "ordinal",
+        0x60 => "sound",
+        0x61 => "levelpos",
+        _ => "unknown",
+    }
+}
+
+fn expression_name(code: u8) -> &'static str {
+    match code {
+        0xD8 => "t_msec",
+        0xD9 => "t_sec",
+        0xDA => "t_min",
+        0xDB => "t_hour",
+        0xDC => "t_day",
+        0xDD => "t_wday",
+        0xDE => "t_mon",
+        0xDF => "t_year",
+        0xE0 => "gteq",
+        0xE1 => "gt",
+        0xE2 => "lteq",
+        0xE3 => "lt",
+        0xE4 => "eq",
+        0xE5 => "neq",
+        0xE8 => "lnum",
+        0xE9 => "gnum",
+        0xEA => "lstr",
+        0xEB => "gstr",
+        0xEC => "stackcolor",
+        _ => "unknown",
+    }
+}
+
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn read_byte(&mut self) -> Option<u8> {
+        let byte = *self.data.get(self.pos)?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    /// Integers below 0xD0 are stored in one byte, larger ones have a marker saying which of the four bytes follow.
+    fn read_integer(&mut self, marker: u8) -> Option<u32> {
+        if marker < 0xD0 {
+            return Some(u32::from(marker).saturating_sub(1));
+        }
+
+        let mask = (marker.wrapping_add(1)) & 0xF;
+        let mut value = 0;
+        for i in (0..4).rev() {
+            if mask & (1 << i) != 0 {
+                value |= u32::from(self.read_byte()?) << (i * 8);
+            }
+        }
+
+        Some(value)
+    }
+
+    fn read_expression(&mut self) -> Option<Expression> {
+        let marker = self.read_byte()?;
+
+        match marker {
+            0x01..=0xCF | 0xF0..=0xFE => self.read_integer(marker).map(Expression::Integer),
+            0xD8..=0xDF | 0xEC => Some(Expression::Placeholder(marker)),
+            0xE0..=0xE5 => {
+                let left = self.read_operand()?;
+                let right = self.read_operand()?;
+                Some(Expression::Binary(marker, left, right))
+            }
+            0xE8..=0xEB => Some(Expression::Unary(marker, self.read_operand()?)),
+            0xFF => {
+                let length_marker = self.read_byte()?;
+                let length = self.read_integer(length_marker)? as usize;
+                let end = self.pos.checked_add(length)?;
+                let string = self.data.get(self.pos..end)?;
+                self.pos = end;
+
+                if self.depth >= MAX_DEPTH {
+                    return None;
+                }
+
+                let mut parser = Parser {
+                    data: string,
+                    pos: 0,
+                    depth: self.depth + 1,
+                };
+                Some(Expression::String(parser.read_payloads()?))
+            }
+            _ => None,
+        }
+    }
+
+    /// Reads an operand of a comparison or parameter, which counts towards `MAX_DEPTH` like nested strings do.
+    fn read_operand(&mut self) -> Option<Box<Expression>> {
+        if self.depth >= MAX_DEPTH {
+            return None;
+        }
+
+        self.depth += 1;
+        let operand = self.read_expression();
+        self.depth -= 1;
+
+        operand.map(Box::new)
+    }
+
+    fn read_macro(&mut self) -> Option<Payload> {
+        let code = self.read_byte()?;
+        let length_marker = self.read_byte()?;
+        let length = self.read_integer(length_marker)? as usize;
+        let end = self.pos.checked_add(length)?;
+
+        let mut args_parser = Parser {
+            data: self.data.get(self.pos..end)?,
+            pos: 0,
+            depth: self.depth,
+        };
+        let mut args = Vec::new();
+        while args_parser.pos < args_parser.data.len() {
+            args.push(args_parser.read_expression()?);
+        }
+
+        self.pos = end;
+        if self.read_byte()? != MACRO_END {
+            return None;
+        }
+
+        Some(Payload::Macro { code, args })
+    }
+
+    fn read_payloads(&mut self) -> Option<Vec<Payload>> {
+        let mut payloads = Vec::new();
+
+        while self.pos < self.data.len() {
+            if self.data[self.pos] == MACRO_START {
+                self.pos += 1;
+                payloads.push(self.read_macro()?);
+            } else {
+                let start = self.pos;
+                while self.pos < self.data.len() && self.data[self.pos] != MACRO_START {
+                    self.pos += 1;
+                }
+
+                payloads.push(Payload::Text(
+                    String::from_utf8_lossy(&self.data[start..self.pos]).into_owned(),
+                ));
+            }
+        }
+
+        Some(payloads)
+    }
+}
+
+/// Parses the SeString in `data`, or returns None if a macro is malformed.
+pub(crate) fn parse(data: &[u8]) -> Option<Vec<Payload>> {
+    Parser {
+        data,
+        pos: 0,
+        depth: 0,
+    }
+    .read_payloads()
+}
+
+/// Renders `payloads` as plain text, leaving out any macros that don't stand for text themselves.
+pub(crate) fn to_plain_text(payloads: &[Payload]) -> String {
+    let mut text = String::new();
+    for payload in payloads {
+        match payload {
+            Payload::Text(s) => text.push_str(s),
+            Payload::Macro { code: 0x10, .. } => text.push('\n'),
+            Payload::Macro { code: 0x16, .. } => text.push('\u{AD}'),
+            Payload::Macro { code: 0x1D, .. } => text.push('\u{A0}'),
+            Payload::Macro { code: 0x1F, .. } => text.push('-'),
+            Payload::Macro { .. } => {}
+        }
+    }
+
+    text
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub enum physis_SeStringNodeKind {
+    /// Plain text, stored in `text`.
+    Text,
+    /// A macro like a color change or a conditional. `text` is its name, `code` is its code and the children are its
+    /// arguments.
+    Macro,
+    /// An integer argument, stored in `value`.
+    Integer,
+    /// A value only known at runtime, like the time. `text` is its name.
+    Placeholder,
+    /// An operation like reading a parameter or comparing two values. `text` is its name and the children are its
+    /// operands.
+    Operation,
+    /// A string argument, the children are its payloads.
+    String,
+}
+
+#[repr(C)]
+pub struct physis_SeStringNode {
+    pub kind: physis_SeStringNodeKind,
+    pub text: *const c_char,
+    pub code: u8,
+    pub value: u32,
+    pub child_count: u32,
+    pub children: *mut physis_SeStringNode,
+}
+
+fn to_c_nodes(nodes: Vec<physis_SeStringNode>) -> (u32, *mut physis_SeStringNode) {
+    let mut nodes = nodes;
+    let result = (nodes.len() as u32, nodes.as_mut_ptr());
+
+    mem::forget(nodes);
+
+    result
+}
+
+fn to_c_node(
+    kind: physis_SeStringNodeKind,
+    text: &str,
+    code: u8,
+    value: u32,
+    children: Vec<physis_SeStringNode>,
+) -> physis_SeStringNode {
+    let (child_count, children) = to_c_nodes(children);
+
+    physis_SeStringNode {
+        kind,
+        text: ffi_to_c_string(&text.to_string()),
+        code,
+        value,
+        child_count,
+        children,
+    }
+}
+
+fn payload_to_c(payload: &Payload) -> physis_SeStringNode {
+    match payload {
+        Payload::Text(s) => to_c_node(physis_SeStringNodeKind::Text, s, 0, 0, Vec::new()),
+        Payload::Macro { code, args } => to_c_node(
+            physis_SeStringNodeKind::Macro,
+            macro_name(*code),
+            *code,
+            0,
+            args.iter().map(expression_to_c).collect(),
+        ),
+    }
+}
+
+fn expression_to_c(expression: &Expression) -> physis_SeStringNode {
+    match expression {
+        Expression::Integer(value) => {
+            to_c_node(physis_SeStringNodeKind::Integer, "", 0, *value, Vec::new())
+        }
+        Expression::Placeholder(code) => to_c_node(
+            physis_SeStringNodeKind::Placeholder,
+            expression_name(*code),
+            *code,
+            0,
+            Vec::new(),
+        ),
+        Expression::Unary(code, operand) => to_c_node(
+            physis_SeStringNodeKind::Operation,
+            expression_name(*code),
+            *code,
+            0,
+            vec![expression_to_c(operand)],
+        ),
+        Expression::Binary(code, left, right) => to_c_node(
+            physis_SeStringNodeKind::Operation,
+            expression_name(*code),
+            *code,
+            0,
+            vec![expression_to_c(left), expression_to_c(right)],
+        ),
+        Expression::String(payloads) => to_c_node(
+            physis_SeStringNodeKind::String,
+            "",
+            0,
+            0,
+            payloads.iter().map(payload_to_c).collect(),
+        ),
+    }
+}
+
+fn free_c_nodes(count: u32, nodes: *mut physis_SeStringNode) {
+    let nodes = ffi_to_vec(nodes, count);
+    for node in &nodes {
+        if !node.text.is_null() {
+            ffi_free_string(node.text);
+        }
+        free_c_nodes(node.child_count, node.children);
+    }
+}
+
+/// A parsed SeString, see `physis_sestring_parse`.
+#[repr(C)]
+pub struct physis_SeString {
+    pub payload_count: u32,
+    /// The top level payloads, which are either `Text` or `Macro` nodes.
+    pub payloads: *mut physis_SeStringNode,
+    /// The string rendered as plain text.
+    pub text: *const c_char,
+}
+
+impl Default for physis_SeString {
+    fn default() -> Self {
+        Self {
+            payload_count: 0,
+            payloads: null_mut(),
+            text: null(),
+        }
+    }
+}
+
+fn to_c_sestring(data: &[u8]) -> physis_SeString {
+    let Some(payloads) = parse(data) else {
+        set_last_error(physis_ErrorCode::ParseFailed, "Malformed SeString");
+        return physis_SeString::default();
+    };
+
+    let (payload_count, c_payloads) = to_c_nodes(payloads.iter().map(payload_to_c).collect());
+
+    physis_SeString {
+        payload_count,
+        payloads: c_payloads,
+        text: ffi_to_c_string(&to_plain_text(&payloads)),
+    }
+}
+
+/// Parses the raw SeString in `buffer` into a tree of payloads, and renders it as plain text. If it's malformed,
+/// `text` is NULL. This must be freed with `physis_sestring_free`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_sestring_parse(buffer: physis_Buffer) -> physis_SeString {
+    ffi_guard(physis_SeString::default(), || {
+        if buffer.data.is_null() {
+            return to_c_sestring(&[]);
+        }
+
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+        to_c_sestring(data)
+    })
+}
+
+/// Same as `physis_sestring_parse`, but for a string column. Physis decodes these before they reach us, so macro
+/// payloads that aren't valid UTF-8 are already lost and the result may be wrong. Use `physis_excel_sheet_raw_string`
+/// with `physis_sestring_parse` instead to parse the column as it's stored in the sheet.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_sestring_parse_field(field: &physis_Field) -> physis_SeString {
+    ffi_guard(physis_SeString::default(), || match field {
+        physis_Field::String(s) if !s.is_null() => {
+            let data = unsafe { CStr::from_ptr(*s) }.to_bytes();
+            to_c_sestring(data)
+        }
+        physis_Field::String(_) => {
+            set_last_error(
+                physis_ErrorCode::InvalidArgument,
+                "The string couldn't be converted, use physis_excel_sheet_raw_string instead",
+            );
+            physis_SeString::default()
+        }
+        _ => {
+            set_last_error(physis_ErrorCode::InvalidArgument, "Field is not a string");
+            physis_SeString::default()
+        }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_sestring_free(sestring: &physis_SeString) {
+    ffi_guard((), || {
+        if sestring.text.is_null() {
+            return;
+        }
+
+        ffi_free_string(sestring.text);
+        free_c_nodes(sestring.payload_count, sestring.payloads);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes a length the way `Parser::read_integer` reads it.
+    fn length(len: usize) -> Vec<u8> {
+        if len + 1 < 0xD0 {
+            vec![len as u8 + 1]
+        } else {
+            vec![0xF2, (len >> 8) as u8, len as u8]
+        }
+    }
+
+    /// A `string` macro whose argument is `inner`, nested `depth` times.
+    fn nested_strings(depth: usize) -> Vec<u8> {
+        let mut data = b"text".to_vec();
+        for _ in 0..depth {
+            let mut args: Vec<u8> = vec![0xFF];
+            args.extend(length(data.len()));
+            args.extend(data);
+
+            data = vec![MACRO_START, 0x29];
+            data.extend(length(args.len()));
+            data.extend(args);
+            data.push(MACRO_END);
+        }
+
+        data
+    }
+
+    /// A `num` macro whose argument reads a local number, whose index reads a local number and so on, `depth` times.
+    fn nested_operands(depth: usize) -> Vec<u8> {
+        let mut args: Vec<u8> = vec![0xE8; depth];
+        args.push(0x02);
+
+        let mut data = vec![MACRO_START, 0x20];
+        data.extend(length(args.len()));
+        data.extend(args);
+        data.push(MACRO_END);
+
+        data
+    }
+
+    #[test]
+    fn plain_text_is_kept() {
+        let payloads = parse(b"Hello").unwrap();
+        assert_eq!(to_plain_text(&payloads), "Hello");
+    }
+
+    #[test]
+    fn nested_strings_are_limited() {
+        assert!(parse(&nested_strings(MAX_DEPTH / 2)).is_some());
+        assert!(parse(&nested_strings(MAX_DEPTH * 2)).is_none());
+    }
+
+    #[test]
+    fn nested_operands_are_limited() {
+        assert!(parse(&nested_operands(MAX_DEPTH / 2)).is_some());
+        assert!(parse(&nested_operands(MAX_DEPTH * 2)).is_none());
+
+        // Deep enough to overflow the stack without the limit
+        assert!(parse(&nested_operands(60_000)).is_none());
+    }
+
+    #[test]
+    fn truncated_macros_are_rejected() {
+        let mut data = nested_strings(1);
+        data.pop();
+
+        assert!(parse(&data).is_none());
+    }
+}
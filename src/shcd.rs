@@ -1,12 +1,16 @@
 // SPDX-FileCopyrightText: 2024 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::{ffi_to_c_string, physis_Buffer};
+use crate::error::{ffi_result, set_physis_error};
+use crate::json::{ToJson, enum_to_json, ffi_parsed_to_json};
+use crate::panic::ffi_guard;
+use crate::{ffi_to_c_string, ffi_to_vec, physis_Buffer};
 use physis::shcd::SHCD;
 use physis::shcd::ShaderStage;
 use physis::{Platform, ReadableFile};
+use serde_json::{Value, json};
 use std::ffi::c_char;
-use std::ptr::null_mut;
+use std::ptr::{null, null_mut};
 use std::slice;
 
 #[repr(C)]
@@ -29,23 +33,36 @@ impl Default for physis_SHCD {
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_shcd_parse(platform: Platform, buffer: physis_Buffer) -> physis_SHCD {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(physis_SHCD::default(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-    if let Ok(shcd) = SHCD::from_existing(platform, data) {
-        let mut c_bytecode = shcd.bytecode.clone();
+        if let Some(shcd) = ffi_result(SHCD::from_existing(platform, data)) {
+            let mut c_bytecode = shcd.bytecode.clone();
 
-        let shcd = physis_SHCD {
-            stage: shcd.stage,
-            len: c_bytecode.len() as u32,
-            bytecode: c_bytecode.as_mut_ptr(),
-        };
+            let shcd = physis_SHCD {
+                stage: shcd.stage,
+                len: c_bytecode.len() as u32,
+                bytecode: c_bytecode.as_mut_ptr(),
+            };
 
-        std::mem::forget(c_bytecode);
+            std::mem::forget(c_bytecode);
 
-        shcd
-    } else {
-        physis_SHCD::default()
-    }
+            shcd
+        } else {
+            physis_SHCD::default()
+        }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_shcd_free(shcd: &physis_SHCD) {
+    ffi_guard((), || {
+        if shcd.bytecode.is_null() {
+            return;
+        }
+
+        drop(ffi_to_vec(shcd.bytecode, shcd.len));
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -53,10 +70,31 @@ pub unsafe extern "C" fn physis_shcd_debug(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> *const c_char {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(null(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-    match SHCD::from_existing(platform, data) {
-        Ok(shcd) => ffi_to_c_string(&format!("{shcd:#?}")),
-        Err(err) => ffi_to_c_string(&format!("{err:#?}")),
+        match SHCD::from_existing(platform, data) {
+            Ok(shcd) => ffi_to_c_string(&format!("{shcd:#?}")),
+            Err(err) => {
+                set_physis_error(&err);
+                ffi_to_c_string(&format!("{err:#?}"))
+            }
+        }
+    })
+}
+
+impl ToJson for physis_SHCD {
+    fn to_json(&self) -> Value {
+        json!({
+            "stage": enum_to_json(&self.stage),
+            "len": self.len,
+        })
     }
 }
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_shcd_to_json(platform: Platform, buffer: physis_Buffer) -> *const c_char {
+    ffi_guard(null(), || {
+        ffi_parsed_to_json(physis_shcd_parse(platform, buffer), physis_shcd_free)
+    })
+}
@@ -5,10 +5,15 @@ use physis::Platform;
 use physis::ReadableFile;
 use physis::shpk::MaterialParameter;
 use physis::shpk::{Key, Node, Pass, ResourceParameter, ShaderPackage};
+use serde_json::{Value, json};
 use std::ffi::c_char;
-use std::ptr::null_mut;
+use std::ptr::{null, null_mut};
 use std::{mem, slice};
 
+use crate::error::{ffi_result, physis_ErrorCode, set_last_error, set_physis_error};
+use crate::handle::{HandleRegistry, lock_value, physis_Handle};
+use crate::json::{ToJson, array_to_json, ffi_parsed_to_json, raw_to_json, string_to_json};
+use crate::panic::ffi_guard;
 use crate::{ffi_free_string, ffi_from_c_string, ffi_to_c_string, ffi_to_vec, physis_Buffer};
 
 #[repr(C)]
@@ -29,10 +34,12 @@ pub struct physis_Shader {
     resource_parameters: *mut physis_ShaderParameter,
 }
 
+static SHADER_PACKAGES: HandleRegistry<ShaderPackage> = HandleRegistry::new("SHPK");
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct physis_SHPK {
-    p_ptr: *mut ShaderPackage,
+    handle: physis_Handle,
     num_vertex_shaders: u32,
     vertex_shaders: *mut physis_Shader,
     num_pixel_shaders: u32,
@@ -69,7 +76,7 @@ pub struct physis_SHPK {
 impl Default for physis_SHPK {
     fn default() -> Self {
         Self {
-            p_ptr: null_mut(),
+            handle: physis_Handle::default(),
             num_vertex_shaders: 0,
             vertex_shaders: null_mut(),
             num_pixel_shaders: 0,
@@ -118,210 +125,218 @@ fn physis_get_shader_parameter_array(
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_shpk_parse(platform: Platform, buffer: physis_Buffer) -> physis_SHPK {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(physis_SHPK::default(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-    if let Ok(shpk) = ShaderPackage::from_existing(platform, data) {
-        let mut c_vertex_shaders = vec![];
-        let mut c_fragment_shaders = vec![];
+        if let Some(shpk) = ffi_result(ShaderPackage::from_existing(platform, data)) {
+            let mut c_vertex_shaders = vec![];
+            let mut c_fragment_shaders = vec![];
 
-        for shader in &shpk.vertex_shaders {
-            let mut bytecode = shader.bytecode.clone();
+            for shader in &shpk.vertex_shaders {
+                let mut bytecode = shader.bytecode.clone();
 
-            let (num_scalar_params, scalar_params) =
-                physis_get_shader_parameter_array(&shader.scalar_parameters);
-            let (num_resource_params, resource_params) =
-                physis_get_shader_parameter_array(&shader.resource_parameters);
+                let (num_scalar_params, scalar_params) =
+                    physis_get_shader_parameter_array(&shader.scalar_parameters);
+                let (num_resource_params, resource_params) =
+                    physis_get_shader_parameter_array(&shader.resource_parameters);
 
-            let shader = physis_Shader {
-                len: bytecode.len() as u32,
-                bytecode: bytecode.as_mut_ptr(),
-                num_scalar_parameters: num_scalar_params,
-                scalar_parameters: scalar_params,
-                num_resource_parameters: num_resource_params,
-                resource_parameters: resource_params,
-            };
+                let shader = physis_Shader {
+                    len: bytecode.len() as u32,
+                    bytecode: bytecode.as_mut_ptr(),
+                    num_scalar_parameters: num_scalar_params,
+                    scalar_parameters: scalar_params,
+                    num_resource_parameters: num_resource_params,
+                    resource_parameters: resource_params,
+                };
 
-            c_vertex_shaders.push(shader);
+                c_vertex_shaders.push(shader);
 
-            mem::forget(bytecode);
-        }
+                mem::forget(bytecode);
+            }
 
-        for shader in &shpk.pixel_shaders {
-            let mut bytecode = shader.bytecode.clone();
+            for shader in &shpk.pixel_shaders {
+                let mut bytecode = shader.bytecode.clone();
 
-            let (num_scalar_params, scalar_params) =
-                physis_get_shader_parameter_array(&shader.scalar_parameters);
-            let (num_resource_params, resource_params) =
-                physis_get_shader_parameter_array(&shader.resource_parameters);
+                let (num_scalar_params, scalar_params) =
+                    physis_get_shader_parameter_array(&shader.scalar_parameters);
+                let (num_resource_params, resource_params) =
+                    physis_get_shader_parameter_array(&shader.resource_parameters);
 
-            let shader = physis_Shader {
-                len: bytecode.len() as u32,
-                bytecode: bytecode.as_mut_ptr(),
-                num_scalar_parameters: num_scalar_params,
-                scalar_parameters: scalar_params,
-                num_resource_parameters: num_resource_params,
-                resource_parameters: resource_params,
-            };
+                let shader = physis_Shader {
+                    len: bytecode.len() as u32,
+                    bytecode: bytecode.as_mut_ptr(),
+                    num_scalar_parameters: num_scalar_params,
+                    scalar_parameters: scalar_params,
+                    num_resource_parameters: num_resource_params,
+                    resource_parameters: resource_params,
+                };
 
-            c_fragment_shaders.push(shader);
+                c_fragment_shaders.push(shader);
 
-            mem::forget(bytecode);
-        }
+                mem::forget(bytecode);
+            }
 
-        let mut system_keys = shpk.system_keys.clone();
-        let mut scene_keys = shpk.scene_keys.clone();
-        let mut material_keys = shpk.material_keys.clone();
-        let mut material_params = shpk.material_parameters.clone();
-        let mut material_default_params = shpk.mat_param_defaults.clone();
+            let mut system_keys = shpk.system_keys.clone();
+            let mut scene_keys = shpk.scene_keys.clone();
+            let mut material_keys = shpk.material_keys.clone();
+            let mut material_params = shpk.material_parameters.clone();
+            let mut material_default_params = shpk.mat_param_defaults.clone();
 
-        let mut nodes = Vec::new();
-        for node in &shpk.nodes {
-            nodes.push(convert_node(node));
-        }
+            let mut nodes = Vec::new();
+            for node in &shpk.nodes {
+                nodes.push(convert_node(node));
+            }
 
-        let (num_scalar_params, scalar_params) =
-            physis_get_shader_parameter_array(&shpk.scalar_parameters);
-        let (num_texture_params, texture_params) =
-            physis_get_shader_parameter_array(&shpk.texture_parameters);
-
-        let mat = physis_SHPK {
-            num_vertex_shaders: c_vertex_shaders.len() as u32,
-            vertex_shaders: c_vertex_shaders.as_mut_ptr(),
-            num_pixel_shaders: c_fragment_shaders.len() as u32,
-            pixel_shaders: c_fragment_shaders.as_mut_ptr(),
-            num_system_keys: system_keys.len() as u32,
-            system_keys: system_keys.as_mut_ptr(),
-            num_scene_keys: scene_keys.len() as u32,
-            scene_keys: scene_keys.as_mut_ptr(),
-            num_material_keys: material_keys.len() as u32,
-            material_keys: material_keys.as_mut_ptr(),
-            sub_view_key1_default: shpk.sub_view_key1_default,
-            sub_view_key2_default: shpk.sub_view_key2_default,
-            material_parameters_size: shpk.material_parameters_size,
-            num_material_parameters: material_params.len() as u32,
-            material_parameters: material_params.as_mut_ptr(),
-            material_default_parameters_size: material_default_params.len() as u32,
-            material_default_parameters: material_default_params.as_mut_ptr(),
-            num_nodes: nodes.len() as u32,
-            nodes: nodes.as_mut_ptr(),
-            num_scalar_parameters: num_scalar_params,
-            scalar_parameters: scalar_params,
-            num_texture_parameters: num_texture_params,
-            texture_parameters: texture_params,
-            p_ptr: Box::leak(Box::new(shpk)),
-        };
+            let (num_scalar_params, scalar_params) =
+                physis_get_shader_parameter_array(&shpk.scalar_parameters);
+            let (num_texture_params, texture_params) =
+                physis_get_shader_parameter_array(&shpk.texture_parameters);
+
+            let mat = physis_SHPK {
+                num_vertex_shaders: c_vertex_shaders.len() as u32,
+                vertex_shaders: c_vertex_shaders.as_mut_ptr(),
+                num_pixel_shaders: c_fragment_shaders.len() as u32,
+                pixel_shaders: c_fragment_shaders.as_mut_ptr(),
+                num_system_keys: system_keys.len() as u32,
+                system_keys: system_keys.as_mut_ptr(),
+                num_scene_keys: scene_keys.len() as u32,
+                scene_keys: scene_keys.as_mut_ptr(),
+                num_material_keys: material_keys.len() as u32,
+                material_keys: material_keys.as_mut_ptr(),
+                sub_view_key1_default: shpk.sub_view_key1_default,
+                sub_view_key2_default: shpk.sub_view_key2_default,
+                material_parameters_size: shpk.material_parameters_size,
+                num_material_parameters: material_params.len() as u32,
+                material_parameters: material_params.as_mut_ptr(),
+                material_default_parameters_size: material_default_params.len() as u32,
+                material_default_parameters: material_default_params.as_mut_ptr(),
+                num_nodes: nodes.len() as u32,
+                nodes: nodes.as_mut_ptr(),
+                num_scalar_parameters: num_scalar_params,
+                scalar_parameters: scalar_params,
+                num_texture_parameters: num_texture_params,
+                texture_parameters: texture_params,
+                handle: SHADER_PACKAGES.insert(shpk),
+            };
 
-        mem::forget(c_vertex_shaders);
-        mem::forget(c_fragment_shaders);
-        mem::forget(system_keys);
-        mem::forget(scene_keys);
-        mem::forget(material_keys);
-        mem::forget(material_params);
-        mem::forget(material_default_params);
-        mem::forget(nodes);
-
-        mat
-    } else {
-        physis_SHPK::default()
-    }
+            mem::forget(c_vertex_shaders);
+            mem::forget(c_fragment_shaders);
+            mem::forget(system_keys);
+            mem::forget(scene_keys);
+            mem::forget(material_keys);
+            mem::forget(material_params);
+            mem::forget(material_default_params);
+            mem::forget(nodes);
+
+            mat
+        } else {
+            physis_SHPK::default()
+        }
+    })
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_shpk_free(shpk: &physis_SHPK) {
-    if shpk.p_ptr.is_null() {
-        return;
-    }
-
-    unsafe {
-        let data = ffi_to_vec(shpk.texture_parameters, shpk.num_texture_parameters);
-        for parameter in &data {
-            ffi_free_string(parameter.name);
+    ffi_guard((), || {
+        if shpk.handle.is_null() {
+            return;
         }
-        drop(data);
 
-        let data = ffi_to_vec(shpk.scalar_parameters, shpk.num_scalar_parameters);
-        for parameter in &data {
-            ffi_free_string(parameter.name);
-        }
-        drop(data);
-
-        let data = ffi_to_vec(shpk.nodes, shpk.num_nodes);
-        for node in &data {
-            let data = ffi_to_vec(node.passes, node.pass_count);
-            drop(data);
+        let Some(package) = SHADER_PACKAGES.remove(shpk.handle) else {
+            return;
+        };
 
-            let data = ffi_to_vec(node.subview_keys, node.subview_key_count);
+        unsafe {
+            let data = ffi_to_vec(shpk.texture_parameters, shpk.num_texture_parameters);
+            for parameter in &data {
+                ffi_free_string(parameter.name);
+            }
             drop(data);
 
-            let data = ffi_to_vec(node.material_keys, node.material_key_count);
+            let data = ffi_to_vec(shpk.scalar_parameters, shpk.num_scalar_parameters);
+            for parameter in &data {
+                ffi_free_string(parameter.name);
+            }
             drop(data);
 
-            let data = ffi_to_vec(node.scene_keys, node.scene_key_count);
-            drop(data);
+            let data = ffi_to_vec(shpk.nodes, shpk.num_nodes);
+            for node in &data {
+                let data = ffi_to_vec(node.passes, node.pass_count);
+                drop(data);
 
-            let data = ffi_to_vec(node.system_keys, node.system_key_count);
-            drop(data);
-        }
-        drop(data);
+                let data = ffi_to_vec(node.subview_keys, node.subview_key_count);
+                drop(data);
 
-        let data = ffi_to_vec(
-            shpk.material_default_parameters,
-            shpk.material_default_parameters_size,
-        );
-        drop(data);
+                let data = ffi_to_vec(node.material_keys, node.material_key_count);
+                drop(data);
 
-        let data = ffi_to_vec(shpk.material_parameters, shpk.num_material_parameters);
-        drop(data);
+                let data = ffi_to_vec(node.scene_keys, node.scene_key_count);
+                drop(data);
 
-        let data = ffi_to_vec(shpk.material_keys, shpk.num_material_keys);
-        drop(data);
+                let data = ffi_to_vec(node.system_keys, node.system_key_count);
+                drop(data);
+            }
+            drop(data);
 
-        let data = ffi_to_vec(shpk.scene_keys, shpk.num_scene_keys);
-        drop(data);
+            let data = ffi_to_vec(
+                shpk.material_default_parameters,
+                shpk.material_default_parameters_size,
+            );
+            drop(data);
 
-        let data = ffi_to_vec(shpk.system_keys, shpk.num_system_keys);
-        drop(data);
+            let data = ffi_to_vec(shpk.material_parameters, shpk.num_material_parameters);
+            drop(data);
 
-        let data = ffi_to_vec(shpk.pixel_shaders, shpk.num_pixel_shaders);
-        for shader in &data {
-            let data = ffi_to_vec(shader.resource_parameters, shader.num_resource_parameters);
-            for parameter in &data {
-                ffi_free_string(parameter.name);
-            }
+            let data = ffi_to_vec(shpk.material_keys, shpk.num_material_keys);
             drop(data);
 
-            let data = ffi_to_vec(shader.scalar_parameters, shader.num_scalar_parameters);
-            for parameter in &data {
-                ffi_free_string(parameter.name);
-            }
+            let data = ffi_to_vec(shpk.scene_keys, shpk.num_scene_keys);
             drop(data);
 
-            let data = ffi_to_vec(shader.bytecode, shader.len);
+            let data = ffi_to_vec(shpk.system_keys, shpk.num_system_keys);
             drop(data);
-        }
-        drop(data);
 
-        let data = ffi_to_vec(shpk.vertex_shaders, shpk.num_vertex_shaders);
-        for shader in &data {
-            let data = ffi_to_vec(shader.resource_parameters, shader.num_resource_parameters);
-            for parameter in &data {
-                ffi_free_string(parameter.name);
+            let data = ffi_to_vec(shpk.pixel_shaders, shpk.num_pixel_shaders);
+            for shader in &data {
+                let data = ffi_to_vec(shader.resource_parameters, shader.num_resource_parameters);
+                for parameter in &data {
+                    ffi_free_string(parameter.name);
+                }
+                drop(data);
+
+                let data = ffi_to_vec(shader.scalar_parameters, shader.num_scalar_parameters);
+                for parameter in &data {
+                    ffi_free_string(parameter.name);
+                }
+                drop(data);
+
+                let data = ffi_to_vec(shader.bytecode, shader.len);
+                drop(data);
             }
             drop(data);
 
-            let data = ffi_to_vec(shader.scalar_parameters, shader.num_scalar_parameters);
-            for parameter in &data {
-                ffi_free_string(parameter.name);
+            let data = ffi_to_vec(shpk.vertex_shaders, shpk.num_vertex_shaders);
+            for shader in &data {
+                let data = ffi_to_vec(shader.resource_parameters, shader.num_resource_parameters);
+                for parameter in &data {
+                    ffi_free_string(parameter.name);
+                }
+                drop(data);
+
+                let data = ffi_to_vec(shader.scalar_parameters, shader.num_scalar_parameters);
+                for parameter in &data {
+                    ffi_free_string(parameter.name);
+                }
+                drop(data);
+
+                let data = ffi_to_vec(shader.bytecode, shader.len);
+                drop(data);
             }
             drop(data);
 
-            let data = ffi_to_vec(shader.bytecode, shader.len);
-            drop(data);
+            drop(package);
         }
-        drop(data);
-
-        drop(Box::from_raw(shpk.p_ptr));
-    }
+    })
 }
 
 #[repr(C)]
@@ -340,6 +355,25 @@ pub struct physis_SHPKNode {
     passes: *mut Pass,
 }
 
+impl Default for physis_SHPKNode {
+    fn default() -> Self {
+        Self {
+            selector: 0,
+            pass_count: 0,
+            pass_indices: [0; 16],
+            system_key_count: 0,
+            system_keys: null_mut(),
+            scene_key_count: 0,
+            scene_keys: null_mut(),
+            material_key_count: 0,
+            material_keys: null_mut(),
+            subview_key_count: 0,
+            subview_keys: null_mut(),
+            passes: null_mut(),
+        }
+    }
+}
+
 fn convert_node(node: &Node) -> physis_SHPKNode {
     let mut c_system_keys = node.system_keys.clone();
     let mut c_scene_keys = node.scene_keys.clone();
@@ -373,26 +407,22 @@ fn convert_node(node: &Node) -> physis_SHPKNode {
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_shpk_get_node(shpk: *const physis_SHPK, key: u32) -> physis_SHPKNode {
-    unsafe {
-        if let Some(node) = (*(*shpk).p_ptr).find_node(key) {
+    ffi_guard(physis_SHPKNode::default(), || unsafe {
+        let Some(shared) = SHADER_PACKAGES.shared((*shpk).handle) else {
+            return physis_SHPKNode::default();
+        };
+        let package = lock_value(&shared);
+
+        if let Some(node) = package.find_node(key) {
             convert_node(node)
         } else {
-            physis_SHPKNode {
-                selector: 0,
-                pass_count: 0,
-                pass_indices: [0; 16],
-                system_key_count: 0,
-                system_keys: null_mut(),
-                scene_key_count: 0,
-                scene_keys: null_mut(),
-                material_key_count: 0,
-                material_keys: null_mut(),
-                subview_key_count: 0,
-                subview_keys: null_mut(),
-                passes: null_mut(),
-            }
+            set_last_error(
+                physis_ErrorCode::InvalidArgument,
+                format!("No node with key {key}"),
+            );
+            physis_SHPKNode::default()
         }
-    }
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -406,38 +436,46 @@ pub extern "C" fn physis_shpk_build_selector_from_all_keys(
     subview_keys: *const u32,
     subview_key_count: u32,
 ) -> u32 {
-    let system_keys = if !system_keys.is_null() {
-        unsafe { slice::from_raw_parts(system_keys, system_key_count as usize) }
-    } else {
-        &[]
-    };
-    let scene_keys = if !scene_keys.is_null() {
-        unsafe { slice::from_raw_parts(scene_keys, scene_key_count as usize) }
-    } else {
-        &[]
-    };
-    let material_keys = if !material_keys.is_null() {
-        unsafe { slice::from_raw_parts(material_keys, material_key_count as usize) }
-    } else {
-        &[]
-    };
-    let subview_keys = if !subview_keys.is_null() {
-        unsafe { slice::from_raw_parts(subview_keys, subview_key_count as usize) }
-    } else {
-        &[]
-    };
+    ffi_guard(0, || {
+        let system_keys = if !system_keys.is_null() {
+            unsafe { slice::from_raw_parts(system_keys, system_key_count as usize) }
+        } else {
+            &[]
+        };
+        let scene_keys = if !scene_keys.is_null() {
+            unsafe { slice::from_raw_parts(scene_keys, scene_key_count as usize) }
+        } else {
+            &[]
+        };
+        let material_keys = if !material_keys.is_null() {
+            unsafe { slice::from_raw_parts(material_keys, material_key_count as usize) }
+        } else {
+            &[]
+        };
+        let subview_keys = if !subview_keys.is_null() {
+            unsafe { slice::from_raw_parts(subview_keys, subview_key_count as usize) }
+        } else {
+            &[]
+        };
 
-    ShaderPackage::build_selector_from_all_keys(
-        system_keys,
-        scene_keys,
-        material_keys,
-        subview_keys,
-    )
+        ShaderPackage::build_selector_from_all_keys(
+            system_keys,
+            scene_keys,
+            material_keys,
+            subview_keys,
+        )
+    })
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_shpk_crc(name: *const c_char) -> u32 {
-    ShaderPackage::crc(&ffi_from_c_string(name).unwrap())
+    ffi_guard(0, || {
+        let Some(r_name) = ffi_from_c_string(name) else {
+            return 0;
+        };
+
+        ShaderPackage::crc(&r_name)
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -445,10 +483,127 @@ pub unsafe extern "C" fn physis_shpk_debug(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> *const c_char {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(null(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+
+        match ShaderPackage::from_existing(platform, data) {
+            Ok(shpk) => ffi_to_c_string(&format!("{shpk:#?}")),
+            Err(err) => {
+                set_physis_error(&err);
+                ffi_to_c_string(&format!("{err:#?}"))
+            }
+        }
+    })
+}
+
+fn shader_parameters_to_json(parameters: *const physis_ShaderParameter, count: u32) -> Value {
+    array_to_json(parameters, count as usize, |parameter| {
+        json!({
+            "slot": parameter.slot,
+            "name": string_to_json(parameter.name),
+        })
+    })
+}
 
-    match ShaderPackage::from_existing(platform, data) {
-        Ok(shpk) => ffi_to_c_string(&format!("{shpk:#?}")),
-        Err(err) => ffi_to_c_string(&format!("{err:#?}")),
+impl ToJson for physis_Shader {
+    fn to_json(&self) -> Value {
+        json!({
+            "len": self.len,
+            "scalar_parameters": shader_parameters_to_json(
+                self.scalar_parameters,
+                self.num_scalar_parameters
+            ),
+            "resource_parameters": shader_parameters_to_json(
+                self.resource_parameters,
+                self.num_resource_parameters
+            ),
+        })
+    }
+}
+
+impl ToJson for physis_SHPKNode {
+    fn to_json(&self) -> Value {
+        json!({
+            "selector": self.selector,
+            "pass_indices": self.pass_indices,
+            "system_keys": array_to_json(
+                self.system_keys,
+                self.system_key_count as usize,
+                |key| json!(key)
+            ),
+            "scene_keys": array_to_json(
+                self.scene_keys,
+                self.scene_key_count as usize,
+                |key| json!(key)
+            ),
+            "material_keys": array_to_json(
+                self.material_keys,
+                self.material_key_count as usize,
+                |key| json!(key)
+            ),
+            "subview_keys": array_to_json(
+                self.subview_keys,
+                self.subview_key_count as usize,
+                |key| json!(key)
+            ),
+            "passes": array_to_json(self.passes, self.pass_count as usize, raw_to_json),
+        })
+    }
+}
+
+impl ToJson for physis_SHPK {
+    fn to_json(&self) -> Value {
+        json!({
+            "vertex_shaders": array_to_json(
+                self.vertex_shaders,
+                self.num_vertex_shaders as usize,
+                ToJson::to_json
+            ),
+            "pixel_shaders": array_to_json(
+                self.pixel_shaders,
+                self.num_pixel_shaders as usize,
+                ToJson::to_json
+            ),
+            "system_keys": array_to_json(
+                self.system_keys,
+                self.num_system_keys as usize,
+                raw_to_json
+            ),
+            "scene_keys": array_to_json(self.scene_keys, self.num_scene_keys as usize, raw_to_json),
+            "material_keys": array_to_json(
+                self.material_keys,
+                self.num_material_keys as usize,
+                raw_to_json
+            ),
+            "sub_view_key1_default": self.sub_view_key1_default,
+            "sub_view_key2_default": self.sub_view_key2_default,
+            "material_parameters_size": self.material_parameters_size,
+            "material_parameters": array_to_json(
+                self.material_parameters,
+                self.num_material_parameters as usize,
+                raw_to_json
+            ),
+            "material_default_parameters": array_to_json(
+                self.material_default_parameters,
+                self.material_default_parameters_size as usize,
+                |parameter| json!(parameter)
+            ),
+            "nodes": array_to_json(self.nodes, self.num_nodes as usize, ToJson::to_json),
+            "scalar_parameters": shader_parameters_to_json(
+                self.scalar_parameters,
+                self.num_scalar_parameters
+            ),
+            "texture_parameters": shader_parameters_to_json(
+                self.texture_parameters,
+                self.num_texture_parameters
+            ),
+        })
     }
 }
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_shpk_to_json(platform: Platform, buffer: physis_Buffer) -> *const c_char {
+    ffi_guard(null(), || {
+        ffi_parsed_to_json(physis_shpk_parse(platform, buffer), physis_shpk_free)
+    })
+}
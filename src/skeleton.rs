@@ -1,13 +1,17 @@
 // SPDX-FileCopyrightText: 2024 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use crate::error::{ffi_result, set_physis_error};
+use crate::json::{ToJson, array_to_json, ffi_parsed_to_json, string_to_json};
+use crate::panic::ffi_guard;
 use crate::{ffi_free_string, ffi_to_c_string, ffi_to_vec, physis_Buffer};
 use physis::Platform;
 use physis::ReadableFile;
 use physis::race::{Gender, Race, Tribe};
 use physis::skeleton::Skeleton;
+use serde_json::{Value, json};
 use std::os::raw::c_char;
-use std::ptr::null_mut;
+use std::ptr::{null, null_mut};
 use std::{mem, slice};
 
 #[repr(C)]
@@ -77,13 +81,15 @@ pub extern "C" fn physis_skeleton_parse(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> physis_Skeleton {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(physis_Skeleton::default(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-    if let Ok(skeleton) = Skeleton::from_existing(platform, data) {
-        convert_skeleton(&skeleton)
-    } else {
-        physis_Skeleton::default()
-    }
+        if let Some(skeleton) = ffi_result(Skeleton::from_existing(platform, data)) {
+            convert_skeleton(&skeleton)
+        } else {
+            physis_Skeleton::default()
+        }
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -91,28 +97,68 @@ pub unsafe extern "C" fn physis_skeleton_debug(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> *const c_char {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(null(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+
+        match Skeleton::from_existing(platform, data) {
+            Ok(sklb) => ffi_to_c_string(&format!("{sklb:#?}")),
+            Err(err) => {
+                set_physis_error(&err);
+                ffi_to_c_string(&format!("{err:#?}"))
+            }
+        }
+    })
+}
 
-    match Skeleton::from_existing(platform, data) {
-        Ok(sklb) => ffi_to_c_string(&format!("{sklb:#?}")),
-        Err(err) => ffi_to_c_string(&format!("{err:#?}")),
+impl ToJson for physis_Skeleton {
+    fn to_json(&self) -> Value {
+        // parent_bone and root_bone are left out, since they only point back into bones
+        json!({
+            "bones": array_to_json(self.bones, self.num_bones as usize, |bone| {
+                json!({
+                    "index": bone.index,
+                    "name": string_to_json(bone.name),
+                    "parent_index": bone.parent_index,
+                    "position": bone.position,
+                    "rotation": bone.rotation,
+                    "scale": bone.scale,
+                })
+            }),
+        })
     }
 }
 
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_skeleton_to_json(
+    platform: Platform,
+    buffer: physis_Buffer,
+) -> *const c_char {
+    ffi_guard(null(), || {
+        ffi_parsed_to_json(
+            physis_skeleton_parse(platform, buffer),
+            physis_skeleton_free,
+        )
+    })
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_skeleton_free(skeleton: &physis_Skeleton) {
-    if skeleton.root_bone.is_null() {
-        return;
-    }
+    ffi_guard((), || {
+        if skeleton.root_bone.is_null() {
+            return;
+        }
 
-    let data = ffi_to_vec(skeleton.bones, skeleton.num_bones);
-    for bone in &data {
-        ffi_free_string(bone.name);
-    }
-    drop(data);
+        let data = ffi_to_vec(skeleton.bones, skeleton.num_bones);
+        for bone in &data {
+            ffi_free_string(bone.name);
+        }
+        drop(data);
+    })
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_skeleton_path(race: Race, tribe: Tribe, gender: Gender) -> *const c_char {
-    ffi_to_c_string(&Skeleton::path(race, tribe, gender))
+    ffi_guard(null(), || {
+        ffi_to_c_string(&Skeleton::path(race, tribe, gender))
+    })
 }
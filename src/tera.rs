@@ -1,12 +1,16 @@
 // SPDX-FileCopyrightText: 2024 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::{ffi_to_c_string, physis_Buffer};
+use crate::error::{ffi_result, set_physis_error};
+use crate::json::{ToJson, array_to_json, ffi_parsed_to_json, string_to_json};
+use crate::panic::ffi_guard;
+use crate::{ffi_free_string, ffi_to_c_string, ffi_to_vec, physis_Buffer};
 use physis::Platform;
 use physis::ReadableFile;
 use physis::tera::Terrain;
+use serde_json::{Value, json};
 use std::os::raw::c_char;
-use std::ptr::null_mut;
+use std::ptr::{null, null_mut};
 use std::{mem, slice};
 
 #[repr(C)]
@@ -39,30 +43,47 @@ pub extern "C" fn physis_terrain_parse(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> physis_Terrain {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(physis_Terrain::default(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-    if let Ok(tera) = Terrain::from_existing(platform, data) {
-        let mut c_plates = vec![];
+        if let Some(tera) = ffi_result(Terrain::from_existing(platform, data)) {
+            let mut c_plates = vec![];
 
-        for (i, plate) in tera.plates.iter().enumerate() {
-            c_plates.push(physis_PlateModel {
-                position: tera.plate_position(plate),
-                filename: ffi_to_c_string(&Terrain::mdl_filename(i)),
-            });
-        }
+            for (i, plate) in tera.plates.iter().enumerate() {
+                c_plates.push(physis_PlateModel {
+                    position: tera.plate_position(plate),
+                    filename: ffi_to_c_string(&Terrain::mdl_filename(i)),
+                });
+            }
 
-        let mat = physis_Terrain {
-            num_plates: c_plates.len() as i32,
-            plates: c_plates.as_mut_ptr(),
-            clip_distance: tera.clip_distance,
-        };
+            let mat = physis_Terrain {
+                num_plates: c_plates.len() as i32,
+                plates: c_plates.as_mut_ptr(),
+                clip_distance: tera.clip_distance,
+            };
 
-        mem::forget(c_plates);
+            mem::forget(c_plates);
 
-        mat
-    } else {
-        physis_Terrain::default()
-    }
+            mat
+        } else {
+            physis_Terrain::default()
+        }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_terrain_free(terrain: &physis_Terrain) {
+    ffi_guard((), || {
+        if terrain.plates.is_null() {
+            return;
+        }
+
+        let plates = ffi_to_vec(terrain.plates, terrain.num_plates as u32);
+        for plate in &plates {
+            ffi_free_string(plate.filename);
+        }
+        drop(plates);
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -70,10 +91,36 @@ pub unsafe extern "C" fn physis_tera_debug(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> *const c_char {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(null(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+
+        match Terrain::from_existing(platform, data) {
+            Ok(tera) => ffi_to_c_string(&format!("{tera:#?}")),
+            Err(err) => {
+                set_physis_error(&err);
+                ffi_to_c_string(&format!("{err:#?}"))
+            }
+        }
+    })
+}
 
-    match Terrain::from_existing(platform, data) {
-        Ok(tera) => ffi_to_c_string(&format!("{tera:#?}")),
-        Err(err) => ffi_to_c_string(&format!("{err:#?}")),
+impl ToJson for physis_Terrain {
+    fn to_json(&self) -> Value {
+        json!({
+            "plates": array_to_json(self.plates, self.num_plates as usize, |plate| {
+                json!({
+                    "position": plate.position,
+                    "filename": string_to_json(plate.filename),
+                })
+            }),
+            "clip_distance": self.clip_distance,
+        })
     }
 }
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_tera_to_json(platform: Platform, buffer: physis_Buffer) -> *const c_char {
+    ffi_guard(null(), || {
+        ffi_parsed_to_json(physis_terrain_parse(platform, buffer), physis_terrain_free)
+    })
+}
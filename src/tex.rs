@@ -1,19 +1,29 @@
 // SPDX-FileCopyrightText: 2024 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::{ffi_to_c_string, physis_Buffer};
+use crate::abi::struct_size;
+use crate::error::{ffi_result, physis_ErrorCode, set_last_error, set_physis_error};
+use crate::handle::{HandleRegistry, lock_value, physis_Handle};
+use crate::job::{physis_JobId, physis_JobStatus, spawn_job};
+use crate::json::{ToJson, enum_to_json, ffi_parsed_to_json, flags_to_json};
+use crate::panic::ffi_guard;
+use crate::{ffi_size, ffi_to_c_string, ffi_to_large_buffer, physis_Buffer, physis_LargeBuffer};
 use physis::Platform;
 use physis::ReadableFile;
 use physis::tex::TextureAttribute;
 use physis::tex::{Texture, TextureFormat};
-use std::ffi::c_char;
-use std::ptr::null_mut;
+use serde_json::{Value, json};
+use std::ffi::{c_char, c_void};
+use std::ptr::{null, null_mut};
 use std::{mem, slice};
 
+static TEXTURES: HandleRegistry<Texture> = HandleRegistry::new("Texture");
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct physis_Texture {
-    p_ptr: *mut Texture,
+    struct_size: u32,
+    handle: physis_Handle,
 
     attribute: TextureAttribute,
     format: TextureFormat,
@@ -29,7 +39,8 @@ pub struct physis_Texture {
 impl Default for physis_Texture {
     fn default() -> Self {
         Self {
-            p_ptr: null_mut(),
+            struct_size: struct_size::<physis_Texture>(),
+            handle: physis_Handle::default(),
             attribute: TextureAttribute::MANAGED,
             format: TextureFormat::A8_UNORM,
             width: 0,
@@ -48,13 +59,82 @@ pub extern "C" fn physis_texture_parse(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> physis_Texture {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(physis_Texture::default(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+
+        let texture = parse_texture(platform, data);
+        if ffi_size(texture.data_size).is_none() {
+            drop(TEXTURES.remove(texture.texture.handle));
+            return physis_Texture::default();
+        }
+
+        texture.texture
+    })
+}
 
-    if let Ok(texture) = Texture::from_existing(platform, data) {
-        let mut texture = Box::new(texture);
+/// Called when an asynchronous Texture parse finishes. The result must be freed with `physis_tex_free`.
+pub type physis_TextureCallback = extern "C" fn(
+    user_data: *mut c_void,
+    job: physis_JobId,
+    status: physis_JobStatus,
+    texture: physis_Texture,
+);
+
+/// Same as `physis_texture_parse`, but parses on a worker thread and then calls `callback` from there. `buffer` must stay valid
+/// until then. Returns the job id, or 0 if it couldn't be queued.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_texture_parse_async(
+    platform: Platform,
+    buffer: physis_Buffer,
+    callback: physis_TextureCallback,
+    user_data: *mut c_void,
+) -> physis_JobId {
+    ffi_guard(0, || {
+        spawn_job(
+            move || physis_texture_parse(platform, buffer),
+            move |job, status, texture| callback(user_data, job, status, texture),
+        )
+    })
+}
+
+/// Like `physis_Texture`, but with a 64-bit size for texture data larger than 4 GiB. `texture` can be passed to every
+/// other texture function, and must be freed with `physis_tex_free`. Its `data_size` is 0 if the data is that large.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct physis_LargeTexture {
+    texture: physis_Texture,
+    data_size: usize,
+}
+
+impl Default for physis_LargeTexture {
+    fn default() -> Self {
+        Self {
+            texture: physis_Texture::default(),
+            data_size: 0,
+        }
+    }
+}
+
+/// Same as `physis_texture_parse`, but for textures larger than 4 GiB.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_texture_parse_large(
+    platform: Platform,
+    buffer: physis_LargeBuffer,
+) -> physis_LargeTexture {
+    ffi_guard(physis_LargeTexture::default(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size) };
+
+        parse_texture(platform, data)
+    })
+}
+
+fn parse_texture(platform: Platform, data: &[u8]) -> physis_LargeTexture {
+    if let Some(mut texture) = ffi_result(Texture::from_existing(platform, data)) {
+        let data_size = texture.data.len();
 
         let mut tex = physis_Texture {
-            p_ptr: null_mut(),
+            struct_size: struct_size::<physis_Texture>(),
+            handle: physis_Handle::default(),
             attribute: texture.attribute,
             format: texture.format,
             width: texture.width,
@@ -62,14 +142,18 @@ pub extern "C" fn physis_texture_parse(
             depth: texture.depth,
             mip_levels: texture.mip_levels,
             layers: texture.layers(),
-            data_size: texture.data.len() as u32,
+            data_size: u32::try_from(data_size).unwrap_or(0),
             data: texture.data.as_mut_ptr(),
         };
-        tex.p_ptr = Box::into_raw(texture);
+        // The texture data is heap allocated, so the pointer above stays valid after moving it into the registry
+        tex.handle = TEXTURES.insert(texture);
 
-        tex
+        physis_LargeTexture {
+            texture: tex,
+            data_size,
+        }
     } else {
-        physis_Texture::default()
+        physis_LargeTexture::default()
     }
 }
 
@@ -89,33 +173,63 @@ impl Default for physis_TextureRgba {
     }
 }
 
+fn texture_to_rgba(texture: &physis_Texture) -> Option<Vec<u8>> {
+    let parsed = TEXTURES.with(texture.handle, |texture| texture.to_rgba())?;
+
+    if parsed.is_none() {
+        set_last_error(
+            physis_ErrorCode::ParseFailed,
+            "Failed to convert texture to RGBA, the format may be unsupported",
+        );
+    }
+
+    parsed
+}
+
+/// Converts `texture` to RGBA8. If the result is larger than 4 GiB, this fails with `TooLarge` and
+/// `physis_texture_to_rgba_large` has to be used instead.
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_texture_to_rgba(texture: physis_Texture) -> physis_TextureRgba {
-    unsafe {
-        if let Some(mut parsed) = (*texture.p_ptr).to_rgba() {
-            let rgba = physis_TextureRgba {
-                rgba_size: parsed.len() as u32,
-                rgba: parsed.as_mut_ptr(),
-            };
+    ffi_guard(physis_TextureRgba::default(), || {
+        let Some(mut parsed) = texture_to_rgba(&texture) else {
+            return physis_TextureRgba::default();
+        };
 
-            mem::forget(parsed);
+        let Some(rgba_size) = ffi_size(parsed.len()) else {
+            return physis_TextureRgba::default();
+        };
 
-            rgba
-        } else {
-            physis_TextureRgba::default()
-        }
-    }
+        let rgba = physis_TextureRgba {
+            rgba_size,
+            rgba: parsed.as_mut_ptr(),
+        };
+
+        mem::forget(parsed);
+
+        rgba
+    })
+}
+
+/// Same as `physis_texture_to_rgba`, but for results of any size. The buffer must be freed with
+/// `physis_free_large_buffer`.
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_texture_to_rgba_large(texture: physis_Texture) -> physis_LargeBuffer {
+    ffi_guard(physis_LargeBuffer::default(), || {
+        texture_to_rgba(&texture)
+            .map(ffi_to_large_buffer)
+            .unwrap_or_default()
+    })
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_tex_free(tex: &physis_Texture) {
-    if tex.p_ptr.is_null() {
-        return;
-    }
+    ffi_guard((), || {
+        if tex.handle.is_null() {
+            return;
+        }
 
-    unsafe {
-        drop(Box::from_raw(tex.p_ptr));
-    }
+        drop(TEXTURES.remove(tex.handle));
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -123,14 +237,41 @@ pub unsafe extern "C" fn physis_tex_debug(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> *const c_char {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(null(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+
+        match Texture::from_existing(platform, data) {
+            Ok(tex) => ffi_to_c_string(&format!("{tex:#?}")),
+            Err(err) => {
+                set_physis_error(&err);
+                ffi_to_c_string(&format!("{err:#?}"))
+            }
+        }
+    })
+}
 
-    match Texture::from_existing(platform, data) {
-        Ok(tex) => ffi_to_c_string(&format!("{tex:#?}")),
-        Err(err) => ffi_to_c_string(&format!("{err:#?}")),
+impl ToJson for physis_Texture {
+    fn to_json(&self) -> Value {
+        json!({
+            "attribute": flags_to_json(&self.attribute),
+            "format": enum_to_json(&self.format),
+            "width": self.width,
+            "height": self.height,
+            "depth": self.depth,
+            "mip_levels": self.mip_levels,
+            "layers": self.layers,
+            "data_size": self.data_size,
+        })
     }
 }
 
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_tex_to_json(platform: Platform, buffer: physis_Buffer) -> *const c_char {
+    ffi_guard(null(), || {
+        ffi_parsed_to_json(physis_texture_parse(platform, buffer), physis_tex_free)
+    })
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Default)]
 pub struct physis_TextureMipData {
@@ -145,13 +286,14 @@ pub unsafe extern "C" fn physis_tex_mip_data(
     texture: &physis_Texture,
     level: u8,
 ) -> physis_TextureMipData {
-    if texture.p_ptr.is_null() {
-        return physis_TextureMipData::default();
-    }
+    ffi_guard(physis_TextureMipData::default(), || {
+        let Some(shared) = TEXTURES.shared(texture.handle) else {
+            return physis_TextureMipData::default();
+        };
+        let texture = lock_value(&shared);
 
-    unsafe {
-        if let Some((start, end)) = (*texture.p_ptr).mip_data(level) {
-            let (width, height) = (*texture.p_ptr).mip_size(level);
+        if let Some((start, end)) = texture.mip_data(level) {
+            let (width, height) = texture.mip_size(level);
 
             physis_TextureMipData {
                 width,
@@ -160,7 +302,11 @@ pub unsafe extern "C" fn physis_tex_mip_data(
                 end,
             }
         } else {
+            set_last_error(
+                physis_ErrorCode::InvalidArgument,
+                format!("Mip level {level} is out of range"),
+            );
             physis_TextureMipData::default()
         }
-    }
+    })
 }
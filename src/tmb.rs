@@ -1,11 +1,15 @@
 // SPDX-FileCopyrightText: 2026 Joshua Goins <josh@redstrate.com>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use crate::error::{ffi_result, set_physis_error};
+use crate::json::{ToJson, array_to_json, ffi_parsed_to_json, raw_to_json};
+use crate::panic::ffi_guard;
 use crate::{ffi_to_c_string, physis_Buffer};
 use physis::Platform;
 use physis::ReadableFile;
 use physis::tmb::{Attribute, C013, Tmdh};
 use physis::tmb::{TimelineNodeData, Tmb, TmfcData, TmfcRow};
+use serde_json::{Value, json};
 use std::ffi::c_char;
 use std::ptr::null;
 use std::slice;
@@ -78,7 +82,7 @@ pub struct physis_TmfcData {
 }
 
 #[repr(C)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub enum physis_Attribute {
     PositionX,
     PositionY,
@@ -160,19 +164,25 @@ fn to_c_data(tmb: &TimelineNodeData) -> physis_TimelineNodeData {
 
             physis_TimelineNodeData::Tmfc(c_tmfc)
         }
-        _ => physis_TimelineNodeData::Unknown,
+        _ => {
+            log::warn!("Skipping a TMB node type that isn't supported yet: {tmb:?}");
+            physis_TimelineNodeData::Unknown
+        }
     }
 }
 
 fn to_c_tmfc_data(data: &TmfcData) -> physis_TmfcData {
-    let attribute = match data.attribute {
+    let attribute = match &data.attribute {
         Attribute::PositionX => physis_Attribute::PositionX,
         Attribute::PositionY => physis_Attribute::PositionY,
         Attribute::PositionZ => physis_Attribute::PositionZ,
         Attribute::RotationX => physis_Attribute::RotationX,
         Attribute::RotationY => physis_Attribute::RotationY,
         Attribute::RotationZ => physis_Attribute::RotationZ,
-        _ => physis_Attribute::Unknown,
+        attribute => {
+            log::warn!("Unknown TMFC attribute {attribute:?}");
+            physis_Attribute::Unknown
+        }
     };
 
     let c_rows = data.rows.clone();
@@ -190,13 +200,15 @@ fn to_c_tmfc_data(data: &TmfcData) -> physis_TmfcData {
 
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_tmb_parse(platform: Platform, buffer: physis_Buffer) -> physis_Tmb {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(physis_Tmb::default(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
 
-    if let Ok(tmb) = Tmb::from_existing(platform, data) {
-        to_c_tmb(&tmb)
-    } else {
-        physis_Tmb::default()
-    }
+        if let Some(tmb) = ffi_result(Tmb::from_existing(platform, data)) {
+            to_c_tmb(&tmb)
+        } else {
+            physis_Tmb::default()
+        }
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -204,15 +216,86 @@ pub unsafe extern "C" fn physis_tmb_debug(
     platform: Platform,
     buffer: physis_Buffer,
 ) -> *const c_char {
-    let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+    ffi_guard(null(), || {
+        let data = unsafe { slice::from_raw_parts(buffer.data, buffer.size as usize) };
+
+        match Tmb::from_existing(platform, data) {
+            Ok(tmb) => ffi_to_c_string(&format!("{tmb:#?}")),
+            Err(err) => {
+                set_physis_error(&err);
+                ffi_to_c_string(&format!("{err:#?}"))
+            }
+        }
+    })
+}
 
-    match Tmb::from_existing(platform, data) {
-        Ok(tmb) => ffi_to_c_string(&format!("{tmb:#?}")),
-        Err(err) => ffi_to_c_string(&format!("{err:#?}")),
+impl ToJson for physis_Tmb {
+    fn to_json(&self) -> Value {
+        json!({
+            "nodes": array_to_json(
+                self.nodes,
+                self.node_count as usize,
+                |node| node.data.to_json()
+            ),
+        })
     }
 }
 
+impl ToJson for physis_TimelineNodeData {
+    fn to_json(&self) -> Value {
+        match self {
+            physis_TimelineNodeData::Tmdh(tmdh) => json!({
+                "type": "Tmdh",
+                "value": raw_to_json(tmdh),
+            }),
+            physis_TimelineNodeData::Tmac(tmac) => json!({
+                "type": "Tmac",
+                "id": tmac.id,
+                "time": tmac.time,
+                "tmtr_ids": array_to_json(
+                    tmac.tmtr_ids,
+                    tmac.tmtr_id_count as usize,
+                    |id| json!(id)
+                ),
+            }),
+            physis_TimelineNodeData::Tmtr(tmtr) => json!({
+                "type": "Tmtr",
+                "id": tmtr.id,
+                "animation_ids": array_to_json(
+                    tmtr.animation_ids,
+                    tmtr.animation_id_count as usize,
+                    |id| json!(id)
+                ),
+            }),
+            physis_TimelineNodeData::Tmfc(tmfc) => json!({
+                "type": "Tmfc",
+                "id": tmfc.id,
+                "data": array_to_json(tmfc.data, tmfc.data_count as usize, |data| {
+                    json!({
+                        "attribute": format!("{:?}", data.attribute),
+                        "rows": array_to_json(data.rows, data.row_count as usize, raw_to_json),
+                    })
+                }),
+            }),
+            physis_TimelineNodeData::C013(c013) => json!({
+                "type": "C013",
+                "value": raw_to_json(c013),
+            }),
+            physis_TimelineNodeData::Unknown => json!({ "type": "Unknown" }),
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn physis_tmb_to_json(platform: Platform, buffer: physis_Buffer) -> *const c_char {
+    ffi_guard(null(), || {
+        ffi_parsed_to_json(physis_tmb_parse(platform, buffer), physis_tmb_free)
+    })
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn physis_tmb_free(_tmb: &physis_Tmb) {
-    // TODO: stub
+    ffi_guard((), || {
+        // TODO: stub
+    })
 }